@@ -4,6 +4,7 @@
 
 use crate::input::{Keycode, Mods, MouseButton};
 use std::collections::VecDeque;
+use std::time::Instant;
 
 /// Global Dakota Event Queue
 pub struct GlobalEventSystem {
@@ -180,6 +181,8 @@ pub enum PlatformEvent {
         /// Any utf8 characters that were generated by this keystroke
         utf8: String,
         raw_keycode: RawKeycode,
+        /// When the key was pressed
+        time: Instant,
     },
     /// Key has been released.
     InputKeyUp {
@@ -187,20 +190,32 @@ pub enum PlatformEvent {
         /// Any utf8 characters that were generated by this keystroke
         utf8: String,
         raw_keycode: RawKeycode,
+        /// When the key was released
+        time: Instant,
     },
     /// The set of active Keyboard Modifier keys has changed. The modifier
     /// keypresses are also delivered in the `InputKey*` events, but the current
     /// set of modifiers is included separately here for convenience.
-    InputKeyboardModifiers { mods: Mods },
+    InputKeyboardModifiers { mods: Mods, time: Instant },
     /// Movement of the mouse relative to the previous position
     ///
     /// This is the amount the mouse moved.
-    InputMouseMove { dx: i32, dy: i32 },
+    InputMouseMove { dx: i32, dy: i32, time: Instant },
     /// A mouse button has been pressed. The button is specified
     /// in the case that there are multiple buttons on the mouse.
-    InputMouseButtonDown { button: MouseButton, x: i32, y: i32 },
+    InputMouseButtonDown {
+        button: MouseButton,
+        x: i32,
+        y: i32,
+        time: Instant,
+    },
     /// A mouse button has been released
-    InputMouseButtonUp { button: MouseButton, x: i32, y: i32 },
+    InputMouseButtonUp {
+        button: MouseButton,
+        x: i32,
+        y: i32,
+        time: Instant,
+    },
     /// User has taken a scrolling action.
     ///
     /// This is complex since there are a variety of scrolling options
@@ -224,52 +239,95 @@ pub enum PlatformEvent {
         v120_val: (f64, f64),
         /// The axis source.
         source: AxisSource,
+        /// When the scrolling happened
+        time: Instant,
     },
 }
 
+impl PlatformEvent {
+    /// Get when this event happened
+    ///
+    /// This is the time the platform reported for it, which may be
+    /// a while before the app gets around to handling it.
+    pub fn get_time(&self) -> Instant {
+        match self {
+            PlatformEvent::InputKeyDown { time, .. }
+            | PlatformEvent::InputKeyUp { time, .. }
+            | PlatformEvent::InputKeyboardModifiers { time, .. }
+            | PlatformEvent::InputMouseMove { time, .. }
+            | PlatformEvent::InputMouseButtonDown { time, .. }
+            | PlatformEvent::InputMouseButtonUp { time, .. }
+            | PlatformEvent::InputScroll { time, .. } => *time,
+        }
+    }
+}
+
 impl PlatformEventSystem {
-    pub fn add_event_key_down(&mut self, key: Keycode, utf8: String, raw_key: RawKeycode) {
+    pub fn add_event_key_down(
+        &mut self,
+        key: Keycode,
+        utf8: String,
+        raw_key: RawKeycode,
+        time: Instant,
+    ) {
         self.es_event_queue.push_back(PlatformEvent::InputKeyDown {
             key: key,
             utf8: utf8,
             raw_keycode: raw_key,
+            time: time,
         });
     }
-    pub fn add_event_key_up(&mut self, key: Keycode, utf8: String, raw_key: RawKeycode) {
+    pub fn add_event_key_up(
+        &mut self,
+        key: Keycode,
+        utf8: String,
+        raw_key: RawKeycode,
+        time: Instant,
+    ) {
         self.es_event_queue.push_back(PlatformEvent::InputKeyUp {
             key: key,
             utf8: utf8,
             raw_keycode: raw_key,
+            time: time,
         });
     }
 
-    pub fn add_event_keyboard_modifiers(&mut self, mods: Mods) {
+    pub fn add_event_keyboard_modifiers(&mut self, mods: Mods, time: Instant) {
         self.es_event_queue
-            .push_back(PlatformEvent::InputKeyboardModifiers { mods: mods });
+            .push_back(PlatformEvent::InputKeyboardModifiers {
+                mods: mods,
+                time: time,
+            });
     }
 
-    pub fn add_event_mouse_move(&mut self, dx: i32, dy: i32) {
+    pub fn add_event_mouse_move(&mut self, dx: i32, dy: i32, time: Instant) {
         // Update our cached mouse position
         self.es_mouse_pos.0 += dx;
         self.es_mouse_pos.1 += dy;
 
         self.es_event_queue
-            .push_back(PlatformEvent::InputMouseMove { dx: dx, dy: dy });
+            .push_back(PlatformEvent::InputMouseMove {
+                dx: dx,
+                dy: dy,
+                time: time,
+            });
     }
-    pub fn add_event_mouse_button_down(&mut self, button: MouseButton) {
+    pub fn add_event_mouse_button_down(&mut self, button: MouseButton, time: Instant) {
         self.es_event_queue
             .push_back(PlatformEvent::InputMouseButtonDown {
                 button: button,
                 x: self.es_mouse_pos.0,
                 y: self.es_mouse_pos.1,
+                time: time,
             });
     }
-    pub fn add_event_mouse_button_up(&mut self, button: MouseButton) {
+    pub fn add_event_mouse_button_up(&mut self, button: MouseButton, time: Instant) {
         self.es_event_queue
             .push_back(PlatformEvent::InputMouseButtonUp {
                 button: button,
                 x: self.es_mouse_pos.0,
                 y: self.es_mouse_pos.1,
+                time: time,
             });
     }
 
@@ -279,6 +337,7 @@ impl PlatformEventSystem {
         y: Option<i32>,
         v120: (f64, f64),
         source: AxisSource,
+        time: Instant,
     ) {
         self.es_event_queue.push_back(PlatformEvent::InputScroll {
             position: self.es_mouse_pos,
@@ -286,6 +345,7 @@ impl PlatformEventSystem {
             yrel: y,
             v120_val: v120,
             source: source,
+            time: time,
         });
     }
 
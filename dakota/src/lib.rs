@@ -11,7 +11,10 @@ extern crate image;
 extern crate lluvia as ll;
 extern crate thundr as th;
 pub use th::ThundrError as DakotaError;
-pub use th::{Damage, Dmabuf, DmabufPlane, Droppable, MappedImage};
+pub use th::{
+    CaptureImage, Damage, DisplayDescription, Dmabuf, DmabufPlane, Droppable, MappedImage,
+    PresentationTime, SurfaceTransform,
+};
 
 extern crate bitflags;
 
@@ -40,7 +43,7 @@ mod output;
 mod virtual_output;
 pub use virtual_output::VirtualOutput;
 mod render;
-pub use output::{Output, OutputInfo};
+pub use output::{FrameCopy, Output, OutputInfo};
 mod font;
 mod scene;
 pub use scene::Scene;
@@ -291,6 +294,15 @@ impl Dakota {
             .collect()
     }
 
+    /// Get the YUV formats that Dakota's primary GPU can import
+    ///
+    /// These are returned as pairs of DRM fourcc codes and modifiers.
+    pub fn get_supported_drm_ycbcr_formats(&self) -> Vec<(u32, u64)> {
+        self.d_thund
+            .get_primary_dev()
+            .get_supported_ycbcr_drm_formats()
+    }
+
     /// Get list of OutputInfos
     ///
     /// This returns a list of OutputInfo structures that can be used to create
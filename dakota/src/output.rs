@@ -10,9 +10,9 @@
 extern crate utils;
 use crate::event::OutputEventSystem;
 use crate::platform::OutputPlatform;
-use crate::{OutputEvent, OutputId, Scene, VirtualOutput};
+use crate::{DakotaId, OutputEvent, OutputId, Scene, VirtualOutput};
 use utils::log;
-use utils::{anyhow, Error, Result};
+use utils::{anyhow, region::Rect, Error, Result};
 
 use std::ops::DerefMut;
 use std::sync::{Arc, RwLock};
@@ -108,6 +108,22 @@ impl OutputInfo {
     }
 }
 
+/// A copy of part of a frame
+///
+/// This is how screenshots of an Output are taken. The copy can be
+/// recorded partway through drawing, which leaves out anything drawn
+/// on top such as the cursor.
+pub struct FrameCopy<'a> {
+    /// The image to copy into
+    pub fc_image: &'a th::CaptureImage,
+    /// The region of this Output to copy, in pixels
+    pub fc_region: Rect<i32>,
+    /// Copy the frame right before this element is drawn, leaving it
+    /// and everything drawn after it out of the copy. If this is None,
+    /// or the element isn't drawn, the finished frame is copied.
+    pub fc_before: Option<DakotaId>,
+}
+
 /// Dakota Output
 ///
 /// The Output object controls all presentation and rendering logic,
@@ -130,6 +146,9 @@ pub struct Output {
     /// Offset of this Output within the VirtualOutput. This controls
     /// which region of the Scene is drawn.
     pub(crate) d_offset: (i32, i32),
+    /// The number of pixels drawn for each unit of the Scene. This
+    /// magnifies the Scene on high resolution displays.
+    pub(crate) d_scale: f32,
 }
 
 impl Output {
@@ -147,6 +166,7 @@ impl Output {
             d_output_plat: window_plat,
             d_display: display,
             d_offset: (0, 0),
+            d_scale: 1.0,
         })
     }
 
@@ -162,6 +182,30 @@ impl Output {
         self.d_display.get_resolution()
     }
 
+    /// Get the size of the Scene region this Output presents
+    ///
+    /// This is the resolution divided by the scale of this Output.
+    pub fn get_logical_resolution(&self) -> (u32, u32) {
+        let res = self.get_resolution();
+        (
+            (res.0 as f32 / self.d_scale).round() as u32,
+            (res.1 as f32 / self.d_scale).round() as u32,
+        )
+    }
+
+    /// Get the scale this Output draws the Scene at
+    pub fn get_scale(&self) -> f32 {
+        self.d_scale
+    }
+
+    /// Set the scale this Output draws the Scene at
+    ///
+    /// A scale of 2 will draw every unit of the Scene as two pixels. The
+    /// offset of this Output is in Scene units, and is not affected.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.d_scale = scale;
+    }
+
     /// Set the presentation offset of this Output
     ///
     /// Sets the offset of this Output within the VirtualOutput. This controls
@@ -170,11 +214,31 @@ impl Output {
         self.d_offset = (x, y);
     }
 
+    /// Get the name, refresh rate, etc of the display this Output is on
+    pub fn get_description(&self) -> th::DisplayDescription {
+        self.d_display.get_description()
+    }
+
     /// Get the major, minor of the DRM device currently in use
     pub fn get_drm_dev(&self) -> Option<(i64, i64)> {
         self.d_display.get_drm_dev()
     }
 
+    /// Get the number of frames this Output has presented
+    ///
+    /// A redraw which reached the display will increment this.
+    pub fn get_present_count(&self) -> u64 {
+        self.d_display.get_present_count()
+    }
+
+    /// Get when the most recent frame known to be on screen was shown
+    ///
+    /// This may lag behind `get_present_count` until the display reports
+    /// that the latest frame was shown.
+    pub fn get_last_presentation(&self) -> Option<th::PresentationTime> {
+        self.d_display.get_last_presentation()
+    }
+
     /// Set the resolution of the current window
     pub fn set_resolution(&mut self, scene: &mut Scene, width: u32, height: u32) -> Result<()> {
         let dom = scene
@@ -236,13 +300,33 @@ impl Output {
             .collect()
     }
 
+    /// Get the DRM format modifiers this display can scan out
+    ///
+    /// This is empty if the display backend can't tell us.
+    pub fn get_supported_drm_scanout_modifiers(&self) -> Vec<u64> {
+        self.d_display.get_scanout_modifiers()
+    }
+
     /// Draw the next frame
     ///
     /// This dispatches *only* the rendering backend of Dakota. The `dispatch_platform`
     /// call *must* take place before this in order for correct updates to happen, as
     /// this will only render the current state of Dakota.
-    pub fn redraw(&mut self, _virtual_output: &VirtualOutput, scene: &mut Scene) -> Result<()> {
-        match self.draw_surfacelists(scene) {
+    pub fn redraw(&mut self, virtual_output: &VirtualOutput, scene: &mut Scene) -> Result<()> {
+        self.redraw_with_copies(virtual_output, scene, &[])
+    }
+
+    /// Draw the next frame, copying it into `copies` along the way
+    ///
+    /// This is the same as `redraw`. The copies are finished once the
+    /// frame has been drawn, which `CaptureImage::is_copy_done` checks for.
+    pub fn redraw_with_copies(
+        &mut self,
+        _virtual_output: &VirtualOutput,
+        scene: &mut Scene,
+        copies: &[FrameCopy],
+    ) -> Result<()> {
+        match self.draw_surfacelists(scene, copies) {
             Ok(()) => {}
             Err(th::ThundrError::OUT_OF_DATE) => {
                 // If Thundr returned out of date while
@@ -260,6 +344,33 @@ impl Output {
         return Ok(());
     }
 
+    /// Get the DRM format frames of this output are copied in
+    ///
+    /// This is None if the display backend doesn't let us copy frames
+    /// out of the swapchain.
+    pub fn get_capture_format(&self) -> Option<u32> {
+        self.d_display.get_capture_format()
+    }
+
+    /// Create an image to copy frames into
+    ///
+    /// The copied pixels can be read with `CaptureImage::read_bits`.
+    pub fn create_capture_image(&self, width: u32, height: u32) -> Result<th::CaptureImage> {
+        self.d_display
+            .create_capture_image(width, height)
+            .map_err(|e| Error::from(e).context("Thundr: could not create capture image"))
+    }
+
+    /// Import a client's dmabuf to copy frames into
+    pub fn create_capture_image_from_dmabuf(
+        &self,
+        dmabuf: &th::Dmabuf,
+    ) -> Result<th::CaptureImage> {
+        self.d_display
+            .create_capture_image_from_dmabuf(dmabuf)
+            .map_err(|e| Error::from(e).context("Thundr: could not import dmabuf for capture"))
+    }
+
     /// Dump the current swapchain image to a file
     ///
     /// This dumps the image contents to a simple PPM file, used for automated testing
@@ -6,7 +6,7 @@
 extern crate input;
 use input::event::keyboard::{KeyState, KeyboardEvent, KeyboardEventTrait};
 use input::event::pointer;
-use input::event::pointer::{ButtonState, PointerEvent, PointerScrollEvent};
+use input::event::pointer::{ButtonState, PointerEvent, PointerEventTrait, PointerScrollEvent};
 use input::{Libinput, LibinputInterface};
 
 extern crate xkbcommon;
@@ -17,13 +17,14 @@ use crate::event::*;
 use crate::input::{convert_libinput_mouse_to_dakota, convert_xkb_keycode_to_dakota, Mods};
 use crate::OutputId;
 use crate::*;
-use utils::log;
+use utils::{log, timing::get_instant_from_monotonic_usec};
 
 use std::fs::{File, OpenOptions};
 use std::os::fd::{AsRawFd, RawFd};
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::OwnedFd;
 use std::path::Path;
+use std::time::Instant;
 
 /// This is sort of like a private userdata struct which
 /// is used as an interface to the systems devices
@@ -165,6 +166,7 @@ impl LibinputPlat {
         ev: &dyn pointer::PointerScrollEvent,
         source: AxisSource,
         v120: (f64, f64),
+        time: Instant,
     ) {
         let mut horizontal = None;
         let mut vertical = None;
@@ -177,7 +179,7 @@ impl LibinputPlat {
             vertical = Some((ev.scroll_value(pointer::Axis::Vertical) * -1.0) as i32);
         }
 
-        evsys.add_event_scroll(horizontal, vertical, v120, source);
+        evsys.add_event_scroll(horizontal, vertical, v120, source, time);
     }
 
     /// Get the next available event from libinput
@@ -191,14 +193,19 @@ impl LibinputPlat {
         while let Some(ev) = self.dp_libin.next() {
             match ev {
                 input::event::Event::Pointer(PointerEvent::Motion(m)) => {
-                    evsys.add_event_mouse_move(m.dx() as i32, m.dy() as i32);
+                    evsys.add_event_mouse_move(
+                        m.dx() as i32,
+                        m.dy() as i32,
+                        get_instant_from_monotonic_usec(m.time_usec()),
+                    );
                 }
                 // TODO: actually handle advanced scrolling/finger behavior
                 // We should track ScrollWheel using the v120 api, and handle
                 // high-res and wheel click behavior. For ScrollFinger we
                 // should handle kinetic scrolling
                 input::event::Event::Pointer(PointerEvent::ScrollFinger(sf)) => {
-                    self.get_scroll_event(&mut evsys, &sf, AxisSource::Finger, (0.0, 0.0));
+                    let time = get_instant_from_monotonic_usec(sf.time_usec());
+                    self.get_scroll_event(&mut evsys, &sf, AxisSource::Finger, (0.0, 0.0), time);
                 }
                 input::event::Event::Pointer(PointerEvent::ScrollWheel(sw)) => {
                     let mut v120 = (0.0, 0.0);
@@ -212,18 +219,21 @@ impl LibinputPlat {
                         v120.1 = sw.scroll_value_v120(pointer::Axis::Vertical);
                     }
 
-                    self.get_scroll_event(&mut evsys, &sw, AxisSource::Wheel, v120);
+                    let time = get_instant_from_monotonic_usec(sw.time_usec());
+                    self.get_scroll_event(&mut evsys, &sw, AxisSource::Wheel, v120, time);
                 }
                 input::event::Event::Pointer(PointerEvent::Button(b)) => {
                     let button = convert_libinput_mouse_to_dakota(b.button());
+                    let time = get_instant_from_monotonic_usec(b.time_usec());
 
                     if b.button_state() == ButtonState::Pressed {
-                        evsys.add_event_mouse_button_down(button);
+                        evsys.add_event_mouse_button_down(button, time);
                     } else {
-                        evsys.add_event_mouse_button_up(button);
+                        evsys.add_event_mouse_button_up(button, time);
                     }
                 }
                 input::event::Event::Keyboard(KeyboardEvent::Key(k)) => {
+                    let time = get_instant_from_monotonic_usec(k.time_usec());
                     // let xkb keep track of the keyboard state
                     let changed = self.dp_xkb_state.update_key(
                         // add 8 to account for differences between evdev and x11
@@ -261,17 +271,18 @@ impl LibinputPlat {
                         }
 
                         // Add the modifier event with the latest mods
-                        evsys.add_event_keyboard_modifiers(self.dp_current_modifiers);
+                        evsys.add_event_keyboard_modifiers(self.dp_current_modifiers, time);
                     }
 
                     if k.key_state() == KeyState::Pressed {
-                        evsys.add_event_key_down(key, utf, RawKeycode::Linux(k.key()));
+                        evsys.add_event_key_down(key, utf, RawKeycode::Linux(k.key()), time);
                     } else {
                         // Key up events do not generate utf characters
                         evsys.add_event_key_up(
                             key,
                             String::with_capacity(0),
                             RawKeycode::Linux(k.key()),
+                            time,
                         );
                     }
                 }
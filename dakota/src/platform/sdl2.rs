@@ -17,10 +17,23 @@ use sdl2::event::{Event, WindowEvent};
 extern crate xkbcommon;
 use std::os::fd::RawFd;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use xkbcommon::xkb;
 
 const SCROLL_SENSITIVITY: f64 = 32.0;
 
+/// Convert an SDL event timestamp into an Instant
+///
+/// SDL timestamps are the milliseconds since SDL was initialized, so
+/// find how long ago the event was and go back that far from now.
+fn get_event_time(timestamp: u32) -> Instant {
+    let now = Instant::now();
+    let ticks = unsafe { sdl2_sys::SDL_GetTicks() };
+    let elapsed = Duration::from_millis(ticks.saturating_sub(timestamp) as u64);
+
+    now.checked_sub(elapsed).unwrap_or(now)
+}
+
 /// Common SDL2 dispatch backend
 #[allow(dead_code)]
 pub struct SDL2Plat {
@@ -154,11 +167,13 @@ impl SDL2Plat {
                 // enum that we control. See input.rs for how this is done. We
                 // also wrap the Keyboard Modifiercodes in a similar way
                 Event::KeyDown {
+                    timestamp,
                     keycode,
                     keymod,
                     scancode,
                     ..
                 } => {
+                    let time = get_event_time(timestamp);
                     let key = convert_sdl_keycode_to_dakota(keycode.unwrap());
                     let mods = convert_sdl_mods_to_dakota(keymod);
                     self.update_xkb_from_scancode(scancode.unwrap(), xkb::KeyDirection::Down);
@@ -168,6 +183,7 @@ impl SDL2Plat {
                         key,
                         utf,
                         RawKeycode::Linux(raw),
+                        time,
                     );
 
                     if mods != self.sdl_mods {
@@ -175,15 +191,17 @@ impl SDL2Plat {
                         platform_evsys
                             .as_mut()
                             .unwrap()
-                            .add_event_keyboard_modifiers(mods);
+                            .add_event_keyboard_modifiers(mods, time);
                     }
                 }
                 Event::KeyUp {
+                    timestamp,
                     keycode,
                     keymod,
                     scancode,
                     ..
                 } => {
+                    let time = get_event_time(timestamp);
                     let key = convert_sdl_keycode_to_dakota(keycode.unwrap());
                     let mods = convert_sdl_mods_to_dakota(keymod);
                     self.update_xkb_from_scancode(scancode.unwrap(), xkb::KeyDirection::Up);
@@ -193,6 +211,7 @@ impl SDL2Plat {
                         key,
                         String::with_capacity(0), // no utf8 characters are generated for lifting a key
                         RawKeycode::Linux(raw),
+                        time,
                     );
 
                     if mods != self.sdl_mods {
@@ -200,38 +219,50 @@ impl SDL2Plat {
                         platform_evsys
                             .as_mut()
                             .unwrap()
-                            .add_event_keyboard_modifiers(mods);
+                            .add_event_keyboard_modifiers(mods, time);
                     }
                 }
                 // handle pointer inputs. This just looks like the above keyboard
-                Event::MouseButtonDown { mouse_btn, .. } => {
+                Event::MouseButtonDown {
+                    timestamp,
+                    mouse_btn,
+                    ..
+                } => {
                     let button = convert_sdl_mouse_to_dakota(mouse_btn);
                     platform_evsys
                         .as_mut()
                         .unwrap()
-                        .add_event_mouse_button_down(button);
+                        .add_event_mouse_button_down(button, get_event_time(timestamp));
                 }
-                Event::MouseButtonUp { mouse_btn, .. } => {
+                Event::MouseButtonUp {
+                    timestamp,
+                    mouse_btn,
+                    ..
+                } => {
                     let button = convert_sdl_mouse_to_dakota(mouse_btn);
                     platform_evsys
                         .as_mut()
                         .unwrap()
-                        .add_event_mouse_button_up(button);
+                        .add_event_mouse_button_up(button, get_event_time(timestamp));
                 }
-                Event::MouseWheel { x, y, .. } => {
-                    platform_evsys.as_mut().unwrap().add_event_scroll(
-                        // reverse the scroll direction
-                        Some((x as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
-                        Some((y as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
-                        (0.0, 0.0), // v120 value unspecified
-                        AxisSource::Wheel,
-                    )
-                }
-                Event::MouseMotion { x, y, .. } => {
-                    platform_evsys
-                        .as_mut()
-                        .unwrap()
-                        .add_event_mouse_move(x - self.sdl_mouse_pos.0, y - self.sdl_mouse_pos.1);
+                Event::MouseWheel {
+                    timestamp, x, y, ..
+                } => platform_evsys.as_mut().unwrap().add_event_scroll(
+                    // reverse the scroll direction
+                    Some((x as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
+                    Some((y as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
+                    (0.0, 0.0), // v120 value unspecified
+                    AxisSource::Wheel,
+                    get_event_time(timestamp),
+                ),
+                Event::MouseMotion {
+                    timestamp, x, y, ..
+                } => {
+                    platform_evsys.as_mut().unwrap().add_event_mouse_move(
+                        x - self.sdl_mouse_pos.0,
+                        y - self.sdl_mouse_pos.1,
+                        get_event_time(timestamp),
+                    );
 
                     // Update our mouse position
                     self.sdl_mouse_pos.0 = x;
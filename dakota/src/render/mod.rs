@@ -1,6 +1,6 @@
 use crate::font::Glyph;
 use crate::layout::LayoutNode;
-use crate::{dom, DakotaId, Output, Scene};
+use crate::{dom, DakotaId, FrameCopy, Output, Scene};
 
 /// Dakota Drawing logic
 ///
@@ -27,6 +27,10 @@ pub(crate) struct RenderTransaction<'a> {
     rt_default_font_inst: DakotaId,
     rt_glyphs: ll::Snapshot<'a, Glyph>,
     rt_viewports: ll::Snapshot<'a, th::Viewport>,
+    rt_transforms: ll::Snapshot<'a, th::SurfaceTransform>,
+    rt_source_rects: ll::Snapshot<'a, th::Rect<f32>>,
+    /// The Output's scale, used to convert from Scene coordinates to pixels
+    rt_scale: f32,
     rt_layout_nodes: ll::Snapshot<'a, LayoutNode>,
 }
 
@@ -40,6 +44,8 @@ impl<'a> RenderTransaction<'a> {
         self.rt_text_font.precommit();
         self.rt_glyphs.precommit();
         self.rt_viewports.precommit();
+        self.rt_transforms.precommit();
+        self.rt_source_rects.precommit();
         self.rt_layout_nodes.precommit();
 
         // Now do actual commit to WAR ids being dropped
@@ -50,6 +56,8 @@ impl<'a> RenderTransaction<'a> {
         self.rt_text_font.commit();
         self.rt_glyphs.commit();
         self.rt_viewports.commit();
+        self.rt_transforms.commit();
+        self.rt_source_rects.commit();
         self.rt_layout_nodes.commit();
     }
 
@@ -112,6 +120,12 @@ impl<'a> RenderTransaction<'a> {
             let mut content_num = 0;
 
             if self.rt_resource_thundr_image.get(&resource_id).is_some() {
+                if let Some(transform) = self.rt_transforms.get(node) {
+                    surf.set_transform(*transform);
+                }
+                if let Some(rect) = self.rt_source_rects.get(node) {
+                    surf.set_src_rect(*rect);
+                }
                 content_num += 1;
             }
             if let Some(color) = self.rt_resource_color.get(&resource_id) {
@@ -190,6 +204,31 @@ impl<'a> RenderTransaction<'a> {
         return Some(ret);
     }
 
+    /// Convert a span from Scene coordinates to this Output's pixels
+    ///
+    /// Both edges are rounded separately so that neighboring elements
+    /// do not end up with gaps between them.
+    fn scale_span(&self, pos: i32, size: i32) -> (i32, i32) {
+        let start = (pos as f32 * self.rt_scale).round() as i32;
+        let end = ((pos + size) as f32 * self.rt_scale).round() as i32;
+        (start, end - start)
+    }
+
+    /// Restrict drawing to `viewport`, scaling it to this Output's pixels
+    fn set_frame_viewport(
+        &self,
+        frame: &mut th::FrameRenderer<'a>,
+        viewport: &th::Viewport,
+    ) -> th::Result<()> {
+        let mut scaled = viewport.clone();
+        let (x, width) = self.scale_span(viewport.offset.0, viewport.size.0);
+        let (y, height) = self.scale_span(viewport.offset.1, viewport.size.1);
+        scaled.offset = (x, y);
+        scaled.size = (width, height);
+
+        frame.set_viewport(&scaled)
+    }
+
     /// Test if we should skip drawing this node because it is offscreen
     fn is_node_visible(&self, viewport: &th::Viewport, node: &DakotaId, base: (i32, i32)) -> bool {
         let layout = self.rt_layout_nodes.get(node).unwrap();
@@ -234,12 +273,17 @@ impl<'a> RenderTransaction<'a> {
         node: &DakotaId,
         base: (i32, i32),
     ) -> th::Result<()> {
-        let surf = self.get_thundr_surf_for_el(node, base)?;
+        let mut surf = self.get_thundr_surf_for_el(node, base)?;
 
         if !self.is_node_visible(viewport, node, base) {
             return Ok(());
         }
 
+        // Layout happens in Scene coordinates, but Thundr draws in pixels
+        let (x, width) = self.scale_span(surf.s_rect.r_pos.0, surf.s_rect.r_size.0);
+        let (y, height) = self.scale_span(surf.s_rect.r_pos.1, surf.s_rect.r_size.1);
+        surf.s_rect = th::Rect::new(x, y, width, height);
+
         // Get the image to use for this surface, if we have one
         // This is done separately so that we can avoid cloning the image
         // id. The atomic inc/dec to do this shows up in profiling
@@ -258,16 +302,42 @@ impl<'a> RenderTransaction<'a> {
         frame.draw_surface(&surf, image)
     }
 
+    /// Record any copies of the frame that should happen before `node`
+    fn copy_frame_before(
+        &self,
+        frame: &mut th::FrameRenderer<'a>,
+        copies: &mut Vec<&FrameCopy>,
+        node: &DakotaId,
+    ) -> th::Result<()> {
+        let mut i = 0;
+        while i < copies.len() {
+            if copies[i].fc_before.as_ref() == Some(node) {
+                let copy = copies.remove(i);
+                frame.copy_frame(copy.fc_image, &copy.fc_region)?;
+            } else {
+                i += 1;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Recursively draw node and all of its children
     ///
-    /// This does not cross viewport boundaries
+    /// This does not cross viewport boundaries. Copies waiting on `node`
+    /// are taken out of `copies` and recorded before it is drawn.
     fn draw_node_recurse(
         &self,
         frame: &mut th::FrameRenderer<'a>,
         viewport: &th::Viewport,
         node: &DakotaId,
         base: (i32, i32),
+        copies: &mut Vec<&FrameCopy>,
     ) -> th::Result<()> {
+        if !copies.is_empty() {
+            self.copy_frame_before(frame, copies, node)?;
+        }
+
         // If this node is a viewport then update our display viewport
         let new_th_viewport = match self.rt_viewports.get(node).is_some() {
             true => {
@@ -282,7 +352,7 @@ impl<'a> RenderTransaction<'a> {
 
                 // Set Thundr's currently in use viewport
                 let th_viewport = self.get_display_viewport(viewport, node, base).unwrap();
-                frame.set_viewport(&th_viewport)?;
+                self.set_frame_viewport(frame, &th_viewport)?;
 
                 Some(th_viewport)
             }
@@ -309,26 +379,37 @@ impl<'a> RenderTransaction<'a> {
 
         // Now draw each of our children
         for child in layout.l_children.iter() {
-            self.draw_node_recurse(frame, new_viewport, child, new_base)?;
+            self.draw_node_recurse(frame, new_viewport, child, new_base, copies)?;
         }
 
         // If this node was a viewport then restore our old viewport
         if new_th_viewport.is_some() {
-            frame.set_viewport(viewport)?;
+            self.set_frame_viewport(frame, viewport)?;
         }
 
         Ok(())
     }
 
     /// Draw a scene using the provided renderer and transaction view.
+    ///
+    /// Copies which were not recorded partway through are recorded once
+    /// the entire scene has been drawn.
     pub(crate) fn draw_surfacelists(
         &self,
         frame: &mut th::FrameRenderer<'a>,
         root_viewport: &th::Viewport,
         root_node: DakotaId,
         base: (i32, i32),
+        copies: &[FrameCopy],
     ) -> th::Result<()> {
-        self.draw_node_recurse(frame, &root_viewport, &root_node, base)
+        let mut pending: Vec<&FrameCopy> = copies.iter().collect();
+        self.draw_node_recurse(frame, &root_viewport, &root_node, base, &mut pending)?;
+
+        for copy in pending.drain(..) {
+            frame.copy_frame(copy.fc_image, &copy.fc_region)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -339,7 +420,12 @@ impl Output {
     /// present in the specified scene object.
     ///
     /// Offset specifies the starting offset into this Scene to start drawing.
-    pub(crate) fn draw_surfacelists(&mut self, scene: &Scene) -> th::Result<()> {
+    /// The frame is copied into each of `copies` while it is drawn.
+    pub(crate) fn draw_surfacelists(
+        &mut self,
+        scene: &Scene,
+        copies: &[FrameCopy],
+    ) -> th::Result<()> {
         let root_node = scene
             .d_layout_tree_root
             .clone()
@@ -356,6 +442,9 @@ impl Output {
             rt_default_font_inst: scene.d_default_font_inst.clone(),
             rt_glyphs: scene.d_glyphs.snapshot(),
             rt_viewports: scene.d_viewports.snapshot(),
+            rt_transforms: scene.d_transforms.snapshot(),
+            rt_source_rects: scene.d_source_rects.snapshot(),
+            rt_scale: self.d_scale,
             rt_layout_nodes: scene.d_layout_nodes.snapshot(),
         };
         trans.draw_surfacelists(
@@ -365,6 +454,7 @@ impl Output {
             // Invert the direction of our offset. This will shift
             // everything down to the origin of our window
             (-self.d_offset.0, -self.d_offset.1),
+            copies,
         )?;
         trans.commit();
         frame.present()
@@ -110,4 +110,14 @@ impl Scene {
     //
     // This excepts it from being clipped inside of the parent during drawing.
     define_element_property!(unbounded_subsurface, unbounded_subsurf, bool);
+    // Image transform
+    //
+    // The rotation or flip that has been applied to the resource's image.
+    // It is undone while drawing so the image appears upright.
+    define_element_property!(transform, transforms, th::SurfaceTransform);
+    // Image source region
+    //
+    // Only this part of the resource's image is drawn, stretched to fill the
+    // Element. The image spans from (0, 0) to (1, 1).
+    define_element_property!(source_rect, source_rects, th::Rect<f32>);
 }
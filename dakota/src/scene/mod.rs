@@ -57,6 +57,10 @@ pub struct Scene {
     pub d_bounds: ll::Component<dom::Edges>,
     pub d_children: ll::Component<Vec<DakotaId>>,
     pub d_unbounded_subsurf: ll::Component<bool>,
+    /// Rotation or flip of the resource's image within this element
+    pub d_transforms: ll::Component<th::SurfaceTransform>,
+    /// Region of the resource's image to show in this element
+    pub d_source_rects: ll::Component<th::Rect<f32>>,
     /// Is this element a viewport node. If so it will have a viewport
     /// boundary and scroll the content inside of it.
     pub d_is_viewport: ll::Component<bool>,
@@ -110,6 +114,8 @@ impl Scene {
         create_component_and_table!(layout_ecs, dom::Edges, bounds_table);
         create_component_and_table!(layout_ecs, Vec<DakotaId>, children_table);
         create_component_and_table!(layout_ecs, bool, unbounded_subsurf_table);
+        create_component_and_table!(layout_ecs, th::SurfaceTransform, transforms_table);
+        create_component_and_table!(layout_ecs, th::Rect<f32>, source_rects_table);
         create_component_and_table!(layout_ecs, th::Viewport, viewports_table);
         create_component_and_table!(layout_ecs, bool, is_viewports_table);
 
@@ -143,6 +149,8 @@ impl Scene {
             d_children: children_table,
             d_dom: None,
             d_unbounded_subsurf: unbounded_subsurf_table,
+            d_transforms: transforms_table,
+            d_source_rects: source_rects_table,
             d_is_viewport: is_viewports_table,
             d_viewports: viewports_table,
             d_layout_tree_root: None,
@@ -202,6 +210,8 @@ impl Scene {
             || self.d_bounds.is_modified()
             || self.d_children.is_modified()
             || self.d_unbounded_subsurf.is_modified()
+            || self.d_transforms.is_modified()
+            || self.d_source_rects.is_modified()
     }
 
     fn clear_needs_refresh(&mut self) {
@@ -220,6 +230,8 @@ impl Scene {
         self.d_bounds.clear_modified();
         self.d_children.clear_modified();
         self.d_unbounded_subsurf.clear_modified();
+        self.d_transforms.clear_modified();
+        self.d_source_rects.clear_modified();
     }
 
     /// Create a new Dakota Id
@@ -0,0 +1,260 @@
+// Support code for pointer constraints
+//
+// Clients may lock the pointer in place or confine it to a region of
+// their surface. A constraint only takes effect while its surface has
+// the pointer and keyboard focus, and is checked every time the cursor
+// is moved.
+use super::*;
+use wayland_protocols::wp::pointer_constraints::zv1::server::{
+    zwp_confined_pointer_v1 as zcp, zwp_locked_pointer_v1 as zlp,
+};
+
+/// The protocol object of a constraint, which also tells us its type
+pub enum ConstraintKind {
+    Lock(zlp::ZwpLockedPointerV1),
+    Confine(zcp::ZwpConfinedPointerV1),
+}
+
+impl ConstraintKind {
+    pub fn id(&self) -> ws::backend::ObjectId {
+        match self {
+            ConstraintKind::Lock(lock) => lock.id(),
+            ConstraintKind::Confine(confine) => confine.id(),
+        }
+    }
+}
+
+/// A pointer lock or confinement placed on a surface
+pub struct PointerConstraint {
+    pub pc_kind: ConstraintKind,
+    /// Oneshot constraints are gone once deactivated, persistent ones
+    /// may activate again the next time the surface has focus
+    pub pc_oneshot: bool,
+    /// The part of the surface the pointer must be in, in surface
+    /// coordinates. None means the entire surface.
+    pub pc_region: Option<Region>,
+    /// Region set by the client, which is applied on the next commit
+    pub pc_pending_region: Option<Option<Region>>,
+    /// Where the client would like the cursor placed when a lock ends
+    pub pc_cursor_hint: Option<(f64, f64)>,
+    pub pc_pending_cursor_hint: Option<(f64, f64)>,
+    pub pc_active: bool,
+}
+
+impl PointerConstraint {
+    pub fn new(kind: ConstraintKind, oneshot: bool, region: Option<Region>) -> Self {
+        Self {
+            pc_kind: kind,
+            pc_oneshot: oneshot,
+            pc_region: region,
+            pc_pending_region: None,
+            pc_cursor_hint: None,
+            pc_pending_cursor_hint: None,
+            pc_active: false,
+        }
+    }
+
+    fn is_lock(&self) -> bool {
+        match self.pc_kind {
+            ConstraintKind::Lock(_) => true,
+            ConstraintKind::Confine(_) => false,
+        }
+    }
+
+    fn activate(&mut self) {
+        self.pc_active = true;
+        match &self.pc_kind {
+            ConstraintKind::Lock(lock) => lock.locked(),
+            ConstraintKind::Confine(confine) => confine.confined(),
+        }
+    }
+
+    fn deactivate(&mut self) {
+        self.pc_active = false;
+        match &self.pc_kind {
+            ConstraintKind::Lock(lock) => lock.unlocked(),
+            ConstraintKind::Confine(confine) => confine.unconfined(),
+        }
+    }
+}
+
+/// Move the cursor from `old` towards `new` without leaving a constraint
+///
+/// `contains` checks if a point is in the constraint's region. Locked
+/// pointers never move, and confined ones fall back to moving along a
+/// single axis before staying put.
+pub(super) fn constrain_motion<F>(
+    lock: bool,
+    old: (f64, f64),
+    new: (f64, f64),
+    contains: F,
+) -> (f64, f64)
+where
+    F: Fn(f64, f64) -> bool,
+{
+    if lock {
+        return old;
+    }
+
+    for pos in [new, (new.0, old.1), (old.0, new.1)].iter() {
+        if contains(pos.0, pos.1) {
+            return *pos;
+        }
+    }
+    old
+}
+
+impl Atmosphere {
+    pub fn has_pointer_constraint(&self, id: &SurfaceId) -> bool {
+        self.a_pointer_constraint.get(id).is_some()
+    }
+
+    /// Place a new constraint on a surface
+    ///
+    /// The caller must check that the surface is not already constrained.
+    pub fn add_pointer_constraint(&mut self, id: &SurfaceId, constraint: PointerConstraint) {
+        self.a_pointer_constraint.set(id, constraint);
+        self.update_pointer_constraint();
+    }
+
+    /// Forget the constraint on a surface without notifying the client
+    ///
+    /// This is used when the constraint object or surface was destroyed.
+    pub fn remove_pointer_constraint(&mut self, id: &SurfaceId) {
+        if self.a_pointer_constraint.take(id).is_some() {
+            if self.get_pointer_constrained().as_ref() == Some(id) {
+                self.set_pointer_constrained(None);
+            }
+        }
+    }
+
+    /// Apply the double buffered constraint state for a surface
+    pub fn commit_pointer_constraint(&mut self, id: &SurfaceId) {
+        if let Some(mut pc) = self.a_pointer_constraint.get_mut(id) {
+            if let Some(region) = pc.pc_pending_region.take() {
+                pc.pc_region = region;
+            }
+            if let Some(hint) = pc.pc_pending_cursor_hint.take() {
+                pc.pc_cursor_hint = Some(hint);
+            }
+        }
+    }
+
+    /// Is the point (x, y) in the area a constraint holds the pointer to
+    ///
+    /// This is the intersection of the constraint's region and the
+    /// surface's input region.
+    fn pointer_constraint_contains(
+        &self,
+        id: &SurfaceId,
+        pc: &PointerConstraint,
+        x: f64,
+        y: f64,
+    ) -> bool {
+        let (sx, sy) = match self.global_coords_to_surf(id, x, y) {
+            Some((sx, sy)) => (sx as i32, sy as i32),
+            None => return false,
+        };
+
+        let in_input = match self.a_input_region.get(id) {
+            Some(region) => region.lock().unwrap().intersects(sx, sy),
+            None => true,
+        };
+        let in_region = match pc.pc_region.as_ref() {
+            Some(region) => region.intersects(sx, sy),
+            None => true,
+        };
+
+        in_input && in_region
+    }
+
+    /// Activate or deactivate pointer constraints after the focus or
+    /// cursor has changed
+    ///
+    /// The constraint on the surface under the pointer is activated once
+    /// its window is in focus and the cursor is inside of its region.
+    pub fn update_pointer_constraint(&mut self) {
+        let focus = self.get_pointer_focus();
+        let has_focus = |atmos: &Atmosphere, id: &SurfaceId| {
+            let root = atmos.a_root_window.get_clone(id).unwrap_or(id.clone());
+            atmos.get_win_focus() == Some(root)
+        };
+
+        if let Some(id) = self.get_pointer_constrained() {
+            if focus.as_ref() == Some(&id) && has_focus(self, &id) {
+                return;
+            }
+            self.deactivate_pointer_constraint(&id);
+        }
+
+        let id = match focus {
+            Some(id) => id,
+            None => return,
+        };
+        if !has_focus(self, &id) {
+            return;
+        }
+
+        let (cx, cy) = self.get_cursor_pos();
+        let activate = match self.a_pointer_constraint.get(&id) {
+            Some(pc) => self.pointer_constraint_contains(&id, &pc, cx, cy),
+            None => false,
+        };
+        if activate {
+            self.a_pointer_constraint.get_mut(&id).unwrap().activate();
+            self.set_pointer_constrained(Some(id));
+        }
+    }
+
+    /// Release the pointer from the active constraint on `id`
+    ///
+    /// Locked pointers are moved to the position the client hinted at.
+    fn deactivate_pointer_constraint(&mut self, id: &SurfaceId) {
+        self.set_pointer_constrained(None);
+
+        let (oneshot, hint) = match self.a_pointer_constraint.get_mut(id) {
+            Some(mut pc) => {
+                pc.deactivate();
+                let hint = match pc.is_lock() {
+                    true => pc.pc_cursor_hint,
+                    false => None,
+                };
+                (pc.pc_oneshot, hint)
+            }
+            None => return,
+        };
+
+        // The cursor was locked in place over the surface, so we can get
+        // the surface's position from it
+        if let Some((hx, hy)) = hint {
+            let (cx, cy) = self.get_cursor_pos();
+            if let Some((sx, sy)) = self.global_coords_to_surf(id, cx, cy) {
+                self.set_cursor_pos((cx - sx + hx, cy - sy + hy));
+            }
+        }
+
+        if oneshot {
+            self.a_pointer_constraint.take(id);
+        }
+    }
+
+    /// Apply the active constraint to a cursor movement
+    ///
+    /// Returns where the cursor should move to. Confined pointers try
+    /// moving along each axis on its own so they slide along the edges
+    /// of the region instead of sticking to them.
+    pub fn constrain_cursor_pos(&self, old: (f64, f64), new: (f64, f64)) -> (f64, f64) {
+        let id = match self.get_pointer_constrained() {
+            Some(id) => id,
+            None => return new,
+        };
+        let pc = match self.a_pointer_constraint.get(&id) {
+            Some(pc) => pc,
+            None => return new,
+        };
+
+        constrain_motion(pc.is_lock(), old, new, |x, y| {
+            self.pointer_constraint_contains(&id, &pc, x, y)
+        })
+    }
+}
@@ -0,0 +1,413 @@
+// Support code for placing layer shell surfaces
+//
+// Layer surfaces are not part of the window heirarchy. They are
+// anchored to the edges of an output, and may reserve part of it
+// so that toplevel windows are kept out of their way.
+use super::*;
+use crate::category5::ways::protocol::wlr_layer_shell::{
+    zwlr_layer_shell_v1::Layer, zwlr_layer_surface_v1 as zlsv,
+};
+use crate::category5::ways::{data_devices, primary_selection};
+
+/// Layer surface state which is double buffered by wl_surface.commit
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerProperties {
+    pub lp_layer: Layer,
+    pub lp_anchor: zlsv::Anchor,
+    /// The size requested by the client. Zero means stretch between
+    /// the anchors in that dimension.
+    pub lp_size: (u32, u32),
+    /// Distance from each anchored edge (top, right, bottom, left)
+    pub lp_margin: (i32, i32, i32, i32),
+    pub lp_exclusive_zone: i32,
+    pub lp_keyboard_interactivity: zlsv::KeyboardInteractivity,
+}
+
+impl LayerProperties {
+    pub fn new(layer: Layer) -> Self {
+        Self {
+            lp_layer: layer,
+            lp_anchor: zlsv::Anchor::empty(),
+            lp_size: (0, 0),
+            lp_margin: (0, 0, 0, 0),
+            lp_exclusive_zone: 0,
+            lp_keyboard_interactivity: zlsv::KeyboardInteractivity::None,
+        }
+    }
+
+    /// Does a zero size in either dimension have anchors to stretch between
+    pub fn size_is_valid(&self) -> bool {
+        let horiz = zlsv::Anchor::Left | zlsv::Anchor::Right;
+        let vert = zlsv::Anchor::Top | zlsv::Anchor::Bottom;
+
+        (self.lp_size.0 != 0 || self.lp_anchor.contains(horiz))
+            && (self.lp_size.1 != 0 || self.lp_anchor.contains(vert))
+    }
+
+    /// Find where this surface goes inside of `bounds`
+    pub(super) fn place(&self, bounds: &Rect<i32>) -> Rect<i32> {
+        let anchored = |edge| self.lp_anchor.contains(edge);
+        let (top, right, bottom, left) = self.lp_margin;
+
+        let (x, width) = place_on_axis(
+            bounds.r_pos.0,
+            bounds.r_size.0,
+            self.lp_size.0,
+            (anchored(zlsv::Anchor::Left), left),
+            (anchored(zlsv::Anchor::Right), right),
+        );
+        let (y, height) = place_on_axis(
+            bounds.r_pos.1,
+            bounds.r_size.1,
+            self.lp_size.1,
+            (anchored(zlsv::Anchor::Top), top),
+            (anchored(zlsv::Anchor::Bottom), bottom),
+        );
+
+        Rect::new(x, y, width, height)
+    }
+
+    /// Take this surface's exclusive zone out of `usable`
+    ///
+    /// The zone is only meaningful when anchored to a single edge, or
+    /// to one edge and both of the edges perpendicular to it.
+    pub(super) fn reserve(&self, usable: &mut Rect<i32>) {
+        if self.lp_exclusive_zone <= 0 {
+            return;
+        }
+        let anchored = |edge| self.lp_anchor.contains(edge);
+        let (t, b) = (anchored(zlsv::Anchor::Top), anchored(zlsv::Anchor::Bottom));
+        let (l, r) = (anchored(zlsv::Anchor::Left), anchored(zlsv::Anchor::Right));
+        let (top, right, bottom, left) = self.lp_margin;
+        let zone = self.lp_exclusive_zone;
+
+        if t && !b && l == r {
+            usable.r_pos.1 += zone + top;
+            usable.r_size.1 -= zone + top;
+        } else if b && !t && l == r {
+            usable.r_size.1 -= zone + bottom;
+        } else if l && !r && t == b {
+            usable.r_pos.0 += zone + left;
+            usable.r_size.0 -= zone + left;
+        } else if r && !l && t == b {
+            usable.r_size.0 -= zone + right;
+        }
+    }
+}
+
+/// Place a span of `size` along one axis of an output
+///
+/// `near` and `far` are the anchor and margin for the start and end
+/// edges of the axis. Returns the position and length of the span.
+pub(super) fn place_on_axis(
+    start: i32,
+    extent: i32,
+    size: u32,
+    near: (bool, i32),
+    far: (bool, i32),
+) -> (i32, i32) {
+    // Margins only apply to edges we are anchored to
+    let near_margin = if near.0 { near.1 } else { 0 };
+    let far_margin = if far.0 { far.1 } else { 0 };
+
+    let size = match size {
+        0 => (extent - near_margin - far_margin).max(0),
+        size => size as i32,
+    };
+
+    let pos = match (near.0, far.0) {
+        (true, false) => start + near_margin,
+        (false, true) => start + extent - far_margin - size,
+        // Anchoring to both or neither edge centers the surface
+        _ => start + near_margin + (extent - near_margin - far_margin - size) / 2,
+    };
+
+    (pos, size)
+}
+
+/// The committed state of a layer surface
+pub struct LayerState {
+    pub ls_resource: zlsv::ZwlrLayerSurfaceV1,
+    pub ls_props: LayerProperties,
+    /// The id of the output this is placed on
+    pub ls_output: u32,
+    /// The size sent in the last configure event
+    pub ls_configured_size: Option<(u32, u32)>,
+    pub ls_serial: u32,
+}
+
+impl LayerState {
+    pub fn new(resource: zlsv::ZwlrLayerSurfaceV1, props: LayerProperties, output: u32) -> Self {
+        Self {
+            ls_resource: resource,
+            ls_props: props,
+            ls_output: output,
+            ls_configured_size: None,
+            ls_serial: 0,
+        }
+    }
+
+    /// Send a configure event if the size we picked has changed
+    fn configure(&mut self, size: (i32, i32)) {
+        let size = (size.0.max(0) as u32, size.1.max(0) as u32);
+        if self.ls_configured_size == Some(size) {
+            return;
+        }
+
+        self.ls_resource.configure(self.ls_serial, size.0, size.1);
+        self.ls_serial += 1;
+        self.ls_configured_size = Some(size);
+    }
+}
+
+impl Atmosphere {
+    /// Pick the output for a new layer surface
+    ///
+    /// This is the output the client asked for, or the one the cursor
+    /// is on if it left it up to us.
+    pub fn get_layer_surface_output(&self, requested: Option<u32>) -> u32 {
+        if let Some(output) = requested {
+            return output;
+        }
+
+        let (cx, cy) = self.get_cursor_pos();
+        self.get_output_at_point(cx as i32, cy as i32)
+            .or(self.a_outputs.first())
+            .map(|o| o.ol_id)
+            .unwrap_or(0)
+    }
+
+    /// Get the index of the output a layer surface is on
+    ///
+    /// The WM tracks outputs by their position in our output list.
+    pub fn get_layer_surface_output_index(&self, id: &SurfaceId) -> usize {
+        self.a_layer_state
+            .get(id)
+            .and_then(|state| {
+                let output = state.ls_output;
+                self.a_outputs.iter().position(|o| o.ol_id == output)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Start tracking a new layer surface
+    pub fn add_layer_surface(&mut self, id: &SurfaceId, state: LayerState) {
+        self.a_layer_state.set(id, state);
+        self.a_layer_surfaces.push(id.clone());
+        self.mark_changed();
+    }
+
+    /// Stop tracking a layer surface, freeing any space it reserved
+    pub fn remove_layer_surface(&mut self, id: &SurfaceId) {
+        if self.get_win_focus().as_ref() == Some(id) {
+            self.unfocus_layer_surface();
+        }
+        if self.a_layer_state.take(id).is_some() {
+            self.a_layer_surfaces.retain(|layer_surf| layer_surf != id);
+            self.arrange_layers();
+        }
+    }
+
+    pub fn is_layer_surface(&self, id: &SurfaceId) -> bool {
+        self.a_layer_state.get(id).is_some()
+    }
+
+    /// Does this layer surface want keyboard input
+    pub fn layer_accepts_keyboard(&self, id: &SurfaceId) -> bool {
+        match self.a_layer_state.get(id) {
+            Some(state) => {
+                state.ls_props.lp_keyboard_interactivity != zlsv::KeyboardInteractivity::None
+            }
+            None => false,
+        }
+    }
+
+    /// Is the keyboard held by a layer surface that won't share it
+    ///
+    /// Exclusive keyboard interactivity only applies to the top and
+    /// overlay layers. Launchers and the like use this so that typing
+    /// always goes to them while they are open.
+    pub fn layer_has_exclusive_keyboard(&self) -> bool {
+        let focus = match self.get_win_focus() {
+            Some(focus) => focus,
+            None => return false,
+        };
+        match self.a_layer_state.get(&focus) {
+            Some(state) => {
+                state.ls_props.lp_keyboard_interactivity == zlsv::KeyboardInteractivity::Exclusive
+                    && match state.ls_props.lp_layer {
+                        Layer::Top | Layer::Overlay => true,
+                        _ => false,
+                    }
+            }
+            None => false,
+        }
+    }
+
+    /// Give a layer surface the keyboard focus
+    ///
+    /// Layer surfaces aren't part of the window order, so this only
+    /// changes the focus. The window that had it is remembered so that
+    /// it can get the keyboard back.
+    pub fn focus_layer_surface(&mut self, id: &SurfaceId) {
+        if self.is_locked() || !self.layer_accepts_keyboard(id) {
+            return;
+        }
+        let prev = self.get_surf_focus();
+        if prev.as_ref() == Some(id) {
+            return;
+        }
+        if let Some(prev) = prev.as_ref() {
+            Input::keyboard_leave(self, prev);
+        }
+
+        // Keep the original window if we are moving between layer surfaces
+        let prev_win = self.get_win_focus();
+        if !prev_win
+            .as_ref()
+            .map(|w| self.is_layer_surface(w))
+            .unwrap_or(false)
+        {
+            self.a_layer_prev_focus = prev_win;
+        }
+        self.set_win_focus(Some(id.clone()));
+        self.set_surf_focus(Some(id.clone()));
+        if let Some(owner) = self.a_owner.get_clone(id) {
+            data_devices::send_selection(self, &owner);
+            primary_selection::send_primary_selection(self, &owner);
+        }
+        Input::keyboard_enter(self, id);
+    }
+
+    /// Give the keyboard back to the windows
+    ///
+    /// Does nothing unless a layer surface has the focus. The window
+    /// that had focus before gets it back, or the front window if that
+    /// one has gone away.
+    pub fn unfocus_layer_surface(&mut self) {
+        match self.get_win_focus() {
+            Some(focus) if self.is_layer_surface(&focus) => {
+                Input::keyboard_leave(self, &focus);
+            }
+            _ => return,
+        }
+
+        let next = match self.a_layer_prev_focus.take() {
+            Some(prev) => Some(prev),
+            None => self.visible_windows().next(),
+        };
+        self.set_win_focus(next.clone());
+        self.set_surf_focus(next.clone());
+        if let Some(id) = next.as_ref() {
+            if let Some(owner) = self.a_owner.get_clone(id) {
+                data_devices::send_selection(self, &owner);
+                primary_selection::send_primary_selection(self, &owner);
+            }
+            Input::keyboard_enter(self, id);
+        }
+    }
+
+    /// Get the layer surfaces in any of `layers`, topmost layer first
+    pub fn get_layer_surfaces_in(&self, layers: &[Layer]) -> Vec<SurfaceId> {
+        let mut ret = Vec::new();
+        for layer in layers.iter() {
+            for id in self.a_layer_surfaces.iter() {
+                if self.a_layer_state.get(id).unwrap().ls_props.lp_layer == *layer {
+                    ret.push(id.clone());
+                }
+            }
+        }
+        ret
+    }
+
+    /// Get the position of a layer surface relative to its output
+    ///
+    /// The WM uses this since layer surfaces are drawn as part of the
+    /// output they are placed on.
+    pub fn get_layer_surface_output_pos(&self, id: &SurfaceId) -> Option<(f32, f32)> {
+        let output = self.a_layer_state.get(id)?.ls_output;
+        let origin = self
+            .a_outputs
+            .iter()
+            .find(|o| o.ol_id == output)?
+            .ol_rect
+            .r_pos;
+        let (x, y) = *self.a_surface_pos.get(id)?;
+
+        // Surface positions are in desktop coordinates, undo the menubar offset
+        Some((
+            x - origin.0 as f32,
+            y + wm::DESKTOP_OFFSET as f32 - origin.1 as f32,
+        ))
+    }
+
+    /// Recalculate the positions of all layer surfaces
+    ///
+    /// Each output is filled from the topmost layer down. Surfaces with an
+    /// exclusive zone are placed first and shrink the space left for the
+    /// rest, and whatever remains is the usable area for toplevel windows.
+    pub fn arrange_layers(&mut self) {
+        let mut outputs = self.get_outputs();
+        let layers = [Layer::Overlay, Layer::Top, Layer::Bottom, Layer::Background];
+
+        for output in outputs.iter_mut() {
+            let full = output.ol_rect;
+            // The menubar is always reserved at the top of the output
+            let mut usable = Rect::new(
+                full.r_pos.0,
+                full.r_pos.1 + wm::DESKTOP_OFFSET,
+                full.r_size.0,
+                full.r_size.1 - wm::DESKTOP_OFFSET,
+            );
+
+            for exclusive in [true, false].iter() {
+                for id in self.get_layer_surfaces_in(&layers) {
+                    let rect = {
+                        let mut state = self.a_layer_state.get_mut(&id).unwrap();
+                        let zone = state.ls_props.lp_exclusive_zone;
+                        if state.ls_output != output.ol_id || (zone > 0) != *exclusive {
+                            continue;
+                        }
+
+                        // A negative zone asks to ignore everyone else's zones
+                        let bounds = if zone < 0 { full } else { usable };
+                        let rect = state.ls_props.place(&bounds);
+                        state.ls_props.reserve(&mut usable);
+                        state.configure(rect.r_size);
+                        rect
+                    };
+
+                    let pos =
+                        self.get_adjusted_desktop_coord(rect.r_pos.0 as f32, rect.r_pos.1 as f32);
+                    self.a_surface_pos.set(&id, pos);
+                    self.a_window_pos.set(&id, pos);
+                }
+            }
+
+            output.ol_usable = usable;
+        }
+
+        self.set_outputs(outputs);
+    }
+
+    /// The area of the desktop that toplevel windows should be placed in
+    ///
+    /// This is the usable area of the output the window is on, in desktop
+    /// coordinates. The menubar and layer shell exclusive zones are left out.
+    pub fn get_usable_desktop_area(&self, id: &SurfaceId) -> Rect<f32> {
+        match self.get_window_output(id) {
+            Some(output) => {
+                let usable = &output.ol_usable;
+                let (x, y) =
+                    self.get_adjusted_desktop_coord(usable.r_pos.0 as f32, usable.r_pos.1 as f32);
+                Rect::new(x, y, usable.r_size.0 as f32, usable.r_size.1 as f32)
+            }
+            // Before vkcomp sets up our outputs just use the whole desktop
+            None => {
+                let res = self.get_resolution();
+                let (_, height) = self.get_adjusted_desktop_coord(0.0, res.1 as f32);
+                Rect::new(0.0, 0.0, res.0 as f32, height)
+            }
+        }
+    }
+}
@@ -10,19 +10,37 @@
 // Austin Shafer - 2020
 extern crate wayland_server as ws;
 use crate::category5::ws::Resource;
-use ws::protocol::{wl_buffer, wl_callback, wl_shm, wl_surface};
+use ws::protocol::{wl_buffer, wl_callback, wl_data_source, wl_shm, wl_surface};
+extern crate wayland_protocols;
+use wayland_protocols::wp::presentation_time::server::wp_presentation_feedback as wppf;
+use wayland_protocols::wp::primary_selection::zv1::server::zwp_primary_selection_source_v1 as zpss;
 extern crate paste;
 use paste::paste;
 
 extern crate dakota as dak;
 extern crate lluvia as ll;
 
+mod constraints;
+mod layers;
+mod popup_grab;
+mod session_lock;
 mod skiplist;
 
+#[cfg(test)]
+mod tests;
+
+pub use constraints::{ConstraintKind, PointerConstraint};
+pub use layers::{LayerProperties, LayerState};
+pub use session_lock::{LockSurfaceState, SessionLock};
+
 use crate::category5::input::Input;
 use crate::category5::vkcomp::{release_info::GenericReleaseInfo, wm};
-use crate::category5::ways::{seat::Seat, shm::ShmBuffer, surface::*, wl_region::Region};
-use utils::log;
+use crate::category5::ways::{
+    data_devices::DragAndDrop, input_method::InputMethod,
+    protocol::wlr_screencopy::zwlr_screencopy_frame_v1 as zscf, seat::Seat, shm::ShmBuffer,
+    surface::*, wl_region::Region,
+};
+use utils::{log, region::Rect};
 
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
@@ -51,6 +69,63 @@ pub type BufferId = dak::DakotaId;
 /// release the attached buffer immediately.
 struct ShadowBuffer {}
 
+/// The placement of a display output on the desktop
+///
+/// These are in global coordinates, and are filled in by vkcomp.
+#[derive(Debug, Clone)]
+pub struct OutputLayout {
+    /// Unique id of this output, which stays the same while
+    /// other outputs come and go
+    pub ol_id: u32,
+    /// The display's name, make, refresh rate, etc
+    pub ol_desc: dak::DisplayDescription,
+    pub ol_rect: Rect<i32>,
+    /// The part of the output left for app windows, after taking out
+    /// the menubar and the exclusive zones of layer surfaces
+    pub ol_usable: Rect<i32>,
+    /// The size of the display in pixels. `ol_rect` is this divided
+    /// by the scale.
+    pub ol_resolution: (u32, u32),
+    /// The number of pixels drawn for each unit of the desktop
+    pub ol_scale: f32,
+    /// The DRM format frames of this output are copied out in. This is
+    /// None if the display can't be copied from.
+    pub ol_capture_format: Option<u32>,
+}
+
+impl OutputLayout {
+    /// The integer scale to tell clients without wp_fractional_scale
+    ///
+    /// Fractional scales are rounded up. These clients render a buffer of
+    /// `ol_rect`'s size times this scale, which we then shrink down to
+    /// the resolution. This keeps wl_output.scale and the logical size
+    /// sent through xdg_output consistent, since the logical size is the
+    /// same no matter which scale a client renders at.
+    pub fn get_buffer_scale(&self) -> i32 {
+        self.ol_scale.ceil() as i32
+    }
+}
+
+/// The buttons drawn on a server side titlebar
+///
+/// These are packed against the right edge of the bar, in the
+/// order listed here.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TitlebarButton {
+    Close,
+    Maximize,
+    Minimize,
+}
+
+impl TitlebarButton {
+    /// All buttons, from right to left
+    pub const ALL: [TitlebarButton; 3] = [
+        TitlebarButton::Close,
+        TitlebarButton::Maximize,
+        TitlebarButton::Minimize,
+    ];
+}
+
 /// Global state tracking
 ///
 /// Our atmosphere holds all of the ECS data in one place, and is essentially
@@ -92,6 +167,47 @@ pub struct Atmosphere {
     /// The name of the DRM node in use. This will be filled in by vkcomp
     /// and populated from VK_EXT_physical_device_drm
     pub a_drm_dev: (i64, i64),
+    /// The data source currently holding the clipboard selection for
+    /// the seat. This is offered to whichever client gains keyboard focus.
+    pub a_selection: Option<wl_data_source::WlDataSource>,
+    /// The source of the primary selection (middle click paste). This
+    /// is independent of `a_selection`.
+    pub a_primary_selection: Option<zpss::ZwpPrimarySelectionSourceV1>,
+    /// The drag and drop session in progress, if any. While this is set
+    /// the pointer is grabbed and input is routed to the drag.
+    pub a_dnd: Option<DragAndDrop>,
+    /// The layout of each display output, indexed the same as the
+    /// outputs in vkcomp
+    pub a_outputs: Vec<OutputLayout>,
+    /// All layer shell surfaces, in the order they were created
+    pub a_layer_surfaces: Vec<SurfaceId>,
+    /// The window that was in focus before a layer surface took the
+    /// keyboard. It gets the keyboard back when the layer surface is done.
+    pub a_layer_prev_focus: Option<SurfaceId>,
+    /// The surface whose pointer constraint is active. The cursor is
+    /// held in place or kept inside its region while this is set.
+    pub a_pointer_constrained: Option<SurfaceId>,
+    /// The input method (IME) in use. Only one client may act as the
+    /// input method at a time.
+    pub a_input_method: Option<Arc<Mutex<InputMethod>>>,
+    /// Windows which asked for focus but were not allowed to take it.
+    /// The menubar lists these until the user focuses them.
+    pub a_urgent_windows: Vec<SurfaceId>,
+    /// Surfaces with an idle inhibitor. There is one entry for each
+    /// inhibitor, so a surface may be listed more than once.
+    pub a_idle_inhibitors: Vec<SurfaceId>,
+    /// Popups with a reactive positioner. These are placed again if
+    /// their parent moves and they no longer fit on the output.
+    pub a_reactive_popups: Vec<SurfaceId>,
+    /// The chain of popups holding an explicit grab, from the bottom
+    /// up. The last one has the keyboard.
+    pub a_popup_grab: Vec<SurfaceId>,
+    /// Set while the session is locked. Only the lock surfaces are
+    /// shown and given input until the locker unlocks.
+    pub a_session_lock: Option<SessionLock>,
+    /// Screencopy frames waiting to be copied. The WM takes these
+    /// and copies them while drawing the output they are for.
+    pub a_screencopy_frames: Vec<zscf::ZwlrScreencopyFrameV1>,
 
     pub a_changed: bool,
 
@@ -122,8 +238,22 @@ pub struct Atmosphere {
     /// The client that created this window
     pub a_owner: ll::Component<ClientId>,
     /// does this window have the toplevel role
-    /// this controls if SSD are drawn
     pub a_toplevel: ll::Component<bool>,
+    /// Has this window negotiated server side decorations
+    /// this controls if the WM draws a titlebar for it
+    pub a_ssd: ll::Component<bool>,
+    /// The title of a toplevel window, displayed in its titlebar
+    pub a_window_title: ll::Component<String>,
+    /// Is this window minimized. Minimized windows are skipped
+    /// when walking the visible windows
+    pub a_minimized: ll::Component<bool>,
+    /// Is this window fullscreen. Fullscreen windows cover their whole
+    /// output and are drawn without a titlebar.
+    pub a_fullscreen: ll::Component<bool>,
+    /// The committed layer shell state, if this is a layer surface
+    pub a_layer_state: ll::Component<LayerState>,
+    /// The pointer lock or confinement requested for this surface
+    pub a_pointer_constraint: ll::Component<PointerConstraint>,
     /// the position of the visible portion of the window
     pub a_window_pos: ll::Component<(f32, f32)>,
     /// size of the visible portion : `ll::Component<non-CSD>` of the window
@@ -133,9 +263,19 @@ pub struct Atmosphere {
     /// be offsets from the base of the parent window
     pub a_surface_pos: ll::Component<(f32, f32)>,
     /// the size of the surface
-    /// aka the size of the last buffer attached
+    /// aka the size of the last buffer attached, divided by the buffer scale
     /// vkcomp uses this
     pub a_surface_size: ll::Component<(f32, f32)>,
+    /// The size in pixels of the last buffer committed
+    pub a_buffer_size: ll::Component<(i32, i32)>,
+    /// The scale set with wl_surface.set_buffer_scale. The buffer is this
+    /// many times larger than the surface.
+    pub a_buffer_scale: ll::Component<i32>,
+    /// The rotation or flip set with wl_surface.set_buffer_transform
+    pub a_buffer_transform: ll::Component<dak::SurfaceTransform>,
+    /// The part of the buffer shown in the surface, from wp_viewporter.
+    /// The whole buffer spans from (0, 0) to (1, 1).
+    pub a_source_rect: ll::Component<Rect<f32>>,
     /// This window's position in the desktop order
     ///
     /// The next window behind this one
@@ -179,6 +319,11 @@ pub struct Atmosphere {
     /// These will be signaled on the next draw point so the
     /// surface can commit new contents
     pub a_frame_callbacks: ll::Component<Vec<wl_callback::WlCallback>>,
+    /// wp_presentation feedback for the committed contents
+    ///
+    /// vkcomp moves these to the output the surface is drawn on, which
+    /// reports when they were actually shown.
+    pub a_presentation_feedback: ll::Component<Vec<wppf::WpPresentationFeedback>>,
     /// The opaque region.
     /// vkcomp can optimize displaying this region
     pub a_opaque_region: ll::Component<Arc<Mutex<Region>>>,
@@ -225,6 +370,13 @@ impl Atmosphere {
     define_global_getters!(cursor_surface, Option<SurfaceId>);
     define_global_getters!(renderdoc_recording, bool);
     define_global_getters!(drm_dev, (i64, i64));
+    define_global_getters!(selection, Option<wl_data_source::WlDataSource>);
+    define_global_getters!(primary_selection, Option<zpss::ZwpPrimarySelectionSourceV1>);
+    define_global_getters!(dnd, Option<DragAndDrop>);
+    define_global_getters!(outputs, Vec<OutputLayout>);
+    define_global_getters!(pointer_constrained, Option<SurfaceId>);
+    define_global_getters!(input_method, Option<Arc<Mutex<InputMethod>>>);
+    define_global_getters!(urgent_windows, Vec<SurfaceId>);
 }
 
 impl Atmosphere {
@@ -252,6 +404,20 @@ impl Atmosphere {
             a_renderdoc_recording: false,
             a_changed: false,
             a_drm_dev: (0, 0),
+            a_selection: None,
+            a_primary_selection: None,
+            a_dnd: None,
+            a_outputs: Vec::new(),
+            a_layer_surfaces: Vec::new(),
+            a_layer_prev_focus: None,
+            a_pointer_constrained: None,
+            a_input_method: None,
+            a_urgent_windows: Vec::new(),
+            a_idle_inhibitors: Vec::new(),
+            a_reactive_popups: Vec::new(),
+            a_popup_grab: Vec::new(),
+            a_session_lock: None,
+            a_screencopy_frames: Vec::new(),
             a_wm_tasks: VecDeque::new(),
             // ---------------------
             a_windows_for_client: client_ecs.add_component(),
@@ -261,10 +427,20 @@ impl Atmosphere {
             a_window_in_use: surf_ecs.add_component(),
             a_owner: surf_ecs.add_component(),
             a_toplevel: surf_ecs.add_component(),
+            a_ssd: surf_ecs.add_component(),
+            a_window_title: surf_ecs.add_component(),
+            a_minimized: surf_ecs.add_component(),
+            a_fullscreen: surf_ecs.add_component(),
+            a_layer_state: surf_ecs.add_component(),
+            a_pointer_constraint: surf_ecs.add_component(),
             a_window_pos: surf_ecs.add_component(),
             a_window_size: surf_ecs.add_component(),
             a_surface_pos: surf_ecs.add_component(),
             a_surface_size: surf_ecs.add_component(),
+            a_buffer_size: surf_ecs.add_component(),
+            a_buffer_scale: surf_ecs.add_component(),
+            a_buffer_transform: surf_ecs.add_component(),
+            a_source_rect: surf_ecs.add_component(),
             a_skiplist_next: surf_ecs.add_component(),
             a_skiplist_prev: surf_ecs.add_component(),
             a_skiplist_skip: surf_ecs.add_component(),
@@ -277,6 +453,7 @@ impl Atmosphere {
             a_surface_damage: surf_ecs.add_component(),
             a_buffer_damage: surf_ecs.add_component(),
             a_frame_callbacks: surf_ecs.add_component(),
+            a_presentation_feedback: surf_ecs.add_component(),
             a_opaque_region: surf_ecs.add_component(),
             a_input_region: surf_ecs.add_component(),
             a_surf_resource: scene.resource(),
@@ -297,10 +474,19 @@ impl Atmosphere {
             || self.a_window_in_use.is_modified()
             || self.a_owner.is_modified()
             || self.a_toplevel.is_modified()
+            || self.a_ssd.is_modified()
+            || self.a_window_title.is_modified()
+            || self.a_minimized.is_modified()
+            || self.a_fullscreen.is_modified()
+            || self.a_layer_state.is_modified()
             || self.a_window_pos.is_modified()
             || self.a_window_size.is_modified()
             || self.a_surface_pos.is_modified()
             || self.a_surface_size.is_modified()
+            || self.a_buffer_size.is_modified()
+            || self.a_buffer_scale.is_modified()
+            || self.a_buffer_transform.is_modified()
+            || self.a_source_rect.is_modified()
             || self.a_skiplist_next.is_modified()
             || self.a_skiplist_prev.is_modified()
             || self.a_skiplist_skip.is_modified()
@@ -322,10 +508,19 @@ impl Atmosphere {
         self.a_window_in_use.clear_modified();
         self.a_owner.clear_modified();
         self.a_toplevel.clear_modified();
+        self.a_ssd.clear_modified();
+        self.a_window_title.clear_modified();
+        self.a_minimized.clear_modified();
+        self.a_fullscreen.clear_modified();
+        self.a_layer_state.clear_modified();
         self.a_window_pos.clear_modified();
         self.a_window_size.clear_modified();
         self.a_surface_pos.clear_modified();
         self.a_surface_size.clear_modified();
+        self.a_buffer_size.clear_modified();
+        self.a_buffer_scale.clear_modified();
+        self.a_buffer_transform.clear_modified();
+        self.a_source_rect.clear_modified();
         self.a_skiplist_next.clear_modified();
         self.a_skiplist_prev.clear_modified();
         self.a_skiplist_skip.clear_modified();
@@ -348,6 +543,95 @@ impl Atmosphere {
         self.get_resolution().1 as f32 * 0.02
     }
 
+    /// Does the WM draw a titlebar above this window
+    ///
+    /// Windows using server side decorations get one, unless they
+    /// are fullscreen.
+    pub fn has_titlebar(&self, id: &SurfaceId) -> bool {
+        self.a_ssd.get(id).map(|ssd| *ssd) == Some(true)
+            && self.a_fullscreen.get(id).map(|f| *f) != Some(true)
+    }
+
+    /// Get the height of the titlebar drawn above this window
+    ///
+    /// This is zero if the window draws its own decorations
+    pub fn get_titlebar_height(&self, id: &SurfaceId) -> f32 {
+        match self.has_titlebar(id) {
+            true => self.get_barsize(),
+            false => 0.0,
+        }
+    }
+
+    /// The size of a maximized window
+    ///
+    /// This is the usable desktop area of the window's output, leaving
+    /// room for the window's titlebar.
+    pub fn get_maximized_window_size(&self, id: &SurfaceId) -> (f32, f32) {
+        let area = self.get_usable_desktop_area(id);
+        (area.r_size.0, area.r_size.1 - self.get_titlebar_height(id))
+    }
+
+    /// Where a maximized window is placed
+    ///
+    /// This is the top left of the usable desktop area, pushed down to
+    /// leave room for the window's titlebar.
+    pub fn get_maximized_window_pos(&self, id: &SurfaceId) -> (f32, f32) {
+        let area = self.get_usable_desktop_area(id);
+        (area.r_pos.0, area.r_pos.1 + self.get_titlebar_height(id))
+    }
+
+    /// The area covered by a fullscreen window
+    ///
+    /// This is the entire output, menubar included, in desktop
+    /// coordinates. `output` is the id of the output the client asked
+    /// for, otherwise the output the window is on is used.
+    pub fn get_fullscreen_area(&self, id: &SurfaceId, output: Option<u32>) -> Rect<f32> {
+        let layout = output
+            .and_then(|o| self.a_outputs.iter().find(|ol| ol.ol_id == o))
+            .or_else(|| self.get_window_output(id));
+
+        match layout {
+            Some(layout) => {
+                let rect = &layout.ol_rect;
+                let (x, y) =
+                    self.get_adjusted_desktop_coord(rect.r_pos.0 as f32, rect.r_pos.1 as f32);
+                Rect::new(x, y, rect.r_size.0 as f32, rect.r_size.1 as f32)
+            }
+            // Before vkcomp sets up our outputs just use the whole desktop
+            None => {
+                let res = self.get_resolution();
+                let (x, y) = self.get_adjusted_desktop_coord(0.0, 0.0);
+                Rect::new(x, y, res.0 as f32, res.1 as f32)
+            }
+        }
+    }
+
+    /// The area that popups opened from `id` should stay inside of
+    ///
+    /// This is the usable desktop area of the output that the root window
+    /// is on, or the entire output if that window is fullscreen. This is
+    /// in desktop coordinates.
+    pub fn get_popup_bounds(&self, id: &SurfaceId) -> Rect<f32> {
+        let root = self.a_root_window.get_clone(id).unwrap_or(id.clone());
+
+        match self.a_fullscreen.get(&root).map(|f| *f) == Some(true) {
+            true => self.get_fullscreen_area(&root, None),
+            false => self.get_usable_desktop_area(&root),
+        }
+    }
+
+    /// Move a window so that its geometry starts at `pos`
+    ///
+    /// The surface is moved along with it, keeping the window
+    /// geometry at the same offset inside the surface.
+    pub fn set_window_origin(&mut self, id: &SurfaceId, pos: (f32, f32)) {
+        let wp = *self.a_window_pos.get(id).unwrap();
+        let sp = *self.a_surface_pos.get(id).unwrap();
+        self.a_surface_pos
+            .set(id, (pos.0 + sp.0 - wp.0, pos.1 + sp.1 - wp.1));
+        self.a_window_pos.set(id, pos);
+    }
+
     // ------------------------------
     // For the sake of abstraction, the atmosphere will be the
     // point of contact for modifying global state. We will
@@ -426,10 +710,34 @@ impl Atmosphere {
         self.print_surface_tree();
 
         // we also need to remove this surface from focus
+        self.session_lock_remove_window(id);
+        // This hands the keyboard back to a window if a layer surface had it
+        self.remove_layer_surface(id);
+        if self.a_layer_prev_focus.as_ref() == Some(id) {
+            self.a_layer_prev_focus = None;
+        }
         self.skiplist_remove_win_focus(id);
         self.skiplist_remove_surf_focus(id);
         // remove this id from the heirarchy
         self.skiplist_remove_window(id);
+        self.remove_pointer_constraint(id);
+        self.a_urgent_windows.retain(|urgent| urgent != id);
+        self.a_idle_inhibitors.retain(|inhibitor| inhibitor != id);
+        self.a_reactive_popups.retain(|popup| popup != id);
+        self.a_popup_grab.retain(|popup| popup != id);
+        // A drag carries on without its icon or the surface it was over
+        if let Some(mut dnd) = self.get_dnd() {
+            if dnd.dnd_icon.as_ref() == Some(id) {
+                dnd.dnd_icon = None;
+                self.add_wm_task(wm::task::Task::set_dnd_icon { id: None });
+            }
+            if dnd.dnd_focus.as_ref() == Some(id) {
+                dnd.dnd_focus = None;
+                dnd.dnd_devices.clear();
+                dnd.dnd_offers.clear();
+            }
+            self.set_dnd(Some(dnd));
+        }
         // TODO: generate RemoveWindow event?
 
         // remove this window from the clients list
@@ -451,28 +759,73 @@ impl Atmosphere {
         self.print_surface_tree();
     }
 
-    /// convert a global location to a surface local coordinates.
-    /// Returns None if the location given is not over the surface
-    pub fn global_coords_to_surf(&self, id: &SurfaceId, x: f64, y: f64) -> Option<(f64, f64)> {
-        let (x, y) = self.get_adjusted_desktop_coord(x as f32, y as f32);
-        let (x, y) = (x as f64, y as f64);
-        // get the surface-local position
-        let (mut wx, mut wy) = *self.a_surface_pos.get(id).unwrap();
-        let (ww, wh) = *self.a_surface_size.get(id).unwrap();
+    /// Should the user be kept from going idle
+    ///
+    /// Idle inhibitors only apply while their surface can be seen. A
+    /// video player that was minimized shouldn't keep the screen on.
+    pub fn is_idle_inhibited(&self) -> bool {
+        self.a_idle_inhibitors.iter().any(|id| {
+            let root = self.a_root_window.get_clone(id).unwrap_or(id.clone());
+            self.a_layer_surfaces.contains(&root) || self.visible_windows().any(|win| win == root)
+        })
+    }
+
+    /// Get the output a window is on
+    ///
+    /// This is the output containing the window's top left corner. Windows
+    /// that are not on any output, or have not been placed yet, are treated
+    /// as being on the first output.
+    pub fn get_window_output(&self, id: &SurfaceId) -> Option<&OutputLayout> {
+        let found = self.a_window_pos.get(id).and_then(|pos| {
+            self.get_output_at_point(pos.0 as i32, pos.1 as i32 + wm::DESKTOP_OFFSET)
+        });
+
+        found.or(self.a_outputs.first())
+    }
+
+    /// Find the output containing a point in global coordinates
+    pub fn get_output_at_point(&self, x: i32, y: i32) -> Option<&OutputLayout> {
+        self.a_outputs.iter().find(|o| {
+            let rect = &o.ol_rect;
+            x >= rect.r_pos.0
+                && y >= rect.r_pos.1
+                && x < rect.r_pos.0 + rect.r_size.0
+                && y < rect.r_pos.1 + rect.r_size.1
+        })
+    }
+
+    /// Get the position of a surface on the desktop
+    ///
+    /// Subsurface positions are relative to their parent, so this adds
+    /// up the positions of every surface above `id`.
+    pub fn get_surface_desktop_pos(&self, id: &SurfaceId) -> (f32, f32) {
+        let (mut x, mut y) = *self.a_surface_pos.get(id).unwrap();
 
-        // Add any parent surface's positions to our surface offset to account
-        // for this surface being a subsurf
         let mut parent = self.a_parent_window.get_clone(id);
-        while parent.is_some() {
-            let p = parent.take().unwrap();
+        while let Some(p) = parent {
             let (px, py) = *self.a_surface_pos.get(&p).unwrap();
-            wx += px;
-            wy += py;
+            x += px;
+            y += py;
 
             parent = self.a_parent_window.get_clone(&p);
         }
 
-        // offset into the surface
+        (x, y)
+    }
+
+    /// convert a global location to a surface local coordinates.
+    /// Returns None if the location given is not over the surface
+    pub fn global_coords_to_surf(&self, id: &SurfaceId, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (x, y) = self.get_adjusted_desktop_coord(x as f32, y as f32);
+        let (x, y) = (x as f64, y as f64);
+        // get the surface-local position, accounting for this surface
+        // being a subsurf
+        let (wx, wy) = self.get_surface_desktop_pos(id);
+        let (ww, wh) = *self.a_surface_size.get(id).unwrap();
+
+        // offset into the surface. The surface size has already been
+        // divided by the buffer scale, so this is in surface coordinates
+        // and not the buffer's pixels.
         let (sx, sy) = (x - wx as f64, y - wy as f64);
 
         // if the cursor is out of the valid bounds for the surface
@@ -619,10 +972,12 @@ impl Atmosphere {
     /// Add an offset to the cursor patch
     ///
     /// This increments the cursor position, which will later
-    /// get replayed into the hemisphere
+    /// get replayed into the hemisphere. The movement is limited
+    /// by any active pointer constraint.
     pub fn add_cursor_pos(&mut self, dx: f64, dy: f64) {
         let pos = self.get_cursor_pos();
-        self.set_cursor_pos((pos.0 + dx, pos.1 + dy));
+        let new_pos = self.constrain_cursor_pos(pos, (pos.0 + dx, pos.1 + dy));
+        self.set_cursor_pos(new_pos);
 
         // Now update the grabbed window if it exists
         let grabbed = match self.get_grabbed() {
@@ -702,4 +1057,24 @@ impl Atmosphere {
             }
         }
     }
+
+    /// Take the presentation feedback for the surface's current contents
+    ///
+    /// This is called by vkcomp when it draws the surface.
+    pub fn take_presentation_feedback(
+        &mut self,
+        id: &SurfaceId,
+    ) -> Vec<wppf::WpPresentationFeedback> {
+        match self.a_presentation_feedback.get_mut(id) {
+            Some(mut feedback) => feedback.drain(0..).collect(),
+            None => Vec::with_capacity(0),
+        }
+    }
+
+    /// Tell clients that the surface's current contents were never shown
+    pub fn discard_presentation_feedback(&mut self, id: &SurfaceId) {
+        for feedback in self.take_presentation_feedback(id) {
+            feedback.discarded();
+        }
+    }
 }
@@ -0,0 +1,123 @@
+// Support code for xdg_popup grabs
+//
+// A popup which grabs input takes the keyboard from its parent until it
+// is dismissed. Grabbing popups may open more grabbing popups on top of
+// themselves, forming a chain. Clicking anywhere outside of that chain
+// dismisses all of it.
+use super::*;
+use crate::category5::ways::role::Role;
+
+impl Atmosphere {
+    /// Get the topmost popup holding a grab
+    ///
+    /// This popup gets the keyboard until it is dismissed.
+    pub fn get_popup_grab(&self) -> Option<SurfaceId> {
+        self.a_popup_grab.last().cloned()
+    }
+
+    /// Is `id` part of the grabbing popup chain
+    ///
+    /// Subsurfaces and popups opened from a grabbing popup count as
+    /// part of the chain.
+    pub fn is_in_popup_grab(&self, id: &SurfaceId) -> bool {
+        let mut cur = Some(id.clone());
+        while let Some(surf) = cur {
+            if self.a_popup_grab.contains(&surf) {
+                return true;
+            }
+            cur = self.a_parent_window.get_clone(&surf);
+        }
+        false
+    }
+
+    /// The surface which gets the keyboard back once the grab ends
+    ///
+    /// For xdg popups this is their toplevel. Layer surfaces only get it
+    /// back if they accept keyboard input, otherwise their popups return
+    /// it to whatever had it before.
+    fn get_popup_grab_return(&self, id: &SurfaceId) -> Option<SurfaceId> {
+        match self.a_root_window.get_clone(id) {
+            Some(root) if !self.is_layer_surface(&root) || self.layer_accepts_keyboard(&root) => {
+                Some(root)
+            }
+            _ => self.get_surf_focus(),
+        }
+    }
+
+    /// Add a popup to the top of the grab chain
+    ///
+    /// The keyboard is taken from the previous holder of the grab, or the
+    /// surface in focus if this starts a new chain. Nothing may take the
+    /// keyboard from the lock surfaces, so this is ignored while locked.
+    pub fn start_popup_grab(&mut self, id: &SurfaceId) {
+        if self.is_locked() {
+            log::debug!("Ignoring grab by popup {:?} while locked", id);
+            return;
+        }
+        log::debug!("Popup {:?} grabbed input", id);
+        let prev = match self.get_popup_grab() {
+            Some(top) => Some(top),
+            None => self.get_surf_focus(),
+        };
+        if let Some(prev) = prev.as_ref() {
+            Input::keyboard_leave(self, prev);
+        }
+
+        self.a_popup_grab.push(id.clone());
+        Input::keyboard_enter(self, id);
+    }
+
+    /// Remove a popup from the grab chain
+    ///
+    /// This is called when the popup is dismissed or destroyed. If it was
+    /// the topmost popup the keyboard goes back to the popup below it, or
+    /// to the parent window if the chain is now empty.
+    pub fn end_popup_grab(&mut self, id: &SurfaceId) {
+        let was_top = self.get_popup_grab().as_ref() == Some(id);
+        if !self.a_popup_grab.contains(id) {
+            return;
+        }
+        log::debug!("Popup {:?} released its grab", id);
+        self.a_popup_grab.retain(|popup| popup != id);
+
+        if !was_top {
+            return;
+        }
+        Input::keyboard_leave(self, id);
+
+        match self.get_popup_grab() {
+            Some(top) => Input::keyboard_enter(self, &top),
+            None => {
+                if let Some(ret) = self.get_popup_grab_return(id) {
+                    self.set_surf_focus(Some(ret.clone()));
+                    Input::keyboard_enter(self, &ret);
+                }
+            }
+        }
+    }
+
+    /// Dismiss every popup in the grab chain
+    ///
+    /// popup_done is sent starting with the topmost popup, as the spec
+    /// requires.
+    pub fn dismiss_popup_grab(&mut self) {
+        for id in self.a_popup_grab.clone().iter().rev() {
+            let surf_cell = match self.get_surface_from_id(id) {
+                Some(surf) => surf,
+                None => {
+                    self.end_popup_grab(id);
+                    continue;
+                }
+            };
+            // The ShellSurface has to be locked before the Surface
+            let shsurf = match surf_cell.lock().unwrap().s_role.as_ref() {
+                Some(Role::xdg_shell_popup(ss)) => Some(ss.clone()),
+                _ => None,
+            };
+            match shsurf {
+                Some(ss) => ss.lock().unwrap().popup_done(self),
+                None => self.end_popup_grab(id),
+            }
+        }
+    }
+}
@@ -0,0 +1,369 @@
+// Support code for locking the session
+//
+// While the session is locked only the lock surfaces of the screen
+// locker are drawn or given input. The window order of the desktop is
+// set aside and restored when the locker unlocks the session.
+use super::*;
+use crate::category5::ways::{data_devices, primary_selection};
+use wayland_protocols::ext::session_lock::v1::server::{
+    ext_session_lock_surface_v1 as esls, ext_session_lock_v1 as esl,
+};
+
+/// A surface covering one output while the session is locked
+pub struct LockSurfaceState {
+    pub lk_resource: esls::ExtSessionLockSurfaceV1,
+    pub lk_id: SurfaceId,
+    /// The id of the output this surface covers
+    pub lk_output: u32,
+    /// Configures the client has not acked yet, along with their sizes
+    lk_pending: Vec<(u32, (u32, u32))>,
+    /// The size of the last configure acked by the client. Its buffers
+    /// must match this.
+    pub lk_acked_size: Option<(u32, u32)>,
+    lk_serial: u32,
+}
+
+impl LockSurfaceState {
+    pub fn new(resource: esls::ExtSessionLockSurfaceV1, id: SurfaceId, output: u32) -> Self {
+        Self {
+            lk_resource: resource,
+            lk_id: id,
+            lk_output: output,
+            lk_pending: Vec::new(),
+            lk_acked_size: None,
+            lk_serial: 0,
+        }
+    }
+
+    /// Send a configure event if the output size has changed
+    fn configure(&mut self, size: (u32, u32)) {
+        let last = match self.lk_pending.last() {
+            Some((_, size)) => Some(*size),
+            None => self.lk_acked_size,
+        };
+        if last == Some(size) {
+            return;
+        }
+
+        self.lk_resource.configure(self.lk_serial, size.0, size.1);
+        self.lk_pending.push((self.lk_serial, size));
+        self.lk_serial += 1;
+    }
+
+    /// Handle ack_configure
+    ///
+    /// Returns false if we never sent `serial`.
+    pub fn ack_configure(&mut self, serial: u32) -> bool {
+        match self.lk_pending.iter().position(|(s, _)| *s == serial) {
+            Some(index) => {
+                self.lk_acked_size = Some(self.lk_pending[index].1);
+                // Acking a configure also acks the ones before it
+                self.lk_pending.drain(..=index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// The state of a locked session
+pub struct SessionLock {
+    /// The lock object of the locker. If the locker exits without
+    /// unlocking this is None, and the session stays locked until
+    /// another locker takes over.
+    pub sl_lock: Option<esl::ExtSessionLockV1>,
+    pub sl_surfaces: Vec<LockSurfaceState>,
+    /// The window in focus when we locked. This is the front of the
+    /// desktop window order, which is restored when unlocking.
+    sl_desktop_focus: Option<SurfaceId>,
+    /// Has every output presented a frame without the desktop. The
+    /// locked event is sent once this is set.
+    sl_presented: bool,
+}
+
+impl Atmosphere {
+    pub fn is_locked(&self) -> bool {
+        self.a_session_lock.is_some()
+    }
+
+    pub fn is_lock_surface(&self, id: &SurfaceId) -> bool {
+        match self.a_session_lock.as_ref() {
+            Some(lock) => lock.sl_surfaces.iter().any(|s| &s.lk_id == id),
+            None => false,
+        }
+    }
+
+    pub fn get_lock_surface_mut(&mut self, id: &SurfaceId) -> Option<&mut LockSurfaceState> {
+        self.a_session_lock
+            .as_mut()?
+            .sl_surfaces
+            .iter_mut()
+            .find(|s| &s.lk_id == id)
+    }
+
+    /// Get the lock surfaces to draw and deliver input to
+    pub fn get_lock_surfaces(&self) -> Vec<SurfaceId> {
+        match self.a_session_lock.as_ref() {
+            Some(lock) => lock.sl_surfaces.iter().map(|s| s.lk_id.clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get the window at the front of the desktop
+    ///
+    /// While locked the lock surfaces have focus instead, so this is the
+    /// window that gets focus back when unlocking.
+    pub fn get_desktop_front(&self) -> Option<SurfaceId> {
+        match self.a_session_lock.as_ref() {
+            Some(lock) => lock.sl_desktop_focus.clone(),
+            None => self.get_win_focus(),
+        }
+    }
+
+    /// Does `lock` belong to the locker currently in charge
+    pub fn is_active_session_lock(&self, lock: &esl::ExtSessionLockV1) -> bool {
+        self.a_session_lock
+            .as_ref()
+            .and_then(|l| l.sl_lock.as_ref())
+            .map(|l| l == lock)
+            == Some(true)
+    }
+
+    /// Lock the session
+    ///
+    /// This takes focus away from every window and tells the WM to hide
+    /// the desktop. The locker is sent the locked event once the WM has
+    /// presented a frame without the desktop on every output. Returns
+    /// false if another locker already holds the lock.
+    pub fn lock_session(&mut self, lock: esl::ExtSessionLockV1) -> bool {
+        // A new locker may take over if the last one died
+        if let Some(session_lock) = self.a_session_lock.as_mut() {
+            if session_lock.sl_lock.is_some() {
+                return false;
+            }
+            if session_lock.sl_presented {
+                lock.locked();
+            }
+            session_lock.sl_lock = Some(lock);
+            return true;
+        }
+
+        // Menus are closed first, since dismissing them hands the
+        // keyboard back to their window. Drags are cancelled so they
+        // stop following the pointer over the lock surfaces.
+        self.dismiss_popup_grab();
+        data_devices::cancel_drag(self);
+        let desktop_focus = self.get_win_focus();
+        if let Some(focus) = self.get_surf_focus() {
+            Input::keyboard_leave(self, &focus);
+        }
+        self.set_win_focus(None);
+        self.set_surf_focus(None);
+        self.set_grabbed(None);
+        self.set_resizing(None);
+
+        self.a_session_lock = Some(SessionLock {
+            sl_lock: Some(lock),
+            sl_surfaces: Vec::new(),
+            sl_desktop_focus: desktop_focus,
+            sl_presented: false,
+        });
+        self.add_wm_task(wm::task::Task::lock_session);
+        self.add_wm_task(wm::task::Task::reset_cursor);
+        self.recalculate_pointer_focus();
+        true
+    }
+
+    /// Every output has presented a frame with the desktop hidden
+    ///
+    /// This is called by the WM, and sends the locked event the first
+    /// time it happens.
+    pub fn set_session_lock_presented(&mut self) {
+        let session_lock = match self.a_session_lock.as_mut() {
+            Some(session_lock) => session_lock,
+            None => return,
+        };
+        if session_lock.sl_presented {
+            return;
+        }
+
+        session_lock.sl_presented = true;
+        if let Some(lock) = session_lock.sl_lock.as_ref() {
+            log::debug!("Session lock presented on every output");
+            lock.locked();
+        }
+    }
+
+    /// Unlock the session and give the desktop back its focus
+    pub fn unlock_session(&mut self) {
+        let session_lock = match self.a_session_lock.take() {
+            Some(session_lock) => session_lock,
+            None => return,
+        };
+
+        if let Some(focus) = self.get_surf_focus() {
+            Input::keyboard_leave(self, &focus);
+        }
+        self.set_win_focus(session_lock.sl_desktop_focus.clone());
+        self.set_surf_focus(session_lock.sl_desktop_focus.clone());
+        if let Some(id) = session_lock.sl_desktop_focus.as_ref() {
+            if let Some(owner) = self.a_owner.get_clone(id) {
+                data_devices::send_selection(self, &owner);
+                primary_selection::send_primary_selection(self, &owner);
+            }
+            Input::keyboard_enter(self, id);
+        }
+
+        self.add_wm_task(wm::task::Task::unlock_session);
+        self.recalculate_pointer_focus();
+    }
+
+    /// The locker's lock object was destroyed
+    ///
+    /// If it did not unlock first then the locker probably crashed. We
+    /// stay locked, showing nothing, until another locker comes along.
+    pub fn session_locker_gone(&mut self, lock: &esl::ExtSessionLockV1) {
+        if self.is_active_session_lock(lock) {
+            log::error!("Session locker exited without unlocking, staying locked");
+            self.a_session_lock.as_mut().unwrap().sl_lock = None;
+        }
+    }
+
+    /// Start showing a lock surface
+    ///
+    /// The first lock surface gets the keyboard focus.
+    pub fn add_lock_surface(&mut self, state: LockSurfaceState) {
+        let id = state.lk_id.clone();
+        let output = state.lk_output;
+        match self.a_session_lock.as_mut() {
+            Some(lock) => lock.sl_surfaces.push(state),
+            None => return,
+        }
+
+        let index = self.a_outputs.iter().position(|o| o.ol_id == output);
+        if let Some(index) = index {
+            self.add_wm_task(wm::task::Task::set_lock_surface {
+                id: id.clone(),
+                output: index,
+            });
+        }
+        self.arrange_lock_surfaces();
+
+        if self.get_surf_focus().is_none() {
+            self.focus_lock_surface(&id);
+        }
+        self.recalculate_pointer_focus();
+    }
+
+    /// Give a lock surface the keyboard focus
+    ///
+    /// Lock surfaces aren't part of the desktop's window order, so this
+    /// only changes the focus.
+    pub fn focus_lock_surface(&mut self, id: &SurfaceId) {
+        let prev = self.get_surf_focus();
+        if prev.as_ref() == Some(id) {
+            return;
+        }
+        if let Some(prev) = prev.as_ref() {
+            Input::keyboard_leave(self, prev);
+        }
+
+        self.set_win_focus(Some(id.clone()));
+        self.set_surf_focus(Some(id.clone()));
+        Input::keyboard_enter(self, id);
+    }
+
+    /// Focus a window on the desktop while the session is locked
+    ///
+    /// The window is moved to the front of the saved window order, so
+    /// that new windows still get placed. It gets focus when we unlock.
+    pub fn add_to_locked_desktop(&mut self, id: &SurfaceId) {
+        let front = match self.a_session_lock.as_ref() {
+            Some(lock) => lock.sl_desktop_focus.clone(),
+            None => return,
+        };
+        // Subsurfaces are already placed in their window's tree
+        if self.a_root_window.get(id).is_some() || front.as_ref() == Some(id) {
+            return;
+        }
+
+        self.skiplist_remove_window(id);
+        if let Some(front) = front.as_ref() {
+            self.a_skiplist_prev.set(front, id.clone());
+        }
+        self.a_skiplist_next.set_opt(id, front);
+        self.a_skiplist_prev.set_opt(id, None);
+        self.a_session_lock.as_mut().unwrap().sl_desktop_focus = Some(id.clone());
+        self.add_wm_task(wm::task::Task::move_to_front(id.clone()));
+    }
+
+    /// Stop showing a lock surface
+    ///
+    /// This takes away its focus, which the locker can give to another
+    /// lock surface by clicking on it.
+    pub fn remove_lock_surface(&mut self, id: &SurfaceId) {
+        if !self.is_lock_surface(id) {
+            return;
+        }
+
+        if self.get_surf_focus().as_ref() == Some(id) {
+            Input::keyboard_leave(self, id);
+            self.set_win_focus(None);
+            self.set_surf_focus(None);
+        }
+        self.session_lock_remove_window(id);
+        self.add_wm_task(wm::task::Task::close_window(id.clone()));
+        self.recalculate_pointer_focus();
+    }
+
+    /// Remove a destroyed surface from the session lock
+    ///
+    /// This also keeps the saved desktop order valid if its front
+    /// window was destroyed.
+    pub fn session_lock_remove_window(&mut self, id: &SurfaceId) {
+        let next = self.a_skiplist_next.get_clone(id);
+        let lock = match self.a_session_lock.as_mut() {
+            Some(lock) => lock,
+            None => return,
+        };
+
+        lock.sl_surfaces.retain(|s| &s.lk_id != id);
+        if lock.sl_desktop_focus.as_ref() == Some(id) {
+            lock.sl_desktop_focus = next;
+        }
+    }
+
+    /// Place each lock surface over its output
+    ///
+    /// This sends a new configure if the output has been resized.
+    pub fn arrange_lock_surfaces(&mut self) {
+        let outputs = self.get_outputs();
+        let mut positions = Vec::new();
+
+        if let Some(lock) = self.a_session_lock.as_mut() {
+            for surf in lock.sl_surfaces.iter_mut() {
+                if let Some(output) = outputs.iter().find(|o| o.ol_id == surf.lk_output) {
+                    let rect = output.ol_rect;
+                    surf.configure((rect.r_size.0 as u32, rect.r_size.1 as u32));
+                    positions.push((surf.lk_id.clone(), rect.r_pos));
+                }
+            }
+        }
+
+        for (id, pos) in positions.iter() {
+            let pos = self.get_adjusted_desktop_coord(pos.0 as f32, pos.1 as f32);
+            self.a_surface_pos.set(id, pos);
+            self.a_window_pos.set(id, pos);
+        }
+    }
+
+    /// Get the position of a lock surface relative to its output
+    ///
+    /// Lock surfaces always cover the entire output they are drawn on.
+    pub fn get_lock_surface_output_pos(&self, id: &SurfaceId) -> Option<(f32, f32)> {
+        match self.is_lock_surface(id) {
+            true => Some((0.0, 0.0)),
+            false => None,
+        }
+    }
+}
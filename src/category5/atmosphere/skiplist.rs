@@ -7,6 +7,8 @@ use wayland_protocols::xdg::shell::server::xdg_toplevel::ResizeEdge;
 use super::*;
 use crate::category5::input::Input;
 use crate::category5::vkcomp::wm::task::Task;
+use crate::category5::ways::protocol::wlr_layer_shell::zwlr_layer_shell_v1::Layer;
+use crate::category5::ways::{data_devices, primary_selection};
 use utils::log;
 
 // A skiplist is an entry in a linked list designed to be
@@ -162,6 +164,33 @@ impl Atmosphere {
     pub fn focus_on(&mut self, win: Option<SurfaceId>) {
         log::debug!("focusing on window {:?}", win);
 
+        // While locked, only the lock surfaces may have focus
+        if self.is_locked() {
+            if let Some(id) = win.as_ref() {
+                match self.is_lock_surface(id) {
+                    true => self.focus_lock_surface(id),
+                    false => self.add_to_locked_desktop(id),
+                }
+            }
+            return;
+        }
+
+        // Layer surfaces aren't windows, so they only take the keyboard
+        if let Some(id) = win.as_ref() {
+            let root = self.a_root_window.get_clone(id).unwrap_or(id.clone());
+            if self.is_layer_surface(&root) {
+                self.focus_layer_surface(&root);
+                return;
+            }
+        }
+        // Launchers and such keep the keyboard until they are closed
+        if self.layer_has_exclusive_keyboard() {
+            log::debug!("Not focusing {:?}, a layer surface has the keyboard", win);
+            return;
+        }
+        // Otherwise the keyboard goes back to the windows
+        self.unfocus_layer_surface();
+
         if let Some(id) = win.as_ref() {
             // check if a new app was selected
             let root = self.a_root_window.get_clone(id);
@@ -175,6 +204,10 @@ impl Atmosphere {
                 };
 
                 if cur != prev {
+                    // Focusing another window ends any popup grab. This
+                    // hands the keyboard back to prev so it can leave.
+                    self.dismiss_popup_grab();
+
                     // point the previous focus at the new focus
                     self.a_skiplist_prev.set(&prev, id.clone());
 
@@ -193,6 +226,7 @@ impl Atmosphere {
                 self.a_skiplist_next.set_opt(id, prev_win_focus);
                 self.a_skiplist_prev.set_opt(id, None);
                 self.set_win_focus(Some(id.clone()));
+                self.a_urgent_windows.retain(|urgent| urgent != id);
                 // Tell vkcomp to reorder its surface list. This is tricky,
                 // since we want to keep a separation between the two subsystems,
                 // and we want to avoid having to scan the skiplist to calculate
@@ -206,6 +240,12 @@ impl Atmosphere {
             // wl_subsurface changes the order
             // set win to the surf focus
             self.set_surf_focus(Some(id.clone()));
+            // The clipboard and primary selections have to be advertised
+            // before the client receives keyboard focus
+            if let Some(owner) = self.a_owner.get_clone(id) {
+                data_devices::send_selection(self, &owner);
+                primary_selection::send_primary_selection(self, &owner);
+            }
             // Send enter event(s) to the new focus
             // spec says this MUST be done after the leave events are sent
             Input::keyboard_enter(self, id);
@@ -219,6 +259,81 @@ impl Atmosphere {
         // TODO: recalculate skip
     }
 
+    /// Hide a window from the desktop
+    ///
+    /// The window keeps its place in the skiplist, but is no longer
+    /// returned by `visible_windows`. If it was in focus then the
+    /// next visible window gets focus instead.
+    pub fn minimize_window(&mut self, id: &SurfaceId) {
+        self.a_minimized.set(id, true);
+        self.add_wm_task(Task::minimize_window(id.clone()));
+
+        if self.get_root_win_in_focus().as_ref() == Some(id) {
+            // id is skipped now, so this is the window behind it
+            if let Some(next) = self.visible_windows().next() {
+                self.focus_on(Some(next));
+            }
+        }
+        self.recalculate_pointer_focus();
+    }
+
+    /// Show a minimized window again
+    ///
+    /// The window is put back on the desktop and given focus.
+    pub fn restore_window(&mut self, id: &SurfaceId) {
+        if self.a_minimized.get(id).map(|m| *m) != Some(true) {
+            return;
+        }
+        self.a_minimized.set(id, false);
+        self.add_wm_task(Task::new_toplevel(id.clone()));
+        self.focus_on(Some(id.clone()));
+        self.recalculate_pointer_focus();
+    }
+
+    /// Show the front-most minimized window again
+    ///
+    /// This is how the user gets minimized windows back, by clicking
+    /// on the menubar.
+    pub fn restore_minimized_window(&mut self) {
+        let minimized = self
+            .get_toplevels()
+            .into_iter()
+            .find(|id| self.a_minimized.get(id).map(|m| *m) == Some(true));
+
+        if let Some(id) = minimized {
+            self.restore_window(&id);
+        }
+    }
+
+    /// Get every toplevel window, including minimized ones
+    ///
+    /// These are returned in the desktop order, from front to back.
+    pub fn get_toplevels(&self) -> Vec<SurfaceId> {
+        let mut ret = Vec::new();
+        let mut cur = self.get_desktop_front();
+
+        while let Some(id) = cur {
+            if self.a_toplevel.get(&id).map(|t| *t) == Some(true) {
+                ret.push(id.clone());
+            }
+            cur = self.a_skiplist_next.get_clone(&id);
+        }
+
+        ret
+    }
+
+    /// Draw the user's attention to a window
+    ///
+    /// This is used instead of focusing a window when it was not
+    /// allowed to take focus itself. The window stays urgent until
+    /// it is focused.
+    pub fn mark_urgent(&mut self, id: &SurfaceId) {
+        if !self.a_urgent_windows.contains(id) {
+            self.a_urgent_windows.push(id.clone());
+            self.mark_changed();
+        }
+    }
+
     /// Adds the surface `win` as the top subsurface of `parent`.
     pub fn add_new_top_subsurf(&mut self, parent: &SurfaceId, win: &SurfaceId) {
         log::info!(
@@ -249,6 +364,24 @@ impl Atmosphere {
         });
     }
 
+    /// Detach the subsurface `win` from its parent
+    ///
+    /// `win` is no longer drawn, but it may later be attached again
+    /// with `add_new_top_subsurf`.
+    pub fn remove_subsurf(&mut self, win: &SurfaceId) {
+        self.skiplist_remove_window(win);
+        self.a_skiplist_next.take(win);
+        self.a_skiplist_prev.take(win);
+        self.a_root_window.take(win);
+
+        if let Some(parent) = self.a_parent_window.take(win) {
+            self.add_wm_task(Task::remove_subsurface {
+                id: win.clone(),
+                parent: parent,
+            });
+        }
+    }
+
     /// Convert a global position in the screen to a position
     /// within the usable desktop region. This offsets the menubar
     /// at the top of the screen
@@ -256,6 +389,11 @@ impl Atmosphere {
         (x, y - wm::DESKTOP_OFFSET as f32)
     }
 
+    /// Is this global location over the menubar
+    pub fn point_is_on_menubar(&self, x: f32, y: f32) -> bool {
+        self.get_adjusted_desktop_coord(x, y).1 < 0.0
+    }
+
     /// Find the window id of the top window whose input region contains (x, y).
     ///
     /// In the case of delivering input enter/leave events, we don't just check
@@ -319,11 +457,19 @@ impl Atmosphere {
 
     /// Is the current point over the titlebar of the window
     ///
-    /// Id should have first been found with find_window_at_point
+    /// Only windows which negotiated server side decorations have a
+    /// titlebar. It sits directly above the window geometry.
     pub fn point_is_on_titlebar(&self, id: &SurfaceId, x: f32, y: f32) -> bool {
+        if !self.has_titlebar(id) {
+            return false;
+        }
+        let (x, y) = self.get_adjusted_desktop_coord(x, y);
         let barsize = self.get_barsize();
-        let (wx, wy) = *self.a_surface_pos.get(id).unwrap();
-        let (ww, _wh) = *self.a_surface_size.get(id).unwrap();
+        let (wx, wy) = *self.a_window_pos.get(id).unwrap();
+        let (ww, _wh) = match self.a_window_size.get(id) {
+            Some(size) => *size,
+            None => return false,
+        };
 
         // If this window contains (x, y) then return it
         if x > wx && y > (wy - barsize) && x < (wx + ww) && y < wy {
@@ -332,6 +478,52 @@ impl Atmosphere {
         return false;
     }
 
+    /// Find the window whose titlebar is at (x, y)
+    ///
+    /// Titlebars are drawn outside of the surface, so they are never
+    /// found by `find_window_with_input_at_point`. Windows are checked
+    /// from front to back, and stop at the first window body covering
+    /// the point.
+    pub fn find_titlebar_at_point(&self, x: f32, y: f32) -> Option<SurfaceId> {
+        let (ax, ay) = self.get_adjusted_desktop_coord(x, y);
+
+        for win in self.visible_windows() {
+            if self.point_is_on_titlebar(&win, x, y) {
+                return Some(win);
+            }
+
+            let (sx, sy) = *self.a_surface_pos.get(&win).unwrap();
+            let (sw, sh) = *self.a_surface_size.get(&win).unwrap();
+            if ax > sx && ay > sy && ax < sx + sw && ay < sy + sh {
+                return None;
+            }
+        }
+        return None;
+    }
+
+    /// Find which titlebar button of `id` is at (x, y), if any
+    ///
+    /// Buttons are squares the height of the bar, packed against
+    /// its right edge. `id` should have been found with
+    /// `find_titlebar_at_point`.
+    pub fn titlebar_button_at_point(
+        &self,
+        id: &SurfaceId,
+        x: f32,
+        y: f32,
+    ) -> Option<TitlebarButton> {
+        if !self.point_is_on_titlebar(id, x, y) {
+            return None;
+        }
+        let (x, _) = self.get_adjusted_desktop_coord(x, y);
+        let barsize = self.get_barsize();
+        let (wx, _) = *self.a_window_pos.get(id).unwrap();
+        let (ww, _) = *self.a_window_size.get(id).unwrap();
+
+        let index = ((wx + ww - x) / barsize) as usize;
+        TitlebarButton::ALL.get(index).copied()
+    }
+
     /// calculates if a position is over the part of a window that
     /// procs a resize
     pub fn point_is_on_window_edge(&self, id: &SurfaceId, x: f32, y: f32) -> ResizeEdge {
@@ -417,7 +609,21 @@ impl Atmosphere {
     where
         F: FnMut(SurfaceId, (f32, f32)) -> bool,
     {
-        for win in self.visible_windows() {
+        // Layer surfaces in the top layers are in front of every window,
+        // and the bottom layers are behind them
+        let above = self.get_layer_surfaces_in(&[Layer::Overlay, Layer::Top]);
+        let below = self.get_layer_surfaces_in(&[Layer::Bottom, Layer::Background]);
+        // The lock surfaces replace everything while locked
+        let surfaces: Vec<SurfaceId> = match self.is_locked() {
+            true => self.get_lock_surfaces(),
+            false => above
+                .into_iter()
+                .chain(self.visible_windows())
+                .chain(below.into_iter())
+                .collect(),
+        };
+
+        for win in surfaces {
             if !self.map_on_surf_tree_recurse(inorder, win.clone(), &mut func, (0.0, 0.0)) {
                 return;
             }
@@ -524,12 +730,20 @@ impl<'a> Iterator for VisibleWindowIterator<'a> {
     type Item = SurfaceId;
 
     fn next(&mut self) -> Option<SurfaceId> {
-        let ret = self.vwi_cur.take();
-        // TODO: actually skip
-        if let Some(id) = ret.as_ref() {
-            self.vwi_cur = self.vwi_atmos.a_skiplist_next.get_clone(id);
-        }
+        loop {
+            let ret = self.vwi_cur.take();
+            // TODO: actually skip
+            if let Some(id) = ret.as_ref() {
+                self.vwi_cur = self.vwi_atmos.a_skiplist_next.get_clone(id);
+
+                // Minimized windows stay in the skiplist so they keep
+                // their place, but they aren't visible
+                if self.vwi_atmos.a_minimized.get(id).map(|m| *m) == Some(true) {
+                    continue;
+                }
+            }
 
-        return ret;
+            return ret;
+        }
     }
 }
@@ -0,0 +1,181 @@
+/// Atmosphere tests
+use super::constraints::constrain_motion;
+use super::layers::place_on_axis;
+use super::*;
+use crate::category5::ways::protocol::wlr_layer_shell::{
+    zwlr_layer_shell_v1::Layer, zwlr_layer_surface_v1 as zlsv,
+};
+
+/// The usable area of a 1920x1080 output, below the menubar
+fn desktop() -> Rect<i32> {
+    Rect::new(0, 32, 1920, 1048)
+}
+
+fn layer_props(anchor: zlsv::Anchor, size: (u32, u32), zone: i32) -> LayerProperties {
+    let mut props = LayerProperties::new(Layer::Top);
+    props.lp_anchor = anchor;
+    props.lp_size = size;
+    props.lp_exclusive_zone = zone;
+    props
+}
+
+/// Surfaces start at the margin of the edge they are anchored to
+#[test]
+fn place_on_axis_single_edge() {
+    assert_eq!(place_on_axis(0, 100, 20, (true, 5), (false, 7)), (5, 20));
+    assert_eq!(place_on_axis(0, 100, 20, (false, 5), (true, 7)), (73, 20));
+    // The output may not be at the origin
+    assert_eq!(
+        place_on_axis(1920, 100, 20, (true, 5), (false, 7)),
+        (1925, 20)
+    );
+}
+
+/// Anchoring to both edges stretches or centers, and no anchors centers
+#[test]
+fn place_on_axis_opposite_edges() {
+    // A size of zero fills the space between the margins
+    assert_eq!(place_on_axis(0, 100, 0, (true, 5), (true, 7)), (5, 88));
+    assert_eq!(place_on_axis(0, 100, 20, (true, 5), (true, 7)), (39, 20));
+    // Margins are ignored for edges we aren't anchored to
+    assert_eq!(place_on_axis(0, 100, 20, (false, 5), (false, 7)), (40, 20));
+}
+
+/// A panel along one edge reserves its zone and margin
+#[test]
+fn exclusive_zone_edge_anchored() {
+    let mut props = layer_props(
+        zlsv::Anchor::Top | zlsv::Anchor::Left | zlsv::Anchor::Right,
+        (0, 30),
+        30,
+    );
+    props.lp_margin = (2, 0, 0, 0);
+    let mut usable = desktop();
+
+    assert_eq!(props.place(&usable), Rect::new(0, 34, 1920, 30));
+    props.reserve(&mut usable);
+    assert_eq!(usable, Rect::new(0, 64, 1920, 1016));
+
+    // Anchoring to only one edge is also fine
+    let props = layer_props(zlsv::Anchor::Bottom, (200, 40), 40);
+    let mut usable = desktop();
+
+    assert_eq!(props.place(&usable), Rect::new(860, 1040, 200, 40));
+    props.reserve(&mut usable);
+    assert_eq!(usable, Rect::new(0, 32, 1920, 1008));
+}
+
+/// Zones only apply when it is clear which edge they are for
+#[test]
+fn exclusive_zone_opposite_anchored() {
+    // Left with both perpendicular edges is a dock along the left
+    let props = layer_props(
+        zlsv::Anchor::Left | zlsv::Anchor::Top | zlsv::Anchor::Bottom,
+        (64, 0),
+        64,
+    );
+    let mut usable = desktop();
+    assert_eq!(props.place(&usable), Rect::new(0, 32, 64, 1048));
+    props.reserve(&mut usable);
+    assert_eq!(usable, Rect::new(64, 32, 1856, 1048));
+
+    // Anchored to two opposite edges doesn't pick one of them
+    let props = layer_props(zlsv::Anchor::Top | zlsv::Anchor::Bottom, (64, 0), 64);
+    let mut usable = desktop();
+    props.reserve(&mut usable);
+    assert_eq!(usable, desktop());
+
+    let props = layer_props(zlsv::Anchor::all(), (0, 0), 64);
+    let mut usable = desktop();
+    props.reserve(&mut usable);
+    assert_eq!(usable, desktop());
+
+    // Zero and negative zones don't reserve anything
+    for zone in [0, -1].iter() {
+        let props = layer_props(zlsv::Anchor::Top, (0, 30), *zone);
+        let mut usable = desktop();
+        props.reserve(&mut usable);
+        assert_eq!(usable, desktop());
+    }
+}
+
+/// Each exclusive surface is placed in the space left by the previous ones
+#[test]
+fn exclusive_zone_stacked() {
+    let bars = [
+        layer_props(
+            zlsv::Anchor::Top | zlsv::Anchor::Left | zlsv::Anchor::Right,
+            (0, 30),
+            30,
+        ),
+        layer_props(
+            zlsv::Anchor::Top | zlsv::Anchor::Left | zlsv::Anchor::Right,
+            (0, 20),
+            20,
+        ),
+        layer_props(
+            zlsv::Anchor::Left | zlsv::Anchor::Top | zlsv::Anchor::Bottom,
+            (64, 0),
+            64,
+        ),
+    ];
+    let expected = [
+        Rect::new(0, 32, 1920, 30),
+        Rect::new(0, 62, 1920, 20),
+        Rect::new(0, 82, 64, 998),
+    ];
+    let mut usable = desktop();
+
+    for (props, rect) in bars.iter().zip(expected.iter()) {
+        assert_eq!(props.place(&usable), *rect);
+        props.reserve(&mut usable);
+    }
+    assert_eq!(usable, Rect::new(64, 82, 1856, 998));
+}
+
+/// A confinement region of (100, 100) to (300, 200)
+fn in_region(x: f64, y: f64) -> bool {
+    x >= 100.0 && x < 300.0 && y >= 100.0 && y < 200.0
+}
+
+/// Locked pointers stay put no matter where the cursor goes
+#[test]
+fn constrain_locked() {
+    let old = (150.0, 150.0);
+    assert_eq!(constrain_motion(true, old, (160.0, 140.0), in_region), old);
+    assert_eq!(
+        constrain_motion(true, old, (500.0, 500.0), |_, _| true),
+        old
+    );
+}
+
+/// Confined pointers move freely inside the region
+#[test]
+fn constrain_confined_inside() {
+    let new = (250.0, 120.0);
+    assert_eq!(constrain_motion(false, (150.0, 150.0), new, in_region), new);
+}
+
+/// Moving diagonally out of the region slides along its edge
+#[test]
+fn constrain_confined_slides_along_edge() {
+    let old = (290.0, 150.0);
+    // Past the right edge, keep moving vertically
+    assert_eq!(
+        constrain_motion(false, old, (310.0, 170.0), in_region),
+        (290.0, 170.0)
+    );
+    // Past the bottom edge, keep moving horizontally
+    let old = (150.0, 190.0);
+    assert_eq!(
+        constrain_motion(false, old, (170.0, 220.0), in_region),
+        (170.0, 190.0)
+    );
+}
+
+/// Moving out through a corner doesn't move at all
+#[test]
+fn constrain_confined_corner() {
+    let old = (295.0, 195.0);
+    assert_eq!(constrain_motion(false, old, (310.0, 210.0), in_region), old);
+}
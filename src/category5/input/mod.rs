@@ -42,14 +42,17 @@ use ws::protocol::wl_keyboard;
 use ws::protocol::wl_pointer;
 use ws::Resource;
 
-use crate::category5::atmosphere::{Atmosphere, SurfaceId};
+use crate::category5::atmosphere::{Atmosphere, SurfaceId, TitlebarButton};
 use crate::category5::vkcomp::wm;
-use crate::category5::ways::role::Role;
+use crate::category5::ways::{data_devices, input_method, role::Role, text_input::TextInput};
 use utils::{log, timing::*};
 
 use xkbcommon::xkb;
 
 use core::convert::TryFrom;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 /// This represents an input system
 ///
@@ -77,6 +80,31 @@ pub struct Input {
     pub i_mod_caps: bool,
     pub i_mod_meta: bool,
     pub i_mod_num: bool,
+
+    /// Set while handling keys which the input method sent back to us
+    /// through a virtual keyboard. These must not be grabbed by it again.
+    i_bypass_input_method: bool,
+
+    /// Keymaps uploaded by virtual keyboards, by the keyboard's id
+    i_virtual_keymaps: HashMap<ws::backend::ObjectId, VirtualKeymap>,
+    /// Set while handling keys from a virtual keyboard. These are
+    /// interpreted with that keyboard's keymap instead of ours.
+    i_virtual_keyboard: Option<ws::backend::ObjectId>,
+
+    /// When we last handled an input event. The user is considered
+    /// idle once enough time has passed since this.
+    pub i_last_activity: Instant,
+}
+
+/// A keymap uploaded by a virtual keyboard
+///
+/// Every virtual keyboard may use its own layout, which clients need
+/// to be sent before they get any keys from it.
+struct VirtualKeymap {
+    /// The keymap text to share with clients
+    vkm_text: String,
+    /// xkb state machine for this keymap
+    vkm_state: xkb::State,
 }
 
 #[derive(Copy, Eq, PartialEq, Clone)]
@@ -94,6 +122,17 @@ fn map_key_state(state: ButtonState) -> wl_keyboard::KeyState {
     }
 }
 
+// Serialize the modifiers of an xkb state for the
+// wl_keyboard.modifiers event
+fn serialize_modifiers(state: &xkb::State) -> (u32, u32, u32, u32) {
+    let depressed = state.serialize_mods(xkb::STATE_MODS_DEPRESSED);
+    let latched = state.serialize_mods(xkb::STATE_MODS_LATCHED);
+    let locked = state.serialize_mods(xkb::STATE_MODS_LOCKED);
+    let layout = state.serialize_layout(xkb::STATE_LAYOUT_LOCKED);
+
+    (depressed, latched, locked, layout)
+}
+
 // NOTE:
 // The XKB entries above are not marked send/sync. Due to the way
 // cat5 is written they will never be used from multiple threads,
@@ -134,6 +173,10 @@ impl Input {
             i_mod_caps: false,
             i_mod_meta: false,
             i_mod_num: false,
+            i_bypass_input_method: false,
+            i_virtual_keymaps: HashMap::new(),
+            i_virtual_keyboard: None,
+            i_last_activity: Instant::now(),
         }
     }
 
@@ -237,6 +280,10 @@ impl Input {
     /// so atmos' rc may be held.
     pub fn keyboard_enter(atmos: &Atmosphere, id: &SurfaceId) {
         log::error!("Keyboard entered SurfaceId {:?}", id);
+        // Layer surfaces only get the keyboard if they asked for it
+        if atmos.is_layer_surface(id) && !atmos.layer_accepts_keyboard(id) {
+            return;
+        }
         if let Some(cell) = atmos.get_seat_from_surface_id(id) {
             let seat = cell.lock().unwrap();
             // TODO: verify
@@ -252,6 +299,18 @@ impl Input {
                         );
                     }
                 }
+                // Text inputs follow the keyboard focus
+                for text_input in si.si_text_inputs.iter() {
+                    if let Some(surf) = atmos.get_wl_surface_from_id(id) {
+                        text_input.enter(&surf);
+                        text_input
+                            .data::<Arc<Mutex<TextInput>>>()
+                            .unwrap()
+                            .lock()
+                            .unwrap()
+                            .ti_focus = Some(id.clone());
+                    }
+                }
             }
         }
     }
@@ -261,7 +320,7 @@ impl Input {
     //
     // Atmos is passed since this is called from `atmos.focus_on`,
     // so atmos' rc may be held.
-    pub fn keyboard_leave(atmos: &Atmosphere, id: &SurfaceId) {
+    pub fn keyboard_leave(atmos: &mut Atmosphere, id: &SurfaceId) {
         log::error!("Keyboard left SurfaceId {:?}", id);
         if let Some(cell) = atmos.get_seat_from_surface_id(id) {
             let seat = cell.lock().unwrap();
@@ -274,6 +333,20 @@ impl Input {
                         keyboard.leave(seat.s_serial, &surf);
                     }
                 }
+                // The input method can't keep typing into this client
+                for text_input in si.si_text_inputs.iter() {
+                    let focus = text_input
+                        .data::<Arc<Mutex<TextInput>>>()
+                        .unwrap()
+                        .lock()
+                        .unwrap()
+                        .ti_focus
+                        .take();
+                    if let Some(surf) = focus.and_then(|f| atmos.get_wl_surface_from_id(&f)) {
+                        text_input.leave(&surf);
+                    }
+                    input_method::text_input_removed(atmos, text_input);
+                }
             }
         }
     }
@@ -359,9 +432,18 @@ impl Input {
     /// Also generates wl_pointer.motion events to the surface
     /// in focus if the cursor is on that surface
     fn handle_pointer_move(&mut self, atmos: &mut Atmosphere, dx: i32, dy: i32) {
-        // Update the atmosphere with the new cursor pos
+        // Update the atmosphere with the new cursor pos. This may not
+        // move at all if the pointer is locked.
+        let old_pos = atmos.get_cursor_pos();
         atmos.add_cursor_pos(dx as f64, dy as f64);
 
+        // A drag and drop session grabs the pointer, so the motion
+        // goes to the drag instead of the surface under the cursor
+        if atmos.get_dnd().is_some() {
+            data_devices::dnd_motion(atmos);
+            return;
+        }
+
         // If a resize is happening then collect the cursor changes
         // to send at the end of the frame
         if atmos.get_resizing().is_some() {
@@ -370,6 +452,7 @@ impl Input {
         }
 
         let (cx, cy) = atmos.get_cursor_pos();
+        let moved = (cx, cy) != old_pos;
         atmos.recalculate_pointer_focus();
 
         // deliver the motion event
@@ -377,13 +460,35 @@ impl Input {
             if let Some(cell) = atmos.get_seat_from_surface_id(&id) {
                 // get the seat for this client
                 let seat = cell.lock().unwrap();
-                // Get the pointer
+                // relative_motion timestamps are in microseconds
+                let utime = get_current_time().as_micros() as u64;
                 for si in seat.s_proxies.iter() {
+                    // Relative motion is sent even if the cursor was held
+                    // in place. We don't have an accelerated value, so the
+                    // same deltas are used for both.
+                    for relative_pointer in si.si_relative_pointers.iter() {
+                        relative_pointer.relative_motion(
+                            (utime >> 32) as u32,
+                            utime as u32,
+                            dx as f64,
+                            dy as f64,
+                            dx as f64,
+                            dy as f64,
+                        );
+                    }
+
+                    // Get the pointer
                     for pointer in si.si_pointers.iter() {
+                        let mut sent = si.si_relative_pointers.len() > 0;
                         // If the pointer is over this surface
                         if let Some((sx, sy)) = atmos.global_coords_to_surf(&id, cx, cy) {
-                            // deliver the motion event
-                            pointer.motion(get_current_millis(), sx, sy);
+                            if moved {
+                                // deliver the motion event
+                                pointer.motion(get_current_millis(), sx, sy);
+                                sent = true;
+                            }
+                        }
+                        if sent {
                             Self::send_pointer_frame(pointer);
                         }
                     }
@@ -409,6 +514,25 @@ impl Input {
     ) {
         let cursor = atmos.get_cursor_pos();
 
+        // Releasing the button ends any drag and drop session
+        if atmos.get_dnd().is_some() {
+            if state == ButtonState::Released {
+                data_devices::dnd_drop(atmos);
+            }
+            return;
+        }
+
+        // Pressing anywhere outside of a grabbing popup chain dismisses
+        // it. The click itself is still handled below.
+        if state == ButtonState::Pressed && atmos.get_popup_grab().is_some() {
+            let inside = atmos
+                .find_window_with_input_at_point(cursor.0 as f32, cursor.1 as f32)
+                .map(|id| atmos.is_in_popup_grab(&id));
+            if inside != Some(true) {
+                atmos.dismiss_popup_grab();
+            }
+        }
+
         // first check if we are releasing a grab
         if let Some(_id) = atmos.get_grabbed() {
             match state {
@@ -451,9 +575,23 @@ impl Input {
                     }
                 }
             }
+        } else if let Some(id) = atmos.find_titlebar_at_point(cursor.0 as f32, cursor.1 as f32) {
+            self.handle_click_on_titlebar(atmos, id, state);
         } else if let Some(id) =
             atmos.find_window_with_input_at_point(cursor.0 as f32, cursor.1 as f32)
         {
+            // Layer surfaces (and their popups) aren't windows, so they
+            // can't be moved or resized. They may take the keyboard if
+            // they asked for it, otherwise just pass the click on.
+            let root = atmos.a_root_window.get_clone(&id).unwrap_or(id.clone());
+            if atmos.is_layer_surface(&root) {
+                if state == ButtonState::Pressed {
+                    atmos.focus_on(Some(id.clone()));
+                }
+                Self::send_pointer_button(atmos, &id, button, state);
+                return;
+            }
+
             // will our click bring a window into focus?
             let mut set_focus = false;
             if let Some(focus) = atmos.get_root_win_in_focus() {
@@ -493,46 +631,98 @@ impl Input {
                         }
                     }
                 }
-            } else if atmos.point_is_on_titlebar(&id, cursor.0 as f32, cursor.1 as f32) {
-                // now check if we are over the titlebar
-                // if so we will grab the bar
-                match state {
-                    ButtonState::Pressed => {
-                        log::debug!("Grabbing window {:?}", id);
-                        atmos.set_grabbed(Some(id));
-                    }
-                    ButtonState::Released => {
-                        log::debug!("Ungrabbing window {:?}", id);
-                        atmos.set_grabbed(None);
-                    }
-                }
             } else if !set_focus {
                 // else the click was over the meat of the window, so
                 // deliver the event to the wayland client
+                Self::send_pointer_button(atmos, &id, button, state);
+            }
+        } else if state == ButtonState::Pressed
+            && !atmos.is_locked()
+            && atmos.point_is_on_menubar(cursor.0 as f32, cursor.1 as f32)
+        {
+            // Clicking the menubar brings back minimized windows
+            atmos.restore_minimized_window();
+        }
+    }
 
-                // get the seat for this client
-                if let Some(cell) = atmos.get_seat_from_surface_id(&id) {
-                    let seat = cell.lock().unwrap();
-                    for si in seat.s_proxies.iter() {
-                        for pointer in si.si_pointers.iter() {
-                            // Trigger a button event
-                            pointer.button(
-                                seat.s_serial,
-                                get_current_millis(),
-                                button.to_linux_button_code(),
-                                match state {
-                                    ButtonState::Pressed => wl_pointer::ButtonState::Pressed,
-                                    ButtonState::Released => wl_pointer::ButtonState::Released,
-                                },
-                            );
-                            Self::send_pointer_frame(pointer);
-                        }
-                    }
+    /// Deliver a button event to the client owning `id`
+    fn send_pointer_button(
+        atmos: &Atmosphere,
+        id: &SurfaceId,
+        button: dak::MouseButton,
+        state: ButtonState,
+    ) {
+        // get the seat for this client
+        if let Some(cell) = atmos.get_seat_from_surface_id(id) {
+            let mut seat = cell.lock().unwrap();
+            if let ButtonState::Pressed = state {
+                seat.s_input_serial = Some(seat.s_serial);
+            }
+            for si in seat.s_proxies.iter() {
+                for pointer in si.si_pointers.iter() {
+                    // Trigger a button event
+                    pointer.button(
+                        seat.s_serial,
+                        get_current_millis(),
+                        button.to_linux_button_code(),
+                        match state {
+                            ButtonState::Pressed => wl_pointer::ButtonState::Pressed,
+                            ButtonState::Released => wl_pointer::ButtonState::Released,
+                        },
+                    );
+                    Self::send_pointer_frame(pointer);
                 }
             }
         }
     }
 
+    /// Handle a click on a server side titlebar
+    ///
+    /// Pressing one of the buttons acts on the window. Pressing anywhere
+    /// else on the bar grabs the window so it can be moved.
+    fn handle_click_on_titlebar(
+        &mut self,
+        atmos: &mut Atmosphere,
+        id: SurfaceId,
+        state: ButtonState,
+    ) {
+        if state != ButtonState::Pressed {
+            return;
+        }
+        let cursor = atmos.get_cursor_pos();
+        atmos.focus_on(Some(id.clone()));
+
+        let surf_cell = match atmos.get_surface_from_id(&id) {
+            Some(surf) => surf,
+            None => return,
+        };
+        let (ss, maximized) = {
+            let surf = surf_cell.lock().unwrap();
+            match &surf.s_role {
+                Some(Role::xdg_shell_toplevel(_, ss)) => (
+                    ss.clone(),
+                    surf.s_state
+                        .cs_xdg_state
+                        .xs_tlstate
+                        .as_ref()
+                        .map(|tl| tl.tl_maximized)
+                        .unwrap_or(false),
+                ),
+                _ => return,
+            }
+        };
+
+        match atmos.titlebar_button_at_point(&id, cursor.0 as f32, cursor.1 as f32) {
+            Some(TitlebarButton::Close) => ss.lock().unwrap().close(),
+            Some(TitlebarButton::Maximize) => ss.lock().unwrap().set_maximized(atmos, !maximized),
+            Some(TitlebarButton::Minimize) => atmos.minimize_window(&id),
+            None => {
+                log::debug!("Grabbing window {:?}", id);
+                atmos.set_grabbed(Some(id));
+            }
+        }
+    }
+
     // TODO: add gesture recognition
     fn handle_compositor_shortcut(
         &mut self,
@@ -551,9 +741,39 @@ impl Input {
         return false;
     }
 
+    /// Refresh our modifier tracking from the xkb state
+    ///
+    /// Returns the modifiers serialized for the wl_keyboard.modifiers event.
+    fn update_modifiers(&mut self) -> (u32, u32, u32, u32) {
+        // First we need to update our own tracking of what keys are held down
+        self.i_mod_ctrl = self
+            .i_xkb_state
+            .mod_name_is_active(&xkb::MOD_NAME_CTRL, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_alt = self
+            .i_xkb_state
+            .mod_name_is_active(&xkb::MOD_NAME_ALT, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_shift = self
+            .i_xkb_state
+            .mod_name_is_active(&xkb::MOD_NAME_SHIFT, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_caps = self
+            .i_xkb_state
+            .mod_name_is_active(&xkb::MOD_NAME_CAPS, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_meta = self
+            .i_xkb_state
+            .mod_name_is_active(&xkb::MOD_NAME_LOGO, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_num = self
+            .i_xkb_state
+            .mod_name_is_active(&xkb::MOD_NAME_NUM, xkb::STATE_MODS_EFFECTIVE);
+
+        // Now we can serialize the modifiers into a format suitable
+        // for sending to the client
+        serialize_modifiers(&self.i_xkb_state)
+    }
+
     /// Handle the user typing on the keyboard.
     ///
-    /// Deliver the wl_keyboard.key and modifier events.
+    /// Deliver the wl_keyboard.key and modifier events. If an input
+    /// method has grabbed the keyboard they are delivered to it instead.
     fn handle_keyboard(
         &mut self,
         atmos: &mut Atmosphere,
@@ -568,54 +788,69 @@ impl Input {
         // Do the xkbcommon keyboard update first, since it needs to happen
         // even if there isn't a window in focus
         // let xkb keep track of the keyboard state
-        let changed = self.i_xkb_state.update_key(
-            // add 8 to account for differences between evdev and x11
-            key + 8,
-            match state {
-                ButtonState::Pressed => xkb::KeyDirection::Down,
-                ButtonState::Released => xkb::KeyDirection::Up,
-            },
-        );
+        let direction = match state {
+            ButtonState::Pressed => xkb::KeyDirection::Down,
+            ButtonState::Released => xkb::KeyDirection::Up,
+        };
+        let vkm = match self.i_virtual_keyboard.as_ref() {
+            Some(id) => self.i_virtual_keymaps.get_mut(id),
+            None => None,
+        };
+
+        // add 8 to account for differences between evdev and x11
+        let (changed, cur_mods) = match vkm {
+            // Virtual keyboards have their own keymap, so their modifiers
+            // don't affect our shortcuts
+            Some(vkm) => {
+                let changed = vkm.vkm_state.update_key(key + 8, direction);
+                (changed, serialize_modifiers(&vkm.vkm_state))
+            }
+            None => {
+                let changed = self.i_xkb_state.update_key(key + 8, direction);
+                (changed, self.update_modifiers())
+            }
+        };
 
         // if any modifiers were touched we should send their event
-        let mods = if changed != 0 {
-            // First we need to update our own tracking of what keys are held down
-            self.i_mod_ctrl = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_CTRL, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_alt = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_ALT, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_shift = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_SHIFT, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_caps = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_CAPS, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_meta = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_LOGO, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_num = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_NUM, xkb::STATE_MODS_EFFECTIVE);
-
-            // Now we can serialize the modifiers into a format suitable
-            // for sending to the client
-            let depressed = self.i_xkb_state.serialize_mods(xkb::STATE_MODS_DEPRESSED);
-            let latched = self.i_xkb_state.serialize_mods(xkb::STATE_MODS_LATCHED);
-            let locked = self.i_xkb_state.serialize_mods(xkb::STATE_MODS_LOCKED);
-            let layout = self.i_xkb_state.serialize_layout(xkb::STATE_LAYOUT_LOCKED);
-
-            Some((depressed, latched, locked, layout))
-        } else {
-            None
+        let mods = if changed != 0 { Some(cur_mods) } else { None };
+
+        // While composing text the input method gets the keys instead,
+        // and sends the text it makes to the client itself
+        let input_method = match self.i_bypass_input_method {
+            true => None,
+            false => atmos.get_input_method(),
+        };
+        if let Some(im) = input_method {
+            let mut im = im.lock().unwrap();
+            // A new keymap resets the modifiers, so send them again
+            let mods = match im.update_keymap(self) {
+                true => Some(cur_mods),
+                false => mods,
+            };
+            if im.send_key(get_current_millis(), key, map_key_state(state), mods) {
+                return;
+            }
+        }
+
+        // A grabbing popup takes the keys, even if it belongs to a
+        // layer surface and not the window in focus. While locked only
+        // the lock surfaces get them.
+        let focus = match atmos.get_popup_grab() {
+            Some(popup) if !atmos.is_locked() => atmos.a_owner.get_clone(&popup),
+            _ => atmos.get_client_in_focus(),
         };
 
         // if there is a window in focus
-        if let Some(id) = atmos.get_client_in_focus() {
+        if let Some(id) = focus {
             // get the seat for this client
             if let Some(cell) = atmos.get_seat_from_client_id(&id) {
                 let mut seat = cell.lock().unwrap();
+                // Clients need the keymap of the keyboard this key is from,
+                // and a new keymap resets the modifiers
+                let mods = match seat.update_keymap(self) {
+                    true => Some(cur_mods),
+                    false => mods,
+                };
                 for si in seat.s_proxies.iter() {
                     for keyboard in si.si_keyboards.iter() {
                         if let Some((depressed, latched, locked, layout)) = mods {
@@ -631,6 +866,9 @@ impl Input {
                         keyboard.key(seat.s_serial, time, key, state);
                     }
                 }
+                if let ButtonState::Pressed = state {
+                    seat.s_input_serial = Some(seat.s_serial);
+                }
                 // increment the serial for next time
                 seat.s_serial += 1;
             }
@@ -645,6 +883,10 @@ impl Input {
     /// we need to figure out the appropriate destination and perform
     /// the right action.
     pub fn handle_input_event(&mut self, atmos: &mut Atmosphere, ev: &dak::PlatformEvent) {
+        // Use when the event happened, since it may have waited in
+        // the queue for a while
+        self.i_last_activity = self.i_last_activity.max(ev.get_time());
+
         match ev {
             dak::PlatformEvent::InputMouseMove { dx, dy } => {
                 self.handle_pointer_move(atmos, *dx, *dy)
@@ -684,5 +926,143 @@ impl Input {
             ),
             _ => (),
         };
+
+        // The pointer or keyboard focus may have changed, which turns
+        // pointer constraints on or off
+        atmos.update_pointer_constraint();
+    }
+
+    /// Dispatch a key from a virtual keyboard
+    ///
+    /// These are handled the same as keys from real devices, except
+    /// that they are interpreted with the keymap the virtual keyboard
+    /// uploaded. Keys from the input method's own virtual keyboard also
+    /// skip its keyboard grab. Those are keys it chose not to handle.
+    pub fn handle_virtual_key(
+        &mut self,
+        atmos: &mut Atmosphere,
+        keyboard: &ws::backend::ObjectId,
+        key: u32,
+        pressed: bool,
+        from_input_method: bool,
+    ) {
+        // Virtual keyboards only give us the raw key, so we need to use
+        // their keymap to find the symbol it produces.
+        let keysym = match self.i_virtual_keymaps.get(keyboard) {
+            Some(vkm) => vkm.vkm_state.key_get_one_sym(key + 8),
+            None => return,
+        };
+        let keycode = dak::input::convert_xkb_keycode_to_dakota(keysym);
+        let raw_keycode = dak::RawKeycode::Linux(key);
+        // The time clients send has no defined base, so these are
+        // stamped when we get them
+        let ev = match pressed {
+            true => dak::PlatformEvent::InputKeyDown {
+                key: keycode,
+                utf8: String::new(),
+                raw_keycode: raw_keycode,
+                time: Instant::now(),
+            },
+            false => dak::PlatformEvent::InputKeyUp {
+                key: keycode,
+                utf8: String::new(),
+                raw_keycode: raw_keycode,
+                time: Instant::now(),
+            },
+        };
+
+        self.i_virtual_keyboard = Some(keyboard.clone());
+        self.i_bypass_input_method = from_input_method;
+        self.handle_input_event(atmos, &ev);
+        self.i_bypass_input_method = false;
+        self.i_virtual_keyboard = None;
+    }
+
+    /// Compile the keymap uploaded by a virtual keyboard
+    ///
+    /// This replaces any keymap it uploaded before. Returns false if
+    /// the keymap could not be compiled.
+    pub fn set_virtual_keymap(&mut self, keyboard: ws::backend::ObjectId, text: String) -> bool {
+        let keymap = match xkb::Keymap::new_from_string(
+            &self.i_xkb_ctx,
+            text.clone(),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        ) {
+            Some(keymap) => keymap,
+            None => return false,
+        };
+
+        self.i_virtual_keymaps.insert(
+            keyboard,
+            VirtualKeymap {
+                vkm_text: text,
+                vkm_state: xkb::State::new(&keymap),
+            },
+        );
+        true
+    }
+
+    /// Forget the keymap of a destroyed virtual keyboard
+    pub fn remove_virtual_keymap(&mut self, keyboard: &ws::backend::ObjectId) {
+        self.i_virtual_keymaps.remove(keyboard);
+    }
+
+    /// The keymap of the keyboard whose keys we are handling
+    ///
+    /// This is the id of a virtual keyboard, or None for our own keymap.
+    pub fn get_keymap(&self) -> Option<&ws::backend::ObjectId> {
+        self.i_virtual_keyboard
+            .as_ref()
+            .filter(|id| self.i_virtual_keymaps.contains_key(*id))
+    }
+
+    /// Get the text of a keymap returned by `get_keymap`
+    ///
+    /// Keymaps of virtual keyboards which have since been destroyed
+    /// fall back to our own.
+    pub fn get_keymap_text(&self, keymap: Option<&ws::backend::ObjectId>) -> &str {
+        match keymap.and_then(|id| self.i_virtual_keymaps.get(id)) {
+            Some(vkm) => vkm.vkm_text.as_str(),
+            None => self.i_xkb_keymap_name.as_str(),
+        }
+    }
+
+    /// Set the modifier state from a virtual keyboard
+    ///
+    /// These are in terms of the virtual keyboard's keymap, which the
+    /// client in focus is sent first.
+    pub fn handle_virtual_modifiers(
+        &mut self,
+        atmos: &mut Atmosphere,
+        keyboard: &ws::backend::ObjectId,
+        depressed: u32,
+        latched: u32,
+        locked: u32,
+        group: u32,
+    ) {
+        let (depressed, latched, locked, layout) = match self.i_virtual_keymaps.get_mut(keyboard) {
+            Some(vkm) => {
+                vkm.vkm_state
+                    .update_mask(depressed, latched, locked, 0, 0, group);
+                serialize_modifiers(&vkm.vkm_state)
+            }
+            None => return,
+        };
+
+        if let Some(id) = atmos.get_client_in_focus() {
+            if let Some(cell) = atmos.get_seat_from_client_id(&id) {
+                let mut seat = cell.lock().unwrap();
+                self.i_virtual_keyboard = Some(keyboard.clone());
+                seat.update_keymap(self);
+                self.i_virtual_keyboard = None;
+                for si in seat.s_proxies.iter() {
+                    for keyboard in si.si_keyboards.iter() {
+                        keyboard.modifiers(seat.s_serial, depressed, latched, locked, layout);
+                    }
+                }
+                seat.s_serial += 1;
+            }
+        }
     }
 }
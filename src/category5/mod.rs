@@ -16,9 +16,38 @@ use atmosphere::{Atmosphere, ClientId};
 use cat5_utils::{log, Result};
 use vkcomp::wm::*;
 
+use wayland_protocols::ext::foreign_toplevel_list::v1::server::ext_foreign_toplevel_list_v1 as eftl;
+use wayland_protocols::ext::idle_notify::v1::server::{
+    ext_idle_notification_v1 as ein, ext_idle_notifier_v1 as eint,
+};
+use wayland_protocols::ext::session_lock::v1::server::ext_session_lock_manager_v1 as eslm;
+use wayland_protocols::wp::fractional_scale::v1::server::{
+    wp_fractional_scale_manager_v1 as wpfsm, wp_fractional_scale_v1 as wpfs,
+};
+use wayland_protocols::wp::idle_inhibit::zv1::server::zwp_idle_inhibit_manager_v1 as ziim;
 use wayland_protocols::wp::linux_dmabuf::zv1::server::zwp_linux_dmabuf_v1 as zldv1;
+use wayland_protocols::wp::pointer_constraints::zv1::server::zwp_pointer_constraints_v1 as zpc;
+use wayland_protocols::wp::presentation_time::server::wp_presentation as wpp;
+use wayland_protocols::wp::primary_selection::zv1::server::zwp_primary_selection_device_manager_v1 as zpsdm;
+use wayland_protocols::wp::relative_pointer::zv1::server::zwp_relative_pointer_manager_v1 as zrpm;
+use wayland_protocols::wp::text_input::zv3::server::zwp_text_input_manager_v3 as ztim;
+use wayland_protocols::wp::viewporter::server::wp_viewporter as wpvr;
+use wayland_protocols::xdg::activation::v1::server::xdg_activation_v1 as xa;
+use wayland_protocols::xdg::decoration::zv1::server::zxdg_decoration_manager_v1 as zxdm;
 use wayland_protocols::xdg::shell::server::*;
+use wayland_protocols::xdg::xdg_output::zv1::server::{
+    zxdg_output_manager_v1 as zxom, zxdg_output_v1 as zxo,
+};
+use ways::foreign_toplevel::ForeignToplevels;
+use ways::linux_dmabuf::DmabufFormats;
+use ways::protocol::input_method::zwp_input_method_manager_v2 as zimm;
+use ways::protocol::virtual_keyboard::zwp_virtual_keyboard_manager_v1 as zvkm;
 use ways::protocol::wl_drm::wl_drm;
+use ways::protocol::wlr_foreign_toplevel::zwlr_foreign_toplevel_manager_v1 as zwftm;
+use ways::protocol::wlr_layer_shell::zwlr_layer_shell_v1 as zlsh;
+use ways::protocol::wlr_virtual_pointer::zwlr_virtual_pointer_manager_v1 as zvpm;
+use ways::wl_output::OutputGlobal;
+use ways::xdg_activation::ActivationToken;
 use ws::protocol::{
     wl_compositor as wlci, wl_data_device_manager as wlddm, wl_output, wl_seat, wl_shell, wl_shm,
     wl_subcompositor,
@@ -26,6 +55,7 @@ use ws::protocol::{
 
 use std::ops::DerefMut;
 use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 // The category5 compositor
@@ -66,8 +96,9 @@ pub struct Climate {
     /// This is the virtual surface that we lay out a desktop on
     /// and present portions of.
     c_virtual_output: dak::VirtualOutput,
-    /// The DRM format modifiers supported by the primary GPU
-    c_primary_render_mods: Vec<u64>,
+    /// The dmabuf formats we can import. This is filled in once the
+    /// window manager has set up our outputs.
+    c_dmabuf_formats: Option<DmabufFormats>,
     /// This is our scene, a layout tree of the Dakota Elements which
     /// correspond to our Wayland surfaces.
     c_scene: dak::Scene,
@@ -80,8 +111,25 @@ pub struct Climate {
     /// changes and the like.
     // TODO: make this a Component for OutputId
     c_outputs: Vec<wl_output::WlOutput>,
+    /// One wl_output global for each of vkcomp's outputs
+    c_output_globals: Vec<OutputGlobal>,
+    /// The screencopy global, which is only advertised while there is
+    /// an output we can copy frames out of
+    c_screencopy_global: Option<ws::backend::GlobalId>,
+    /// All xdg_output objects, which need updating along with c_outputs
+    c_xdg_outputs: Vec<zxo::ZxdgOutputV1>,
+    /// All wp_fractional_scale objects, which are sent a new scale when
+    /// the outputs change
+    c_fractional_scales: Vec<wpfs::WpFractionalScaleV1>,
     /// The input subsystem
     c_input: Input,
+    /// Activation tokens which have been handed out but not used
+    c_activation_tokens: Vec<ActivationToken>,
+    /// All ext_idle_notification objects, which are checked against
+    /// the time since the last input event
+    c_idle_notifications: Vec<ein::ExtIdleNotificationV1>,
+    /// Toplevel windows which have been announced to taskbars
+    c_foreign_toplevels: ForeignToplevels,
 }
 
 impl Climate {
@@ -102,12 +150,19 @@ impl Climate {
 
         Self {
             c_atmos: Arc::new(Mutex::new(Atmosphere::new(&scene))),
-            c_primary_render_mods: dakota.get_supported_drm_render_modifiers(),
+            c_dmabuf_formats: None,
             c_dakota: dakota,
             c_virtual_output: virtual_output,
             c_scene: scene,
             c_outputs: Vec::with_capacity(1),
+            c_output_globals: Vec::with_capacity(1),
+            c_screencopy_global: None,
+            c_xdg_outputs: Vec::new(),
+            c_fractional_scales: Vec::new(),
             c_input: Input::new(),
+            c_activation_tokens: Vec::new(),
+            c_idle_notifications: Vec::new(),
+            c_foreign_toplevels: ForeignToplevels::new(),
         }
     }
 }
@@ -119,6 +174,8 @@ impl Climate {
 pub struct ClientInfo {
     ci_id: ClientId,
     _ci_atmos: Arc<Mutex<Atmosphere>>,
+    /// May this client use privileged protocols such as virtual input
+    ci_trusted: AtomicBool,
 }
 
 impl ws::backend::ClientData for ClientInfo {
@@ -172,7 +229,14 @@ impl EventManager {
         )
         .expect("Could not create Window Manager");
 
-        let evman = EventManager {
+        // Prefer the modifiers our displays can use for client buffers
+        state.c_dmabuf_formats = Some(DmabufFormats::new(
+            &state.c_dakota.get_supported_drm_render_modifiers(),
+            &state.c_dakota.get_supported_drm_ycbcr_formats(),
+            &wm.get_scanout_modifiers(),
+        ));
+
+        let mut evman = EventManager {
             em_wm: wm,
             em_climate: state,
             em_display: display,
@@ -184,18 +248,41 @@ impl EventManager {
         // --------------------------
         // wl_compositor
         display_handle.create_global::<Climate, wlci::WlCompositor, ()>(5, ());
-        display_handle.create_global::<Climate, xdg_wm_base::XdgWmBase, ()>(1, ());
+        display_handle.create_global::<Climate, xdg_wm_base::XdgWmBase, ()>(3, ());
+        display_handle.create_global::<Climate, zxdm::ZxdgDecorationManagerV1, ()>(1, ());
+        display_handle.create_global::<Climate, zlsh::ZwlrLayerShellV1, ()>(4, ());
         display_handle.create_global::<Climate, wl_seat::WlSeat, ()>(8, ());
         display_handle.create_global::<Climate, wl_subcompositor::WlSubcompositor, ()>(1, ());
-        display_handle.create_global::<Climate, wl_output::WlOutput, ()>(4, ());
+        display_handle.create_global::<Climate, zrpm::ZwpRelativePointerManagerV1, ()>(1, ());
+        display_handle.create_global::<Climate, zpc::ZwpPointerConstraintsV1, ()>(1, ());
+        display_handle.create_global::<Climate, ztim::ZwpTextInputManagerV3, ()>(1, ());
+        display_handle.create_global::<Climate, zimm::ZwpInputMethodManagerV2, ()>(1, ());
+        display_handle.create_global::<Climate, xa::XdgActivationV1, ()>(1, ());
+        display_handle.create_global::<Climate, ziim::ZwpIdleInhibitManagerV1, ()>(1, ());
+        display_handle.create_global::<Climate, eint::ExtIdleNotifierV1, ()>(1, ());
+        display_handle.create_global::<Climate, eslm::ExtSessionLockManagerV1, ()>(1, ());
+        // Virtual input devices are hidden from clients the user hasn't
+        // trusted. See ways::utils::process_is_trusted
+        display_handle.create_global::<Climate, zvkm::ZwpVirtualKeyboardManagerV1, ()>(1, ());
+        display_handle.create_global::<Climate, zvpm::ZwlrVirtualPointerManagerV1, ()>(2, ());
+        evman.em_climate.update_output_globals(&display_handle);
+        evman.em_climate.update_screencopy_global(&display_handle);
+        display_handle.create_global::<Climate, zxom::ZxdgOutputManagerV1, ()>(3, ());
+        display_handle.create_global::<Climate, wpvr::WpViewporter, ()>(1, ());
+        display_handle.create_global::<Climate, wpfsm::WpFractionalScaleManagerV1, ()>(1, ());
+        display_handle.create_global::<Climate, wpp::WpPresentation, ()>(1, ());
+        display_handle.create_global::<Climate, eftl::ExtForeignToplevelListV1, ()>(1, ());
+        display_handle.create_global::<Climate, zwftm::ZwlrForeignToplevelManagerV1, ()>(3, ());
         if evman.em_climate.c_atmos.lock().unwrap().get_drm_dev() != (0, 0) {
             log::debug!("No DRM device detected, not advertising DRM-based interfaces");
-            display_handle.create_global::<Climate, zldv1::ZwpLinuxDmabufV1, ()>(3, ());
+            display_handle.create_global::<Climate, zldv1::ZwpLinuxDmabufV1, ()>(4, ());
             display_handle.create_global::<Climate, wl_drm::WlDrm, ()>(2, ());
         }
         display_handle.create_global::<Climate, wl_shell::WlShell, ()>(1, ());
         display_handle.create_global::<Climate, wl_shm::WlShm, ()>(1, ());
         display_handle.create_global::<Climate, wlddm::WlDataDeviceManager, ()>(3, ());
+        display_handle
+            .create_global::<Climate, zpsdm::ZwpPrimarySelectionDeviceManagerV1, ()>(1, ());
 
         return evman;
     }
@@ -215,14 +302,25 @@ impl EventManager {
         // make a new client id
         let id = atmos.mint_client_id();
         // add our ClientData
-        self.em_display.handle().insert_client(
+        let handle = self.em_display.handle();
+        let client = handle.insert_client(
             client_stream,
             Arc::new(ClientInfo {
                 ci_id: id.clone(),
                 _ci_atmos: self.em_climate.c_atmos.clone(),
+                ci_trusted: AtomicBool::new(false),
             }),
         )?;
 
+        // Check the executable now, before the client can bind anything
+        if let Ok(creds) = client.get_credentials(&handle) {
+            if ways::utils::process_is_trusted(creds.pid) {
+                log::debug!("Client {:?} (pid {}) is trusted", id, creds.pid);
+                let info = client.get_data::<ClientInfo>().unwrap();
+                info.ci_trusted.store(true, Ordering::Release);
+            }
+        }
+
         return Ok(id);
     }
 
@@ -241,12 +339,16 @@ impl EventManager {
             .c_dakota
             .add_watch_fd(self.em_socket.as_raw_fd());
 
+        // How long until the next idle notification is due. We need to
+        // wake up for it even if nothing else happens.
+        let mut idle_timeout: Option<std::time::Duration> = None;
+
         loop {
             log::debug!("starting loop");
 
             self.em_climate
                 .c_dakota
-                .dispatch(None)
+                .dispatch(idle_timeout.map(|t| t.as_millis() as usize + 1))
                 .expect("Dispatching Dakota platform handlers");
             log::debug!("dispatch_platform done");
 
@@ -308,8 +410,22 @@ impl EventManager {
                 )
                 .unwrap();
             atmos.clear_changed();
+            drop(atmos);
+            // Advertise any outputs that were added or changed
+            self.em_climate
+                .update_output_globals(&self.em_display.handle());
+            self.em_climate
+                .update_screencopy_global(&self.em_display.handle());
             log::debug!("Output handling done");
 
+            // Keep taskbars in sync with the open windows
+            self.em_climate.update_foreign_toplevels();
+            // Popups may need to follow their parent to a new spot
+            self.em_climate.update_reactive_popups();
+
+            // Tell clients if the user has gone idle or come back
+            idle_timeout = self.em_climate.update_idle_notifications();
+
             // Flush any wayland events we sent here
             // The rendering code will send the wayland frame notifications, which
             // have been queued but not yet flushed to the wayland socket.
@@ -43,10 +43,14 @@ extern crate utils;
 use dak::{anyhow, dom, DakotaId};
 
 use crate::category5::atmosphere::*;
-use utils::{log, Context, Result};
+use crate::category5::ways::{presentation_time, screencopy};
+use utils::{log, region::Rect, Context, Result};
 
 pub mod task;
+use crate::category5::ways::protocol::wlr_layer_shell::zwlr_layer_shell_v1::Layer;
+use crate::category5::ways::protocol::wlr_screencopy::zwlr_screencopy_frame_v1 as zscf;
 use task::*;
+use wayland_protocols::wp::presentation_time::server::wp_presentation_feedback as wppf;
 
 #[cfg(feature = "renderdoc")]
 extern crate renderdoc;
@@ -66,6 +70,15 @@ pub struct WMOutput {
     /// This is our presentation object which actually shows pixels
     /// on a presentable surface such as a physical display.
     wm_output: dak::Output,
+    /// Our id for this output. Used to match it to its wl_output global.
+    wm_id: u32,
+    /// The name advertised for this output. The backend's connector name
+    /// is used unless another output already has it.
+    wm_name: String,
+    /// How many pixels are drawn for each unit of the desktop. The menubar
+    /// and windows are laid out in desktop units, so this output shows
+    /// `resolution / wm_scale` of the desktop.
+    wm_scale: f32,
     /// This Element is the container that holds this output's
     /// background effects
     wm_background_root: DakotaId,
@@ -73,8 +86,112 @@ pub struct WMOutput {
     /// desktop.
     /// The menubar and others will be children of this.
     wm_effects_root: DakotaId,
+    /// The element on the lock layer covering this output. Lock
+    /// surfaces for this output are its children.
+    wm_lock_root: DakotaId,
+    /// The menubar across the top of this output
+    wm_menubar: DakotaId,
+    /// Lists the windows asking for attention and the minimized
+    /// windows. This is only attached to the menubar while there are any.
+    wm_window_list: DakotaId,
     /// The date time string UI element.
     wm_datetime: DakotaId,
+    /// Presentation feedback for surfaces drawn in the frame being recorded
+    wm_pending_feedback: Vec<wppf::WpPresentationFeedback>,
+    /// Presentation feedback waiting for its frame to reach the screen,
+    /// along with the present count of that frame
+    wm_presentation_feedback: Vec<(u64, Vec<wppf::WpPresentationFeedback>)>,
+    /// Screencopy frames to copy out of the next frame drawn. Frames
+    /// waiting for damage stay here until their region changes.
+    wm_screencopy_frames: Vec<zscf::ZwlrScreencopyFrameV1>,
+    /// Screencopy frames copied in a frame that hasn't finished drawing.
+    /// These are polled and sent to their clients once it has.
+    wm_screencopy_pending: Vec<(zscf::ZwlrScreencopyFrameV1, dak::CaptureImage)>,
+    /// Has a frame drawn while the session is locked been presented.
+    /// The locker is told the session is locked once every output has.
+    wm_lock_presented: bool,
+}
+
+impl WMOutput {
+    /// Match up our presentation feedback with the frames shown
+    ///
+    /// `prev_frame` is the present count from before this output was
+    /// redrawn. If no new frame was presented then the pending feedback
+    /// is for content that will never be shown.
+    fn update_presentation_feedback(&mut self, prev_frame: u64) {
+        let pending = std::mem::take(&mut self.wm_pending_feedback);
+        let frame = self.wm_output.get_present_count();
+        if frame == prev_frame {
+            for feedback in pending {
+                feedback.discarded();
+            }
+        } else if pending.len() > 0 {
+            self.wm_presentation_feedback.push((frame, pending));
+        }
+
+        let presented = match self.wm_output.get_last_presentation() {
+            Some(presented) => presented,
+            None => return,
+        };
+        let refresh_rate = self.wm_output.get_description().dd_refresh_rate;
+
+        // Anything older than the last frame shown was replaced by a
+        // later frame before it could make it to the screen
+        self.wm_presentation_feedback.retain(|(frame, feedback)| {
+            if *frame > presented.pt_frame {
+                return true;
+            }
+            for fb in feedback.iter() {
+                match *frame == presented.pt_frame {
+                    true => presentation_time::send_presented(fb, &presented, refresh_rate),
+                    false => fb.discarded(),
+                }
+            }
+            false
+        });
+    }
+
+    /// Hand finished screencopies to their clients
+    ///
+    /// This doesn't wait, copies in frames still being drawn are checked
+    /// again the next time around. Frames waiting for damage that didn't
+    /// get any go back to wait for the next frame.
+    fn finish_screencopies(&mut self) {
+        for (frame, image) in std::mem::take(&mut self.wm_screencopy_pending) {
+            if !image.is_copy_done() {
+                self.wm_screencopy_pending.push((frame, image));
+            } else if !screencopy::finish_copy(&frame, &image) {
+                self.wm_screencopy_frames.push(frame);
+            }
+        }
+    }
+
+    /// Discard all feedback since this output will not present again
+    fn discard_presentation_feedback(&mut self) {
+        for feedback in self.wm_pending_feedback.drain(0..) {
+            feedback.discarded();
+        }
+        for (_, feedback) in self.wm_presentation_feedback.drain(0..) {
+            for fb in feedback {
+                fb.discarded();
+            }
+        }
+    }
+}
+
+/// Server side decorations for a window
+///
+/// These are the Dakota elements making up the titlebar drawn above
+/// windows which asked for server side decorations.
+struct Titlebar {
+    /// The bar itself. This is a child of the window's element.
+    tb_bar: DakotaId,
+    /// Text element displaying the window title
+    tb_title: DakotaId,
+    /// The title currently displayed, used to skip re-laying out text
+    tb_title_text: String,
+    /// One element for each button, in the order of `TitlebarButton::ALL`
+    tb_buttons: Vec<DakotaId>,
 }
 
 /// Encapsulates vkcomp and provides a sensible windowing API
@@ -90,6 +207,8 @@ pub struct WindowManager {
     wm_atmos_ids: Vec<SurfaceId>,
     /// The list of displays we are presenting desktops to
     wm_outputs: Vec<WMOutput>,
+    /// The id to give the next output we create
+    wm_next_output_id: u32,
     /// The root element for our scene
     ///
     /// In Dakota layout is heirarchical, so we have a root node that we attach all
@@ -109,8 +228,16 @@ pub struct WindowManager {
     /// This is the effects layer, which holds the per-Output window or menu bars.
     /// This is layed out on top of the desktop layer.
     wm_effects_layer: DakotaId,
+    /// Fullscreen windows are moved here from the app layer, so that
+    /// they are drawn over the menubar.
+    wm_fullscreen_layer: DakotaId,
+    /// This replaces the other layers while the session is locked. It
+    /// is blank except for any lock surfaces.
+    wm_lock_layer: DakotaId,
     /// Font definition for UI widgets
     wm_menubar_font: DakotaId,
+    /// The window list currently shown in the menubar
+    wm_window_list_text: String,
     /// The cursor layer. This is the uppermost layer which lays the cursor
     /// image on top of everything. This is the size of the virtual output and
     /// the cursor element moves on top of it.
@@ -120,6 +247,16 @@ pub struct WindowManager {
     wm_cursor: Option<DakotaId>,
     /// Category5's cursor, used when the client hasn't set one.
     wm_default_cursor: DakotaId,
+    /// The drag and drop icon. This follows the cursor on the cursor layer.
+    wm_dnd_icon: Option<DakotaId>,
+    /// Titlebars for windows using server side decorations
+    wm_titlebars: ll::Component<Titlebar>,
+    /// The output root element each layer shell or lock surface is drawn in
+    wm_layer_parents: ll::Component<DakotaId>,
+    /// Color of the titlebar and its buttons
+    wm_titlebar_color: DakotaId,
+    wm_button_color: DakotaId,
+    wm_close_button_color: DakotaId,
     #[cfg(feature = "renderdoc")]
     wm_renderdoc: RenderDoc<renderdoc::V141>,
 }
@@ -174,6 +311,133 @@ impl WindowManager {
         return menubar;
     }
 
+    /// Define all of the Dakota elements that make up a window's titlebar
+    ///
+    /// The bar is attached to the window element, but is positioned above
+    /// the window contents. Its size is updated in `update_titlebar`.
+    fn create_titlebar(&self, scene: &mut dak::Scene, id: &SurfaceId) -> Titlebar {
+        let bar = scene.create_element().unwrap();
+        scene.resource().set(&bar, self.wm_titlebar_color.clone());
+        // The bar lies outside of the window's bounds, so don't let
+        // Dakota clip it to the window element
+        scene.unbounded_subsurface().set(&bar, true);
+        scene.add_child_to_element(id, bar.clone());
+
+        let title = scene.create_element().unwrap();
+        scene.text_font().set(&title, self.wm_menubar_font.clone());
+        scene.add_child_to_element(&bar, title.clone());
+
+        let mut buttons = Vec::new();
+        for button in TitlebarButton::ALL.iter() {
+            let (label, color) = match button {
+                TitlebarButton::Close => ("x", &self.wm_close_button_color),
+                TitlebarButton::Maximize => ("+", &self.wm_button_color),
+                TitlebarButton::Minimize => ("-", &self.wm_button_color),
+            };
+
+            let el = scene.create_element().unwrap();
+            scene.resource().set(&el, color.clone());
+            let text = scene.create_element().unwrap();
+            scene.set_text_regular(&text, label);
+            scene.text_font().set(&text, self.wm_menubar_font.clone());
+            scene.add_child_to_element(&el, text);
+
+            scene.add_child_to_element(&bar, el.clone());
+            buttons.push(el);
+        }
+
+        Titlebar {
+            tb_bar: bar,
+            tb_title: title,
+            tb_title_text: String::new(),
+            tb_buttons: buttons,
+        }
+    }
+
+    /// Create, update, or remove the titlebar of a window
+    ///
+    /// Windows get a titlebar while they are using server side
+    /// decorations. The layout here has to match the hit testing
+    /// in `Atmosphere::titlebar_button_at_point`.
+    fn update_titlebar(
+        &self,
+        atmos: &Atmosphere,
+        scene: &mut dak::Scene,
+        id: &SurfaceId,
+    ) -> Result<()> {
+        if !atmos.has_titlebar(id) {
+            if let Some(titlebar) = self.wm_titlebars.take(id) {
+                scene.remove_child_from_element(id, &titlebar.tb_bar)?;
+            }
+            return Ok(());
+        }
+
+        // We can't size the bar until the window has been committed
+        let window_size = match atmos.a_window_size.get(id) {
+            Some(size) => *size,
+            None => return Ok(()),
+        };
+        if self.wm_titlebars.get(id).is_none() {
+            let titlebar = self.create_titlebar(scene, id);
+            self.wm_titlebars.set(id, titlebar);
+        }
+        let mut titlebar = self.wm_titlebars.get_mut(id).unwrap();
+
+        // The bar is placed relative to the window element, which is
+        // at the surface position. Line it up with the window geometry.
+        let surface_pos = *atmos.a_surface_pos.get(id).unwrap();
+        let window_pos = *atmos.a_window_pos.get(id).unwrap();
+        let barsize = atmos.get_barsize() as i32;
+        let width = window_size.0 as i32;
+
+        scene.offset().set(
+            &titlebar.tb_bar,
+            dom::RelativeOffset {
+                x: dom::Value::Constant((window_pos.0 - surface_pos.0) as i32),
+                y: dom::Value::Constant((window_pos.1 - surface_pos.1) as i32 - barsize),
+            },
+        );
+        scene
+            .width()
+            .set(&titlebar.tb_bar, dom::Value::Constant(width));
+        scene
+            .height()
+            .set(&titlebar.tb_bar, dom::Value::Constant(barsize));
+
+        // The title takes whatever room the buttons leave
+        let buttons_width = barsize * titlebar.tb_buttons.len() as i32;
+        scene.width().set(
+            &titlebar.tb_title,
+            dom::Value::Constant((width - buttons_width).max(0)),
+        );
+        scene
+            .height()
+            .set(&titlebar.tb_title, dom::Value::Relative(1.0));
+        let title = atmos.a_window_title.get_clone(id).unwrap_or_default();
+        if title != titlebar.tb_title_text {
+            scene.set_text_regular(&titlebar.tb_title, &title);
+            scene
+                .text_font()
+                .set(&titlebar.tb_title, self.wm_menubar_font.clone());
+            titlebar.tb_title_text = title;
+        }
+
+        // Buttons are squares packed against the right edge
+        for (i, button) in titlebar.tb_buttons.iter().enumerate() {
+            scene.offset().set(
+                button,
+                dom::RelativeOffset {
+                    x: dom::Value::Constant(width - barsize * (i as i32 + 1)),
+                    y: dom::Value::Constant(0),
+                },
+            );
+            scene.width().set(button, dom::Value::Constant(barsize));
+            scene.height().set(button, dom::Value::Constant(barsize));
+        }
+
+        Ok(())
+    }
+
     /// Refresh the date and time string in the menubar
     ///
     /// This should be called every time change.
@@ -197,24 +461,76 @@ impl WindowManager {
         );
     }
 
+    /// Show the urgent and minimized windows in the menubar
+    ///
+    /// Urgent windows tried to take focus but were not allowed to.
+    /// Minimized windows are listed so the user knows clicking the
+    /// menubar will bring them back. They are listed by title after the
+    /// menubar name.
+    fn update_window_list(&mut self, atmos: &Atmosphere, scene: &mut dak::Scene) -> Result<()> {
+        let get_title = |id: &SurfaceId| match atmos.a_window_title.get_clone(id) {
+            Some(title) if !title.is_empty() => title,
+            _ => "Untitled".to_string(),
+        };
+        let urgent: Vec<String> = atmos.a_urgent_windows.iter().map(get_title).collect();
+        let minimized: Vec<String> = atmos
+            .get_toplevels()
+            .iter()
+            .filter(|id| atmos.a_minimized.get(id).map(|m| *m) == Some(true))
+            .map(get_title)
+            .collect();
+
+        let mut sections = Vec::new();
+        if !urgent.is_empty() {
+            sections.push(format!("Attention: {}", urgent.join(", ")));
+        }
+        if !minimized.is_empty() {
+            sections.push(format!("Minimized: {}", minimized.join(", ")));
+        }
+        let text = sections.join("  ");
+        if text == self.wm_window_list_text {
+            return Ok(());
+        }
+
+        for wmo in self.wm_outputs.iter() {
+            if !self.wm_window_list_text.is_empty() {
+                scene.remove_child_from_element(&wmo.wm_menubar, &wmo.wm_window_list)?;
+            }
+            if !text.is_empty() {
+                scene.set_text_regular(&wmo.wm_window_list, &text);
+                scene
+                    .text_font()
+                    .set(&wmo.wm_window_list, self.wm_menubar_font.clone());
+                scene.add_child_to_element(&wmo.wm_menubar, wmo.wm_window_list.clone());
+            }
+        }
+        self.wm_window_list_text = text;
+
+        Ok(())
+    }
+
     /// Reorganize our Outputs within the global VirtualOutput
     ///
     /// This is called after we have added or removed an output and
     /// we need to recalculate the positions of the remainders. It will
     /// set the Output positions and then calculate the new total VirtualOutput
-    /// size.
+    /// size. The new layout is published in atmos so layer surfaces can be
+    /// arranged on it.
     pub fn reposition_outputs(
         &mut self,
+        atmos: &mut Atmosphere,
         virtual_output: &mut dak::VirtualOutput,
         scene: &mut dak::Scene,
     ) -> Result<()> {
         let mut next_offset_x = 0;
         let mut new_extent = (0, 0);
+        let mut layouts = Vec::with_capacity(self.wm_outputs.len());
 
         // Position this new output as the rightmost.
         // We do this by summing up the widths of all outputs so far
         for output in self.wm_outputs.iter_mut() {
-            let output_size = output.wm_output.get_resolution();
+            // Outputs are placed in desktop units, not pixels
+            let output_size = output.wm_output.get_logical_resolution();
 
             // For now just align them all at the top
             output.wm_output.set_offset(next_offset_x, 0);
@@ -232,10 +548,17 @@ impl WindowManager {
                     dom::Value::Constant(0),
                 ),
             );
+            scene.offset().set(
+                &output.wm_lock_root,
+                dom::RelativeOffset::new(
+                    dom::Value::Constant(next_offset_x),
+                    dom::Value::Constant(0),
+                ),
+            );
 
             // Ensure the size of the output's root elements matches
             // the size of the Output itself.
-            let resolution = output.wm_output.get_resolution();
+            let resolution = output_size;
             scene.width().set(
                 &output.wm_background_root,
                 dom::Value::Constant(resolution.0 as i32),
@@ -252,6 +575,27 @@ impl WindowManager {
                 &output.wm_effects_root,
                 dom::Value::Constant(resolution.1 as i32),
             );
+            scene.width().set(
+                &output.wm_lock_root,
+                dom::Value::Constant(resolution.0 as i32),
+            );
+            scene.height().set(
+                &output.wm_lock_root,
+                dom::Value::Constant(resolution.1 as i32),
+            );
+
+            let rect = Rect::new(next_offset_x, 0, resolution.0 as i32, resolution.1 as i32);
+            let mut desc = output.wm_output.get_description();
+            desc.dd_name = output.wm_name.clone();
+            layouts.push(OutputLayout {
+                ol_id: output.wm_id,
+                ol_desc: desc,
+                ol_rect: rect,
+                ol_usable: rect,
+                ol_resolution: output.wm_output.get_resolution(),
+                ol_scale: output.wm_scale,
+                ol_capture_format: output.wm_output.get_capture_format(),
+            });
 
             next_offset_x += output_size.0 as i32;
             new_extent.0 += output_size.0;
@@ -261,9 +605,31 @@ impl WindowManager {
         // Resize our scene to accommodate it
         virtual_output.set_size(new_extent);
 
+        atmos.set_outputs(layouts);
+        atmos.arrange_layers();
+        atmos.arrange_lock_surfaces();
+
         Ok(())
     }
 
+    /// Get the DRM format modifiers every output can scan out
+    ///
+    /// Client buffers using these can be shown on any of our displays
+    /// without a conversion.
+    pub fn get_scanout_modifiers(&self) -> Vec<u64> {
+        let mut outputs = self.wm_outputs.iter();
+        let mut mods = match outputs.next() {
+            Some(output) => output.wm_output.get_supported_drm_scanout_modifiers(),
+            None => return Vec::new(),
+        };
+        for output in outputs {
+            let other = output.wm_output.get_supported_drm_scanout_modifiers();
+            mods.retain(|m| other.contains(m));
+        }
+
+        mods
+    }
+
     /// Add a new output to the system
     ///
     /// This adds the Output to our list and resizes the VirtualOutput
@@ -275,10 +641,30 @@ impl WindowManager {
         virtual_output: &mut dak::VirtualOutput,
         scene: &mut dak::Scene,
     ) -> Result<()> {
-        let output = dakota
+        let mut output = dakota
             .create_output_with_info(output_info, virtual_output)
             .context("Failed to create Dakota Output")?;
 
+        // Allow for scaling up the desktop on high resolution displays.
+        // Fractional values are allowed.
+        let scale = match std::env::var("CATEGORY5_OUTPUT_SCALE") {
+            Ok(value) => match value.parse::<f32>().ok().filter(|s| *s > 0.0) {
+                Some(scale) => scale,
+                None => {
+                    log::error!(
+                        "Ignoring CATEGORY5_OUTPUT_SCALE={:?}, it must be a positive number",
+                        value
+                    );
+                    1.0
+                }
+            },
+            Err(_) => 1.0,
+        };
+        // wp_fractional_scale sends the scale in 120ths, round to that so
+        // all clients see the same scale and logical size
+        let scale = ((scale * 120.0).round() / 120.0).max(1.0 / 120.0);
+        output.set_scale(scale);
+
         // First create the root element for this output
         // ------------------------------------------------------------------
         let background_root = scene.create_element().unwrap();
@@ -297,11 +683,25 @@ impl WindowManager {
         let effects_root = scene.create_element().unwrap();
         scene.add_child_to_element(&self.wm_effects_layer, effects_root.clone());
 
+        let lock_root = scene.create_element().unwrap();
+        scene.add_child_to_element(&self.wm_lock_layer, lock_root.clone());
+
         // Now create our menu bar across the top of the screen
         // ------------------------------------------------------------------
         let menubar = Self::create_menubar(scene, self.wm_menubar_font.clone());
         scene.add_child_to_element(&effects_root, menubar.clone());
 
+        // This will hold the titles of any urgent or minimized windows
+        let window_list = scene.create_element().unwrap();
+        scene.height().set(&window_list, dom::Value::Relative(1.0));
+        if !self.wm_window_list_text.is_empty() {
+            scene.set_text_regular(&window_list, &self.wm_window_list_text);
+            scene
+                .text_font()
+                .set(&window_list, self.wm_menubar_font.clone());
+            scene.add_child_to_element(&menubar, window_list.clone());
+        }
+
         // Add a text region to display our date and time
         // ------------------------------------------------------------------
         let datetime = scene.create_element().unwrap();
@@ -313,13 +713,33 @@ impl WindowManager {
             },
         );
 
+        // Headless and windowed backends may give every output the
+        // same name, so tack on our id in that case
+        let id = self.wm_next_output_id;
+        self.wm_next_output_id += 1;
+        let mut name = output.get_description().dd_name;
+        if self.wm_outputs.iter().any(|o| o.wm_name == name) {
+            name = format!("{}-{}", name, id);
+        }
+
         // Now we can create our output tracking object and add it to our
         // output list
         self.wm_outputs.push(WMOutput {
             wm_output: output,
+            wm_id: id,
+            wm_name: name,
+            wm_scale: scale,
+            wm_menubar: menubar,
+            wm_window_list: window_list,
             wm_datetime: datetime,
             wm_background_root: background_root,
             wm_effects_root: effects_root,
+            wm_lock_root: lock_root,
+            wm_pending_feedback: Vec::new(),
+            wm_presentation_feedback: Vec::new(),
+            wm_screencopy_frames: Vec::new(),
+            wm_screencopy_pending: Vec::new(),
+            wm_lock_presented: false,
         });
 
         Ok(())
@@ -422,6 +842,42 @@ impl WindowManager {
             .height()
             .set(&effects_layer, dom::Value::Relative(1.0));
 
+        // Fullscreen windows go above everything but the cursor. Window
+        // positions are desktop coordinates, which start below the menubar,
+        // so shift this layer down to line windows up with their outputs.
+        // ------------------------------------------------------------------
+        let fullscreen_layer = scene.create_element().unwrap();
+        scene.offset().set(
+            &fullscreen_layer,
+            dom::RelativeOffset::new(
+                dom::Value::Constant(0),
+                dom::Value::Constant(DESKTOP_OFFSET),
+            ),
+        );
+        scene.add_child_to_element(&root, fullscreen_layer.clone());
+        scene
+            .width()
+            .set(&fullscreen_layer, dom::Value::Relative(1.0));
+        scene
+            .height()
+            .set(&fullscreen_layer, dom::Value::Relative(1.0));
+
+        // The lock layer is only attached to the root while the session
+        // is locked. The screen is black wherever there isn't a lock surface.
+        // ------------------------------------------------------------------
+        let lock_layer = scene.create_element().unwrap();
+        let lock_color = scene.create_resource().unwrap();
+        scene
+            .resource_color()
+            .set(&lock_color, dom::Color::new(0.0, 0.0, 0.0, 1.0));
+        scene.resource().set(&lock_layer, lock_color);
+        scene.offset().set(
+            &lock_layer,
+            dom::RelativeOffset::new(dom::Value::Constant(0), dom::Value::Constant(0)),
+        );
+        scene.width().set(&lock_layer, dom::Value::Relative(1.0));
+        scene.height().set(&lock_layer, dom::Value::Relative(1.0));
+
         // now add a cursor on top of this
         // ------------------------------------------------------------------
         let cursor_layer = scene.create_element().unwrap();
@@ -436,16 +892,41 @@ impl WindowManager {
         let cursor = WindowManager::get_default_cursor(scene);
         scene.add_child_to_element(&cursor_layer, cursor.clone());
 
+        // Colors for server side decorations
+        // ------------------------------------------------------------------
+        let titlebar_color = scene.create_resource().unwrap();
+        scene
+            .resource_color()
+            .set(&titlebar_color, dom::Color::new(0.085, 0.09, 0.088, 0.9));
+        let button_color = scene.create_resource().unwrap();
+        scene
+            .resource_color()
+            .set(&button_color, dom::Color::new(0.2, 0.21, 0.2, 1.0));
+        let close_button_color = scene.create_resource().unwrap();
+        scene
+            .resource_color()
+            .set(&close_button_color, dom::Color::new(0.6, 0.15, 0.12, 1.0));
+
         let mut ret = WindowManager {
             wm_outputs: Vec::with_capacity(1),
+            wm_next_output_id: 0,
             wm_scene_root: root,
             wm_app_layer: app_layer,
             wm_background_layer: background_layer,
             wm_effects_layer: effects_layer,
+            wm_fullscreen_layer: fullscreen_layer,
+            wm_lock_layer: lock_layer,
             wm_cursor_layer: cursor_layer,
             wm_cursor: Some(cursor.clone()),
             wm_default_cursor: cursor,
+            wm_dnd_icon: None,
+            wm_titlebars: scene.get_ecs_instance().add_component(),
+            wm_layer_parents: scene.get_ecs_instance().add_component(),
+            wm_titlebar_color: titlebar_color,
+            wm_button_color: button_color,
+            wm_close_button_color: close_button_color,
             wm_menubar_font: menubar_font,
+            wm_window_list_text: String::new(),
             wm_atmos_ids: Vec::new(),
             #[cfg(feature = "renderdoc")]
             wm_renderdoc: doc,
@@ -476,12 +957,14 @@ impl WindowManager {
             }
         }
 
-        ret.reposition_outputs(virtual_output, scene)
+        ret.reposition_outputs(atmos, virtual_output, scene)
             .context("Failed to position Outputs")?;
 
         if ret.wm_outputs.len() == 0 {
             return Err(anyhow!("Could not find an Output to present to"));
         }
+        // The output scale changes how much of the desktop is visible
+        atmos.set_resolution(ret.wm_outputs[0].wm_output.get_logical_resolution());
 
         if let Some(drm_dev) = ret.wm_outputs[0].wm_output.get_drm_dev() {
             atmos.set_drm_dev(drm_dev);
@@ -530,8 +1013,15 @@ impl WindowManager {
     ) -> Result<()> {
         log::debug!("Closing window {:?}", id);
 
+        // Layer surfaces live in one of the output's root elements
+        if let Some(parent) = self.wm_layer_parents.take(id) {
+            scene.remove_child_from_element(&parent, id)?;
+            return Ok(());
+        }
+
         // remove this surface in case it is a toplevel window
         scene.remove_child_from_element(&self.wm_app_layer, id)?;
+        scene.remove_child_from_element(&self.wm_fullscreen_layer, id)?;
         // If this is a subsurface, remove it from its parent
         if let Some(parent) = atmos.a_parent_window.get_clone(id) {
             scene.remove_child_from_element(&parent, id)?;
@@ -559,22 +1049,184 @@ impl WindowManager {
         };
 
         // Move this surface to the front child of the window parent
+        let layer = self.get_window_layer(atmos, &root);
         scene
-            .move_child_to_front(&self.wm_app_layer, &root)
+            .move_child_to_front(&layer, &root)
             .context(format!("Moving window {:?} to the front", win))?;
 
         Ok(())
     }
 
+    /// Get the element a toplevel window is drawn in
+    ///
+    /// This is the fullscreen layer for fullscreen windows, and the app
+    /// layer for everything else.
+    fn get_window_layer(&self, atmos: &Atmosphere, id: &SurfaceId) -> DakotaId {
+        match atmos.a_fullscreen.get(id).map(|f| *f) == Some(true) {
+            true => self.wm_fullscreen_layer.clone(),
+            false => self.wm_app_layer.clone(),
+        }
+    }
+
     /// Add a new toplevel surface
     ///
     /// This maps a new toplevel surface and places it in the desktop. This
     /// is where the scene element is added to the desktop as a child.
-    fn new_toplevel(&mut self, scene: &mut dak::Scene, surf: &SurfaceId) -> Result<()> {
+    fn new_toplevel(
+        &mut self,
+        atmos: &Atmosphere,
+        scene: &mut dak::Scene,
+        surf: &SurfaceId,
+    ) -> Result<()> {
         // We might have not added this element to the desktop, moving to front
         // as part of focus is one of the first things that happens when a
         // new window is created
-        scene.add_child_to_element(&self.wm_app_layer, surf.clone());
+        let layer = self.get_window_layer(atmos, surf);
+        scene.add_child_to_element(&layer, surf.clone());
+
+        Ok(())
+    }
+
+    /// Hide a minimized window
+    ///
+    /// The element is removed from the desktop, and will be added back
+    /// by `new_toplevel` when the window is restored.
+    fn minimize_window(&mut self, scene: &mut dak::Scene, surf: &SurfaceId) -> Result<()> {
+        scene.remove_child_from_element(&self.wm_app_layer, surf)?;
+        scene.remove_child_from_element(&self.wm_fullscreen_layer, surf)?;
+
+        Ok(())
+    }
+
+    /// Move a window between the app and fullscreen layers
+    ///
+    /// Minimized windows aren't in either layer. They are placed in the
+    /// right one by `new_toplevel` when they are restored.
+    fn set_fullscreen(
+        &mut self,
+        atmos: &Atmosphere,
+        scene: &mut dak::Scene,
+        id: &SurfaceId,
+        fullscreen: bool,
+    ) -> Result<()> {
+        if atmos.a_minimized.get(id).map(|m| *m) == Some(true) {
+            return Ok(());
+        }
+
+        let (from, to) = match fullscreen {
+            true => (&self.wm_app_layer, &self.wm_fullscreen_layer),
+            false => (&self.wm_fullscreen_layer, &self.wm_app_layer),
+        };
+        scene.remove_child_from_element(from, id)?;
+        scene.add_child_to_element(to, id.clone());
+
+        Ok(())
+    }
+
+    /// Place a layer shell surface in one of an output's root elements
+    ///
+    /// Background and bottom layer surfaces go in the background root,
+    /// behind the app windows. Top and overlay surfaces go in the effects
+    /// root above them. This is also called when a surface changes layers.
+    fn set_layer_surface(
+        &mut self,
+        scene: &mut dak::Scene,
+        id: &SurfaceId,
+        layer: Layer,
+        output: usize,
+    ) -> Result<()> {
+        if let Some(old) = self.wm_layer_parents.take(id) {
+            scene.remove_child_from_element(&old, id)?;
+        }
+
+        let wm_output = self
+            .wm_outputs
+            .get(output)
+            .context("Layer surface placed on a nonexistent output")?;
+        let parent = match layer {
+            Layer::Background | Layer::Bottom => wm_output.wm_background_root.clone(),
+            _ => wm_output.wm_effects_root.clone(),
+        };
+
+        scene.add_child_to_element(&parent, id.clone());
+        // Keep bottom surfaces above the background, and overlays above
+        // everything else on the output
+        match layer {
+            Layer::Bottom | Layer::Overlay => scene
+                .move_child_to_front(&parent, id)
+                .context("Moving layer surface to the front")?,
+            _ => {}
+        }
+        self.wm_layer_parents.set(id, parent);
+
+        Ok(())
+    }
+
+    /// Hide the desktop while the session is locked
+    ///
+    /// Everything but the cursor is taken out of the scene and replaced
+    /// by the lock layer.
+    fn lock_session(&mut self, scene: &mut dak::Scene) -> Result<()> {
+        for layer in [
+            &self.wm_background_layer,
+            &self.wm_app_layer,
+            &self.wm_effects_layer,
+            &self.wm_fullscreen_layer,
+        ]
+        .iter()
+        {
+            scene.remove_child_from_element(&self.wm_scene_root, layer)?;
+        }
+        scene.add_child_to_element(&self.wm_scene_root, self.wm_lock_layer.clone());
+        // The desktop is still on screen until the next frame is presented
+        for output in self.wm_outputs.iter_mut() {
+            output.wm_lock_presented = false;
+        }
+        scene
+            .move_child_to_front(&self.wm_scene_root, &self.wm_cursor_layer)
+            .context("Moving cursor layer to the front")?;
+
+        Ok(())
+    }
+
+    /// Show the desktop again once the session is unlocked
+    fn unlock_session(&mut self, scene: &mut dak::Scene) -> Result<()> {
+        scene.remove_child_from_element(&self.wm_scene_root, &self.wm_lock_layer)?;
+        for layer in [
+            &self.wm_background_layer,
+            &self.wm_app_layer,
+            &self.wm_effects_layer,
+            &self.wm_fullscreen_layer,
+        ]
+        .iter()
+        {
+            scene.add_child_to_element(&self.wm_scene_root, (*layer).clone());
+        }
+        scene
+            .move_child_to_front(&self.wm_scene_root, &self.wm_cursor_layer)
+            .context("Moving cursor layer to the front")?;
+
+        Ok(())
+    }
+
+    /// Place a lock surface over its output
+    fn set_lock_surface(
+        &mut self,
+        scene: &mut dak::Scene,
+        id: &SurfaceId,
+        output: usize,
+    ) -> Result<()> {
+        let parent = self
+            .wm_outputs
+            .get(output)
+            .context("Lock surface placed on a nonexistent output")?
+            .wm_lock_root
+            .clone();
+
+        scene.add_child_to_element(&parent, id.clone());
+        // Reuse the layer surface tracking so that the surface is removed
+        // from the lock root when it is closed
+        self.wm_layer_parents.set(id, parent);
 
         Ok(())
     }
@@ -632,6 +1284,23 @@ impl WindowManager {
         Ok(())
     }
 
+    /// Update the drag and drop icon
+    ///
+    /// The icon surface is placed on the cursor layer for the duration of
+    /// the drag. Passing None removes it.
+    fn set_dnd_icon(&mut self, scene: &mut dak::Scene, surf: Option<SurfaceId>) -> Result<()> {
+        if let Some(old) = self.wm_dnd_icon.take() {
+            scene.remove_child_from_element(&self.wm_cursor_layer, &old)?;
+        }
+
+        if let Some(surf) = surf.as_ref() {
+            scene.add_child_to_element(&self.wm_cursor_layer, surf.clone());
+        }
+        self.wm_dnd_icon = surf;
+
+        Ok(())
+    }
+
     /// Adds a new subsurface to the parent.
     ///
     /// The new subsurface will be moved to the top of the subsurface
@@ -708,6 +1377,9 @@ impl WindowManager {
             Task::new_subsurface { id, parent } => self
                 .new_subsurface(scene, id, parent)
                 .context("Task: new_subsurface"),
+            Task::remove_subsurface { id, parent } => scene
+                .remove_child_from_element(parent, id)
+                .context("Task: remove_subsurface"),
             Task::place_subsurface_above { id, other } => self
                 .subsurf_place_above(atmos, scene, id, other)
                 .context("Task: place_subsurface_above"),
@@ -717,13 +1389,32 @@ impl WindowManager {
             Task::close_window(id) => self
                 .close_window(atmos, scene, id)
                 .context("Task: close_window"),
-            Task::new_toplevel(id) => self.new_toplevel(scene, id).context("Task: new_toplevel"),
+            Task::new_toplevel(id) => self
+                .new_toplevel(atmos, scene, id)
+                .context("Task: new_toplevel"),
+            Task::minimize_window(id) => self
+                .minimize_window(scene, id)
+                .context("Task: minimize_window"),
+            Task::set_fullscreen { id, fullscreen } => self
+                .set_fullscreen(atmos, scene, id, *fullscreen)
+                .context("Task: set_fullscreen"),
             Task::set_cursor { id } => self
                 .set_cursor(atmos, scene, id.clone())
                 .context("Task: set_cursor"),
             Task::reset_cursor => self
                 .reset_cursor(atmos, scene)
                 .context("Task: reset_cursor"),
+            Task::set_dnd_icon { id } => self
+                .set_dnd_icon(scene, id.clone())
+                .context("Task: set_dnd_icon"),
+            Task::set_layer_surface { id, layer, output } => self
+                .set_layer_surface(scene, id, *layer, *output)
+                .context("Task: set_layer_surface"),
+            Task::lock_session => self.lock_session(scene).context("Task: lock_session"),
+            Task::unlock_session => self.unlock_session(scene).context("Task: unlock_session"),
+            Task::set_lock_surface { id, output } => self
+                .set_lock_surface(scene, id, *output)
+                .context("Task: set_lock_surface"),
         };
 
         match err {
@@ -760,6 +1451,44 @@ impl WindowManager {
                 },
             );
         }
+
+        // The drag icon is offset from the cursor by any attach offsets
+        // the client has given it.
+        if let Some(icon) = self.wm_dnd_icon.as_ref() {
+            // The icon may not have committed a buffer yet, or may have
+            // been destroyed during the drag
+            let icon_pos = atmos.a_surface_pos.get(icon).map(|p| *p);
+            let icon_size = atmos.a_surface_size.get(icon).map(|s| *s);
+            if let (Some(icon_pos), Some(icon_size)) = (icon_pos, icon_size) {
+                scene.offset().set(
+                    icon,
+                    dom::RelativeOffset {
+                        x: dom::Value::Constant(cursor_x as i32 + icon_pos.0 as i32),
+                        y: dom::Value::Constant(cursor_y as i32 + icon_pos.1 as i32),
+                    },
+                );
+                scene
+                    .width()
+                    .set(icon, dom::Value::Constant(icon_size.0 as i32));
+                scene
+                    .height()
+                    .set(icon, dom::Value::Constant(icon_size.1 as i32));
+                atmos.send_frame_callbacks_for_surf(icon);
+                Self::queue_presentation_feedback(&mut self.wm_outputs, atmos, icon);
+            }
+        }
+        // ----------------------------------------------------------------
+
+        // Update the server side decorations of our toplevel windows
+        // ----------------------------------------------------------------
+        for id in atmos.visible_windows() {
+            if let Err(e) = self.update_titlebar(atmos, scene, &id) {
+                log::error!("Could not update titlebar for {:?}: {:?}", id, e);
+            }
+        }
+        if let Err(e) = self.update_window_list(atmos, scene) {
+            log::error!("Could not update the window list in the menubar: {:?}", e);
+        }
         // ----------------------------------------------------------------
 
         // Draw all of our windows on the desktop
@@ -787,7 +1516,15 @@ impl WindowManager {
             // Now render the windows
             // get parameters
             // ----------------------------------------------------------------
-            let surface_pos = *atmos.a_surface_pos.get(id).unwrap();
+            // Layer and lock surfaces are drawn relative to their output
+            // instead of the desktop
+            let surface_pos = match atmos
+                .get_layer_surface_output_pos(id)
+                .or_else(|| atmos.get_lock_surface_output_pos(id))
+            {
+                Some(pos) => pos,
+                None => *atmos.a_surface_pos.get(id).unwrap(),
+            };
             let surface_size = *atmos.a_surface_size.get(id).unwrap();
             log::debug!(
                 "placing scene element at {:?} with size {:?}",
@@ -809,10 +1546,49 @@ impl WindowManager {
             scene
                 .height()
                 .set(id, dom::Value::Constant(surface_size.1 as i32));
+            if let Some(transform) = atmos.a_buffer_transform.get_clone(id) {
+                scene.transform().set(id, transform);
+            }
+            if let Some(rect) = atmos.a_source_rect.get_clone(id) {
+                scene.source_rect().set(id, rect);
+            }
             // ----------------------------------------------------------------
 
             // Send any pending frame callbacks
             atmos.send_frame_callbacks_for_surf(id);
+            Self::queue_presentation_feedback(&mut self.wm_outputs, atmos, id);
+        }
+    }
+
+    /// Hand a surface's presentation feedback to the output it is drawn on
+    ///
+    /// Surfaces spanning multiple outputs are reported by the output that
+    /// their window is placed on.
+    fn queue_presentation_feedback(
+        outputs: &mut [WMOutput],
+        atmos: &mut Atmosphere,
+        id: &SurfaceId,
+    ) {
+        let feedback = atmos.take_presentation_feedback(id);
+        if feedback.len() == 0 {
+            return;
+        }
+
+        let root = atmos.a_root_window.get_clone(id).unwrap_or(id.clone());
+        let output_id = atmos.get_window_output(&root).map(|o| o.ol_id);
+        let output = match outputs.iter().position(|o| Some(o.wm_id) == output_id) {
+            Some(i) => outputs.get_mut(i),
+            None => outputs.first_mut(),
+        };
+
+        match output {
+            Some(output) => output.wm_pending_feedback.extend(feedback),
+            // There is nowhere to show this surface
+            None => {
+                for fb in feedback {
+                    fb.discarded();
+                }
+            }
         }
     }
 
@@ -836,6 +1612,18 @@ impl WindowManager {
             self.process_task(atmos, scene, &task);
         }
 
+        // Hand new screencopy frames to the outputs they copy
+        for frame in atmos.a_screencopy_frames.drain(..) {
+            let (output_id, _, _) = screencopy::get_frame_source(&frame);
+            match self.wm_outputs.iter_mut().find(|o| o.wm_id == output_id) {
+                Some(output) => output.wm_screencopy_frames.push(frame),
+                None => screencopy::send_failed(&frame),
+            }
+        }
+        for output in self.wm_outputs.iter_mut() {
+            output.finish_screencopies();
+        }
+
         // If nothing has changed then we can exit
         //
         // TODO: track this per-output to prevent excess redraws
@@ -855,14 +1643,66 @@ impl WindowManager {
         // Have Dakota redraw the scene.
         // For now do this for all outputs
         // TODO: per-output draw tracking
+        let cursor_layer = self.wm_cursor_layer.clone();
         for output in self.wm_outputs.iter_mut() {
+            let prev_frame = output.wm_output.get_present_count();
+
+            let wm_output = &output.wm_output;
+            let captures: Vec<_> = output
+                .wm_screencopy_frames
+                .drain(..)
+                .filter_map(|frame| {
+                    screencopy::begin_copy(&frame, wm_output).map(|image| (frame, image))
+                })
+                .collect();
+            let copies: Vec<_> = captures
+                .iter()
+                .map(|(frame, image)| {
+                    let (_, region, overlay_cursor) = screencopy::get_frame_source(frame);
+                    dak::FrameCopy {
+                        fc_image: image,
+                        fc_region: region,
+                        // Copying before the cursor layer is drawn leaves
+                        // out the cursor and the drag and drop icon
+                        fc_before: match overlay_cursor {
+                            true => None,
+                            false => Some(cursor_layer.clone()),
+                        },
+                    }
+                })
+                .collect();
+
             output
                 .wm_output
-                .redraw(virtual_output, scene)
+                .redraw_with_copies(virtual_output, scene, &copies)
                 .context("Redrawing WM Output")?;
+            output.update_presentation_feedback(prev_frame);
+            output.wm_screencopy_pending.extend(captures);
+            if atmos.is_locked() && output.wm_output.get_present_count() != prev_frame {
+                output.wm_lock_presented = true;
+            }
         }
 
         atmos.clear_changed();
+        // The locker can't be told the session is locked until the desktop
+        // is off of every screen, so keep drawing until it is
+        if atmos.is_locked() {
+            match self.wm_outputs.iter().all(|o| o.wm_lock_presented) {
+                true => atmos.set_session_lock_presented(),
+                false => atmos.mark_changed(),
+            }
+        }
+        // Some displays only report that a frame was shown once we
+        // have acquired the next one. Keep drawing until every client
+        // has heard back about its content. The same goes for copies
+        // which have to be checked for again.
+        if self
+            .wm_outputs
+            .iter()
+            .any(|o| o.wm_presentation_feedback.len() > 0 || o.wm_screencopy_pending.len() > 0)
+        {
+            atmos.mark_changed();
+        }
         log::debug!("_____________________________ FRAME END");
 
         atmos.print_surface_tree();
@@ -900,14 +1740,11 @@ impl WindowManager {
                     }
                     // Our output surface is out of date, reallocate it
                     dak::OutputEvent::Resized => {
-                        let res = self.wm_outputs[i].wm_output.get_resolution();
+                        let res = self.wm_outputs[i].wm_output.get_logical_resolution();
                         {
                             atmos.mark_changed();
                             atmos.set_resolution(res);
                         }
-                        // TODO: implement after refactoring WindowManager on top
-                        //self.em_climate.send_all_geometry();
-
                         // First handle the resize on this output
                         self.wm_outputs[i]
                             .wm_output
@@ -915,7 +1752,7 @@ impl WindowManager {
                             .expect("Failed to resize output");
 
                         // Recalculate Output layout
-                        self.reposition_outputs(virtual_output, scene)
+                        self.reposition_outputs(atmos, virtual_output, scene)
                             .context("Failed to position Outputs")?;
 
                         // Break here to avoid redrawing before we have recalculated
@@ -934,8 +1771,15 @@ impl WindowManager {
                         )?;
 
                         // Remove this output and reposition the remainders
+                        self.wm_outputs[i].discard_presentation_feedback();
+                        for frame in self.wm_outputs[i].wm_screencopy_frames.drain(..) {
+                            screencopy::send_failed(&frame);
+                        }
+                        for (frame, _) in self.wm_outputs[i].wm_screencopy_pending.drain(..) {
+                            screencopy::send_failed(&frame);
+                        }
                         self.wm_outputs.remove(i);
-                        self.reposition_outputs(virtual_output, scene)
+                        self.reposition_outputs(atmos, virtual_output, scene)
                             .context("Failed to position Outputs")?;
 
                         needs_render = true;
@@ -5,6 +5,7 @@
 // Austin Shafer - 2020
 #![allow(dead_code)]
 use crate::category5::atmosphere::SurfaceId;
+use crate::category5::ways::protocol::wlr_layer_shell::zwlr_layer_shell_v1::Layer;
 
 // Tell wm the desktop background
 //
@@ -27,9 +28,45 @@ pub enum Task {
     close_window(SurfaceId),
     move_to_front(SurfaceId),
     new_toplevel(SurfaceId),
-    new_subsurface { id: SurfaceId, parent: SurfaceId },
-    place_subsurface_above { id: SurfaceId, other: SurfaceId },
-    place_subsurface_below { id: SurfaceId, other: SurfaceId },
-    set_cursor { id: Option<SurfaceId> },
+    minimize_window(SurfaceId),
+    /// Move a window to or from the fullscreen layer
+    set_fullscreen {
+        id: SurfaceId,
+        fullscreen: bool,
+    },
+    new_subsurface {
+        id: SurfaceId,
+        parent: SurfaceId,
+    },
+    remove_subsurface {
+        id: SurfaceId,
+        parent: SurfaceId,
+    },
+    place_subsurface_above {
+        id: SurfaceId,
+        other: SurfaceId,
+    },
+    place_subsurface_below {
+        id: SurfaceId,
+        other: SurfaceId,
+    },
+    set_cursor {
+        id: Option<SurfaceId>,
+    },
     reset_cursor,
+    set_dnd_icon {
+        id: Option<SurfaceId>,
+    },
+    set_layer_surface {
+        id: SurfaceId,
+        layer: Layer,
+        output: usize,
+    },
+    /// Hide the desktop and show only the lock surfaces
+    lock_session,
+    unlock_session,
+    set_lock_surface {
+        id: SurfaceId,
+        output: usize,
+    },
 }
@@ -3,10 +3,22 @@
 // Austin Shafer - 2020
 extern crate wayland_server as ws;
 use ws::protocol::{
-    wl_data_device as wlddv, wl_data_device_manager as wlddm, wl_data_source as wlds,
+    wl_data_device as wlddv, wl_data_device_manager as wlddm, wl_data_offer as wldo,
+    wl_data_source as wlds,
 };
+use ws::Resource;
 
+use super::role::Role;
+use super::seat::Seat;
+use super::surface::Surface;
+use crate::category5::atmosphere::{Atmosphere, ClientId, SurfaceId};
+use crate::category5::input::Input;
+use crate::category5::vkcomp::wm::task::Task;
 use crate::category5::Climate;
+use utils::{log, timing::*};
+
+use std::os::fd::AsFd;
+use std::sync::{Arc, Mutex};
 
 #[allow(unused_variables)]
 impl ws::GlobalDispatch<wlddm::WlDataDeviceManager, ()> for Climate {
@@ -36,10 +48,27 @@ impl ws::Dispatch<wlddm::WlDataDeviceManager, ()> for Climate {
     ) {
         match request {
             wlddm::Request::CreateDataSource { id } => {
-                data_init.init(id, ());
+                data_init.init(id, Arc::new(Mutex::new(DataSource::new())));
             }
             wlddm::Request::GetDataDevice { id, seat } => {
-                data_init.init(id, ());
+                let atmos = state.c_atmos.lock().unwrap();
+                let seat_cell = seat
+                    .data::<Arc<Mutex<Seat>>>()
+                    .expect("wl_seat did not have a Seat attached")
+                    .clone();
+
+                let device = data_init.init(id, seat_cell.clone());
+                let owner = {
+                    let mut s = seat_cell.lock().unwrap();
+                    s.add_data_device(&seat, device);
+                    s.s_id.clone()
+                };
+
+                // If this client is already in focus then it missed the
+                // selection event, so send it now
+                if atmos.get_client_in_focus() == Some(owner.clone()) {
+                    send_selection(&atmos, &owner);
+                }
             }
             _ => {}
         };
@@ -55,47 +84,572 @@ impl ws::Dispatch<wlddm::WlDataDeviceManager, ()> for Climate {
 }
 
 #[allow(unused_variables)]
-impl ws::Dispatch<wlddv::WlDataDevice, ()> for Climate {
+impl ws::Dispatch<wlddv::WlDataDevice, Arc<Mutex<Seat>>> for Climate {
     fn request(
         state: &mut Self,
         client: &ws::Client,
         resource: &wlddv::WlDataDevice,
         request: wlddv::Request,
-        data: &(),
+        data: &Arc<Mutex<Seat>>,
         dhandle: &ws::DisplayHandle,
         data_init: &mut ws::DataInit<'_, Self>,
     ) {
-        // TODO
+        match request {
+            wlddv::Request::SetSelection { source, serial } => {
+                // Only the client the user is interacting with may
+                // replace the clipboard, otherwise background clients
+                // could overwrite it whenever they like.
+                if !data.lock().unwrap().is_recent_input_serial(serial) {
+                    log::debug!("Ignoring set_selection with stale serial {}", serial);
+                    if let Some(source) = source {
+                        source.cancelled();
+                    }
+                    return;
+                }
+
+                let mut atmos = state.c_atmos.lock().unwrap();
+                set_selection(&mut atmos, source);
+            }
+            wlddv::Request::StartDrag {
+                source,
+                origin,
+                icon,
+                serial,
+            } => {
+                let mut atmos = state.c_atmos.lock().unwrap();
+                let owner = data.lock().unwrap().s_id.clone();
+                let origin_id = origin
+                    .data::<Arc<Mutex<Surface>>>()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .s_id
+                    .clone();
+
+                // The drag has to be started from an implicit grab on the origin,
+                // which means the pointer must be over it. Otherwise ignore this.
+                if atmos.get_dnd().is_some()
+                    || atmos.get_pointer_focus().as_ref() != Some(&origin_id)
+                {
+                    log::debug!("Ignoring start_drag from {:?}", origin_id);
+                    return;
+                }
+
+                let icon_id = match icon {
+                    Some(icon) => {
+                        let mut surf = icon.data::<Arc<Mutex<Surface>>>().unwrap().lock().unwrap();
+                        if match surf.s_role {
+                            None | Some(Role::dnd_icon) => false,
+                            _ => true,
+                        } {
+                            resource.post_error(
+                                wlddv::Error::Role,
+                                "Surface is already assigned a non-dnd_icon role",
+                            );
+                            return;
+                        }
+                        surf.s_role = Some(Role::dnd_icon);
+
+                        Some(surf.s_id.clone())
+                    }
+                    None => None,
+                };
+
+                start_drag(&mut atmos, owner, source, icon_id);
+            }
+            wlddv::Request::Release => {}
+            _ => unimplemented!(),
+        }
     }
 
     fn destroyed(
         state: &mut Self,
         _client: ws::backend::ClientId,
-        _resource: &wlddv::WlDataDevice,
-        data: &(),
+        resource: &wlddv::WlDataDevice,
+        data: &Arc<Mutex<Seat>>,
     ) {
+        data.lock().unwrap().remove_data_device(resource);
+    }
+}
+
+/// Private state for a wl_data_source
+///
+/// This tracks the mime types that the source client has said
+/// it can provide.
+pub struct DataSource {
+    pub ds_mime_types: Vec<String>,
+    /// The drag and drop actions supported by the source
+    pub ds_dnd_actions: wlddm::DndAction,
+}
+
+impl DataSource {
+    fn new() -> Self {
+        Self {
+            ds_mime_types: Vec::new(),
+            ds_dnd_actions: wlddm::DndAction::empty(),
+        }
     }
 }
 
 #[allow(unused_variables)]
-impl ws::Dispatch<wlds::WlDataSource, ()> for Climate {
+impl ws::Dispatch<wlds::WlDataSource, Arc<Mutex<DataSource>>> for Climate {
     fn request(
         state: &mut Self,
         client: &ws::Client,
         resource: &wlds::WlDataSource,
         request: wlds::Request,
-        data: &(),
+        data: &Arc<Mutex<DataSource>>,
         dhandle: &ws::DisplayHandle,
         data_init: &mut ws::DataInit<'_, Self>,
     ) {
-        // TODO
+        match request {
+            wlds::Request::Offer { mime_type } => {
+                data.lock().unwrap().ds_mime_types.push(mime_type)
+            }
+            wlds::Request::SetActions { dnd_actions } => {
+                data.lock().unwrap().ds_dnd_actions = dnd_actions
+                    .into_result()
+                    .unwrap_or(wlddm::DndAction::empty())
+            }
+            wlds::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
     }
 
     fn destroyed(
         state: &mut Self,
         _client: ws::backend::ClientId,
-        _resource: &wlds::WlDataSource,
-        data: &(),
+        resource: &wlds::WlDataSource,
+        data: &Arc<Mutex<DataSource>>,
+    ) {
+        // If this source was the selection then the selection is now empty
+        let mut atmos = state.c_atmos.lock().unwrap();
+        if atmos.get_selection().as_ref() == Some(resource) {
+            atmos.set_selection(None);
+            if let Some(focus) = atmos.get_client_in_focus() {
+                send_selection(&atmos, &focus);
+            }
+        }
+
+        // A drag can't continue once its source is gone
+        if let Some(dnd) = atmos.get_dnd() {
+            if dnd.dnd_source.as_ref() == Some(resource) {
+                for device in dnd.dnd_devices.iter() {
+                    device.leave();
+                }
+                end_drag(&mut atmos);
+            }
+        }
+    }
+}
+
+/// Private state for a wl_data_offer
+///
+/// An offer is our representation of a data source handed to
+/// another client. Requests on it are forwarded to the source.
+pub struct DataOffer {
+    pub do_source: wlds::WlDataSource,
+    /// The mime type the destination accepted during drag and drop
+    pub do_accepted: Option<String>,
+    /// Actions the destination is willing to perform
+    pub do_dnd_actions: wlddm::DndAction,
+    pub do_preferred_action: wlddm::DndAction,
+    /// The action chosen from negotiating with the source
+    pub do_action: wlddm::DndAction,
+}
+
+impl DataOffer {
+    fn new(source: wlds::WlDataSource) -> Self {
+        Self {
+            do_source: source,
+            do_accepted: None,
+            do_dnd_actions: wlddm::DndAction::empty(),
+            do_preferred_action: wlddm::DndAction::None,
+            do_action: wlddm::DndAction::None,
+        }
+    }
+
+    /// Pick the drag and drop action to use
+    ///
+    /// The preferred action of the destination wins if the source supports
+    /// it. Otherwise we choose the first shared action in the order copy,
+    /// move, ask. Both sides are told if the result changed.
+    fn negotiate_action(&mut self, offer: &wldo::WlDataOffer) {
+        let source_actions = match self.do_source.data::<Arc<Mutex<DataSource>>>() {
+            Some(ds) => ds.lock().unwrap().ds_dnd_actions,
+            None => wlddm::DndAction::empty(),
+        };
+        let shared = source_actions & self.do_dnd_actions;
+
+        let action =
+            if !self.do_preferred_action.is_empty() && shared.contains(self.do_preferred_action) {
+                self.do_preferred_action
+            } else {
+                [
+                    wlddm::DndAction::Copy,
+                    wlddm::DndAction::Move,
+                    wlddm::DndAction::Ask,
+                ]
+                .into_iter()
+                .find(|a| shared.contains(*a))
+                .unwrap_or(wlddm::DndAction::None)
+            };
+
+        if action == self.do_action {
+            return;
+        }
+        self.do_action = action;
+
+        if offer.version() >= 3 {
+            offer.action(action);
+        }
+        if self.do_source.is_alive() && self.do_source.version() >= 3 {
+            self.do_source.action(action);
+        }
+    }
+
+    /// Will the destination take the data if it is dropped now
+    ///
+    /// Before version 3 there is no action negotiation, so accepting
+    /// a mime type is enough.
+    fn will_accept_drop(&self, offer: &wldo::WlDataOffer) -> bool {
+        self.do_accepted.is_some()
+            && (offer.version() < 3 || self.do_action != wlddm::DndAction::None)
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<wldo::WlDataOffer, Arc<Mutex<DataOffer>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wldo::WlDataOffer,
+        request: wldo::Request,
+        data: &Arc<Mutex<DataOffer>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut offer = data.lock().unwrap();
+
+        match request {
+            wldo::Request::Accept { serial, mime_type } => {
+                if offer.do_source.is_alive() {
+                    offer.do_source.target(mime_type.clone());
+                }
+                offer.do_accepted = mime_type;
+            }
+            wldo::Request::Receive { mime_type, fd } => {
+                // Hand the fd to the source client so it can write the
+                // contents directly to the receiver. Our copy of the fd
+                // is closed when it goes out of scope.
+                if offer.do_source.is_alive() {
+                    offer.do_source.send(mime_type, fd.as_fd());
+                }
+            }
+            wldo::Request::Finish => {
+                if offer.do_source.is_alive() && offer.do_source.version() >= 3 {
+                    offer.do_source.dnd_finished();
+                }
+            }
+            wldo::Request::SetActions {
+                dnd_actions,
+                preferred_action,
+            } => {
+                offer.do_dnd_actions = dnd_actions
+                    .into_result()
+                    .unwrap_or(wlddm::DndAction::empty());
+                offer.do_preferred_action = preferred_action
+                    .into_result()
+                    .unwrap_or(wlddm::DndAction::None);
+                offer.negotiate_action(resource);
+            }
+            wldo::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &wldo::WlDataOffer,
+        data: &Arc<Mutex<DataOffer>>,
     ) {
     }
 }
+
+/// Replace the current selection
+///
+/// The previous source is cancelled, since it will never be used
+/// again. The client in focus is then told about the new selection.
+pub fn set_selection(atmos: &mut Atmosphere, source: Option<wlds::WlDataSource>) {
+    let old = atmos.get_selection();
+    if old == source {
+        return;
+    }
+
+    if let Some(old) = old {
+        if old.is_alive() {
+            old.cancelled();
+        }
+    }
+    atmos.set_selection(source);
+
+    if let Some(focus) = atmos.get_client_in_focus() {
+        send_selection(atmos, &focus);
+    }
+}
+
+/// Create a wl_data_offer for `source` on the client that owns `device`
+///
+/// This sends the data_offer event introducing the new object, followed by
+/// the mime types that the source supports.
+fn create_offer(
+    device: &wlddv::WlDataDevice,
+    source: &wlds::WlDataSource,
+) -> Option<wldo::WlDataOffer> {
+    let client = device.client()?;
+    let handle = ws::DisplayHandle::from(device.handle().upgrade()?);
+
+    let offer = client
+        .create_resource::<wldo::WlDataOffer, Arc<Mutex<DataOffer>>, Climate>(
+            &handle,
+            device.version(),
+            Arc::new(Mutex::new(DataOffer::new(source.clone()))),
+        )
+        .ok()?;
+    device.data_offer(&offer);
+
+    if let Some(ds) = source.data::<Arc<Mutex<DataSource>>>() {
+        for mime in ds.lock().unwrap().ds_mime_types.iter() {
+            offer.offer(mime.clone());
+        }
+    }
+
+    Some(offer)
+}
+
+/// Advertise the current selection to a client
+///
+/// This should be called before the client receives keyboard focus. Every
+/// data device of the client gets a fresh offer. If there is no selection
+/// the client is told that it was cleared.
+pub fn send_selection(atmos: &Atmosphere, client: &ClientId) {
+    let seat = match atmos.get_seat_from_client_id(client) {
+        Some(s) => s,
+        None => return,
+    };
+    let source = atmos.get_selection().filter(|s| s.is_alive());
+
+    let seat = seat.lock().unwrap();
+    for si in seat.s_proxies.iter() {
+        for device in si.si_data_devices.iter() {
+            let offer = source.as_ref().and_then(|s| create_offer(device, s));
+            device.selection(offer.as_ref());
+        }
+    }
+}
+
+/// An in-progress drag and drop session
+///
+/// This lives in the Atmosphere while a drag is happening. The pointer
+/// is grabbed for the duration, and input hands motion and button
+/// release to `dnd_motion` and `dnd_drop`.
+#[derive(Clone)]
+pub struct DragAndDrop {
+    /// The source of the data. A client may drag without one, in which
+    /// case only its own surfaces see the drag.
+    pub dnd_source: Option<wlds::WlDataSource>,
+    /// The client which started the drag
+    pub dnd_owner: ClientId,
+    /// Surface used as the drag icon
+    pub dnd_icon: Option<SurfaceId>,
+    /// The surface currently under the pointer
+    pub dnd_focus: Option<SurfaceId>,
+    /// Data devices which were sent the enter event for `dnd_focus`
+    pub dnd_devices: Vec<wlddv::WlDataDevice>,
+    /// The offers created for `dnd_devices`
+    pub dnd_offers: Vec<wldo::WlDataOffer>,
+}
+
+/// Begin a drag and drop session
+///
+/// This takes the pointer focus away from clients and shows the icon
+/// on the cursor layer. The surface under the pointer is entered
+/// immediately.
+pub fn start_drag(
+    atmos: &mut Atmosphere,
+    owner: ClientId,
+    source: Option<wlds::WlDataSource>,
+    icon: Option<SurfaceId>,
+) {
+    log::debug!("Starting drag and drop with icon {:?}", icon);
+
+    if let Some(id) = atmos.get_pointer_focus() {
+        Input::pointer_leave(atmos, &id);
+        atmos.set_pointer_focus(None);
+    }
+    atmos.add_wm_task(Task::set_dnd_icon { id: icon.clone() });
+
+    atmos.set_dnd(Some(DragAndDrop {
+        dnd_source: source,
+        dnd_owner: owner,
+        dnd_icon: icon,
+        dnd_focus: None,
+        dnd_devices: Vec::new(),
+        dnd_offers: Vec::new(),
+    }));
+    dnd_motion(atmos);
+}
+
+/// Send the enter event to the data devices of the surface `id`
+fn dnd_enter(atmos: &Atmosphere, dnd: &mut DragAndDrop, id: &SurfaceId) {
+    let owner = match atmos.a_owner.get_clone(id) {
+        Some(o) => o,
+        None => return,
+    };
+    // Without a source the drag is only visible to the client that started it
+    if dnd.dnd_source.is_none() && owner != dnd.dnd_owner {
+        return;
+    }
+    let surf = match atmos.get_wl_surface_from_id(id) {
+        Some(s) => s,
+        None => return,
+    };
+    let seat = match atmos.get_seat_from_client_id(&owner) {
+        Some(s) => s,
+        None => return,
+    };
+    let (cx, cy) = atmos.get_cursor_pos();
+    let (sx, sy) = atmos
+        .global_coords_to_surf(id, cx, cy)
+        .unwrap_or((0.0, 0.0));
+
+    let seat = seat.lock().unwrap();
+    for si in seat.s_proxies.iter() {
+        for device in si.si_data_devices.iter() {
+            let offer = match dnd.dnd_source.as_ref() {
+                Some(src) => match create_offer(device, src) {
+                    Some(offer) => {
+                        if offer.version() >= 3 {
+                            if let Some(ds) = src.data::<Arc<Mutex<DataSource>>>() {
+                                offer.source_actions(ds.lock().unwrap().ds_dnd_actions);
+                            }
+                        }
+                        Some(offer)
+                    }
+                    None => continue,
+                },
+                None => None,
+            };
+
+            device.enter(seat.s_serial, &surf, sx, sy, offer.as_ref());
+            dnd.dnd_devices.push(device.clone());
+            dnd.dnd_offers.extend(offer);
+        }
+    }
+}
+
+/// Update the drag after the pointer moved
+///
+/// If the pointer crossed onto a new surface then the old one is left
+/// and the new one is entered, otherwise a motion event is sent.
+pub fn dnd_motion(atmos: &mut Atmosphere) {
+    let mut dnd = match atmos.get_dnd() {
+        Some(d) => d,
+        None => return,
+    };
+    let (cx, cy) = atmos.get_cursor_pos();
+    let target = atmos.find_window_with_input_at_point(cx as f32, cy as f32);
+
+    if target != dnd.dnd_focus {
+        for device in dnd.dnd_devices.drain(..) {
+            device.leave();
+        }
+        dnd.dnd_offers.clear();
+
+        if let Some(id) = target.as_ref() {
+            dnd_enter(atmos, &mut dnd, id);
+        }
+        dnd.dnd_focus = target;
+    } else if let Some(id) = target.as_ref() {
+        if let Some((sx, sy)) = atmos.global_coords_to_surf(id, cx, cy) {
+            let time = get_current_millis();
+            for device in dnd.dnd_devices.iter() {
+                device.motion(time, sx, sy);
+            }
+        }
+    }
+
+    atmos.set_dnd(Some(dnd));
+}
+
+/// Finish the drag when the pointer button was released
+///
+/// If the destination accepted the data and an action was agreed on
+/// then it receives the drop. Otherwise the drag is cancelled.
+pub fn dnd_drop(atmos: &mut Atmosphere) {
+    let dnd = match atmos.get_dnd() {
+        Some(d) => d,
+        None => return,
+    };
+
+    let accepted = match dnd.dnd_source.as_ref() {
+        Some(_) => dnd.dnd_offers.iter().any(|offer| {
+            offer
+                .data::<Arc<Mutex<DataOffer>>>()
+                .map(|o| o.lock().unwrap().will_accept_drop(offer))
+                .unwrap_or(false)
+        }),
+        // Client-local drags are handled entirely by the client
+        None => !dnd.dnd_devices.is_empty(),
+    };
+
+    if accepted {
+        log::debug!("Dropping onto surface {:?}", dnd.dnd_focus);
+        for device in dnd.dnd_devices.iter() {
+            device.drop();
+        }
+        if let Some(src) = dnd.dnd_source.as_ref() {
+            if src.version() >= 3 {
+                src.dnd_drop_performed();
+            }
+        }
+    } else {
+        log::debug!("Drag was not accepted, cancelling it");
+        for device in dnd.dnd_devices.iter() {
+            device.leave();
+        }
+        if let Some(src) = dnd.dnd_source.as_ref() {
+            src.cancelled();
+        }
+    }
+
+    end_drag(atmos);
+}
+
+/// Cancel the drag in progress, if there is one
+///
+/// The destination is left and the source is told the drag was
+/// cancelled. This is used when the session is locked mid-drag.
+pub fn cancel_drag(atmos: &mut Atmosphere) {
+    let dnd = match atmos.get_dnd() {
+        Some(d) => d,
+        None => return,
+    };
+
+    log::debug!("Cancelling drag and drop");
+    for device in dnd.dnd_devices.iter() {
+        device.leave();
+    }
+    if let Some(src) = dnd.dnd_source.as_ref() {
+        src.cancelled();
+    }
+    end_drag(atmos);
+}
+
+/// Tear down the drag state and give the pointer back to clients
+fn end_drag(atmos: &mut Atmosphere) {
+    atmos.set_dnd(None);
+    atmos.add_wm_task(Task::set_dnd_icon { id: None });
+    atmos.recalculate_pointer_focus();
+}
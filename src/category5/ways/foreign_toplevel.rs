@@ -0,0 +1,560 @@
+// Implementation of the foreign toplevel protocols
+//
+// Taskbars and window switchers use these to list the open windows.
+// ext_foreign_toplevel_list only lists them, while the wlr management
+// protocol also reports their state and lets the taskbar activate,
+// close, minimize or maximize them.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::ext::foreign_toplevel_list::v1::server::{
+    ext_foreign_toplevel_handle_v1 as efth, ext_foreign_toplevel_list_v1 as eftl,
+};
+use ws::protocol::wl_output;
+use ws::Resource;
+
+use super::protocol::wlr_foreign_toplevel::{
+    zwlr_foreign_toplevel_handle_v1 as zwfth, zwlr_foreign_toplevel_manager_v1 as zwftm,
+};
+use super::role::Role;
+use super::xdg_shell::ShellSurface;
+use crate::category5::atmosphere::{Atmosphere, SurfaceId};
+use crate::category5::Climate;
+use utils::log;
+
+use std::sync::{Arc, Mutex};
+
+/// What we last told clients about a toplevel
+#[derive(PartialEq, Clone)]
+struct ToplevelInfo {
+    ti_title: String,
+    ti_app_id: String,
+    ti_activated: bool,
+    ti_minimized: bool,
+    ti_maximized: bool,
+    ti_fullscreen: bool,
+    /// The id of the output this window is on
+    ti_output: Option<u32>,
+}
+
+impl ToplevelInfo {
+    fn new(atmos: &Atmosphere, id: &SurfaceId) -> Self {
+        let mut ret = Self {
+            ti_title: String::new(),
+            ti_app_id: String::new(),
+            ti_activated: atmos.get_root_win_in_focus().as_ref() == Some(id),
+            ti_minimized: atmos.a_minimized.get(id).map(|m| *m) == Some(true),
+            ti_maximized: false,
+            ti_fullscreen: false,
+            ti_output: atmos.get_window_output(id).map(|o| o.ol_id),
+        };
+
+        // The rest is only known for xdg_shell windows
+        if let Some(surf) = atmos.get_surface_from_id(id) {
+            let surf = surf.lock().unwrap();
+            if let Some(tl) = surf.s_state.cs_xdg_state.xs_tlstate.as_ref() {
+                ret.ti_title = tl.tl_title.clone().unwrap_or_default();
+                ret.ti_app_id = tl.tl_app_id.clone().unwrap_or_default();
+                ret.ti_maximized = tl.tl_maximized;
+                ret.ti_fullscreen = tl.tl_fullscreen;
+            }
+        }
+
+        ret
+    }
+
+    /// Get the state array for zwlr_foreign_toplevel_handle_v1.state
+    fn get_wlr_states(&self) -> Vec<u8> {
+        let mut states = Vec::new();
+        let mut add = |state: zwfth::State| states.extend((state as u32).to_ne_bytes());
+
+        if self.ti_maximized {
+            add(zwfth::State::Maximized);
+        }
+        if self.ti_minimized {
+            add(zwfth::State::Minimized);
+        }
+        if self.ti_activated {
+            add(zwfth::State::Activated);
+        }
+        if self.ti_fullscreen {
+            add(zwfth::State::Fullscreen);
+        }
+
+        states
+    }
+}
+
+/// A toplevel window which has been announced to clients
+struct ForeignToplevel {
+    ft_id: SurfaceId,
+    /// The identifier sent with ext_foreign_toplevel_handle_v1. This is
+    /// never reused for another window.
+    ft_identifier: String,
+    ft_info: ToplevelInfo,
+    ft_list_handles: Vec<efth::ExtForeignToplevelHandleV1>,
+    ft_wlr_handles: Vec<zwfth::ZwlrForeignToplevelHandleV1>,
+}
+
+/// Everything needed to keep taskbars up to date
+pub struct ForeignToplevels {
+    /// Bound ext_foreign_toplevel_list objects which have not been stopped
+    ft_lists: Vec<eftl::ExtForeignToplevelListV1>,
+    /// Bound zwlr_foreign_toplevel_manager objects which have not been
+    /// stopped
+    ft_managers: Vec<zwftm::ZwlrForeignToplevelManagerV1>,
+    ft_toplevels: Vec<ForeignToplevel>,
+    /// Used to make the identifiers of new toplevels
+    ft_next_identifier: u64,
+}
+
+impl ForeignToplevels {
+    pub fn new() -> Self {
+        Self {
+            ft_lists: Vec::new(),
+            ft_managers: Vec::new(),
+            ft_toplevels: Vec::new(),
+            ft_next_identifier: 0,
+        }
+    }
+}
+
+/// Get the wl_outputs the owner of `resource` has bound for an output
+fn get_client_outputs<'a, R: Resource>(
+    outputs: &'a [wl_output::WlOutput],
+    resource: &'a R,
+    output: u32,
+) -> impl Iterator<Item = &'a wl_output::WlOutput> {
+    let client = resource.client().map(|c| c.id());
+    outputs.iter().filter(move |o| {
+        *o.data::<u32>().unwrap() == output && o.client().map(|c| c.id()) == client
+    })
+}
+
+/// Send everything about a toplevel to a new ext handle
+fn send_list_info(handle: &efth::ExtForeignToplevelHandleV1, toplevel: &ForeignToplevel) {
+    handle.identifier(toplevel.ft_identifier.clone());
+    handle.title(toplevel.ft_info.ti_title.clone());
+    handle.app_id(toplevel.ft_info.ti_app_id.clone());
+    handle.done();
+}
+
+/// Send everything about a toplevel to a new wlr handle
+fn send_wlr_info(
+    handle: &zwfth::ZwlrForeignToplevelHandleV1,
+    toplevel: &ForeignToplevel,
+    outputs: &[wl_output::WlOutput],
+) {
+    let info = &toplevel.ft_info;
+    handle.title(info.ti_title.clone());
+    handle.app_id(info.ti_app_id.clone());
+    if let Some(output) = info.ti_output {
+        for out in get_client_outputs(outputs, handle, output) {
+            handle.output_enter(out);
+        }
+    }
+    handle.state(info.get_wlr_states());
+    handle.done();
+}
+
+/// Announce a toplevel on an ext_foreign_toplevel_list
+fn create_list_handle(list: &eftl::ExtForeignToplevelListV1, toplevel: &mut ForeignToplevel) {
+    let client = match list.client() {
+        Some(client) => client,
+        None => return,
+    };
+    let dhandle = match list.handle().upgrade() {
+        Some(handle) => ws::DisplayHandle::from(handle),
+        None => return,
+    };
+
+    match client.create_resource::<efth::ExtForeignToplevelHandleV1, SurfaceId, Climate>(
+        &dhandle,
+        list.version(),
+        toplevel.ft_id.clone(),
+    ) {
+        Ok(handle) => {
+            list.toplevel(&handle);
+            send_list_info(&handle, toplevel);
+            toplevel.ft_list_handles.push(handle);
+        }
+        Err(e) => log::error!("Could not create foreign toplevel handle: {:?}", e),
+    }
+}
+
+/// Announce a toplevel on a zwlr_foreign_toplevel_manager
+fn create_wlr_handle(
+    manager: &zwftm::ZwlrForeignToplevelManagerV1,
+    toplevel: &mut ForeignToplevel,
+    outputs: &[wl_output::WlOutput],
+) {
+    let client = match manager.client() {
+        Some(client) => client,
+        None => return,
+    };
+    let dhandle = match manager.handle().upgrade() {
+        Some(handle) => ws::DisplayHandle::from(handle),
+        None => return,
+    };
+
+    match client.create_resource::<zwfth::ZwlrForeignToplevelHandleV1, SurfaceId, Climate>(
+        &dhandle,
+        manager.version(),
+        toplevel.ft_id.clone(),
+    ) {
+        Ok(handle) => {
+            manager.toplevel(&handle);
+            send_wlr_info(&handle, toplevel, outputs);
+            toplevel.ft_wlr_handles.push(handle);
+        }
+        Err(e) => log::error!("Could not create foreign toplevel handle: {:?}", e),
+    }
+}
+
+impl Climate {
+    /// Tell taskbars about any windows which were opened, closed or changed
+    ///
+    /// This compares the toplevels in the atmosphere with what we last
+    /// sent, and should be called after each round of dispatching.
+    pub fn update_foreign_toplevels(&mut self) {
+        let atmos = self.c_atmos.lock().unwrap();
+        let current: Vec<(SurfaceId, ToplevelInfo)> = atmos
+            .get_toplevels()
+            .into_iter()
+            .map(|id| {
+                let info = ToplevelInfo::new(&atmos, &id);
+                (id, info)
+            })
+            .collect();
+        drop(atmos);
+
+        let ft = &mut self.c_foreign_toplevels;
+        let outputs = &self.c_outputs;
+
+        // Windows which have been closed
+        ft.ft_toplevels.retain(|toplevel| {
+            if current.iter().any(|(id, _)| *id == toplevel.ft_id) {
+                return true;
+            }
+            for handle in toplevel.ft_list_handles.iter() {
+                handle.closed();
+            }
+            for handle in toplevel.ft_wlr_handles.iter() {
+                handle.closed();
+            }
+            false
+        });
+
+        for (id, info) in current.into_iter() {
+            let toplevel = match ft.ft_toplevels.iter().position(|t| t.ft_id == id) {
+                Some(index) => &mut ft.ft_toplevels[index],
+                None => {
+                    let mut toplevel = ForeignToplevel {
+                        ft_id: id,
+                        ft_identifier: format!("category5-toplevel-{}", ft.ft_next_identifier),
+                        ft_info: info,
+                        ft_list_handles: Vec::new(),
+                        ft_wlr_handles: Vec::new(),
+                    };
+                    ft.ft_next_identifier += 1;
+
+                    for list in ft.ft_lists.iter() {
+                        create_list_handle(list, &mut toplevel);
+                    }
+                    for manager in ft.ft_managers.iter() {
+                        create_wlr_handle(manager, &mut toplevel, outputs);
+                    }
+                    ft.ft_toplevels.push(toplevel);
+                    continue;
+                }
+            };
+
+            if toplevel.ft_info == info {
+                continue;
+            }
+            let old = std::mem::replace(&mut toplevel.ft_info, info.clone());
+
+            // The ext protocol only has the title and app id
+            if old.ti_title != info.ti_title || old.ti_app_id != info.ti_app_id {
+                for handle in toplevel.ft_list_handles.iter() {
+                    if old.ti_title != info.ti_title {
+                        handle.title(info.ti_title.clone());
+                    }
+                    if old.ti_app_id != info.ti_app_id {
+                        handle.app_id(info.ti_app_id.clone());
+                    }
+                    handle.done();
+                }
+            }
+
+            for handle in toplevel.ft_wlr_handles.iter() {
+                if old.ti_title != info.ti_title {
+                    handle.title(info.ti_title.clone());
+                }
+                if old.ti_app_id != info.ti_app_id {
+                    handle.app_id(info.ti_app_id.clone());
+                }
+                if old.ti_output != info.ti_output {
+                    if let Some(output) = old.ti_output {
+                        for out in get_client_outputs(outputs, handle, output) {
+                            handle.output_leave(out);
+                        }
+                    }
+                    if let Some(output) = info.ti_output {
+                        for out in get_client_outputs(outputs, handle, output) {
+                            handle.output_enter(out);
+                        }
+                    }
+                }
+                handle.state(info.get_wlr_states());
+                handle.done();
+            }
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<eftl::ExtForeignToplevelListV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<eftl::ExtForeignToplevelListV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let list = data_init.init(resource, ());
+
+        let ft = &mut state.c_foreign_toplevels;
+        for toplevel in ft.ft_toplevels.iter_mut() {
+            create_list_handle(&list, toplevel);
+        }
+        ft.ft_lists.push(list);
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<eftl::ExtForeignToplevelListV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &eftl::ExtForeignToplevelListV1,
+        request: eftl::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            eftl::Request::Stop => {
+                state.c_foreign_toplevels.ft_lists.retain(|l| l != resource);
+                resource.finished();
+            }
+            eftl::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &eftl::ExtForeignToplevelListV1,
+        data: &(),
+    ) {
+        state.c_foreign_toplevels.ft_lists.retain(|l| l != resource);
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<efth::ExtForeignToplevelHandleV1, SurfaceId> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &efth::ExtForeignToplevelHandleV1,
+        request: efth::Request,
+        data: &SurfaceId,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            efth::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &efth::ExtForeignToplevelHandleV1,
+        data: &SurfaceId,
+    ) {
+        // If the window was closed first then this is already gone
+        for toplevel in state.c_foreign_toplevels.ft_toplevels.iter_mut() {
+            toplevel.ft_list_handles.retain(|h| h != resource);
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zwftm::ZwlrForeignToplevelManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zwftm::ZwlrForeignToplevelManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(resource, ());
+
+        let ft = &mut state.c_foreign_toplevels;
+        for toplevel in ft.ft_toplevels.iter_mut() {
+            create_wlr_handle(&manager, toplevel, &state.c_outputs);
+        }
+        ft.ft_managers.push(manager);
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zwftm::ZwlrForeignToplevelManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zwftm::ZwlrForeignToplevelManagerV1,
+        request: zwftm::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zwftm::Request::Stop => {
+                state
+                    .c_foreign_toplevels
+                    .ft_managers
+                    .retain(|m| m != resource);
+                resource.finished();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zwftm::ZwlrForeignToplevelManagerV1,
+        data: &(),
+    ) {
+        state
+            .c_foreign_toplevels
+            .ft_managers
+            .retain(|m| m != resource);
+    }
+}
+
+/// Get the xdg_shell state of a toplevel
+///
+/// Maximizing and closing are only supported for xdg_shell windows.
+fn get_shell_surface(atmos: &Atmosphere, id: &SurfaceId) -> Option<Arc<Mutex<ShellSurface>>> {
+    let surf = atmos.get_surface_from_id(id)?;
+    let surf = surf.lock().unwrap();
+    match surf.s_role.as_ref() {
+        Some(Role::xdg_shell_toplevel(_, ss)) => Some(ss.clone()),
+        _ => None,
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zwfth::ZwlrForeignToplevelHandleV1, SurfaceId> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zwfth::ZwlrForeignToplevelHandleV1,
+        request: zwfth::Request,
+        data: &SurfaceId,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        // Handles for closed windows are inert
+        if !state
+            .c_foreign_toplevels
+            .ft_toplevels
+            .iter()
+            .any(|t| t.ft_id == *data)
+        {
+            return;
+        }
+        let mut atmos = state.c_atmos.lock().unwrap();
+
+        match request {
+            zwfth::Request::SetMaximized => {
+                if let Some(ss) = get_shell_surface(&atmos, data) {
+                    ss.lock().unwrap().set_maximized(&mut atmos, true);
+                }
+            }
+            zwfth::Request::UnsetMaximized => {
+                if let Some(ss) = get_shell_surface(&atmos, data) {
+                    ss.lock().unwrap().set_maximized(&mut atmos, false);
+                }
+            }
+            zwfth::Request::SetMinimized => {
+                if atmos.a_minimized.get(data).map(|m| *m) != Some(true) {
+                    atmos.minimize_window(data);
+                }
+            }
+            zwfth::Request::UnsetMinimized => atmos.restore_window(data),
+            zwfth::Request::Activate { seat } => {
+                match atmos.a_minimized.get(data).map(|m| *m) == Some(true) {
+                    true => atmos.restore_window(data),
+                    false => atmos.focus_on(Some(data.clone())),
+                }
+            }
+            zwfth::Request::Close => {
+                if let Some(ss) = get_shell_surface(&atmos, data) {
+                    ss.lock().unwrap().close();
+                }
+            }
+            // This is only a hint for minimize animations, which we
+            // don't have. Still check that it is valid.
+            zwfth::Request::SetRectangle {
+                surface,
+                x,
+                y,
+                width,
+                height,
+            } => {
+                if width < 0 || height < 0 {
+                    resource.post_error(
+                        zwfth::Error::InvalidRectangle,
+                        "Rectangle has a negative size",
+                    );
+                }
+            }
+            zwfth::Request::SetFullscreen { output } => {
+                // wl_output userdata is the id of the output it advertises
+                let output = output.and_then(|o| o.data::<u32>().copied());
+                if let Some(ss) = get_shell_surface(&atmos, data) {
+                    ss.lock().unwrap().set_fullscreen(&mut atmos, true, output);
+                }
+            }
+            zwfth::Request::UnsetFullscreen => {
+                if let Some(ss) = get_shell_surface(&atmos, data) {
+                    ss.lock().unwrap().set_fullscreen(&mut atmos, false, None);
+                }
+            }
+            zwfth::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zwfth::ZwlrForeignToplevelHandleV1,
+        data: &SurfaceId,
+    ) {
+        // If the window was closed first then this is already gone
+        for toplevel in state.c_foreign_toplevels.ft_toplevels.iter_mut() {
+            toplevel.ft_wlr_handles.retain(|h| h != resource);
+        }
+    }
+}
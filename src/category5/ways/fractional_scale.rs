@@ -0,0 +1,147 @@
+// Implementation of the wp_fractional_scale protocol
+//
+// wl_output.scale can only advertise whole numbers. This tells
+// clients the exact scale of the output their surface is on, which
+// they use along with wp_viewporter to draw at the display's real
+// resolution.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::wp::fractional_scale::v1::server::{
+    wp_fractional_scale_manager_v1 as wpfsm, wp_fractional_scale_v1 as wpfs,
+};
+use ws::protocol::wl_surface;
+use ws::Resource;
+
+use super::surface::Surface;
+use crate::category5::atmosphere::Atmosphere;
+use crate::category5::Climate;
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<wpfsm::WpFractionalScaleManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<wpfsm::WpFractionalScaleManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<wpfsm::WpFractionalScaleManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wpfsm::WpFractionalScaleManagerV1,
+        request: wpfsm::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            wpfsm::Request::GetFractionalScale { id, surface } => {
+                if state
+                    .c_fractional_scales
+                    .iter()
+                    .any(|fs| fs.data::<wl_surface::WlSurface>() == Some(&surface))
+                {
+                    data_init.post_error(
+                        id,
+                        wpfsm::Error::FractionalScaleExists,
+                        "wl_surface already has a fractional scale object",
+                    );
+                    return;
+                }
+
+                let fs = data_init.init(id, surface);
+                send_preferred_scale(&fs, &state.c_atmos.lock().unwrap());
+                state.c_fractional_scales.push(fs);
+            }
+            wpfsm::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &wpfsm::WpFractionalScaleManagerV1,
+        data: &(),
+    ) {
+    }
+}
+
+// The userdata is the wl_surface the scale is sent for
+#[allow(unused_variables)]
+impl ws::Dispatch<wpfs::WpFractionalScaleV1, wl_surface::WlSurface> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wpfs::WpFractionalScaleV1,
+        request: wpfs::Request,
+        data: &wl_surface::WlSurface,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            wpfs::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &wpfs::WpFractionalScaleV1,
+        data: &wl_surface::WlSurface,
+    ) {
+        state
+            .c_fractional_scales
+            .retain(|fs| fs.id() != resource.id());
+    }
+}
+
+/// Tell the client the scale of the output its surface is on
+///
+/// The scale is sent as a fraction with a denominator of 120.
+fn send_preferred_scale(fs: &wpfs::WpFractionalScaleV1, atmos: &Atmosphere) {
+    let surface = fs.data::<wl_surface::WlSurface>().unwrap();
+    if !surface.is_alive() {
+        return;
+    }
+    let id = surface
+        .data::<Arc<Mutex<Surface>>>()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .s_id
+        .clone();
+
+    // Subsurfaces and popups use the output of their window
+    let root = atmos.a_root_window.get_clone(&id).unwrap_or(id);
+    let scale = atmos
+        .get_window_output(&root)
+        .map(|output| output.ol_scale)
+        .unwrap_or(1.0);
+
+    fs.preferred_scale((scale * 120.0).round() as u32);
+}
+
+impl Climate {
+    /// Resend the preferred scale of every surface
+    ///
+    /// This is called when the output layout changes.
+    pub fn update_fractional_scales(&mut self) {
+        let atmos = self.c_atmos.lock().unwrap();
+        for fs in self.c_fractional_scales.iter() {
+            send_preferred_scale(fs, &atmos);
+        }
+    }
+}
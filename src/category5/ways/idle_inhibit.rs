@@ -0,0 +1,107 @@
+// Implementation of the idle inhibit protocol
+//
+// Video players and the like use this to keep the screen from blanking
+// while they are being watched, even if the user isn't touching any
+// input devices.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::wp::idle_inhibit::zv1::server::{
+    zwp_idle_inhibit_manager_v1 as ziim, zwp_idle_inhibitor_v1 as zii,
+};
+use ws::Resource;
+
+use super::surface::Surface;
+use crate::category5::atmosphere::SurfaceId;
+use crate::category5::Climate;
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<ziim::ZwpIdleInhibitManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<ziim::ZwpIdleInhibitManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<ziim::ZwpIdleInhibitManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &ziim::ZwpIdleInhibitManagerV1,
+        request: ziim::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            ziim::Request::CreateInhibitor { id, surface } => {
+                let surf_id = surface
+                    .data::<Arc<Mutex<Surface>>>()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .s_id
+                    .clone();
+                state
+                    .c_atmos
+                    .lock()
+                    .unwrap()
+                    .a_idle_inhibitors
+                    .push(surf_id.clone());
+                data_init.init(id, surf_id);
+            }
+            ziim::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &ziim::ZwpIdleInhibitManagerV1,
+        data: &(),
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zii::ZwpIdleInhibitorV1, SurfaceId> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zii::ZwpIdleInhibitorV1,
+        request: zii::Request,
+        data: &SurfaceId,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zii::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zii::ZwpIdleInhibitorV1,
+        data: &SurfaceId,
+    ) {
+        // Only remove this inhibitor, the surface may have others. If
+        // the surface was destroyed first then this is already gone.
+        let mut atmos = state.c_atmos.lock().unwrap();
+        if let Some(index) = atmos.a_idle_inhibitors.iter().position(|id| id == data) {
+            atmos.a_idle_inhibitors.remove(index);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Implementation of the ext idle notify protocol
+//
+// Screensavers and lockers ask to be told when the user has been away
+// for some time, and again when they come back.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::ext::idle_notify::v1::server::{
+    ext_idle_notification_v1 as ein, ext_idle_notifier_v1 as eint,
+};
+use ws::Resource;
+
+use crate::category5::Climate;
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<eint::ExtIdleNotifierV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<eint::ExtIdleNotifierV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<eint::ExtIdleNotifierV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &eint::ExtIdleNotifierV1,
+        request: eint::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            eint::Request::GetIdleNotification { id, timeout, seat } => {
+                let notification = data_init.init(
+                    id,
+                    Mutex::new(IdleNotification {
+                        in_timeout: Duration::from_millis(timeout as u64),
+                        in_idle: false,
+                    }),
+                );
+                state.c_idle_notifications.push(notification);
+            }
+            eint::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &eint::ExtIdleNotifierV1,
+        data: &(),
+    ) {
+    }
+}
+
+/// A client's request to be notified after some idle time
+pub struct IdleNotification {
+    /// How long the user must be idle before we send the idled event
+    in_timeout: Duration,
+    /// Did we send the idled event
+    in_idle: bool,
+}
+
+impl Climate {
+    /// Send idled and resumed events to clients
+    ///
+    /// The user is idle if no input was handled for a notification's
+    /// timeout, and no visible surface is inhibiting idle. This returns
+    /// how long until the next notification should be sent, which should
+    /// be used as the timeout for waiting on events.
+    pub fn update_idle_notifications(&mut self) -> Option<Duration> {
+        if self.c_atmos.lock().unwrap().is_idle_inhibited() {
+            // Inhibitors count as activity, so the timers start over
+            // once they are gone
+            self.c_input.i_last_activity = Instant::now();
+        }
+        let idle_time = self.c_input.i_last_activity.elapsed();
+
+        let mut next = None;
+        for notification in self.c_idle_notifications.iter() {
+            let mut data = notification
+                .data::<Mutex<IdleNotification>>()
+                .unwrap()
+                .lock()
+                .unwrap();
+
+            match idle_time >= data.in_timeout {
+                true => {
+                    if !data.in_idle {
+                        notification.idled();
+                        data.in_idle = true;
+                    }
+                }
+                false => {
+                    if data.in_idle {
+                        notification.resumed();
+                        data.in_idle = false;
+                    }
+                    let remaining = data.in_timeout - idle_time;
+                    next = Some(next.map_or(remaining, |n: Duration| n.min(remaining)));
+                }
+            }
+        }
+
+        next
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<ein::ExtIdleNotificationV1, Mutex<IdleNotification>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &ein::ExtIdleNotificationV1,
+        request: ein::Request,
+        data: &Mutex<IdleNotification>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            ein::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &ein::ExtIdleNotificationV1,
+        data: &Mutex<IdleNotification>,
+    ) {
+        state.c_idle_notifications.retain(|n| n != resource);
+    }
+}
@@ -0,0 +1,530 @@
+// Implementation of the input method protocol
+//
+// An input method (IME) is a client which composes text for other
+// clients, such as for typing CJK languages. It grabs the keyboard and
+// sends back preedit and commit strings, which we forward to the text
+// input of the client in focus.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::wp::text_input::zv3::server::zwp_text_input_v3 as zti;
+use ws::protocol::wl_keyboard;
+use ws::Resource;
+
+use super::protocol::input_method::{
+    zwp_input_method_keyboard_grab_v2 as zimkg, zwp_input_method_manager_v2 as zimm,
+    zwp_input_method_v2 as zim, zwp_input_popup_surface_v2 as zips,
+};
+use super::role::Role;
+use super::seat;
+use super::surface::Surface;
+use super::text_input::{TextInput, TextInputState};
+use crate::category5::atmosphere::{Atmosphere, SurfaceId};
+use crate::category5::input::Input;
+use crate::category5::Climate;
+use utils::{log, region::Rect};
+
+use std::os::unix::io::AsFd;
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zimm::ZwpInputMethodManagerV2, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zimm::ZwpInputMethodManagerV2>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    // Input methods see everything the user types, so only trusted
+    // clients may become one
+    fn can_view(client: ws::Client, global_data: &()) -> bool {
+        super::utils::client_is_trusted(&client)
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zimm::ZwpInputMethodManagerV2, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zimm::ZwpInputMethodManagerV2,
+        request: zimm::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zimm::Request::GetInputMethod { seat, input_method } => {
+                let mut atmos = state.c_atmos.lock().unwrap();
+                let trusted = super::utils::client_is_trusted(client);
+                let available = trusted && atmos.get_input_method().is_none();
+
+                let im_cell = Arc::new(Mutex::new(InputMethod::new()));
+                let im = data_init.init(input_method, im_cell.clone());
+                im_cell.lock().unwrap().im_resource = Some(im.clone());
+
+                // We only have one seat, so only one input method can be
+                // in use at a time
+                if !trusted {
+                    log::error!("Client is not allowed to be an input method");
+                }
+                if available {
+                    log::debug!("New input method {:?}", im.id());
+                    atmos.set_input_method(Some(im_cell));
+                } else {
+                    im.unavailable();
+                }
+            }
+            zimm::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zimm::ZwpInputMethodManagerV2,
+        data: &(),
+    ) {
+    }
+}
+
+/// A surface the input method shows next to the text cursor, such
+/// as a list of candidates
+pub struct InputPopup {
+    pub ip_resource: zips::ZwpInputPopupSurfaceV2,
+    pub ip_surface: SurfaceId,
+}
+
+/// The input method client
+///
+/// While a text input is active the input method receives the keyboard
+/// events through its grab, and its popups are attached to the surface
+/// being typed in.
+pub struct InputMethod {
+    /// This is None only while the resource is being created
+    pub im_resource: Option<zim::ZwpInputMethodV2>,
+    pub im_keyboard_grab: Option<zimkg::ZwpInputMethodKeyboardGrabV2>,
+    /// Serial for events sent to the keyboard grab
+    pub im_grab_serial: u32,
+    /// The virtual keyboard whose keymap the grab was last sent, or
+    /// None if it has our own keymap.
+    im_grab_keymap: Option<ws::backend::ObjectId>,
+    pub im_popups: Vec<InputPopup>,
+    /// The text input we are composing text for, and its surface
+    pub im_text_input: Option<(zti::ZwpTextInputV3, SurfaceId)>,
+    /// The number of done events sent
+    pub im_done_count: u32,
+    /// Changes to the text which will be sent on the next commit
+    im_pending_preedit: Option<(String, i32, i32)>,
+    im_pending_commit: Option<String>,
+    im_pending_delete: Option<(u32, u32)>,
+}
+
+impl InputMethod {
+    fn new() -> Self {
+        Self {
+            im_resource: None,
+            im_keyboard_grab: None,
+            im_grab_serial: 0,
+            im_grab_keymap: None,
+            im_popups: Vec::new(),
+            im_text_input: None,
+            im_done_count: 0,
+            im_pending_preedit: None,
+            im_pending_commit: None,
+            im_pending_delete: None,
+        }
+    }
+
+    fn send_done(&mut self) {
+        self.im_resource.as_ref().unwrap().done();
+        self.im_done_count += 1;
+    }
+
+    /// Start composing text for `text_input`
+    fn activate(
+        &mut self,
+        atmos: &mut Atmosphere,
+        text_input: &zti::ZwpTextInputV3,
+        surf: &SurfaceId,
+    ) {
+        self.im_resource.as_ref().unwrap().activate();
+        self.im_text_input = Some((text_input.clone(), surf.clone()));
+        self.im_pending_preedit = None;
+        self.im_pending_commit = None;
+        self.im_pending_delete = None;
+
+        for popup in self.im_popups.iter() {
+            atmos.add_new_top_subsurf(surf, &popup.ip_surface);
+        }
+    }
+
+    /// Stop composing text and hide our popups
+    pub fn deactivate(&mut self, atmos: &mut Atmosphere) {
+        if self.im_text_input.take().is_none() {
+            return;
+        }
+        self.im_resource.as_ref().unwrap().deactivate();
+        self.send_done();
+
+        for popup in self.im_popups.iter() {
+            atmos.remove_subsurf(&popup.ip_surface);
+        }
+    }
+
+    /// Send the state of the active text input
+    fn send_state(&mut self, state: &TextInputState) {
+        let im = self.im_resource.as_ref().unwrap();
+        if let Some((text, cursor, anchor)) = state.ts_surrounding_text.as_ref() {
+            im.surrounding_text(text.clone(), *cursor, *anchor);
+        }
+        im.text_change_cause(state.ts_change_cause);
+        im.content_type(state.ts_content_hint, state.ts_content_purpose);
+        self.send_done();
+    }
+
+    /// Move a popup below the text cursor
+    ///
+    /// The popups are subsurfaces of the text input's surface, so the
+    /// cursor rectangle can be used as their position directly.
+    fn place_popup(atmos: &mut Atmosphere, popup: &InputPopup, cursor: Option<Rect<i32>>) {
+        let cursor = match cursor {
+            Some(cursor) => cursor,
+            None => return,
+        };
+
+        atmos.a_surface_pos.set(
+            &popup.ip_surface,
+            (
+                cursor.r_pos.0 as f32,
+                (cursor.r_pos.1 + cursor.r_size.1) as f32,
+            ),
+        );
+        popup.ip_resource.text_input_rectangle(
+            0,
+            -cursor.r_size.1,
+            cursor.r_size.0,
+            cursor.r_size.1,
+        );
+    }
+
+    /// Forward the pending text changes to the active text input
+    fn commit(&mut self) {
+        let preedit = self.im_pending_preedit.take();
+        let commit = self.im_pending_commit.take();
+        let delete = self.im_pending_delete.take();
+
+        let text_input = match self.im_text_input.as_ref() {
+            Some((text_input, _)) => text_input,
+            None => return,
+        };
+        if !text_input.is_alive() {
+            return;
+        }
+
+        if let Some((text, begin, end)) = preedit {
+            text_input.preedit_string(Some(text), begin, end);
+        }
+        if let Some((before, after)) = delete {
+            text_input.delete_surrounding_text(before, after);
+        }
+        if let Some(text) = commit {
+            text_input.commit_string(Some(text));
+        }
+
+        let serial = text_input
+            .data::<Arc<Mutex<TextInput>>>()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .ti_serial;
+        text_input.done(serial);
+    }
+
+    /// Send the keymap of the keyboard we are handling keys from
+    ///
+    /// This works the same as `Seat::update_keymap`, but for the
+    /// keyboard grab.
+    pub fn update_keymap(&mut self, input: &Input) -> bool {
+        let grab = match self.im_keyboard_grab.as_ref() {
+            Some(grab) => grab,
+            None => return false,
+        };
+        let keymap = input.get_keymap();
+        if self.im_grab_keymap.as_ref() == keymap {
+            return false;
+        }
+
+        let (file, size) = seat::create_keymap_file(input.get_keymap_text(keymap));
+        grab.keymap(wl_keyboard::KeymapFormat::XkbV1, file.as_fd(), size);
+        self.im_grab_keymap = keymap.cloned();
+        true
+    }
+
+    /// Forward a key event from the keyboard grab
+    ///
+    /// Returns false if there is no grab to deliver the key to.
+    pub fn send_key(
+        &mut self,
+        time: u32,
+        key: u32,
+        state: wl_keyboard::KeyState,
+        mods: Option<(u32, u32, u32, u32)>,
+    ) -> bool {
+        if self.im_text_input.is_none() {
+            return false;
+        }
+        let grab = match self.im_keyboard_grab.as_ref() {
+            Some(grab) => grab,
+            None => return false,
+        };
+
+        if let Some((depressed, latched, locked, layout)) = mods {
+            grab.modifiers(self.im_grab_serial, depressed, latched, locked, layout);
+        }
+        grab.key(self.im_grab_serial, time, key, state);
+        self.im_grab_serial += 1;
+
+        true
+    }
+}
+
+/// Is `im` the input method in use
+///
+/// Input methods which were told they are unavailable must have all
+/// of their requests ignored.
+fn is_current(atmos: &Atmosphere, im: &Arc<Mutex<InputMethod>>) -> bool {
+    match atmos.get_input_method() {
+        Some(current) => Arc::ptr_eq(&current, im),
+        None => false,
+    }
+}
+
+/// Update the input method with the state a text input committed
+///
+/// Enabling the text input activates the input method for it, and
+/// disabling it deactivates the input method again.
+pub fn text_input_commit(
+    atmos: &mut Atmosphere,
+    text_input: &zti::ZwpTextInputV3,
+    surf: &SurfaceId,
+    state: &TextInputState,
+) {
+    let im_cell = match atmos.get_input_method() {
+        Some(im) => im,
+        None => return,
+    };
+    let mut im = im_cell.lock().unwrap();
+    let is_active = match im.im_text_input.as_ref() {
+        Some((active, _)) => active == text_input,
+        None => false,
+    };
+
+    if !state.ts_enabled {
+        if is_active {
+            im.deactivate(atmos);
+        }
+        return;
+    }
+
+    if !is_active {
+        // The previous text input may not have been disabled before
+        // focus moved to this one
+        im.deactivate(atmos);
+        im.activate(atmos, text_input, surf);
+    }
+    im.send_state(state);
+
+    for popup in im.im_popups.iter() {
+        InputMethod::place_popup(atmos, popup, state.ts_cursor_rect);
+    }
+}
+
+/// Stop using a text input which lost focus or was destroyed
+pub fn text_input_removed(atmos: &mut Atmosphere, text_input: &zti::ZwpTextInputV3) {
+    if let Some(im_cell) = atmos.get_input_method() {
+        let mut im = im_cell.lock().unwrap();
+        let is_active = match im.im_text_input.as_ref() {
+            Some((active, _)) => active == text_input,
+            None => false,
+        };
+        if is_active {
+            im.deactivate(atmos);
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zim::ZwpInputMethodV2, Arc<Mutex<InputMethod>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zim::ZwpInputMethodV2,
+        request: zim::Request,
+        data: &Arc<Mutex<InputMethod>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        let current = is_current(&atmos, data);
+
+        match request {
+            zim::Request::CommitString { text } => {
+                data.lock().unwrap().im_pending_commit = Some(text)
+            }
+            zim::Request::SetPreeditString {
+                text,
+                cursor_begin,
+                cursor_end,
+            } => data.lock().unwrap().im_pending_preedit = Some((text, cursor_begin, cursor_end)),
+            zim::Request::DeleteSurroundingText {
+                before_length,
+                after_length,
+            } => data.lock().unwrap().im_pending_delete = Some((before_length, after_length)),
+            zim::Request::Commit { serial } => {
+                // A serial older than our last done event means the IME
+                // has not seen the latest state yet. The spec still has
+                // us apply the changes in that case.
+                if current {
+                    data.lock().unwrap().commit();
+                }
+            }
+            zim::Request::GetInputPopupSurface { id, surface } => {
+                let mut surf = surface
+                    .data::<Arc<Mutex<Surface>>>()
+                    .unwrap()
+                    .lock()
+                    .unwrap();
+                if surf.s_role.is_some() {
+                    data_init.post_error(id, 0u32, "wl_surface already has a role");
+                    return;
+                }
+                surf.s_role = Some(Role::input_popup);
+
+                let popup = InputPopup {
+                    ip_resource: data_init.init(id, data.clone()),
+                    ip_surface: surf.s_id.clone(),
+                };
+                let mut im = data.lock().unwrap();
+                // Show the popup right away if a text input is active
+                if let Some((text_input, parent)) = im.im_text_input.clone() {
+                    atmos.add_new_top_subsurf(&parent, &popup.ip_surface);
+                    let cursor = text_input
+                        .data::<Arc<Mutex<TextInput>>>()
+                        .unwrap()
+                        .lock()
+                        .unwrap()
+                        .ti_current
+                        .ts_cursor_rect;
+                    InputMethod::place_popup(&mut atmos, &popup, cursor);
+                }
+                im.im_popups.push(popup);
+            }
+            zim::Request::GrabKeyboard { keyboard } => {
+                let grab = data_init.init(keyboard, data.clone());
+                let (file, size) = seat::create_keymap_file(state.c_input.get_keymap_text(None));
+                grab.keymap(wl_keyboard::KeymapFormat::XkbV1, file.as_fd(), size);
+                // We don't repeat keys, same as wl_keyboard
+                grab.repeat_info(0, 0);
+
+                if current {
+                    let mut im = data.lock().unwrap();
+                    im.im_keyboard_grab = Some(grab);
+                    im.im_grab_keymap = None;
+                }
+            }
+            zim::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zim::ZwpInputMethodV2,
+        data: &Arc<Mutex<InputMethod>>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        if is_current(&atmos, data) {
+            let mut im = data.lock().unwrap();
+            im.im_text_input = None;
+            for popup in im.im_popups.iter() {
+                atmos.remove_subsurf(&popup.ip_surface);
+            }
+            atmos.set_input_method(None);
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zips::ZwpInputPopupSurfaceV2, Arc<Mutex<InputMethod>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zips::ZwpInputPopupSurfaceV2,
+        request: zips::Request,
+        data: &Arc<Mutex<InputMethod>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zips::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zips::ZwpInputPopupSurfaceV2,
+        data: &Arc<Mutex<InputMethod>>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        let mut im = data.lock().unwrap();
+        let active = im.im_text_input.is_some();
+
+        if let Some(index) = im.im_popups.iter().position(|p| p.ip_resource == *resource) {
+            let popup = im.im_popups.remove(index);
+            if active {
+                atmos.remove_subsurf(&popup.ip_surface);
+            }
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zimkg::ZwpInputMethodKeyboardGrabV2, Arc<Mutex<InputMethod>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zimkg::ZwpInputMethodKeyboardGrabV2,
+        request: zimkg::Request,
+        data: &Arc<Mutex<InputMethod>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zimkg::Request::Release => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zimkg::ZwpInputMethodKeyboardGrabV2,
+        data: &Arc<Mutex<InputMethod>>,
+    ) {
+        let mut im = data.lock().unwrap();
+        if im.im_keyboard_grab.as_ref() == Some(resource) {
+            im.im_keyboard_grab = None;
+        }
+    }
+}
@@ -0,0 +1,259 @@
+// Implementation of the wlr-layer-shell protocol
+//
+// This is used by desktop components like panels, docks, and
+// wallpapers. Instead of being managed like windows, layer surfaces
+// are anchored to an output and drawn above or below the app windows.
+extern crate wayland_server as ws;
+
+use ws::Resource;
+
+use super::protocol::wlr_layer_shell::{
+    zwlr_layer_shell_v1 as zlsh, zwlr_layer_shell_v1::Layer, zwlr_layer_surface_v1 as zlsv,
+};
+use super::role::Role;
+use super::surface::Surface;
+use super::xdg_shell::ShellSurface;
+use crate::category5::atmosphere::{Atmosphere, LayerProperties, LayerState, SurfaceId};
+use crate::category5::vkcomp::wm;
+use crate::category5::Climate;
+use utils::log;
+
+use std::ops::DerefMut;
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zlsh::ZwlrLayerShellV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zlsh::ZwlrLayerShellV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zlsh::ZwlrLayerShellV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zlsh::ZwlrLayerShellV1,
+        request: zlsh::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zlsh::Request::GetLayerSurface {
+                id,
+                surface,
+                output,
+                layer,
+                namespace,
+            } => {
+                let mut atmos = state.c_atmos.lock().unwrap();
+                let layer = match layer.into_result() {
+                    Ok(layer) => layer,
+                    Err(_) => {
+                        data_init.post_error(id, zlsh::Error::InvalidLayer, "Unknown layer");
+                        return;
+                    }
+                };
+
+                let surf_cell = surface.data::<Arc<Mutex<Surface>>>().unwrap().clone();
+                let mut surf = surf_cell.lock().unwrap();
+                if surf.s_role.is_some() {
+                    data_init.post_error(
+                        id,
+                        zlsh::Error::Role,
+                        "wl_surface already has a role assigned",
+                    );
+                    return;
+                }
+                log::debug!(
+                    "Creating layer surface {:?} for {}",
+                    surf.s_id.get_raw_id(),
+                    namespace
+                );
+
+                let output = atmos.get_layer_surface_output(
+                    output.as_ref().and_then(|o| o.data::<u32>().copied()),
+                );
+                let props = LayerProperties::new(layer);
+                let layer_surf = Arc::new(Mutex::new(LayerSurface {
+                    l_id: surf.s_id.clone(),
+                    l_surface: surf_cell.clone(),
+                    l_pending: props.clone(),
+                }));
+                let layer_resource = data_init.init(id, layer_surf.clone());
+                surf.s_role = Some(Role::layer_surface(layer_surf));
+
+                atmos.add_layer_surface(&surf.s_id, LayerState::new(layer_resource, props, output));
+                let output = atmos.get_layer_surface_output_index(&surf.s_id);
+                atmos.add_wm_task(wm::task::Task::set_layer_surface {
+                    id: surf.s_id.clone(),
+                    layer,
+                    output: output,
+                });
+            }
+            zlsh::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zlsh::ZwlrLayerShellV1,
+        data: &(),
+    ) {
+    }
+}
+
+/// Private state for a layer surface
+///
+/// Requests are recorded in `l_pending`, and are applied to the
+/// atmosphere when the wl_surface is committed.
+pub struct LayerSurface {
+    l_id: SurfaceId,
+    l_surface: Arc<Mutex<Surface>>,
+    l_pending: LayerProperties,
+}
+
+impl LayerSurface {
+    /// Apply the pending state and rearrange the layers
+    ///
+    /// This is called from the commit of our wl_surface.
+    pub fn commit(&mut self, atmos: &mut Atmosphere) {
+        let old_layer = {
+            let mut state = match atmos.a_layer_state.get_mut(&self.l_id) {
+                Some(state) => state,
+                None => return,
+            };
+            if !self.l_pending.size_is_valid() {
+                state.ls_resource.post_error(
+                    zlsv::Error::InvalidSize,
+                    "A width or height of zero requires anchoring to opposite edges",
+                );
+                return;
+            }
+
+            let old_layer = state.ls_props.lp_layer;
+            state.ls_props = self.l_pending.clone();
+            old_layer
+        };
+
+        if old_layer != self.l_pending.lp_layer {
+            let output = atmos.get_layer_surface_output_index(&self.l_id);
+            atmos.add_wm_task(wm::task::Task::set_layer_surface {
+                id: self.l_id.clone(),
+                layer: self.l_pending.lp_layer,
+                output: output,
+            });
+        }
+        atmos.arrange_layers();
+
+        // Exclusive surfaces on the upper layers take the keyboard right
+        // away, and surfaces which no longer want it give it back
+        match atmos.layer_accepts_keyboard(&self.l_id) {
+            true => {
+                let upper = match self.l_pending.lp_layer {
+                    Layer::Top | Layer::Overlay => true,
+                    _ => false,
+                };
+                if upper
+                    && self.l_pending.lp_keyboard_interactivity
+                        == zlsv::KeyboardInteractivity::Exclusive
+                {
+                    atmos.focus_layer_surface(&self.l_id);
+                }
+            }
+            false => {
+                if atmos.get_win_focus().as_ref() == Some(&self.l_id) {
+                    atmos.unfocus_layer_surface();
+                }
+            }
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zlsv::ZwlrLayerSurfaceV1, Arc<Mutex<LayerSurface>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zlsv::ZwlrLayerSurfaceV1,
+        request: zlsv::Request,
+        data: &Arc<Mutex<LayerSurface>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        let mut ls = data.lock().unwrap();
+
+        match request {
+            zlsv::Request::SetSize { width, height } => ls.l_pending.lp_size = (width, height),
+            zlsv::Request::SetAnchor { anchor } => match anchor.into_result() {
+                Ok(anchor) => ls.l_pending.lp_anchor = anchor,
+                Err(_) => resource.post_error(zlsv::Error::InvalidAnchor, "Unknown anchor"),
+            },
+            zlsv::Request::SetExclusiveZone { zone } => ls.l_pending.lp_exclusive_zone = zone,
+            zlsv::Request::SetMargin {
+                top,
+                right,
+                bottom,
+                left,
+            } => ls.l_pending.lp_margin = (top, right, bottom, left),
+            zlsv::Request::SetKeyboardInteractivity {
+                keyboard_interactivity,
+            } => match keyboard_interactivity.into_result() {
+                Ok(mode) => ls.l_pending.lp_keyboard_interactivity = mode,
+                Err(_) => resource.post_error(
+                    zlsv::Error::InvalidKeyboardInteractivity,
+                    "Unknown keyboard interactivity",
+                ),
+            },
+            zlsv::Request::GetPopup { popup } => {
+                popup
+                    .data::<Arc<Mutex<ShellSurface>>>()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .set_popup_parent(atmos.deref_mut(), &ls.l_id);
+            }
+            // We never wait on the client to resize, so there is nothing
+            // to track here
+            zlsv::Request::AckConfigure { serial } => {}
+            zlsv::Request::SetLayer { layer } => match layer.into_result() {
+                Ok(layer) => ls.l_pending.lp_layer = layer,
+                Err(_) => resource.post_error(zlsh::Error::InvalidLayer, "Unknown layer"),
+            },
+            zlsv::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zlsv::ZwlrLayerSurfaceV1,
+        data: &Arc<Mutex<LayerSurface>>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        let (id, surf_cell) = {
+            let ls = data.lock().unwrap();
+            (ls.l_id.clone(), ls.l_surface.clone())
+        };
+
+        surf_cell.lock().unwrap().s_role = None;
+        // The surface may have already been destroyed and cleaned up
+        if atmos.is_layer_surface(&id) {
+            atmos.remove_layer_surface(&id);
+            atmos.add_wm_task(wm::task::Task::close_window(id));
+        }
+    }
+}
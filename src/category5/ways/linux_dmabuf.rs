@@ -8,26 +8,34 @@ extern crate wayland_server as ws;
 
 use crate::category5::Atmosphere;
 use crate::category5::Climate;
-use utils::log;
+use utils::{log, Context, Result};
 use ws::protocol::wl_buffer;
+use ws::Resource;
 
 use dakota as dak;
 use dakota::{Dmabuf, DmabufPlane};
 use wayland_protocols::wp::linux_dmabuf::zv1::server::{
-    zwp_linux_buffer_params_v1 as zlbpv1, zwp_linux_dmabuf_v1 as zldv1,
+    zwp_linux_buffer_params_v1 as zlbpv1, zwp_linux_dmabuf_feedback_v1 as zldfv1,
+    zwp_linux_dmabuf_v1 as zldv1,
 };
 
-#[cfg(debug_assertions)]
-use std::os::unix::io::AsRawFd;
-use std::os::unix::io::OwnedFd;
+use nix::unistd::ftruncate;
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
 use std::sync::{Arc, Mutex};
 
 // drm formats specified in mesa's private wl_drm
 // protocol. We need this for mesa clients.
 //
 // gross
-const WL_DRM_FORMAT_XRGB8888: u32 = 0x34325258;
-const WL_DRM_FORMAT_ARGB8888: u32 = 0x34325241;
+pub const WL_DRM_FORMAT_XRGB8888: u32 = 0x34325258;
+pub const WL_DRM_FORMAT_ARGB8888: u32 = 0x34325241;
+// YUV formats used by video decoders
+const DRM_FORMAT_NV12: u32 = 0x3231564e;
+const DRM_FORMAT_P010: u32 = 0x30313050;
+const DRM_FORMAT_YUV420: u32 = 0x32315559;
 
 #[allow(unused_variables)]
 impl ws::GlobalDispatch<zldv1::ZwpLinuxDmabufV1, ()> for Climate {
@@ -41,23 +49,191 @@ impl ws::GlobalDispatch<zldv1::ZwpLinuxDmabufV1, ()> for Climate {
     ) {
         let dma = data_init.init(resource, ());
 
-        let drm_formats = [WL_DRM_FORMAT_XRGB8888, WL_DRM_FORMAT_ARGB8888];
+        // Starting with version 4 clients get the formats from
+        // a feedback object instead
+        if dma.version() >= 4 {
+            return;
+        }
 
         // we need to advertise the format/modifier
         // combinations we support
-        for format in drm_formats {
+        let table = match state.c_dmabuf_formats.as_ref() {
+            Some(formats) => &formats.df_table,
+            None => return,
+        };
+        let mut formats: Vec<u32> = table.iter().map(|(format, _)| *format).collect();
+        formats.dedup();
+        for format in formats {
             dma.format(format);
+        }
+        for (format, modifier) in table.iter() {
+            let mod_hi = (modifier >> 32) as u32;
+            let mod_low = (modifier & 0xffffffff) as u32;
+            dma.modifier(*format, mod_hi, mod_low);
+        }
+    }
+}
+
+/// Get the format/modifier pairs that can be imported
+///
+/// The renderer's modifiers are paired with each RGB format we accept,
+/// followed by the YUV formats the renderer can sample. This is also the
+/// order of the entries in the feedback format table.
+fn get_format_table(render_mods: &[u64], ycbcr_formats: &[(u32, u64)]) -> Vec<(u32, u64)> {
+    let mut ret = Vec::new();
+
+    for format in [WL_DRM_FORMAT_XRGB8888, WL_DRM_FORMAT_ARGB8888] {
+        for modifier in render_mods.iter() {
+            ret.push((format, *modifier));
+        }
+
+        // Send our linear modifier as it is always supported
+        if !render_mods.contains(&0) {
+            ret.push((format, 0));
+        }
+    }
+    ret.extend_from_slice(ycbcr_formats);
+
+    ret
+}
+
+/// Put the feedback format table in a file we can share with clients
+///
+/// Every client is handed the same file, so on Linux it is sealed to
+/// keep them from changing it for each other.
+fn create_table_file(table: &[(u32, u64)]) -> Result<File> {
+    // The table is an array of 16 byte entries: the format, four bytes
+    // of padding, and then the modifier
+    let mut table_bytes = Vec::with_capacity(table.len() * 16);
+    for (format, modifier) in table.iter() {
+        table_bytes.extend_from_slice(&format.to_ne_bytes());
+        table_bytes.extend_from_slice(&0u32.to_ne_bytes());
+        table_bytes.extend_from_slice(&modifier.to_ne_bytes());
+    }
 
-            for modifier in state.c_primary_render_mods.iter() {
-                let mod_hi = (modifier >> 32) as u32;
-                let mod_low = (modifier & 0xffffffff) as u32;
-                dma.modifier(format, mod_hi, mod_low);
+    #[cfg(target_os = "freebsd")]
+    let fd = unsafe {
+        libc::shm_open(
+            libc::SHM_ANON,
+            libc::O_CREAT | libc::O_RDWR | libc::O_EXCL | libc::O_CLOEXEC,
+            0o600,
+        )
+    };
+    #[cfg(target_os = "linux")]
+    let fd = unsafe {
+        let memfd_name = std::ffi::CString::new("cat5_dmabuf_formats").unwrap();
+        libc::memfd_create(
+            memfd_name.as_ptr() as *mut i8,
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Could not create the dmabuf format table file");
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+
+    ftruncate(&file, table_bytes.len() as i64)
+        .context("Could not truncate the dmabuf format table file")?;
+    file.write_all(table_bytes.as_slice())
+        .context("Could not write to the dmabuf format table file")?;
+
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+        if libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) < 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("Could not seal the dmabuf format table file");
+        }
+    }
+
+    Ok(file)
+}
+
+/// The formats and modifiers we can import, split into tranches
+///
+/// This is built once at startup since neither the GPU nor the displays
+/// change while we are running. The table file is shared by every
+/// feedback object.
+pub struct DmabufFormats {
+    /// Format/modifier pairs, in the order of the feedback format table
+    df_table: Vec<(u32, u64)>,
+    /// Indices of the table entries our displays can also scan out. These
+    /// go in the first tranche so that clients prefer them.
+    df_preferred: Vec<u16>,
+    /// Indices of everything else
+    df_other: Vec<u16>,
+    /// The table in shared memory and its size in bytes. If this could
+    /// not be created we don't send feedback.
+    df_table_file: Option<(File, u32)>,
+}
+
+impl DmabufFormats {
+    /// Build the format table
+    ///
+    /// `render_mods` and `ycbcr_formats` are what the primary GPU can
+    /// import, and `scanout_mods` are the modifiers the displays can use.
+    pub fn new(render_mods: &[u64], ycbcr_formats: &[(u32, u64)], scanout_mods: &[u64]) -> Self {
+        let table = get_format_table(render_mods, ycbcr_formats);
+
+        // Displays report their modifiers for ARGB8888, which XRGB8888
+        // only differs from in how alpha is treated
+        let (preferred, other) = (0..table.len() as u16).partition(|i| {
+            let (format, modifier) = table[*i as usize];
+            (format == WL_DRM_FORMAT_XRGB8888 || format == WL_DRM_FORMAT_ARGB8888)
+                && scanout_mods.contains(&modifier)
+        });
+
+        let table_file = match create_table_file(&table) {
+            Ok(file) => Some((file, (table.len() * 16) as u32)),
+            Err(e) => {
+                log::error!("Not sending dmabuf feedback: {:?}", e);
+                None
             }
+        };
+
+        Self {
+            df_table: table,
+            df_preferred: preferred,
+            df_other: other,
+            df_table_file: table_file,
+        }
+    }
+}
+
+/// Send the formats and device to use for allocating buffers
+///
+/// We only render with the primary GPU, so the same feedback is sent
+/// for every surface. Formats our displays support are sent in their
+/// own tranche ahead of the rest.
+fn send_feedback(feedback: &zldfv1::ZwpLinuxDmabufFeedbackV1, state: &Climate) {
+    let formats = match state.c_dmabuf_formats.as_ref() {
+        Some(formats) => formats,
+        None => return,
+    };
+    let (file, size) = match formats.df_table_file.as_ref() {
+        Some(table_file) => table_file,
+        None => return,
+    };
 
-            // Send our linear modifier as it is always supported
-            dma.modifier(format, 0, 0);
+    // dev_t of the GPU we import buffers on
+    let (major, minor) = state.c_atmos.lock().unwrap().get_drm_dev();
+    let dev = libc::makedev(major as _, minor as _).to_ne_bytes().to_vec();
+
+    feedback.format_table(file.as_fd(), *size);
+    feedback.main_device(dev.clone());
+    for tranche in [&formats.df_preferred, &formats.df_other] {
+        if tranche.is_empty() {
+            continue;
         }
+        let indices: Vec<u8> = tranche.iter().flat_map(|i| i.to_ne_bytes()).collect();
+
+        feedback.tranche_target_device(dev.clone());
+        feedback.tranche_formats(indices);
+        feedback.tranche_flags(zldfv1::TrancheFlags::empty());
+        feedback.tranche_done();
     }
+    feedback.done();
 }
 
 // Dispatch<Interface, Userdata>
@@ -74,10 +250,23 @@ impl ws::Dispatch<zldv1::ZwpLinuxDmabufV1, ()> for Climate {
     ) {
         match request {
             zldv1::Request::CreateParams { params_id } => {
-                let params = Arc::new(Mutex::new(Params { p_bufs: Vec::new() }));
+                let params = Arc::new(Mutex::new(Params {
+                    p_bufs: Vec::new(),
+                    p_used: false,
+                }));
 
                 data_init.init(params_id, params);
             }
+            zldv1::Request::GetDefaultFeedback { id } => {
+                let feedback = data_init.init(id, ());
+                send_feedback(&feedback, state);
+            }
+            // Our feedback doesn't depend on the surface since all
+            // surfaces are imported on the same GPU
+            zldv1::Request::GetSurfaceFeedback { id, surface } => {
+                let feedback = data_init.init(id, ());
+                send_feedback(&feedback, state);
+            }
             _ => {}
         };
     }
@@ -102,9 +291,16 @@ impl ws::Dispatch<zlbpv1::ZwpLinuxBufferParamsV1, Arc<Mutex<Params>>> for Climat
         dhandle: &ws::DisplayHandle,
         data_init: &mut ws::DataInit<'_, Self>,
     ) {
+        let format_table = match state.c_dmabuf_formats.as_ref() {
+            Some(formats) => formats.df_table.as_slice(),
+            None => &[],
+        };
         data.lock().unwrap().handle_request(
             &mut state.c_scene,
             state.c_atmos.lock().as_mut().unwrap(),
+            format_table,
+            client,
+            dhandle,
             request,
             resource,
             data_init,
@@ -120,9 +316,70 @@ impl ws::Dispatch<zlbpv1::ZwpLinuxBufferParamsV1, Arc<Mutex<Params>>> for Climat
     }
 }
 
+#[allow(unused_variables)]
+impl ws::Dispatch<zldfv1::ZwpLinuxDmabufFeedbackV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zldfv1::ZwpLinuxDmabufFeedbackV1,
+        request: zldfv1::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zldfv1::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zldfv1::ZwpLinuxDmabufFeedbackV1,
+        data: &(),
+    ) {
+    }
+}
+
+/// Get the vertical subsampling of each plane in a format
+///
+/// The length of the result is the number of planes the format is
+/// made of. Returns None if we can't import this format.
+fn get_plane_subsampling(format: u32) -> Option<&'static [u64]> {
+    match format {
+        WL_DRM_FORMAT_XRGB8888 | WL_DRM_FORMAT_ARGB8888 => Some(&[1]),
+        // The chroma planes of 4:2:0 formats have half as many rows
+        DRM_FORMAT_NV12 | DRM_FORMAT_P010 => Some(&[1, 2]),
+        DRM_FORMAT_YUV420 => Some(&[1, 2, 2]),
+        _ => None,
+    }
+}
+
+/// Get the size of the file backing a dmabuf fd
+///
+/// Returns None if the size can't be queried, which some drivers
+/// do not support.
+fn get_fd_size(fd: &OwnedFd) -> Option<u64> {
+    let pos = unsafe { libc::lseek(fd.as_raw_fd(), 0, libc::SEEK_CUR) };
+    if pos < 0 {
+        return None;
+    }
+    let size = unsafe { libc::lseek(fd.as_raw_fd(), 0, libc::SEEK_END) };
+    if size < 0 {
+        return None;
+    }
+    // Put the seek position back where it was
+    unsafe { libc::lseek(fd.as_raw_fd(), pos, libc::SEEK_SET) };
+
+    Some(size as u64)
+}
+
 struct Params {
     // The list of added dma buffers
     p_bufs: Vec<DmabufPlane>,
+    /// Has a buffer already been created from these params
+    p_used: bool,
 }
 
 impl Params {
@@ -131,11 +388,44 @@ impl Params {
         &mut self,
         scene: &mut dak::Scene,
         atmos: &mut Atmosphere,
+        format_table: &[(u32, u64)],
+        client: &ws::Client,
+        dhandle: &ws::DisplayHandle,
         req: zlbpv1::Request,
         params: &zlbpv1::ZwpLinuxBufferParamsV1,
         data_init: &mut ws::DataInit<'_, Climate>,
     ) {
         match req {
+            zlbpv1::Request::Create {
+                width,
+                height,
+                format,
+                flags,
+            } => {
+                log::debug!(
+                    "linux_dmabuf_params: Creating a new wl_buffer of size {}x{}",
+                    width,
+                    height
+                );
+
+                let dmabuf = match self.create(params, format_table, width, height, format) {
+                    Some(dmabuf) => dmabuf,
+                    None => return,
+                };
+                if !self.test_import(scene, atmos, &dmabuf, flags) {
+                    params.failed();
+                    return;
+                }
+
+                // The client doesn't give us an id for this, so we create
+                // the wl_buffer ourselves
+                match client.create_resource::<wl_buffer::WlBuffer, dak::Dmabuf, Climate>(
+                    dhandle, 1, dmabuf,
+                ) {
+                    Ok(buffer) => params.created(&buffer),
+                    Err(_) => params.failed(),
+                }
+            }
             zlbpv1::Request::CreateImmed {
                 buffer_id,
                 width,
@@ -152,21 +442,18 @@ impl Params {
                 // First create our userdata and initialize our wl_buffer. We need this
                 // so we can have a valid buffer object to use as the release data in
                 // the dmabuf import
-                let dmabuf = self.create(width, height, format);
-                let tmp = atmos.mint_buffer_id(scene);
-                // Test that we can import this dmabuf
-                match scene.define_resource_from_dmabuf(&tmp, &dmabuf, None) {
-                    Ok(res) => res,
-                    Err(e) => {
-                        log::error!("Failed to import dmabuf: {:?}", e);
-                        params.failed();
-                        return;
-                    }
+                let dmabuf = match self.create(params, format_table, width, height, format) {
+                    Some(dmabuf) => dmabuf,
+                    None => return,
                 };
+                // There is no failed event for this request, the only way to
+                // report this is with a protocol error
+                if !self.test_import(scene, atmos, &dmabuf, flags) {
+                    params.post_error(zlbpv1::Error::InvalidWlBuffer, "Could not import dmabuf");
+                    return;
+                }
 
-                let buffer = data_init.init(buffer_id, dmabuf);
-
-                params.created(&buffer);
+                data_init.init(buffer_id, dmabuf);
             }
             zlbpv1::Request::Add {
                 fd,
@@ -175,25 +462,151 @@ impl Params {
                 stride,
                 modifier_hi,
                 modifier_lo,
-            } => self.add(fd, plane_idx, offset, stride, modifier_hi, modifier_lo),
+            } => self.add(
+                params,
+                fd,
+                plane_idx,
+                offset,
+                stride,
+                modifier_hi,
+                modifier_lo,
+            ),
             zlbpv1::Request::Destroy => log::debug!("Destroying Dmabuf params"),
             _ => unimplemented!(),
         };
     }
 
+    /// Test that we can import this dmabuf
+    fn test_import(
+        &self,
+        scene: &mut dak::Scene,
+        atmos: &mut Atmosphere,
+        dmabuf: &Dmabuf,
+        flags: ws::WEnum<zlbpv1::Flags>,
+    ) -> bool {
+        // We can't display y-inverted or interlaced buffers
+        match flags.into_result() {
+            Ok(flags) if flags.is_empty() => {}
+            _ => {
+                log::error!("Unsupported dmabuf flags {:?}", flags);
+                return false;
+            }
+        }
+
+        let tmp = atmos.mint_buffer_id(scene);
+        match scene.define_resource_from_dmabuf(&tmp, dmabuf, None) {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("Failed to import dmabuf: {:?}", e);
+                false
+            }
+        }
+    }
+
     /// Constructs a Dmabuf object from these parameters
-    fn create(&mut self, width: i32, height: i32, _format: u32) -> Dmabuf {
-        let mut dmabuf = dak::Dmabuf::new(width, height);
+    ///
+    /// This validates the planes that were added against the format, and
+    /// posts a protocol error if they are invalid.
+    fn create(
+        &mut self,
+        params: &zlbpv1::ZwpLinuxBufferParamsV1,
+        format_table: &[(u32, u64)],
+        width: i32,
+        height: i32,
+        format: u32,
+    ) -> Option<Dmabuf> {
+        if self.p_used {
+            params.post_error(
+                zlbpv1::Error::AlreadyUsed,
+                "A buffer was already created from these params",
+            );
+            return None;
+        }
+        self.p_used = true;
+
+        if width <= 0 || height <= 0 {
+            params.post_error(
+                zlbpv1::Error::InvalidDimensions,
+                format!("Invalid buffer size {}x{}", width, height),
+            );
+            return None;
+        }
+
+        let subsampling = match get_plane_subsampling(format) {
+            Some(subsampling) => subsampling,
+            None => {
+                params.post_error(
+                    zlbpv1::Error::InvalidFormat,
+                    format!("Unsupported format {:#x}", format),
+                );
+                return None;
+            }
+        };
+
+        // Planes must be added for exactly the indices the format uses
+        self.p_bufs.sort_by_key(|plane| plane.db_plane_idx);
+        if self.p_bufs.len() != subsampling.len()
+            || self
+                .p_bufs
+                .iter()
+                .enumerate()
+                .any(|(i, plane)| plane.db_plane_idx != i as u32)
+        {
+            params.post_error(
+                zlbpv1::Error::Incomplete,
+                format!("Format {:#x} needs {} planes", format, subsampling.len()),
+            );
+            return None;
+        }
+
+        let modifier = self.p_bufs[0].db_mods;
+        if !format_table.contains(&(format, modifier)) {
+            params.post_error(
+                zlbpv1::Error::InvalidFormat,
+                format!(
+                    "Unsupported format/modifier pair {:#x}/{:#x}",
+                    format, modifier
+                ),
+            );
+            return None;
+        }
+
+        for (plane, vsub) in self.p_bufs.iter().zip(subsampling.iter()) {
+            // The end of the last row of this plane
+            let rows = (height as u64 + vsub - 1) / vsub;
+            let end = (plane.db_stride as u64)
+                .checked_mul(rows)
+                .and_then(|size| size.checked_add(plane.db_offset as u64));
+            let in_bounds = match (end, get_fd_size(&plane.db_fd)) {
+                (Some(end), Some(size)) => end <= size,
+                (Some(end), None) => end <= u32::MAX as u64,
+                (None, _) => false,
+            };
+
+            if !in_bounds {
+                params.post_error(
+                    zlbpv1::Error::OutOfBounds,
+                    format!(
+                        "Plane {} with offset {} and stride {} is out of bounds",
+                        plane.db_plane_idx, plane.db_offset, plane.db_stride
+                    ),
+                );
+                return None;
+            }
+        }
+
+        let mut dmabuf = dak::Dmabuf::new(width, height, format);
 
         for plane in self.p_bufs.drain(0..) {
             dmabuf.db_planes.push(plane);
         }
 
-        return dmabuf;
+        return Some(dmabuf);
     }
 
     fn add(
         &mut self,
+        params: &zlbpv1::ZwpLinuxBufferParamsV1,
         fd: OwnedFd,
         plane_idx: u32,
         offset: u32,
@@ -201,6 +614,29 @@ impl Params {
         mod_hi: u32,
         mod_low: u32,
     ) {
+        if self.p_used {
+            params.post_error(
+                zlbpv1::Error::AlreadyUsed,
+                "A buffer was already created from these params",
+            );
+            return;
+        }
+        // The protocol allows for at most four planes
+        if plane_idx >= 4 {
+            params.post_error(
+                zlbpv1::Error::PlaneIdx,
+                format!("Plane index {} is out of bounds", plane_idx),
+            );
+            return;
+        }
+        if self.p_bufs.iter().any(|p| p.db_plane_idx == plane_idx) {
+            params.post_error(
+                zlbpv1::Error::PlaneSet,
+                format!("Plane {} was already set", plane_idx),
+            );
+            return;
+        }
+
         let d = DmabufPlane::new(
             fd,
             plane_idx,
@@ -208,6 +644,16 @@ impl Params {
             stride,
             (mod_hi as u64) << 32 | (mod_low as u64),
         );
+        // Every plane of a buffer uses the same modifier
+        if let Some(first) = self.p_bufs.first() {
+            if first.db_mods != d.db_mods {
+                params.post_error(
+                    zlbpv1::Error::InvalidFormat,
+                    "All planes must use the same modifier",
+                );
+                return;
+            }
+        }
         log::debug!("linux_dmabuf_params: Adding {:#?}", d);
         self.p_bufs.push(d);
     }
@@ -53,19 +53,38 @@
 
 // Supported protocols
 pub mod compositor;
-mod data_devices;
+pub mod data_devices;
+pub mod foreign_toplevel;
+mod fractional_scale;
+mod idle_inhibit;
+mod idle_notify;
+pub mod input_method;
 mod keyboard;
+mod layer_shell;
 pub mod linux_dmabuf;
 mod pointer;
+mod pointer_constraints;
+pub mod presentation_time;
+pub mod primary_selection;
 pub mod protocol;
+mod relative_pointer;
+pub mod screencopy;
 pub mod seat;
+mod session_lock;
 pub mod shm;
 pub mod surface;
+pub mod text_input;
+mod viewporter;
+mod virtual_keyboard;
+mod virtual_pointer;
 mod wl_drm;
-mod wl_output;
+pub mod wl_output;
 pub mod wl_region;
 mod wl_shell;
 mod wl_subcompositor;
+pub mod xdg_activation;
+mod xdg_decoration;
+mod xdg_output;
 pub mod xdg_shell;
 
 // Utils
@@ -0,0 +1,259 @@
+// Implementation of the pointer constraints protocol
+//
+// This lets clients lock the pointer in place or confine it to part
+// of their surface. The constraints themselves are enforced by the
+// atmosphere whenever the cursor moves.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::wp::pointer_constraints::zv1::server::{
+    zwp_confined_pointer_v1 as zcp, zwp_locked_pointer_v1 as zlp, zwp_pointer_constraints_v1 as zpc,
+};
+use ws::protocol::{wl_region, wl_surface};
+use ws::Resource;
+
+use super::surface::Surface;
+use super::wl_region::Region;
+use crate::category5::atmosphere::{Atmosphere, ConstraintKind, PointerConstraint, SurfaceId};
+use crate::category5::Climate;
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zpc::ZwpPointerConstraintsV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zpc::ZwpPointerConstraintsV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+/// Get the SurfaceId of a wl_surface
+fn get_surface_id(surface: &wl_surface::WlSurface) -> SurfaceId {
+    surface
+        .data::<Arc<Mutex<Surface>>>()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .s_id
+        .clone()
+}
+
+/// Copy the current contents of a wl_region
+///
+/// Constraints keep the region as it was when the request was made,
+/// even if the client changes or destroys the wl_region afterwards.
+fn copy_region(region: Option<wl_region::WlRegion>) -> Option<Region> {
+    region
+        .as_ref()
+        .and_then(|r| r.data::<Arc<Mutex<Region>>>())
+        .map(|r| r.lock().unwrap().clone())
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zpc::ZwpPointerConstraintsV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zpc::ZwpPointerConstraintsV1,
+        request: zpc::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zpc::Request::LockPointer {
+                id,
+                surface,
+                pointer,
+                region,
+                lifetime,
+            } => {
+                let surf_id = get_surface_id(&surface);
+                let mut atmos = state.c_atmos.lock().unwrap();
+                if atmos.has_pointer_constraint(&surf_id) {
+                    data_init.post_error(
+                        id,
+                        zpc::Error::AlreadyConstrained,
+                        "wl_surface already has a pointer constraint",
+                    );
+                    return;
+                }
+
+                let lock = data_init.init(id, surface);
+                atmos.add_pointer_constraint(
+                    &surf_id,
+                    PointerConstraint::new(
+                        ConstraintKind::Lock(lock),
+                        lifetime == ws::WEnum::Value(zpc::Lifetime::Oneshot),
+                        copy_region(region),
+                    ),
+                );
+            }
+            zpc::Request::ConfinePointer {
+                id,
+                surface,
+                pointer,
+                region,
+                lifetime,
+            } => {
+                let surf_id = get_surface_id(&surface);
+                let mut atmos = state.c_atmos.lock().unwrap();
+                if atmos.has_pointer_constraint(&surf_id) {
+                    data_init.post_error(
+                        id,
+                        zpc::Error::AlreadyConstrained,
+                        "wl_surface already has a pointer constraint",
+                    );
+                    return;
+                }
+
+                let confine = data_init.init(id, surface);
+                atmos.add_pointer_constraint(
+                    &surf_id,
+                    PointerConstraint::new(
+                        ConstraintKind::Confine(confine),
+                        lifetime == ws::WEnum::Value(zpc::Lifetime::Oneshot),
+                        copy_region(region),
+                    ),
+                );
+            }
+            zpc::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zpc::ZwpPointerConstraintsV1,
+        data: &(),
+    ) {
+    }
+}
+
+/// Set the region of the constraint `resource` on `surface`
+///
+/// The region takes effect on the next commit of the surface. Oneshot
+/// constraints which were already deactivated are ignored.
+fn set_pending_region(
+    atmos: &mut Atmosphere,
+    surface: &wl_surface::WlSurface,
+    resource: ws::backend::ObjectId,
+    region: Option<wl_region::WlRegion>,
+) {
+    if !surface.is_alive() {
+        return;
+    }
+    let id = get_surface_id(surface);
+    if let Some(mut pc) = atmos.a_pointer_constraint.get_mut(&id) {
+        if pc.pc_kind.id() == resource {
+            pc.pc_pending_region = Some(copy_region(region));
+        }
+    }
+}
+
+/// Forget the constraint `resource` on `surface` once it is destroyed
+fn remove_constraint(
+    atmos: &mut Atmosphere,
+    surface: &wl_surface::WlSurface,
+    resource: ws::backend::ObjectId,
+) {
+    if !surface.is_alive() {
+        return;
+    }
+    let id = get_surface_id(surface);
+    let matches = match atmos.a_pointer_constraint.get(&id) {
+        Some(pc) => pc.pc_kind.id() == resource,
+        None => false,
+    };
+    if matches {
+        atmos.remove_pointer_constraint(&id);
+    }
+}
+
+// The userdata is the wl_surface the pointer is locked to
+#[allow(unused_variables)]
+impl ws::Dispatch<zlp::ZwpLockedPointerV1, wl_surface::WlSurface> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zlp::ZwpLockedPointerV1,
+        request: zlp::Request,
+        data: &wl_surface::WlSurface,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        match request {
+            zlp::Request::SetCursorPositionHint {
+                surface_x,
+                surface_y,
+            } => {
+                if !data.is_alive() {
+                    return;
+                }
+                let id = get_surface_id(data);
+                if let Some(mut pc) = atmos.a_pointer_constraint.get_mut(&id) {
+                    if pc.pc_kind.id() == resource.id() {
+                        pc.pc_pending_cursor_hint = Some((surface_x, surface_y));
+                    }
+                }
+            }
+            zlp::Request::SetRegion { region } => {
+                set_pending_region(&mut atmos, data, resource.id(), region)
+            }
+            zlp::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zlp::ZwpLockedPointerV1,
+        data: &wl_surface::WlSurface,
+    ) {
+        remove_constraint(&mut state.c_atmos.lock().unwrap(), data, resource.id());
+    }
+}
+
+// The userdata is the wl_surface the pointer is confined to
+#[allow(unused_variables)]
+impl ws::Dispatch<zcp::ZwpConfinedPointerV1, wl_surface::WlSurface> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zcp::ZwpConfinedPointerV1,
+        request: zcp::Request,
+        data: &wl_surface::WlSurface,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zcp::Request::SetRegion { region } => set_pending_region(
+                &mut state.c_atmos.lock().unwrap(),
+                data,
+                resource.id(),
+                region,
+            ),
+            zcp::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zcp::ZwpConfinedPointerV1,
+        data: &wl_surface::WlSurface,
+    ) {
+        remove_constraint(&mut state.c_atmos.lock().unwrap(), data, resource.id());
+    }
+}
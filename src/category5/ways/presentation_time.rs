@@ -0,0 +1,130 @@
+// Implementation of the wp_presentation protocol
+//
+// Presentation feedback tells clients exactly when their content was
+// shown on screen, so that video players and games can time their
+// next frames against the display's refresh cycle.
+extern crate dakota as dak;
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::wp::presentation_time::server::{
+    wp_presentation as wpp, wp_presentation_feedback as wppf,
+};
+use ws::Resource;
+
+use super::surface::Surface;
+use crate::category5::Climate;
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<wpp::WpPresentation, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<wpp::WpPresentation>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let presentation = data_init.init(resource, ());
+        // All of our timestamps come from the display or clock_gettime
+        // using the monotonic clock
+        presentation.clock_id(libc::CLOCK_MONOTONIC as u32);
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<wpp::WpPresentation, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wpp::WpPresentation,
+        request: wpp::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            wpp::Request::Feedback { surface, callback } => {
+                let feedback = data_init.init(callback, ());
+                // The feedback is for the content of the next commit
+                surface
+                    .data::<Arc<Mutex<Surface>>>()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .s_state
+                    .cs_presentation_feedback
+                    .push(feedback);
+            }
+            wpp::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &wpp::WpPresentation,
+        data: &(),
+    ) {
+    }
+}
+
+// wp_presentation_feedback has no requests, it only delivers one of
+// the presented or discarded events before being destroyed
+#[allow(unused_variables)]
+impl ws::Dispatch<wppf::WpPresentationFeedback, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wppf::WpPresentationFeedback,
+        request: wppf::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &wppf::WpPresentationFeedback,
+        data: &(),
+    ) {
+    }
+}
+
+/// Tell the client its content was shown
+///
+/// `refresh_rate` is the refresh rate of the output in mHz, or zero
+/// if it is unknown.
+pub fn send_presented(
+    feedback: &wppf::WpPresentationFeedback,
+    time: &dak::PresentationTime,
+    refresh_rate: u32,
+) {
+    let secs = time.pt_time.as_secs();
+    let refresh = match refresh_rate {
+        0 => 0,
+        rate => (1_000_000_000_000 / rate as u64) as u32,
+    };
+
+    // We always wait for vblank before flipping
+    let mut flags = wppf::Kind::Vsync;
+    if time.pt_hw_clock {
+        flags |= wppf::Kind::HwClock | wppf::Kind::HwCompletion;
+    }
+
+    feedback.presented(
+        (secs >> 32) as u32,
+        secs as u32,
+        time.pt_time.subsec_nanos(),
+        refresh,
+        (time.pt_sequence >> 32) as u32,
+        time.pt_sequence as u32,
+        flags,
+    );
+}
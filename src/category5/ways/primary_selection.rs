@@ -0,0 +1,276 @@
+// Implementation of the primary selection protocol
+//
+// This is the X11 style selection, where highlighting text
+// copies it and middle clicking pastes it. It is tracked
+// separately from the wl_data_device clipboard.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::wp::primary_selection::zv1::server::{
+    zwp_primary_selection_device_manager_v1 as zpsdm, zwp_primary_selection_device_v1 as zpsd,
+    zwp_primary_selection_offer_v1 as zpso, zwp_primary_selection_source_v1 as zpss,
+};
+use ws::Resource;
+
+use super::seat::Seat;
+use crate::category5::atmosphere::{Atmosphere, ClientId};
+use crate::category5::Climate;
+use utils::log;
+
+use std::os::fd::AsFd;
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zpsdm::ZwpPrimarySelectionDeviceManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zpsdm::ZwpPrimarySelectionDeviceManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zpsdm::ZwpPrimarySelectionDeviceManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zpsdm::ZwpPrimarySelectionDeviceManagerV1,
+        request: zpsdm::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zpsdm::Request::CreateSource { id } => {
+                data_init.init(id, Arc::new(Mutex::new(PrimarySource::new())));
+            }
+            zpsdm::Request::GetDevice { id, seat } => {
+                let atmos = state.c_atmos.lock().unwrap();
+                let seat_cell = seat
+                    .data::<Arc<Mutex<Seat>>>()
+                    .expect("wl_seat did not have a Seat attached")
+                    .clone();
+
+                let device = data_init.init(id, seat_cell.clone());
+                let owner = {
+                    let mut s = seat_cell.lock().unwrap();
+                    s.add_primary_selection_device(&seat, device);
+                    s.s_id.clone()
+                };
+
+                if atmos.get_client_in_focus() == Some(owner.clone()) {
+                    send_primary_selection(&atmos, &owner);
+                }
+            }
+            zpsdm::Request::Destroy => {}
+            _ => {}
+        };
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zpsdm::ZwpPrimarySelectionDeviceManagerV1,
+        data: &(),
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zpsd::ZwpPrimarySelectionDeviceV1, Arc<Mutex<Seat>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zpsd::ZwpPrimarySelectionDeviceV1,
+        request: zpsd::Request,
+        data: &Arc<Mutex<Seat>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zpsd::Request::SetSelection { source, serial } => {
+                // Like the clipboard, only the client the user is
+                // interacting with may replace the primary selection
+                if !data.lock().unwrap().is_recent_input_serial(serial) {
+                    log::debug!(
+                        "Ignoring primary set_selection with stale serial {}",
+                        serial
+                    );
+                    if let Some(source) = source {
+                        source.cancelled();
+                    }
+                    return;
+                }
+
+                let mut atmos = state.c_atmos.lock().unwrap();
+                set_primary_selection(&mut atmos, source);
+            }
+            zpsd::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zpsd::ZwpPrimarySelectionDeviceV1,
+        data: &Arc<Mutex<Seat>>,
+    ) {
+        data.lock()
+            .unwrap()
+            .remove_primary_selection_device(resource);
+    }
+}
+
+/// Private state for a primary selection source
+///
+/// Holds the mime types advertised by the client.
+pub struct PrimarySource {
+    pub ps_mime_types: Vec<String>,
+}
+
+impl PrimarySource {
+    fn new() -> Self {
+        Self {
+            ps_mime_types: Vec::new(),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zpss::ZwpPrimarySelectionSourceV1, Arc<Mutex<PrimarySource>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zpss::ZwpPrimarySelectionSourceV1,
+        request: zpss::Request,
+        data: &Arc<Mutex<PrimarySource>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zpss::Request::Offer { mime_type } => {
+                data.lock().unwrap().ps_mime_types.push(mime_type)
+            }
+            zpss::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zpss::ZwpPrimarySelectionSourceV1,
+        data: &Arc<Mutex<PrimarySource>>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        if atmos.get_primary_selection().as_ref() == Some(resource) {
+            atmos.set_primary_selection(None);
+            if let Some(focus) = atmos.get_client_in_focus() {
+                send_primary_selection(&atmos, &focus);
+            }
+        }
+    }
+}
+
+// The offer just forwards receive requests to the source it was made from
+#[allow(unused_variables)]
+impl ws::Dispatch<zpso::ZwpPrimarySelectionOfferV1, zpss::ZwpPrimarySelectionSourceV1> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zpso::ZwpPrimarySelectionOfferV1,
+        request: zpso::Request,
+        data: &zpss::ZwpPrimarySelectionSourceV1,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zpso::Request::Receive { mime_type, fd } => {
+                if data.is_alive() {
+                    data.send(mime_type, fd.as_fd());
+                }
+            }
+            zpso::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zpso::ZwpPrimarySelectionOfferV1,
+        data: &zpss::ZwpPrimarySelectionSourceV1,
+    ) {
+    }
+}
+
+/// Replace the primary selection, cancelling the old source
+pub fn set_primary_selection(
+    atmos: &mut Atmosphere,
+    source: Option<zpss::ZwpPrimarySelectionSourceV1>,
+) {
+    let old = atmos.get_primary_selection();
+    if old == source {
+        return;
+    }
+
+    if let Some(old) = old {
+        if old.is_alive() {
+            old.cancelled();
+        }
+    }
+    atmos.set_primary_selection(source);
+
+    if let Some(focus) = atmos.get_client_in_focus() {
+        send_primary_selection(atmos, &focus);
+    }
+}
+
+/// Create an offer for `source` on the client owning `device`
+fn create_offer(
+    device: &zpsd::ZwpPrimarySelectionDeviceV1,
+    source: &zpss::ZwpPrimarySelectionSourceV1,
+) -> Option<zpso::ZwpPrimarySelectionOfferV1> {
+    let client = device.client()?;
+    let handle = ws::DisplayHandle::from(device.handle().upgrade()?);
+
+    let offer: zpso::ZwpPrimarySelectionOfferV1 = client
+        .create_resource::<_, _, Climate>(&handle, device.version(), source.clone())
+        .ok()?;
+    device.data_offer(&offer);
+
+    if let Some(ps) = source.data::<Arc<Mutex<PrimarySource>>>() {
+        for mime in ps.lock().unwrap().ps_mime_types.iter() {
+            offer.offer(mime.clone());
+        }
+    }
+
+    Some(offer)
+}
+
+/// Advertise the primary selection to a client
+///
+/// Like the clipboard, this is sent to a client right before it
+/// receives keyboard focus.
+pub fn send_primary_selection(atmos: &Atmosphere, client: &ClientId) {
+    let seat = match atmos.get_seat_from_client_id(client) {
+        Some(s) => s,
+        None => return,
+    };
+    let source = atmos.get_primary_selection().filter(|s| s.is_alive());
+
+    let seat = seat.lock().unwrap();
+    for si in seat.s_proxies.iter() {
+        for device in si.si_primary_selection_devices.iter() {
+            let offer = source.as_ref().and_then(|s| create_offer(device, s));
+            device.selection(offer.as_ref());
+        }
+    }
+}
@@ -0,0 +1,17 @@
+// Handle imports for the generated input-method bindings
+use wayland_protocols::wp::text_input::zv3::server::*;
+use wayland_scanner;
+use wayland_server;
+use wayland_server::protocol::*;
+
+pub mod __interfaces {
+    use wayland_server::protocol::__interfaces::*;
+    wayland_scanner::generate_interfaces!(
+        "src/category5/ways/protocol/input-method-unstable-v2.xml"
+    );
+}
+use self::__interfaces::*;
+
+// The input method events reuse the enums from zwp_text_input_v3,
+// which are found through the import above
+wayland_scanner::generate_server_code!("src/category5/ways/protocol/input-method-unstable-v2.xml");
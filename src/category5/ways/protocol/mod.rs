@@ -1 +1,7 @@
+pub mod input_method;
+pub mod virtual_keyboard;
 pub mod wl_drm;
+pub mod wlr_foreign_toplevel;
+pub mod wlr_layer_shell;
+pub mod wlr_screencopy;
+pub mod wlr_virtual_pointer;
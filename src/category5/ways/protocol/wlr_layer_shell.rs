@@ -0,0 +1,20 @@
+// Handle imports for the generated wlr-layer-shell bindings
+use wayland_protocols::xdg::shell::server::*;
+use wayland_scanner;
+use wayland_server;
+use wayland_server::protocol::*;
+
+pub mod __interfaces {
+    // layer surfaces can be the parent of xdg_popups, so we need
+    // the xdg_shell interfaces along with the core ones
+    use wayland_protocols::xdg::shell::server::__interfaces::*;
+    use wayland_server::protocol::__interfaces::*;
+    wayland_scanner::generate_interfaces!(
+        "src/category5/ways/protocol/wlr-layer-shell-unstable-v1.xml"
+    );
+}
+use self::__interfaces::*;
+
+wayland_scanner::generate_server_code!(
+    "src/category5/ways/protocol/wlr-layer-shell-unstable-v1.xml"
+);
@@ -0,0 +1,16 @@
+// Handle imports for the generated wlr-virtual-pointer bindings
+use wayland_scanner;
+use wayland_server;
+use wayland_server::protocol::*;
+
+pub mod __interfaces {
+    use wayland_server::protocol::__interfaces::*;
+    wayland_scanner::generate_interfaces!(
+        "src/category5/ways/protocol/wlr-virtual-pointer-unstable-v1.xml"
+    );
+}
+use self::__interfaces::*;
+
+wayland_scanner::generate_server_code!(
+    "src/category5/ways/protocol/wlr-virtual-pointer-unstable-v1.xml"
+);
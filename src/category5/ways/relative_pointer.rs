@@ -0,0 +1,99 @@
+// Implementation of the relative pointer protocol
+//
+// Relative pointers deliver the raw motion of the mouse, even when
+// the cursor is locked in place or stopped at the edge of the screen.
+// Games and 3D tools use this to drive their cameras.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::wp::relative_pointer::zv1::server::{
+    zwp_relative_pointer_manager_v1 as zrpm, zwp_relative_pointer_v1 as zrp,
+};
+
+use super::seat::Seat;
+use super::utils;
+use crate::category5::Climate;
+
+use std::ops::DerefMut;
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zrpm::ZwpRelativePointerManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zrpm::ZwpRelativePointerManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zrpm::ZwpRelativePointerManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zrpm::ZwpRelativePointerManagerV1,
+        request: zrpm::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zrpm::Request::GetRelativePointer { id, pointer } => {
+                let mut atmos = state.c_atmos.lock().unwrap();
+                let client_id = utils::get_id_from_client(atmos.deref_mut(), client.clone());
+                let seat_cell = atmos
+                    .get_seat_from_client_id(&client_id)
+                    .expect("Client has a wl_pointer but no Seat");
+
+                let relative_pointer = data_init.init(id, seat_cell.clone());
+                seat_cell
+                    .lock()
+                    .unwrap()
+                    .add_relative_pointer(&pointer, relative_pointer);
+            }
+            zrpm::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zrpm::ZwpRelativePointerManagerV1,
+        data: &(),
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zrp::ZwpRelativePointerV1, Arc<Mutex<Seat>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zrp::ZwpRelativePointerV1,
+        request: zrp::Request,
+        data: &Arc<Mutex<Seat>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zrp::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zrp::ZwpRelativePointerV1,
+        data: &Arc<Mutex<Seat>>,
+    ) {
+        data.lock().unwrap().remove_relative_pointer(resource);
+    }
+}
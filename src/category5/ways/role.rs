@@ -5,6 +5,7 @@
 // (window vs cursor vs ...)
 //
 // Austin Shafer 2020
+use super::layer_shell::LayerSurface;
 use super::wl_subcompositor::SubSurface;
 use super::xdg_shell;
 use wayland_protocols::xdg::shell::server::*;
@@ -20,4 +21,12 @@ pub enum Role {
     xdg_shell_toplevel(xdg_surface::XdgSurface, Arc<Mutex<xdg_shell::ShellSurface>>),
     xdg_shell_popup(Arc<Mutex<xdg_shell::ShellSurface>>),
     cursor,
+    // The icon following the pointer during drag and drop
+    dnd_icon,
+    // A panel, background, etc placed by wlr-layer-shell
+    layer_surface(Arc<Mutex<LayerSurface>>),
+    // A candidate window or similar shown by the input method
+    input_popup,
+    // Covers an output while the session is locked
+    lock_surface,
 }
@@ -0,0 +1,523 @@
+// Implementation of the wlr screencopy protocol
+//
+// Screenshot tools and screen recorders use this to get the contents
+// of an output. The frame is copied into the client's buffer while the
+// WM draws it, so copies always wait for the next frame. The global is
+// only advertised while some output can be copied from.
+extern crate dakota as dak;
+extern crate wayland_server as ws;
+
+use ws::protocol::{wl_buffer, wl_shm};
+use ws::Resource;
+
+use super::linux_dmabuf::{WL_DRM_FORMAT_ARGB8888, WL_DRM_FORMAT_XRGB8888};
+use super::protocol::wlr_screencopy::{
+    zwlr_screencopy_frame_v1 as zscf, zwlr_screencopy_manager_v1 as zscm,
+};
+use super::shm::ShmBuffer;
+use crate::category5::atmosphere::OutputLayout;
+use crate::category5::Climate;
+use utils::{log, region::Rect};
+
+use std::sync::{Arc, Mutex};
+
+/// Damage is found by comparing the tiles of this size with the
+/// last copy
+const DAMAGE_TILE_SIZE: usize = 64;
+
+/// The last frame copied by a screencopy manager
+///
+/// Damage is reported relative to the last copy made through the
+/// same manager, so each manager keeps the pixels of its last copy of
+/// every output region.
+pub struct LastCapture {
+    lc_output: u32,
+    lc_region: Rect<i32>,
+    lc_pixels: Vec<u8>,
+    lc_stride: usize,
+}
+
+/// A request to copy the next frame of an output
+pub struct ScreencopyFrame {
+    /// The id of the output to copy
+    sf_output: u32,
+    /// The region of the output to copy, in pixels
+    sf_region: Rect<i32>,
+    /// Should the cursor be included in the copy
+    sf_overlay_cursor: bool,
+    /// The buffer to copy into. This is set once the client asks
+    /// for the copy.
+    sf_buffer: Option<wl_buffer::WlBuffer>,
+    /// Wait for something in the region to change before copying
+    sf_with_damage: bool,
+    /// The last copies made through our manager
+    sf_history: Arc<Mutex<Vec<LastCapture>>>,
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zscm::ZwlrScreencopyManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zscm::ZwlrScreencopyManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, Arc::new(Mutex::new(Vec::new())));
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zscm::ZwlrScreencopyManagerV1, Arc<Mutex<Vec<LastCapture>>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zscm::ZwlrScreencopyManagerV1,
+        request: zscm::Request,
+        data: &Arc<Mutex<Vec<LastCapture>>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let (frame, overlay_cursor, output, region) = match request {
+            zscm::Request::CaptureOutput {
+                frame,
+                overlay_cursor,
+                output,
+            } => (frame, overlay_cursor, output, None),
+            zscm::Request::CaptureOutputRegion {
+                frame,
+                overlay_cursor,
+                output,
+                x,
+                y,
+                width,
+                height,
+            } => (
+                frame,
+                overlay_cursor,
+                output,
+                Some(Rect::new(x, y, width, height)),
+            ),
+            zscm::Request::Destroy => return,
+            _ => unimplemented!(),
+        };
+
+        let output_id = *output.data::<u32>().unwrap();
+        let atmos = state.c_atmos.lock().unwrap();
+        let layout = atmos
+            .get_outputs()
+            .into_iter()
+            .find(|o| o.ol_id == output_id);
+        let sf_region = match layout.as_ref() {
+            Some(layout) => get_pixel_region(layout, region.as_ref()),
+            None => Rect::new(0, 0, 0, 0),
+        };
+
+        let capture_format = layout.as_ref().and_then(|l| l.ol_capture_format);
+        let frame = data_init.init(
+            frame,
+            Mutex::new(ScreencopyFrame {
+                sf_output: output_id,
+                sf_region: sf_region,
+                sf_overlay_cursor: overlay_cursor != 0,
+                sf_buffer: None,
+                sf_with_damage: false,
+                sf_history: data.clone(),
+            }),
+        );
+
+        // The output may have gone away, it may not be possible to copy
+        // from it, or the region may be entirely outside of it
+        let (drm_format, shm_format) = match capture_format.and_then(get_shm_format) {
+            Some(formats) if sf_region.r_size.0 > 0 && sf_region.r_size.1 > 0 => formats,
+            _ => {
+                frame.failed();
+                return;
+            }
+        };
+
+        let (width, height) = (sf_region.r_size.0 as u32, sf_region.r_size.1 as u32);
+        frame.buffer(shm_format, width, height, width * 4);
+        if frame.version() >= 3 {
+            if atmos.get_drm_dev() != (0, 0) {
+                frame.linux_dmabuf(drm_format, width, height);
+            }
+            frame.buffer_done();
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zscm::ZwlrScreencopyManagerV1,
+        data: &Arc<Mutex<Vec<LastCapture>>>,
+    ) {
+    }
+}
+
+impl Climate {
+    /// Advertise screencopy only while there is an output to copy from
+    ///
+    /// Outputs whose swapchain images can't be used as a transfer source,
+    /// or are in a format we can't hand to clients, can't be copied.
+    pub fn update_screencopy_global(&mut self, handle: &ws::DisplayHandle) {
+        let can_capture = self
+            .c_atmos
+            .lock()
+            .unwrap()
+            .get_outputs()
+            .iter()
+            .any(|o| o.ol_capture_format.and_then(get_shm_format).is_some());
+
+        match (can_capture, self.c_screencopy_global.is_some()) {
+            (true, false) => {
+                self.c_screencopy_global =
+                    Some(handle.create_global::<Climate, zscm::ZwlrScreencopyManagerV1, ()>(3, ()));
+            }
+            (false, true) => {
+                log::debug!("No output can be copied from, removing the screencopy global");
+                handle.remove_global::<Climate>(self.c_screencopy_global.take().unwrap());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Get the wl_shm format matching a DRM format we copy frames in
+///
+/// Returns the DRM format along with it, or None if clients can't be
+/// handed copies in this format.
+fn get_shm_format(drm_format: u32) -> Option<(u32, wl_shm::Format)> {
+    match drm_format {
+        WL_DRM_FORMAT_XRGB8888 => Some((drm_format, wl_shm::Format::Xrgb8888)),
+        WL_DRM_FORMAT_ARGB8888 => Some((drm_format, wl_shm::Format::Argb8888)),
+        _ => None,
+    }
+}
+
+/// Get the part of an output to copy in pixels
+///
+/// `region` is in the output's logical coordinates and is clipped to
+/// the output. If it is None then the entire output is copied.
+fn get_pixel_region(layout: &OutputLayout, region: Option<&Rect<i32>>) -> Rect<i32> {
+    let (res_width, res_height) = (layout.ol_resolution.0 as i32, layout.ol_resolution.1 as i32);
+    let region = match region {
+        Some(region) => region,
+        None => return Rect::new(0, 0, res_width, res_height),
+    };
+
+    let scale = |v: i32| (v as f32 * layout.ol_scale).round() as i32;
+    let x = scale(region.r_pos.0).max(0);
+    let y = scale(region.r_pos.1).max(0);
+    let x2 = scale(region.r_pos.0 + region.r_size.0).min(res_width);
+    let y2 = scale(region.r_pos.1 + region.r_size.1).min(res_height);
+
+    Rect::new(x, y, (x2 - x).max(0), (y2 - y).max(0))
+}
+
+/// Check that a client's buffer can hold a copy of `region`
+fn is_valid_buffer(buffer: &wl_buffer::WlBuffer, region: &Rect<i32>) -> bool {
+    let (width, height) = region.r_size;
+
+    if let Some(shm) = buffer.data::<ShmBuffer>() {
+        return (shm.sb_format == wl_shm::Format::Xrgb8888
+            || shm.sb_format == wl_shm::Format::Argb8888)
+            && shm.sb_width == width
+            && shm.sb_height == height
+            && shm.sb_stride >= width * 4;
+    }
+    if let Some(dmabuf) = buffer.data::<dak::Dmabuf>() {
+        return (dmabuf.db_format == WL_DRM_FORMAT_XRGB8888
+            || dmabuf.db_format == WL_DRM_FORMAT_ARGB8888)
+            && dmabuf.db_width == width
+            && dmabuf.db_height == height;
+    }
+
+    false
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zscf::ZwlrScreencopyFrameV1, Mutex<ScreencopyFrame>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zscf::ZwlrScreencopyFrameV1,
+        request: zscf::Request,
+        data: &Mutex<ScreencopyFrame>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let (buffer, with_damage) = match request {
+            zscf::Request::Copy { buffer } => (buffer, false),
+            zscf::Request::CopyWithDamage { buffer } => (buffer, true),
+            zscf::Request::Destroy => return,
+            _ => unimplemented!(),
+        };
+
+        let mut frame = data.lock().unwrap();
+        if frame.sf_buffer.is_some() {
+            resource.post_error(
+                zscf::Error::AlreadyUsed,
+                "This frame has already been copied",
+            );
+            return;
+        }
+        if !is_valid_buffer(&buffer, &frame.sf_region) {
+            resource.post_error(
+                zscf::Error::InvalidBuffer,
+                "Buffer does not match the size and format advertised",
+            );
+            return;
+        }
+
+        frame.sf_buffer = Some(buffer);
+        frame.sf_with_damage = with_damage;
+
+        // Streaming clients wait for something to change, but if there
+        // is nothing to compare against we copy the next frame
+        let has_history = frame
+            .sf_history
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|c| c.lc_output == frame.sf_output && c.lc_region == frame.sf_region);
+        let mut atmos = state.c_atmos.lock().unwrap();
+        atmos.a_screencopy_frames.push(resource.clone());
+        if !with_damage || !has_history {
+            atmos.mark_changed();
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zscf::ZwlrScreencopyFrameV1,
+        data: &Mutex<ScreencopyFrame>,
+    ) {
+        // The WM skips frames that are no longer alive, but we may not
+        // have handed this one off yet
+        state
+            .c_atmos
+            .lock()
+            .unwrap()
+            .a_screencopy_frames
+            .retain(|f| f != resource);
+    }
+}
+
+/// Tell the client its copy could not be made
+pub fn send_failed(frame: &zscf::ZwlrScreencopyFrameV1) {
+    if frame.is_alive() {
+        frame.failed();
+    }
+}
+
+/// Get where a frame should be copied from
+///
+/// Returns the id of the output, the region of it in pixels, and
+/// whether the cursor should be included.
+pub fn get_frame_source(frame: &zscf::ZwlrScreencopyFrameV1) -> (u32, Rect<i32>, bool) {
+    let data = frame
+        .data::<Mutex<ScreencopyFrame>>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    (data.sf_output, data.sf_region, data.sf_overlay_cursor)
+}
+
+/// Get the image the WM should copy a frame into
+///
+/// Shm buffers are copied into system memory and written to the client's
+/// buffer afterwards, while dmabufs are copied into directly. Returns
+/// None if this frame has nothing to copy into.
+pub fn begin_copy(
+    frame: &zscf::ZwlrScreencopyFrameV1,
+    output: &dak::Output,
+) -> Option<dak::CaptureImage> {
+    if !frame.is_alive() {
+        return None;
+    }
+    let data = frame
+        .data::<Mutex<ScreencopyFrame>>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let buffer = match data.sf_buffer.as_ref() {
+        Some(buffer) if buffer.is_alive() => buffer,
+        _ => {
+            frame.failed();
+            return None;
+        }
+    };
+    // The output may have been resized since the client asked
+    let (width, height) = output.get_resolution();
+    let region = &data.sf_region;
+    if region.r_pos.0 + region.r_size.0 > width as i32
+        || region.r_pos.1 + region.r_size.1 > height as i32
+    {
+        frame.failed();
+        return None;
+    }
+
+    let image = match buffer.data::<dak::Dmabuf>() {
+        Some(dmabuf) => output.create_capture_image_from_dmabuf(dmabuf),
+        None => output.create_capture_image(region.r_size.0 as u32, region.r_size.1 as u32),
+    };
+    match image {
+        Ok(image) => Some(image),
+        Err(e) => {
+            log::error!("Could not create image for screencopy: {:?}", e);
+            frame.failed();
+            None
+        }
+    }
+}
+
+/// Find the tiles of `region` which changed since the last copy
+///
+/// Tiles that changed next to each other in the same row are merged
+/// into one rectangle.
+fn get_damage(
+    last: &LastCapture,
+    pixels: &[u8],
+    stride: usize,
+    (width, height): (usize, usize),
+) -> Vec<Rect<i32>> {
+    let mut damage = Vec::new();
+
+    for ty in (0..height).step_by(DAMAGE_TILE_SIZE) {
+        let tile_height = DAMAGE_TILE_SIZE.min(height - ty);
+        let mut run: Option<Rect<i32>> = None;
+
+        for tx in (0..width).step_by(DAMAGE_TILE_SIZE) {
+            let tile_width = DAMAGE_TILE_SIZE.min(width - tx);
+            let changed = (ty..ty + tile_height).any(|y| {
+                let start = tx * 4;
+                let end = start + tile_width * 4;
+                pixels[y * stride + start..y * stride + end]
+                    != last.lc_pixels[y * last.lc_stride + start..y * last.lc_stride + end]
+            });
+
+            match changed {
+                true => match run.as_mut() {
+                    Some(rect) => rect.r_size.0 += tile_width as i32,
+                    None => {
+                        run = Some(Rect::new(
+                            tx as i32,
+                            ty as i32,
+                            tile_width as i32,
+                            tile_height as i32,
+                        ))
+                    }
+                },
+                false => damage.extend(run.take()),
+            }
+        }
+        damage.extend(run.take());
+    }
+
+    damage
+}
+
+/// Hand a finished copy to the client
+///
+/// This is called by the WM once the frame `image` was copied in has
+/// been drawn, see `CaptureImage::is_copy_done`. Returns false if the
+/// frame was not sent because nothing
+/// changed and the client asked to wait for damage. The WM should try
+/// again on the next frame.
+pub fn finish_copy(frame: &zscf::ZwlrScreencopyFrameV1, image: &dak::CaptureImage) -> bool {
+    if !frame.is_alive() {
+        return true;
+    }
+    let data = frame
+        .data::<Mutex<ScreencopyFrame>>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let buffer = match data.sf_buffer.as_ref() {
+        Some(buffer) if buffer.is_alive() => buffer,
+        _ => {
+            frame.failed();
+            return true;
+        }
+    };
+    let (width, height) = (data.sf_region.r_size.0, data.sf_region.r_size.1);
+
+    let damage = match buffer.data::<ShmBuffer>() {
+        Some(shm) => {
+            let bits = match image.read_bits() {
+                Some(bits) => bits,
+                None => {
+                    frame.failed();
+                    return true;
+                }
+            };
+            let stride = bits.mi_stride as usize;
+
+            let mut history = data.sf_history.lock().unwrap();
+            let last = history
+                .iter()
+                .position(|c| c.lc_output == data.sf_output && c.lc_region == data.sf_region);
+            let damage = match last {
+                Some(index) => get_damage(
+                    &history[index],
+                    &bits.mi_data,
+                    stride,
+                    (width as usize, height as usize),
+                ),
+                None => vec![Rect::new(0, 0, width, height)],
+            };
+            if data.sf_with_damage && damage.is_empty() {
+                return false;
+            }
+
+            if !shm.write_pixels(&bits.mi_data, stride) {
+                log::error!("Could not write screencopy to the client's shm buffer");
+                frame.failed();
+                return true;
+            }
+
+            let capture = LastCapture {
+                lc_output: data.sf_output,
+                lc_region: data.sf_region,
+                lc_pixels: bits.mi_data,
+                lc_stride: stride,
+            };
+            match last {
+                Some(index) => history[index] = capture,
+                None => history.push(capture),
+            }
+            damage
+        }
+        // We can't look at the contents of dmabufs, so the entire
+        // frame is reported as damaged
+        None => vec![Rect::new(0, 0, width, height)],
+    };
+
+    frame.flags(zscf::Flags::empty());
+    if data.sf_with_damage {
+        for rect in damage.iter() {
+            frame.damage(
+                rect.r_pos.0 as u32,
+                rect.r_pos.1 as u32,
+                rect.r_size.0 as u32,
+                rect.r_size.1 as u32,
+            );
+        }
+    }
+
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+    let secs = now.tv_sec as u64;
+    frame.ready((secs >> 32) as u32, secs as u32, now.tv_nsec as u32);
+
+    true
+}
@@ -8,9 +8,13 @@ extern crate libc;
 extern crate nix;
 use nix::unistd::ftruncate;
 
+extern crate wayland_protocols;
 extern crate wayland_server as ws;
+use wayland_protocols::wp::primary_selection::zv1::server::zwp_primary_selection_device_v1 as zpsd;
+use wayland_protocols::wp::relative_pointer::zv1::server::zwp_relative_pointer_v1 as zrp;
+use wayland_protocols::wp::text_input::zv3::server::zwp_text_input_v3 as zti;
 use ws::protocol::wl_seat::Capability;
-use ws::protocol::{wl_keyboard, wl_pointer, wl_seat};
+use ws::protocol::{wl_data_device, wl_keyboard, wl_pointer, wl_seat};
 use ws::Resource;
 
 use crate::category5::atmosphere::{Atmosphere, ClientId};
@@ -20,7 +24,7 @@ use crate::category5::Climate;
 use std::fs::File;
 use std::io::Write;
 use std::ops::DerefMut;
-use std::os::unix::io::FromRawFd;
+use std::os::unix::io::{AsFd, FromRawFd};
 use std::sync::{Arc, Mutex};
 
 #[allow(unused_variables)]
@@ -89,6 +93,38 @@ impl ws::Dispatch<wl_seat::WlSeat, Arc<Mutex<Seat>>> for Climate {
     }
 }
 
+/// Write a keymap to a file that can be shared with clients
+///
+/// Returns the file and the size of the keymap in it.
+pub fn create_keymap_file(keymap: &str) -> (File, u32) {
+    // Make a temp fd to share with the client
+    #[cfg(target_os = "freebsd")]
+    let fd = unsafe {
+        libc::shm_open(
+            libc::SHM_ANON,
+            libc::O_CREAT | libc::O_RDWR | libc::O_EXCL | libc::O_CLOEXEC,
+            0o600,
+        )
+    };
+    #[cfg(target_os = "linux")]
+    let fd = unsafe {
+        let memfd_name = std::ffi::CString::new("cat5_keymap").unwrap();
+        libc::memfd_create(memfd_name.as_ptr() as *mut i8, libc::MFD_CLOEXEC)
+    };
+    assert!(fd > 0);
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    // according to the manpage: writes do not extend
+    // shm objects, so we need to call ftruncate first
+    ftruncate(&file, keymap.as_bytes().len() as i64)
+        .expect("Could not truncate the temp xkb keymap file");
+    // write the keymap to our anon file
+    file.write(keymap.as_bytes())
+        .expect("Could not write to the temp xkb keymap file");
+    file.flush().unwrap();
+
+    (file, keymap.as_bytes().len() as u32)
+}
+
 /// See the create_global call in `compositor.rs` for the code
 /// that adds a seat instance to a `Seat`.
 pub struct SeatInstance {
@@ -98,6 +134,14 @@ pub struct SeatInstance {
     pub si_keyboards: Vec<wl_keyboard::WlKeyboard>,
     // wl_pointer handle
     pub si_pointers: Vec<wl_pointer::WlPointer>,
+    // relative pointers extending the above wl_pointers
+    pub si_relative_pointers: Vec<zrp::ZwpRelativePointerV1>,
+    // wl_data_device handles
+    pub si_data_devices: Vec<wl_data_device::WlDataDevice>,
+    // primary selection device handles
+    pub si_primary_selection_devices: Vec<zpsd::ZwpPrimarySelectionDeviceV1>,
+    // text inputs created for this wl_seat
+    pub si_text_inputs: Vec<zti::ZwpTextInputV3>,
 }
 
 impl SeatInstance {
@@ -106,6 +150,10 @@ impl SeatInstance {
             si_seat: seat,
             si_keyboards: Vec::new(),
             si_pointers: Vec::new(),
+            si_relative_pointers: Vec::new(),
+            si_data_devices: Vec::new(),
+            si_primary_selection_devices: Vec::new(),
+            si_text_inputs: Vec::new(),
         }
     }
 
@@ -115,41 +163,14 @@ impl SeatInstance {
     fn get_keyboard(
         &mut self,
         atmos: &mut Atmosphere,
-        input: &mut Input,
+        keymap: &str,
         parent_focus: ClientId,
         parent_serial: u32,
         keyboard: wl_keyboard::WlKeyboard,
     ) {
-        // Make a temp fd to share with the client
-        #[cfg(target_os = "freebsd")]
-        let fd = unsafe {
-            libc::shm_open(
-                libc::SHM_ANON,
-                libc::O_CREAT | libc::O_RDWR | libc::O_EXCL | libc::O_CLOEXEC,
-                0o600,
-            )
-        };
-        #[cfg(target_os = "linux")]
-        let fd = unsafe {
-            let memfd_name = std::ffi::CString::new("cat5_keymap").unwrap();
-            libc::memfd_create(memfd_name.as_ptr() as *mut i8, libc::MFD_CLOEXEC)
-        };
-        assert!(fd > 0);
-        let mut file = unsafe { File::from_raw_fd(fd) };
-        // according to the manpage: writes do not extend
-        // shm objects, so we need to call ftruncate first
-        ftruncate(&file, input.i_xkb_keymap_name.as_bytes().len() as i64)
-            .expect("Could not truncate the temp xkb keymap file");
-        // write the input systems keymap to our anon file
-        file.write(input.i_xkb_keymap_name.as_bytes())
-            .expect("Could not write to the temp xkb keymap file");
-        file.flush().unwrap();
-        // Broadcast our keymap map
-        keyboard.keymap(
-            wl_keyboard::KeymapFormat::XkbV1,
-            unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) },
-            input.i_xkb_keymap_name.as_bytes().len() as u32,
-        );
+        // Broadcast the keymap the seat's other keyboards are using
+        let (file, size) = create_keymap_file(keymap);
+        keyboard.keymap(wl_keyboard::KeymapFormat::XkbV1, file.as_fd(), size);
         // Advertise the server repeat capabilities. This is needed
         // to make gtk apps not crash. They will check for this event
         // and if it is not found will resort to checking the peripherals
@@ -221,6 +242,13 @@ pub struct Seat {
     pub s_proxies: Vec<SeatInstance>,
     // the serial number for this set of input events
     pub s_serial: u32,
+    /// The serial of the last key or button press. Requests that must
+    /// be triggered by the user, such as xdg_activation, have to use a
+    /// serial at least this new.
+    pub s_input_serial: Option<u32>,
+    /// The virtual keyboard whose keymap was last sent to this seat's
+    /// keyboards, or None if they have our own keymap.
+    pub s_keymap: Option<ws::backend::ObjectId>,
 }
 
 impl Seat {
@@ -235,6 +263,37 @@ impl Seat {
             s_id: id,
             s_proxies: Vec::new(),
             s_serial: 0,
+            s_input_serial: None,
+            s_keymap: None,
+        }
+    }
+
+    /// Send the keymap of the keyboard we are handling keys from
+    ///
+    /// Virtual keyboards may each use a different keymap, so this
+    /// has to be checked before every key or modifiers event. Returns
+    /// true if a new keymap was sent.
+    pub fn update_keymap(&mut self, input: &Input) -> bool {
+        let keymap = input.get_keymap();
+        if self.s_keymap.as_ref() == keymap {
+            return false;
+        }
+
+        let (file, size) = create_keymap_file(input.get_keymap_text(keymap));
+        for si in self.s_proxies.iter() {
+            for keyboard in si.si_keyboards.iter() {
+                keyboard.keymap(wl_keyboard::KeymapFormat::XkbV1, file.as_fd(), size);
+            }
+        }
+        self.s_keymap = keymap.cloned();
+        true
+    }
+
+    /// Was this serial sent with the latest user input
+    pub fn is_recent_input_serial(&self, serial: u32) -> bool {
+        match self.s_input_serial {
+            Some(input_serial) => serial >= input_serial && serial <= self.s_serial,
+            None => false,
         }
     }
 
@@ -252,6 +311,78 @@ impl Seat {
         self.s_proxies.push(SeatInstance::new(seat));
     }
 
+    /// Register a wl_data_device with the seat instance for `seat`
+    pub fn add_data_device(
+        &mut self,
+        seat: &wl_seat::WlSeat,
+        device: wl_data_device::WlDataDevice,
+    ) {
+        if let Some(si) = self.s_proxies.iter_mut().find(|s| s.si_seat == *seat) {
+            si.si_data_devices.push(device);
+        }
+    }
+
+    /// Forget a wl_data_device that has been destroyed
+    pub fn remove_data_device(&mut self, device: &wl_data_device::WlDataDevice) {
+        for si in self.s_proxies.iter_mut() {
+            si.si_data_devices.retain(|d| d != device);
+        }
+    }
+
+    /// Register a primary selection device with the seat instance for `seat`
+    pub fn add_primary_selection_device(
+        &mut self,
+        seat: &wl_seat::WlSeat,
+        device: zpsd::ZwpPrimarySelectionDeviceV1,
+    ) {
+        if let Some(si) = self.s_proxies.iter_mut().find(|s| s.si_seat == *seat) {
+            si.si_primary_selection_devices.push(device);
+        }
+    }
+
+    /// Forget a primary selection device that has been destroyed
+    pub fn remove_primary_selection_device(&mut self, device: &zpsd::ZwpPrimarySelectionDeviceV1) {
+        for si in self.s_proxies.iter_mut() {
+            si.si_primary_selection_devices.retain(|d| d != device);
+        }
+    }
+
+    /// Register a relative pointer with the seat instance of `pointer`
+    pub fn add_relative_pointer(
+        &mut self,
+        pointer: &wl_pointer::WlPointer,
+        relative_pointer: zrp::ZwpRelativePointerV1,
+    ) {
+        if let Some(si) = self
+            .s_proxies
+            .iter_mut()
+            .find(|s| s.si_pointers.contains(pointer))
+        {
+            si.si_relative_pointers.push(relative_pointer);
+        }
+    }
+
+    /// Forget a relative pointer that has been destroyed
+    pub fn remove_relative_pointer(&mut self, relative_pointer: &zrp::ZwpRelativePointerV1) {
+        for si in self.s_proxies.iter_mut() {
+            si.si_relative_pointers.retain(|r| r != relative_pointer);
+        }
+    }
+
+    /// Register a text input with the seat instance for `seat`
+    pub fn add_text_input(&mut self, seat: &wl_seat::WlSeat, text_input: zti::ZwpTextInputV3) {
+        if let Some(si) = self.s_proxies.iter_mut().find(|s| s.si_seat == *seat) {
+            si.si_text_inputs.push(text_input);
+        }
+    }
+
+    /// Forget a text input that has been destroyed
+    pub fn remove_text_input(&mut self, text_input: &zti::ZwpTextInputV3) {
+        for si in self.s_proxies.iter_mut() {
+            si.si_text_inputs.retain(|t| t != text_input);
+        }
+    }
+
     /// Handle client requests
     ///
     /// This basically just creates and registers the different
@@ -274,7 +405,13 @@ impl Seat {
         match req {
             wl_seat::Request::GetKeyboard { id } => {
                 let kb = data_init.init(id, ());
-                si.get_keyboard(atmos, input, self.s_id.clone(), self.s_serial, kb);
+                si.get_keyboard(
+                    atmos,
+                    input.get_keymap_text(self.s_keymap.as_ref()),
+                    self.s_id.clone(),
+                    self.s_serial,
+                    kb,
+                );
             }
             wl_seat::Request::GetPointer { id } => {
                 let ptr = data_init.init(id, ());
@@ -0,0 +1,239 @@
+// Implementation of the ext session lock protocol
+//
+// Screen lockers use this to lock the session. While locked we only
+// show the locker's surfaces and only give them input. If the locker
+// dies we stay locked, so a crash can't be used to get past it.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::ext::session_lock::v1::server::{
+    ext_session_lock_manager_v1 as eslm, ext_session_lock_surface_v1 as esls,
+    ext_session_lock_v1 as esl,
+};
+use ws::Resource;
+
+use super::role::Role;
+use super::surface::Surface;
+use crate::category5::atmosphere::{Atmosphere, LockSurfaceState, SurfaceId};
+use crate::category5::Climate;
+use utils::log;
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<eslm::ExtSessionLockManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<eslm::ExtSessionLockManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<eslm::ExtSessionLockManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &eslm::ExtSessionLockManagerV1,
+        request: eslm::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            eslm::Request::Lock { id } => {
+                let lock = data_init.init(id, ());
+                // The locker is only told the session is locked once
+                // the desktop is off of every output, which the WM
+                // lets us know about
+                if !state.c_atmos.lock().unwrap().lock_session(lock.clone()) {
+                    log::error!("The session is already locked by another client");
+                    lock.finished();
+                }
+            }
+            eslm::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &eslm::ExtSessionLockManagerV1,
+        data: &(),
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<esl::ExtSessionLockV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &esl::ExtSessionLockV1,
+        request: esl::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        let active = atmos.is_active_session_lock(resource);
+
+        match request {
+            esl::Request::GetLockSurface {
+                id,
+                surface,
+                output,
+            } => {
+                let surf_cell = surface.data::<Arc<Mutex<Surface>>>().unwrap().clone();
+                let mut surf = surf_cell.lock().unwrap();
+                if surf.s_role.is_some() {
+                    data_init.post_error(
+                        id,
+                        esl::Error::Role,
+                        "wl_surface already has a role assigned",
+                    );
+                    return;
+                }
+                if atmos.a_buffer_size.get(&surf.s_id).is_some() {
+                    data_init.post_error(
+                        id,
+                        esl::Error::AlreadyConstructed,
+                        "wl_surface already has a buffer attached",
+                    );
+                    return;
+                }
+                let output_id = *output.data::<u32>().unwrap();
+                let duplicate = atmos
+                    .a_session_lock
+                    .as_ref()
+                    .map(|lock| lock.sl_surfaces.iter().any(|s| s.lk_output == output_id))
+                    == Some(true);
+                if active && duplicate {
+                    data_init.post_error(
+                        id,
+                        esl::Error::DuplicateOutput,
+                        "This output already has a lock surface",
+                    );
+                    return;
+                }
+
+                let lock_surface = data_init.init(id, surf_cell.clone());
+                surf.s_role = Some(Role::lock_surface);
+                // Lockers which were refused the lock get inert surfaces
+                if active {
+                    atmos.add_lock_surface(LockSurfaceState::new(
+                        lock_surface,
+                        surf.s_id.clone(),
+                        output_id,
+                    ));
+                }
+            }
+            esl::Request::UnlockAndDestroy => match active {
+                true => atmos.unlock_session(),
+                false => resource.post_error(
+                    esl::Error::InvalidUnlock,
+                    "This lock was never granted and can't unlock",
+                ),
+            },
+            esl::Request::Destroy => {
+                if active {
+                    resource.post_error(
+                        esl::Error::InvalidDestroy,
+                        "The session must be unlocked before destroying the lock",
+                    );
+                }
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &esl::ExtSessionLockV1,
+        data: &(),
+    ) {
+        state.c_atmos.lock().unwrap().session_locker_gone(resource);
+    }
+}
+
+/// Check the state committed to a lock surface
+///
+/// Lock surfaces must always be the size of their output, which is
+/// the size we configured them with. This is called from the commit
+/// of the wl_surface.
+pub fn commit_lock_surface(atmos: &mut Atmosphere, id: &SurfaceId) {
+    let (resource, acked_size) = match atmos.get_lock_surface_mut(id) {
+        Some(state) => (state.lk_resource.clone(), state.lk_acked_size),
+        None => return,
+    };
+
+    let acked_size = match acked_size {
+        Some(size) => size,
+        None => {
+            resource.post_error(
+                esls::Error::CommitBeforeFirstAck,
+                "Lock surface committed before acking a configure",
+            );
+            return;
+        }
+    };
+    if atmos.a_buffer_size.get(id).is_none() {
+        resource.post_error(esls::Error::NullBuffer, "Lock surface has no buffer");
+        return;
+    }
+    let size = *atmos.a_surface_size.get(id).unwrap();
+    if (size.0 as u32, size.1 as u32) != acked_size {
+        resource.post_error(
+            esls::Error::DimensionsMismatch,
+            "Lock surface does not match the configured size",
+        );
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<esls::ExtSessionLockSurfaceV1, Arc<Mutex<Surface>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &esls::ExtSessionLockSurfaceV1,
+        request: esls::Request,
+        data: &Arc<Mutex<Surface>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            esls::Request::AckConfigure { serial } => {
+                let id = data.lock().unwrap().s_id.clone();
+                let mut atmos = state.c_atmos.lock().unwrap();
+                if let Some(lock_surface) = atmos.get_lock_surface_mut(&id) {
+                    if !lock_surface.ack_configure(serial) {
+                        resource.post_error(esls::Error::InvalidSerial, "Unknown configure serial");
+                    }
+                }
+            }
+            esls::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &esls::ExtSessionLockSurfaceV1,
+        data: &Arc<Mutex<Surface>>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        let mut surf = data.lock().unwrap();
+
+        surf.s_role = None;
+        atmos.remove_lock_surface(&surf.s_id);
+    }
+}
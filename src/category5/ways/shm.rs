@@ -155,6 +155,10 @@ struct ShmRegion {
     sr_fd: OwnedFd,
     sr_raw_ptr: std::ptr::NonNull<c_void>,
     sr_size: usize,
+    /// A writable mapping of the pool. This is only made once one of
+    /// its buffers is used as a screencopy target, since everything
+    /// else only needs to read from it.
+    sr_write_ptr: Option<std::ptr::NonNull<c_void>>,
 }
 
 // Have to do this manually because of the void *
@@ -185,6 +189,7 @@ impl ShmRegion {
                 sr_fd: fd,
                 sr_raw_ptr: ptr,
                 sr_size: size,
+                sr_write_ptr: None,
             })
         }
     }
@@ -193,6 +198,8 @@ impl ShmRegion {
     // Shrinking a pool is not supported
     fn resize(&mut self, size: usize) {
         assert!(self.sr_size <= size);
+        // The writable mapping is made again the next time it is needed
+        self.unmap_writable();
         self.sr_size = size;
 
         unsafe {
@@ -210,10 +217,43 @@ impl ShmRegion {
             };
         }
     }
+
+    // Get a writable mapping of the pool
+    //
+    // Clients may hand us fds they opened read only, in which case
+    // this fails and their buffers can't be written to.
+    fn get_writable_ptr(&mut self) -> Option<std::ptr::NonNull<c_void>> {
+        if self.sr_write_ptr.is_none() {
+            self.sr_write_ptr = unsafe {
+                mman::mmap(
+                    None,
+                    core::num::NonZeroUsize::new(self.sr_size).unwrap(),
+                    mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE,
+                    mman::MapFlags::MAP_SHARED,
+                    &self.sr_fd,
+                    0,
+                )
+            }
+            .map_err(|e| log::error!("Could not map the shm pool as writable: {:?}", e))
+            .ok();
+        }
+
+        self.sr_write_ptr
+    }
+
+    // Drop the writable mapping, if we made one
+    fn unmap_writable(&mut self) {
+        if let Some(ptr) = self.sr_write_ptr.take() {
+            unsafe {
+                mman::munmap(ptr, self.sr_size).unwrap();
+            }
+        }
+    }
 }
 
 impl Drop for ShmRegion {
     fn drop(&mut self) {
+        self.unmap_writable();
         unsafe {
             // We need to manually unmap this region whenever
             // it goes out of scope. These prevent memory leaks
@@ -265,6 +305,42 @@ impl ShmBuffer {
 
         return ret;
     }
+
+    // Copy pixels into this buffer
+    //
+    // data holds rows of stride bytes, which are copied into
+    // the rows of this buffer. This is how frames are handed to
+    // screen capture clients. Returns false if the client's
+    // pool can't hold the buffer or can't be mapped writable.
+    pub fn write_pixels(&self, data: &[u8], stride: usize) -> bool {
+        let mut reg = self.sb_reg.lock().unwrap();
+        let row_size = self.sb_width as usize * 4;
+        let last_row = (self.sb_height as usize).saturating_sub(1);
+        let end = self.sb_offset as usize + self.sb_stride as usize * last_row + row_size;
+
+        if self.sb_offset < 0
+            || end > reg.sr_size
+            || stride < row_size
+            || data.len() < stride * last_row + row_size
+        {
+            return false;
+        }
+        let ptr = match reg.get_writable_ptr() {
+            Some(ptr) => ptr,
+            None => return false,
+        };
+
+        for row in 0..self.sb_height as usize {
+            let src = &data[row * stride..row * stride + row_size];
+            unsafe {
+                let dst = (ptr.as_ptr() as *mut u8)
+                    .offset(self.sb_offset as isize + (row * self.sb_stride as usize) as isize);
+                std::ptr::copy_nonoverlapping(src.as_ptr(), dst, row_size);
+            }
+        }
+
+        true
+    }
 }
 
 // Handle buffers with shm attached
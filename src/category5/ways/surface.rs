@@ -11,7 +11,11 @@ use ws::protocol::wl_surface::Request;
 use ws::protocol::{wl_buffer, wl_callback, wl_output, wl_region, wl_surface as wlsi};
 use ws::Resource;
 
+use wayland_protocols::wp::presentation_time::server::wp_presentation_feedback as wppf;
+
 use super::role::Role;
+use super::session_lock;
+use super::viewporter::Viewport;
 use super::wl_region::Region;
 use super::{shm::ShmBuffer, wl_subcompositor::SubSurfaceState, xdg_shell::XdgState};
 use crate::category5::atmosphere::{Atmosphere, SurfaceId};
@@ -56,6 +60,40 @@ impl ws::Dispatch<wlsi::WlSurface, Arc<Mutex<Surface>>> for Climate {
     }
 }
 
+/// Get the surface size of a buffer
+///
+/// The buffer is `scale` times larger than the surface, and is turned
+/// on its side for 90 and 270 degree transforms.
+fn get_surface_size(
+    buffer_size: (i32, i32),
+    scale: i32,
+    transform: dak::SurfaceTransform,
+) -> (f32, f32) {
+    let size = (
+        (buffer_size.0 / scale) as f32,
+        (buffer_size.1 / scale) as f32,
+    );
+
+    match transform.swaps_dimensions() {
+        true => (size.1, size.0),
+        false => size,
+    }
+}
+
+/// Convert a wayland transform to the one Thundr applies while drawing
+fn get_surface_transform(transform: wl_output::Transform) -> dak::SurfaceTransform {
+    match transform {
+        wl_output::Transform::_90 => dak::SurfaceTransform::Rotate90,
+        wl_output::Transform::_180 => dak::SurfaceTransform::Rotate180,
+        wl_output::Transform::_270 => dak::SurfaceTransform::Rotate270,
+        wl_output::Transform::Flipped => dak::SurfaceTransform::Flipped,
+        wl_output::Transform::Flipped90 => dak::SurfaceTransform::Flipped90,
+        wl_output::Transform::Flipped180 => dak::SurfaceTransform::Flipped180,
+        wl_output::Transform::Flipped270 => dak::SurfaceTransform::Flipped270,
+        _ => dak::SurfaceTransform::Normal,
+    }
+}
+
 /// State of a wl_surface
 ///
 /// wl_surface works by receiving a number of requests and setting some
@@ -76,6 +114,8 @@ pub struct CommitState {
     /// This is a power saving feature, we will signal this when the
     /// client should redraw this surface
     pub cs_frame_callbacks: Vec<wl_callback::WlCallback>,
+    /// wp_presentation feedback for the contents of this commit
+    pub cs_presentation_feedback: Vec<wppf::WpPresentationFeedback>,
     /// The opaque region.
     /// vkcomp can optimize displaying this region
     pub cs_opaque: Option<Arc<Mutex<Region>>>,
@@ -89,6 +129,14 @@ pub struct CommitState {
     pub cs_damage: dak::Damage,
     /// Surface position change from attach/offset
     cs_attached_xy: Option<(i32, i32)>,
+    /// The scale the client rendered the buffer at. Unlike most
+    /// state this persists until the client changes it.
+    pub cs_buffer_scale: i32,
+    /// How the client rotated or flipped the buffer. This also persists.
+    pub cs_buffer_transform: dak::SurfaceTransform,
+    /// Cropping and scaling from wp_viewporter. This persists until
+    /// the client changes it or destroys the viewport.
+    pub cs_viewport: Option<Viewport>,
 
     /// State programmed by wl_subcompositor
     pub cs_subsurf_state: SubSurfaceState,
@@ -110,11 +158,15 @@ impl CommitState {
             cs_id: id.clone(),
             cs_buffer: None,
             cs_frame_callbacks: Vec::with_capacity(1),
+            cs_presentation_feedback: Vec::with_capacity(0),
             cs_opaque: None,
             cs_input: None,
             cs_surf_damage: dak::Damage::empty(),
             cs_damage: dak::Damage::empty(),
             cs_attached_xy: None,
+            cs_buffer_scale: 1,
+            cs_buffer_transform: dak::SurfaceTransform::Normal,
+            cs_viewport: None,
             cs_subsurf_state: SubSurfaceState::new(id),
             cs_children: Vec::with_capacity(0),
             cs_xdg_state: XdgState::empty(),
@@ -129,6 +181,12 @@ impl CommitState {
         let mut frame_callbacks = Vec::with_capacity(1);
         std::mem::swap(&mut frame_callbacks, &mut self.cs_frame_callbacks);
 
+        let mut presentation_feedback = Vec::with_capacity(0);
+        std::mem::swap(
+            &mut presentation_feedback,
+            &mut self.cs_presentation_feedback,
+        );
+
         let mut children = Vec::with_capacity(0);
         std::mem::swap(&mut children, &mut self.cs_children);
 
@@ -141,11 +199,15 @@ impl CommitState {
             cs_id: self.cs_id.clone(),
             cs_buffer: self.cs_buffer.clone(),
             cs_frame_callbacks: frame_callbacks,
+            cs_presentation_feedback: presentation_feedback,
             cs_opaque: self.cs_opaque.clone(),
             cs_input: self.cs_input.clone(),
             cs_surf_damage: surf_damage,
             cs_damage: damage,
             cs_attached_xy: self.cs_attached_xy.take(),
+            cs_buffer_scale: self.cs_buffer_scale,
+            cs_buffer_transform: self.cs_buffer_transform,
+            cs_viewport: self.cs_viewport.clone(),
             cs_subsurf_state: self.cs_subsurf_state.clone_refresh(),
             cs_xdg_state: self.cs_xdg_state.clone_refresh(),
             cs_children: children,
@@ -170,7 +232,7 @@ impl CommitState {
         let mut surf_size = *atmos.a_surface_size.get(&self.cs_id).unwrap();
 
         // ----- Commit our buffer -----
-        // update our size while we are at it
+        // record its size while we are at it
         if let Some(buf) = self.cs_buffer.take() {
             let buffer_id = atmos.mint_buffer_id(scene);
 
@@ -183,7 +245,9 @@ impl CommitState {
                 // Bind this buffer's resource to our Dakota element
                 atmos.a_surf_resource.set(&self.cs_id, buffer_id.clone());
 
-                surf_size = (dmabuf.db_width as f32, dmabuf.db_height as f32)
+                atmos
+                    .a_buffer_size
+                    .set(&self.cs_id, (dmabuf.db_width, dmabuf.db_height));
             } else if let Some(shm_buffer) = buf.data::<ShmBuffer>() {
                 // Create a dakota resource for this buffer
                 if let Err(e) = atmos.update_shm_resource(scene, &self.cs_id, shm_buffer, &buf) {
@@ -191,10 +255,63 @@ impl CommitState {
                     return;
                 }
 
-                surf_size = (shm_buffer.sb_width as f32, shm_buffer.sb_height as f32)
+                atmos
+                    .a_buffer_size
+                    .set(&self.cs_id, (shm_buffer.sb_width, shm_buffer.sb_height));
             } else {
                 panic!("Could not find dmabuf or shmbuf private data for wl_buffer");
             }
+
+            // The content waiting to be drawn has been replaced and
+            // will never be shown
+            atmos.discard_presentation_feedback(&self.cs_id);
+        }
+
+        // The buffer is scaled, rotated, and then cropped and stretched by
+        // any viewport to get the surface size. This is redone on every
+        // commit since any of these may change without a new buffer.
+        atmos.a_buffer_scale.set(&self.cs_id, self.cs_buffer_scale);
+        atmos
+            .a_buffer_transform
+            .set(&self.cs_id, self.cs_buffer_transform);
+        if let Some(buffer_size) = atmos.a_buffer_size.get_clone(&self.cs_id) {
+            // The buffer has to be a whole number of surface pixels. This
+            // only became an error in version 6, older clients get away
+            // with rounding down.
+            let scale = self.cs_buffer_scale;
+            if buffer_size.0 % scale != 0 || buffer_size.1 % scale != 0 {
+                match atmos.get_wl_surface_from_id(&self.cs_id) {
+                    Some(surf) if surf.version() >= 6 => {
+                        surf.post_error(
+                            wlsi::Error::InvalidSize,
+                            "Buffer size is not a multiple of the buffer scale",
+                        );
+                        return;
+                    }
+                    _ => log::error!(
+                        "Surface {:?}: buffer size {:?} is not a multiple of scale {}",
+                        self.cs_id.get_raw_id(),
+                        buffer_size,
+                        scale
+                    ),
+                }
+            }
+
+            surf_size =
+                get_surface_size(buffer_size, self.cs_buffer_scale, self.cs_buffer_transform);
+
+            let mut src_rect = dak::Rect::new(0.0, 0.0, 1.0, 1.0);
+            if let Some(viewport) = self.cs_viewport.as_ref() {
+                match viewport.apply(surf_size) {
+                    Some((size, rect)) => {
+                        surf_size = size;
+                        src_rect = rect;
+                    }
+                    // The viewport was invalid and an error was posted
+                    None => return,
+                }
+            }
+            atmos.a_source_rect.set(&self.cs_id, src_rect);
         }
 
         atmos.a_surface_size.set(&self.cs_id, surf_size);
@@ -219,6 +336,19 @@ impl CommitState {
             self.cs_frame_callbacks.clear();
         }
 
+        // ----- Commit our presentation feedback -----
+        // vkcomp will hand these to the output this surface is drawn on
+        if self.cs_presentation_feedback.len() > 0 {
+            if atmos.a_presentation_feedback.get_mut(&self.cs_id).is_none() {
+                atmos
+                    .a_presentation_feedback
+                    .set(&self.cs_id, Vec::with_capacity(1));
+            }
+
+            let mut feedback = atmos.a_presentation_feedback.get_mut(&self.cs_id).unwrap();
+            feedback.extend(self.cs_presentation_feedback.drain(0..));
+        }
+
         // ------ Update damage regions -----
         if !self.cs_surf_damage.is_empty() {
             let mut nd = dak::Damage::empty();
@@ -242,6 +372,7 @@ impl CommitState {
             log::debug!("Setting input region of {:?} to {:?}", self.cs_id, reg);
             atmos.a_input_region.set(&self.cs_id, reg);
         }
+        atmos.commit_pointer_constraint(&self.cs_id);
 
         // ----- Move our surfaces position if requested -----
         //
@@ -380,18 +511,21 @@ impl Surface {
             }
             // wayland-rs makes us register a destructor
             wlsi::Request::Destroy => self.destroy(atmos),
-            // TODO: support variable buffer scaling
             wlsi::Request::SetBufferScale { scale } => {
-                if scale != 1 {
-                    panic!("Non-1 Buffer scaling is not implemented")
+                if scale < 1 {
+                    surf.post_error(wlsi::Error::InvalidScale, "Buffer scale must be positive");
+                    return;
                 }
+                self.s_state.cs_buffer_scale = scale;
             }
-            // TODO: support variable buffer transformation
-            wlsi::Request::SetBufferTransform { transform } => {
-                if transform.into_result().unwrap() != wl_output::Transform::Normal {
-                    panic!("Non-normal Buffer transformation is not implemented");
+            wlsi::Request::SetBufferTransform { transform } => match transform.into_result() {
+                Ok(transform) => {
+                    self.s_state.cs_buffer_transform = get_surface_transform(transform)
                 }
-            }
+                Err(_) => {
+                    surf.post_error(wlsi::Error::InvalidTransform, "Unknown buffer transform")
+                }
+            },
             wlsi::Request::Offset { x, y } => self.s_state.cs_attached_xy = Some((x, y)),
             _ => unimplemented!(),
         }
@@ -452,6 +586,8 @@ impl Surface {
         let surf_size = *atmos.a_surface_size.get(&self.s_id).unwrap();
         match &self.s_role {
             Some(Role::wl_shell_toplevel) => atmos.a_window_size.set(&self.s_id, surf_size),
+            Some(Role::layer_surface(ls)) => ls.lock().unwrap().commit(atmos),
+            Some(Role::lock_surface) => session_lock::commit_lock_surface(atmos, &self.s_id),
             _ => {}
         }
     }
@@ -481,6 +617,7 @@ impl Surface {
         self.s_is_destroyed = true;
         let client = atmos.a_owner.get_clone(&self.s_id).unwrap();
         atmos.free_window_id(&client, &self.s_id);
+        atmos.discard_presentation_feedback(&self.s_id);
         atmos.add_wm_task(wm::task::Task::close_window(self.s_id.clone()));
     }
 }
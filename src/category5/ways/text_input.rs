@@ -0,0 +1,236 @@
+// Implementation of the text input protocol
+//
+// Text inputs are how clients ask for composed text, such as from an
+// input method (IME). They describe the text around the cursor and
+// receive the preedit and commit strings the input method creates.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::wp::text_input::zv3::server::{
+    zwp_text_input_manager_v3 as ztim, zwp_text_input_v3 as zti,
+};
+use ws::Resource;
+
+use super::input_method;
+use super::seat::Seat;
+use crate::category5::atmosphere::SurfaceId;
+use crate::category5::Climate;
+use utils::region::Rect;
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<ztim::ZwpTextInputManagerV3, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<ztim::ZwpTextInputManagerV3>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<ztim::ZwpTextInputManagerV3, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &ztim::ZwpTextInputManagerV3,
+        request: ztim::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            ztim::Request::GetTextInput { id, seat } => {
+                let atmos = state.c_atmos.lock().unwrap();
+                let seat_cell = seat
+                    .data::<Arc<Mutex<Seat>>>()
+                    .expect("wl_seat did not have a Seat attached")
+                    .clone();
+
+                let text_input_cell = Arc::new(Mutex::new(TextInput::new(seat_cell.clone())));
+                let text_input = data_init.init(id, text_input_cell.clone());
+                seat_cell
+                    .lock()
+                    .unwrap()
+                    .add_text_input(&seat, text_input.clone());
+
+                // If this client already has the keyboard focus then we
+                // need to enter its surface now
+                if let Some(focus) = atmos.get_surf_focus() {
+                    let has_focus = match atmos.get_seat_from_surface_id(&focus) {
+                        Some(cell) => Arc::ptr_eq(&cell, &seat_cell),
+                        None => false,
+                    };
+                    if has_focus {
+                        if let Some(surf) = atmos.get_wl_surface_from_id(&focus) {
+                            text_input.enter(&surf);
+                            text_input_cell.lock().unwrap().ti_focus = Some(focus);
+                        }
+                    }
+                }
+            }
+            ztim::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &ztim::ZwpTextInputManagerV3,
+        data: &(),
+    ) {
+    }
+}
+
+/// The double buffered state of a text input
+#[derive(Clone)]
+pub struct TextInputState {
+    pub ts_enabled: bool,
+    /// The text around the cursor, followed by the cursor and anchor
+    /// positions within it
+    pub ts_surrounding_text: Option<(String, u32, u32)>,
+    pub ts_change_cause: zti::ChangeCause,
+    pub ts_content_hint: zti::ContentHint,
+    pub ts_content_purpose: zti::ContentPurpose,
+    /// The text cursor in surface coordinates. Input method popups are
+    /// placed next to this.
+    pub ts_cursor_rect: Option<Rect<i32>>,
+}
+
+impl TextInputState {
+    fn new() -> Self {
+        Self {
+            ts_enabled: false,
+            ts_surrounding_text: None,
+            ts_change_cause: zti::ChangeCause::InputMethod,
+            ts_content_hint: zti::ContentHint::None,
+            ts_content_purpose: zti::ContentPurpose::Normal,
+            ts_cursor_rect: None,
+        }
+    }
+}
+
+/// A client's text input on a seat
+///
+/// The text input follows the keyboard focus. While its client has
+/// focus and the text input is enabled, the input method composes text
+/// for it.
+pub struct TextInput {
+    pub ti_seat: Arc<Mutex<Seat>>,
+    /// State set by requests, which is applied on commit
+    pub ti_pending: TextInputState,
+    pub ti_current: TextInputState,
+    /// The number of commit requests received. This is sent in the
+    /// done event.
+    pub ti_serial: u32,
+    /// The surface we sent the enter event for
+    pub ti_focus: Option<SurfaceId>,
+}
+
+impl TextInput {
+    fn new(seat: Arc<Mutex<Seat>>) -> Self {
+        Self {
+            ti_seat: seat,
+            ti_pending: TextInputState::new(),
+            ti_current: TextInputState::new(),
+            ti_serial: 0,
+            ti_focus: None,
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zti::ZwpTextInputV3, Arc<Mutex<TextInput>>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zti::ZwpTextInputV3,
+        request: zti::Request,
+        data: &Arc<Mutex<TextInput>>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zti::Request::Enable => {
+                // Enabling resets all of the state
+                let mut ti = data.lock().unwrap();
+                ti.ti_pending = TextInputState::new();
+                ti.ti_pending.ts_enabled = true;
+            }
+            zti::Request::Disable => data.lock().unwrap().ti_pending.ts_enabled = false,
+            zti::Request::SetSurroundingText {
+                text,
+                cursor,
+                anchor,
+            } => {
+                data.lock().unwrap().ti_pending.ts_surrounding_text =
+                    Some((text, cursor as u32, anchor as u32))
+            }
+            zti::Request::SetTextChangeCause { cause } => {
+                if let ws::WEnum::Value(cause) = cause {
+                    data.lock().unwrap().ti_pending.ts_change_cause = cause;
+                }
+            }
+            zti::Request::SetContentType { hint, purpose } => {
+                let mut ti = data.lock().unwrap();
+                if let ws::WEnum::Value(hint) = hint {
+                    ti.ti_pending.ts_content_hint = hint;
+                }
+                if let ws::WEnum::Value(purpose) = purpose {
+                    ti.ti_pending.ts_content_purpose = purpose;
+                }
+            }
+            zti::Request::SetCursorRectangle {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                data.lock().unwrap().ti_pending.ts_cursor_rect =
+                    Some(Rect::new(x, y, width, height))
+            }
+            zti::Request::Commit => {
+                let mut atmos = state.c_atmos.lock().unwrap();
+                let (focus, current) = {
+                    let mut ti = data.lock().unwrap();
+                    ti.ti_serial += 1;
+                    ti.ti_current = ti.ti_pending.clone();
+                    // The change cause is the only state which is not
+                    // kept for the next commit
+                    ti.ti_pending.ts_change_cause = zti::ChangeCause::InputMethod;
+                    (ti.ti_focus.clone(), ti.ti_current.clone())
+                };
+
+                // Only a text input with keyboard focus may use the IME
+                if let Some(focus) = focus {
+                    input_method::text_input_commit(&mut atmos, resource, &focus, &current);
+                }
+            }
+            zti::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zti::ZwpTextInputV3,
+        data: &Arc<Mutex<TextInput>>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        input_method::text_input_removed(&mut atmos, resource);
+        data.lock()
+            .unwrap()
+            .ti_seat
+            .lock()
+            .unwrap()
+            .remove_text_input(resource);
+    }
+}
@@ -8,6 +8,9 @@ use crate::category5::{
     ClientInfo,
 };
 
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
 /// Grab the id belonging to this client
 ///
 /// The id is stored in the userdata map, which is kind of annoying to deal with
@@ -21,3 +24,40 @@ pub fn get_id_from_client(_atmos: &mut Atmosphere, client: ws::Client) -> Client
         None => panic!("This client wasn't initialized properly"),
     }
 }
+
+/// Does the user trust this process with privileged protocols
+///
+/// Virtual keyboards, virtual pointers and input methods can drive or
+/// read input for every other client, so they are only offered to the
+/// programs listed in CATEGORY5_TRUSTED_CLIENTS. This is a comma separated
+/// list of absolute executable paths, or "all" to trust every client.
+/// The executable is found through /proc/<pid>/exe, which unlike the
+/// process name can't be set by the client. This requires procfs.
+pub fn process_is_trusted(pid: i32) -> bool {
+    let allowed = match std::env::var("CATEGORY5_TRUSTED_CLIENTS") {
+        Ok(allowed) => allowed,
+        Err(_) => return false,
+    };
+    if allowed.trim() == "all" {
+        return true;
+    }
+
+    match std::fs::read_link(format!("/proc/{}/exe", pid)) {
+        Ok(exe) => allowed
+            .split(',')
+            .map(|a| a.trim())
+            .any(|a| !a.is_empty() && Path::new(a) == exe),
+        Err(_) => false,
+    }
+}
+
+/// Was this client trusted with privileged protocols
+///
+/// This is decided once when the client connects, see `process_is_trusted`.
+/// It is also used to hide the privileged globals from other clients.
+pub fn client_is_trusted(client: &ws::Client) -> bool {
+    match client.get_data::<ClientInfo>() {
+        Some(info) => info.ci_trusted.load(Ordering::Acquire),
+        None => false,
+    }
+}
@@ -0,0 +1,216 @@
+// Implementation of the wp_viewporter protocol
+//
+// Viewports let a client crop its buffer and scale it to a size of
+// its choosing, independent of the buffer scale. Clients using
+// fractional scaling render at the exact output size and use this to
+// set the surface size.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::wp::viewporter::server::{wp_viewport as wpv, wp_viewporter as wpvr};
+use ws::protocol::wl_surface;
+use ws::Resource;
+
+use super::surface::Surface;
+use crate::category5::Climate;
+use utils::region::Rect;
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<wpvr::WpViewporter, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<wpvr::WpViewporter>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<wpvr::WpViewporter, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wpvr::WpViewporter,
+        request: wpvr::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            wpvr::Request::GetViewport { id, surface } => {
+                let surf_cell = surface.data::<Arc<Mutex<Surface>>>().unwrap().clone();
+                let mut surf = surf_cell.lock().unwrap();
+                if surf.s_state.cs_viewport.is_some() {
+                    data_init.post_error(
+                        id,
+                        wpvr::Error::ViewportExists,
+                        "wl_surface already has a viewport",
+                    );
+                    return;
+                }
+
+                let viewport = data_init.init(id, surface.clone());
+                surf.s_state.cs_viewport = Some(Viewport::new(viewport));
+            }
+            wpvr::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &wpvr::WpViewporter,
+        data: &(),
+    ) {
+    }
+}
+
+/// The cropping and scaling set on a surface's viewport
+#[derive(Clone)]
+pub struct Viewport {
+    pub v_resource: wpv::WpViewport,
+    /// The region of the buffer to show, in surface coordinates
+    pub v_src: Option<Rect<f64>>,
+    /// The size to stretch the surface to
+    pub v_dst: Option<(i32, i32)>,
+}
+
+impl Viewport {
+    fn new(resource: wpv::WpViewport) -> Self {
+        Self {
+            v_resource: resource,
+            v_src: None,
+            v_dst: None,
+        }
+    }
+
+    /// Crop and scale a surface
+    ///
+    /// `size` is the surface size given by the buffer, after the buffer
+    /// scale and transform have been applied. Returns the new surface
+    /// size and the region of the buffer to sample, where the whole buffer
+    /// spans from (0, 0) to (1, 1).
+    ///
+    /// Returns None if the viewport is invalid, after posting an error.
+    pub fn apply(&self, size: (f32, f32)) -> Option<((f32, f32), Rect<f32>)> {
+        let (width, height) = (size.0 as f64, size.1 as f64);
+        let mut src_rect = Rect::new(0.0, 0.0, 1.0, 1.0);
+        let mut surf_size = size;
+
+        if let Some(src) = self.v_src.as_ref() {
+            if src.r_pos.0 + src.r_size.0 > width || src.r_pos.1 + src.r_size.1 > height {
+                self.v_resource.post_error(
+                    wpv::Error::OutOfBuffer,
+                    "Source rectangle extends outside of the buffer",
+                );
+                return None;
+            }
+
+            src_rect = Rect::new(
+                (src.r_pos.0 / width) as f32,
+                (src.r_pos.1 / height) as f32,
+                (src.r_size.0 / width) as f32,
+                (src.r_size.1 / height) as f32,
+            );
+            surf_size = (src.r_size.0 as f32, src.r_size.1 as f32);
+        }
+
+        match self.v_dst {
+            Some((w, h)) => surf_size = (w as f32, h as f32),
+            // Without a destination the source size becomes the surface size,
+            // which needs to be a whole number
+            None => {
+                if surf_size.0.fract() != 0.0 || surf_size.1.fract() != 0.0 {
+                    self.v_resource.post_error(
+                        wpv::Error::BadSize,
+                        "Source size is not an integer and no destination was set",
+                    );
+                    return None;
+                }
+            }
+        }
+
+        Some((surf_size, src_rect))
+    }
+}
+
+// The userdata is the wl_surface this viewport is for
+#[allow(unused_variables)]
+impl ws::Dispatch<wpv::WpViewport, wl_surface::WlSurface> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wpv::WpViewport,
+        request: wpv::Request,
+        data: &wl_surface::WlSurface,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        if let wpv::Request::Destroy = request {
+            return;
+        }
+        if !data.is_alive() {
+            resource.post_error(wpv::Error::NoSurface, "The wl_surface was destroyed");
+            return;
+        }
+
+        let surf_cell = data.data::<Arc<Mutex<Surface>>>().unwrap();
+        let mut surf = surf_cell.lock().unwrap();
+        let viewport = match surf.s_state.cs_viewport.as_mut() {
+            Some(viewport) => viewport,
+            None => return,
+        };
+
+        match request {
+            wpv::Request::SetSource {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                if x == -1.0 && y == -1.0 && width == -1.0 && height == -1.0 {
+                    viewport.v_src = None;
+                } else if x < 0.0 || y < 0.0 || width <= 0.0 || height <= 0.0 {
+                    resource.post_error(wpv::Error::BadValue, "Invalid source rectangle");
+                } else {
+                    viewport.v_src = Some(Rect::new(x, y, width, height));
+                }
+            }
+            wpv::Request::SetDestination { width, height } => {
+                if width == -1 && height == -1 {
+                    viewport.v_dst = None;
+                } else if width <= 0 || height <= 0 {
+                    resource.post_error(wpv::Error::BadValue, "Invalid destination size");
+                } else {
+                    viewport.v_dst = Some((width, height));
+                }
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &wpv::WpViewport,
+        data: &wl_surface::WlSurface,
+    ) {
+        // The viewport's state is removed on the next commit
+        if let Some(surf_cell) = data.data::<Arc<Mutex<Surface>>>() {
+            let mut surf = surf_cell.lock().unwrap();
+            if let Some(viewport) = surf.s_state.cs_viewport.as_ref() {
+                if viewport.v_resource.id() == resource.id() {
+                    surf.s_state.cs_viewport = None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// Implementation of the virtual keyboard protocol
+//
+// Virtual keyboards let trusted clients type as if they were a real
+// keyboard. Test harnesses and remote desktop tools use this, as do
+// input methods to send back keys they did not handle.
+extern crate nix;
+extern crate wayland_server as ws;
+
+use nix::sys::{mman, stat::fstat};
+
+use ws::protocol::wl_keyboard;
+use ws::Resource;
+
+use super::protocol::virtual_keyboard::{
+    zwp_virtual_keyboard_manager_v1 as zvkm, zwp_virtual_keyboard_v1 as zvk,
+};
+use crate::category5::atmosphere::Atmosphere;
+use crate::category5::Climate;
+use utils::log;
+
+use std::ops::DerefMut;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::sync::Mutex;
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zvkm::ZwpVirtualKeyboardManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zvkm::ZwpVirtualKeyboardManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: ws::Client, global_data: &()) -> bool {
+        super::utils::client_is_trusted(&client)
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zvkm::ZwpVirtualKeyboardManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zvkm::ZwpVirtualKeyboardManagerV1,
+        request: zvkm::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zvkm::Request::CreateVirtualKeyboard { seat, id } => {
+                data_init.init(
+                    id,
+                    Mutex::new(VirtualKeyboard {
+                        vk_has_keymap: false,
+                    }),
+                );
+
+                if !super::utils::client_is_trusted(client) {
+                    log::error!("Client is not allowed to create a virtual keyboard");
+                    resource.post_error(
+                        zvkm::Error::Unauthorized,
+                        "Client is not allowed to inject input",
+                    );
+                }
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zvkm::ZwpVirtualKeyboardManagerV1,
+        data: &(),
+    ) {
+    }
+}
+
+/// A keyboard controlled by a client
+pub struct VirtualKeyboard {
+    /// Clients must provide a keymap before sending keys
+    vk_has_keymap: bool,
+}
+
+/// Read the keymap text a client shared with us
+///
+/// The keymap is a NUL terminated string. Returns None if the fd
+/// is shorter than `size`, could not be mapped, or doesn't hold valid
+/// text.
+fn read_keymap(fd: &OwnedFd, size: u32) -> Option<String> {
+    let len = core::num::NonZeroUsize::new(size as usize)?;
+    // Reading past the end of the file would fault, so the client
+    // can't claim the keymap is larger than it is
+    let stat = fstat(fd.as_raw_fd())
+        .map_err(|e| log::error!("Could not stat the virtual keyboard keymap: {:?}", e))
+        .ok()?;
+    if len.get() as i64 > stat.st_size {
+        log::error!(
+            "Virtual keyboard keymap size {} is larger than its file",
+            size
+        );
+        return None;
+    }
+    unsafe {
+        let ptr = mman::mmap(
+            None,
+            len,
+            mman::ProtFlags::PROT_READ,
+            mman::MapFlags::MAP_PRIVATE,
+            fd,
+            0,
+        )
+        .map_err(|e| log::error!("Could not map the virtual keyboard keymap: {:?}", e))
+        .ok()?;
+
+        let bytes = std::slice::from_raw_parts(ptr.as_ptr() as *const u8, len.get());
+        let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+        let text = std::str::from_utf8(&bytes[..end]).map(|t| t.to_string());
+
+        mman::munmap(ptr, len.get()).unwrap();
+        text.ok()
+    }
+}
+
+/// Did the input method client send this request
+///
+/// Keys from the input method were already passed through its grab.
+fn is_from_input_method(atmos: &Atmosphere, client: &ws::Client) -> bool {
+    match atmos.get_input_method() {
+        Some(im) => match im.lock().unwrap().im_resource.as_ref() {
+            Some(resource) => resource.client().map(|c| c.id()) == Some(client.id()),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zvk::ZwpVirtualKeyboardV1, Mutex<VirtualKeyboard>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zvk::ZwpVirtualKeyboardV1,
+        request: zvk::Request,
+        data: &Mutex<VirtualKeyboard>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zvk::Request::Keymap { format, fd, size } => {
+                if format != wl_keyboard::KeymapFormat::XkbV1 as u32 {
+                    log::error!("Virtual keyboard keymap has unknown format {}", format);
+                    return;
+                }
+
+                let text = match read_keymap(&fd, size) {
+                    Some(text) => text,
+                    None => {
+                        log::error!("Could not read the virtual keyboard keymap");
+                        return;
+                    }
+                };
+                // Keys are interpreted with this keymap, and clients are
+                // sent it before they get keys from this keyboard
+                if !state.c_input.set_virtual_keymap(resource.id(), text) {
+                    log::error!("Could not compile the virtual keyboard keymap");
+                    return;
+                }
+                data.lock().unwrap().vk_has_keymap = true;
+            }
+            zvk::Request::Key {
+                time,
+                key,
+                state: key_state,
+            } => {
+                if !data.lock().unwrap().vk_has_keymap {
+                    resource.post_error(zvk::Error::NoKeymap, "No keymap was set");
+                    return;
+                }
+
+                let mut atmos = state.c_atmos.lock().unwrap();
+                let from_input_method = is_from_input_method(&atmos, client);
+                state.c_input.handle_virtual_key(
+                    atmos.deref_mut(),
+                    &resource.id(),
+                    key,
+                    key_state == wl_keyboard::KeyState::Pressed as u32,
+                    from_input_method,
+                );
+            }
+            zvk::Request::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+            } => {
+                if !data.lock().unwrap().vk_has_keymap {
+                    resource.post_error(zvk::Error::NoKeymap, "No keymap was set");
+                    return;
+                }
+
+                state.c_input.handle_virtual_modifiers(
+                    state.c_atmos.lock().unwrap().deref_mut(),
+                    &resource.id(),
+                    mods_depressed,
+                    mods_latched,
+                    mods_locked,
+                    group,
+                );
+            }
+            zvk::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zvk::ZwpVirtualKeyboardV1,
+        data: &Mutex<VirtualKeyboard>,
+    ) {
+        state.c_input.remove_virtual_keymap(&resource.id());
+    }
+}
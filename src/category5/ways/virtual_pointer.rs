@@ -0,0 +1,304 @@
+// Implementation of the wlr virtual pointer protocol
+//
+// Virtual pointers let trusted clients move the cursor, click and
+// scroll as if they were a mouse. Their requests are turned into the
+// same platform events our real devices produce.
+extern crate dakota as dak;
+extern crate wayland_server as ws;
+
+use ws::protocol::{wl_output, wl_pointer};
+use ws::Resource;
+
+use super::protocol::wlr_virtual_pointer::{
+    zwlr_virtual_pointer_manager_v1 as zvpm, zwlr_virtual_pointer_v1 as zvp,
+};
+use crate::category5::Climate;
+use utils::{log, region::Rect};
+
+use std::ops::DerefMut;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zvpm::ZwlrVirtualPointerManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zvpm::ZwlrVirtualPointerManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: ws::Client, global_data: &()) -> bool {
+        super::utils::client_is_trusted(&client)
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zvpm::ZwlrVirtualPointerManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zvpm::ZwlrVirtualPointerManagerV1,
+        request: zvpm::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let (id, output) = match request {
+            zvpm::Request::CreateVirtualPointer { seat, id } => (id, None),
+            zvpm::Request::CreateVirtualPointerWithOutput { seat, output, id } => (id, output),
+            zvpm::Request::Destroy => return,
+            _ => unimplemented!(),
+        };
+
+        // This protocol has no error for refusing a client, so pointers
+        // created by untrusted clients just ignore all requests
+        let allowed = super::utils::client_is_trusted(client);
+        if !allowed {
+            log::error!("Client is not allowed to create a virtual pointer");
+        }
+        data_init.init(id, Mutex::new(VirtualPointer::new(allowed, output)));
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zvpm::ZwlrVirtualPointerManagerV1,
+        data: &(),
+    ) {
+    }
+}
+
+/// A pointer controlled by a client
+///
+/// Axis requests are collected until the frame request, since a single
+/// scroll event is made from several of them.
+pub struct VirtualPointer {
+    vp_allowed: bool,
+    /// Absolute motion is mapped to this output, or to the entire
+    /// desktop if None
+    vp_output: Option<wl_output::WlOutput>,
+    /// Fractions of a pixel left over from relative motion
+    vp_remainder: (f64, f64),
+    vp_axis_source: dak::AxisSource,
+    vp_axis: (Option<f64>, Option<f64>),
+    vp_axis_discrete: (f64, f64),
+}
+
+impl VirtualPointer {
+    fn new(allowed: bool, output: Option<wl_output::WlOutput>) -> Self {
+        Self {
+            vp_allowed: allowed,
+            vp_output: output,
+            vp_remainder: (0.0, 0.0),
+            vp_axis_source: dak::AxisSource::Wheel,
+            vp_axis: (None, None),
+            vp_axis_discrete: (0.0, 0.0),
+        }
+    }
+
+    /// Record an axis value to be sent with the next frame
+    fn set_axis(&mut self, axis: ws::WEnum<wl_pointer::Axis>, value: f64, discrete: f64) {
+        match axis {
+            ws::WEnum::Value(wl_pointer::Axis::HorizontalScroll) => {
+                self.vp_axis.0 = Some(value);
+                self.vp_axis_discrete.0 = discrete;
+            }
+            ws::WEnum::Value(wl_pointer::Axis::VerticalScroll) => {
+                self.vp_axis.1 = Some(value);
+                self.vp_axis_discrete.1 = discrete;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Find the area of the desktop absolute motion is mapped to
+fn get_absolute_area(state: &Climate, output: Option<&wl_output::WlOutput>) -> Option<Rect<i32>> {
+    let outputs = state.c_atmos.lock().unwrap().get_outputs();
+
+    if let Some(output_id) = output.and_then(|o| o.data::<u32>()) {
+        if let Some(layout) = outputs.iter().find(|o| o.ol_id == *output_id) {
+            return Some(layout.ol_rect);
+        }
+    }
+
+    // Otherwise use the bounding box of all outputs
+    let first = outputs.first()?.ol_rect;
+    let (mut x1, mut y1) = first.r_pos;
+    let (mut x2, mut y2) = (x1 + first.r_size.0, y1 + first.r_size.1);
+    for layout in outputs.iter() {
+        let rect = &layout.ol_rect;
+        x1 = x1.min(rect.r_pos.0);
+        y1 = y1.min(rect.r_pos.1);
+        x2 = x2.max(rect.r_pos.0 + rect.r_size.0);
+        y2 = y2.max(rect.r_pos.1 + rect.r_size.1);
+    }
+
+    Some(Rect::new(x1, y1, x2 - x1, y2 - y1))
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zvp::ZwlrVirtualPointerV1, Mutex<VirtualPointer>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zvp::ZwlrVirtualPointerV1,
+        request: zvp::Request,
+        data: &Mutex<VirtualPointer>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut vp = data.lock().unwrap();
+        if !vp.vp_allowed {
+            return;
+        }
+
+        // The time clients send has no defined base, so events are
+        // stamped when we get them
+        let ev = match request {
+            zvp::Request::Motion { time, dx, dy } => {
+                let x = dx + vp.vp_remainder.0;
+                let y = dy + vp.vp_remainder.1;
+                vp.vp_remainder = (x.fract(), y.fract());
+
+                dak::PlatformEvent::InputMouseMove {
+                    dx: x.trunc() as i32,
+                    dy: y.trunc() as i32,
+                    time: Instant::now(),
+                }
+            }
+            zvp::Request::MotionAbsolute {
+                time,
+                x,
+                y,
+                x_extent,
+                y_extent,
+            } => {
+                if x_extent == 0 || y_extent == 0 {
+                    return;
+                }
+                let area = match get_absolute_area(state, vp.vp_output.as_ref()) {
+                    Some(area) => area,
+                    None => return,
+                };
+
+                // Move by the distance between the cursor and the target
+                let tx = area.r_pos.0 as f64 + area.r_size.0 as f64 * x as f64 / x_extent as f64;
+                let ty = area.r_pos.1 as f64 + area.r_size.1 as f64 * y as f64 / y_extent as f64;
+                let (cx, cy) = state.c_atmos.lock().unwrap().get_cursor_pos();
+
+                dak::PlatformEvent::InputMouseMove {
+                    dx: (tx - cx).round() as i32,
+                    dy: (ty - cy).round() as i32,
+                    time: Instant::now(),
+                }
+            }
+            zvp::Request::Button {
+                time,
+                button,
+                state: button_state,
+            } => {
+                let button = dak::input::convert_libinput_mouse_to_dakota(button);
+                let (cx, cy) = state.c_atmos.lock().unwrap().get_cursor_pos();
+                match button_state {
+                    ws::WEnum::Value(wl_pointer::ButtonState::Pressed) => {
+                        dak::PlatformEvent::InputMouseButtonDown {
+                            button: button,
+                            x: cx as i32,
+                            y: cy as i32,
+                            time: Instant::now(),
+                        }
+                    }
+                    _ => dak::PlatformEvent::InputMouseButtonUp {
+                        button: button,
+                        x: cx as i32,
+                        y: cy as i32,
+                        time: Instant::now(),
+                    },
+                }
+            }
+            zvp::Request::Axis { time, axis, value } => {
+                if let ws::WEnum::Unknown(_) = axis {
+                    resource.post_error(zvp::Error::InvalidAxis, "Invalid axis");
+                    return;
+                }
+                vp.set_axis(axis, value, 0.0);
+                return;
+            }
+            zvp::Request::AxisDiscrete {
+                time,
+                axis,
+                value,
+                discrete,
+            } => {
+                if let ws::WEnum::Unknown(_) = axis {
+                    resource.post_error(zvp::Error::InvalidAxis, "Invalid axis");
+                    return;
+                }
+                // We deliver discrete scrolling as v120 values
+                vp.set_axis(axis, value, discrete as f64 * 120.0);
+                return;
+            }
+            zvp::Request::AxisStop { time, axis } => {
+                if let ws::WEnum::Unknown(_) = axis {
+                    resource.post_error(zvp::Error::InvalidAxis, "Invalid axis");
+                    return;
+                }
+                // A zero value tells clients the axis has stopped
+                vp.set_axis(axis, 0.0, 0.0);
+                return;
+            }
+            zvp::Request::AxisSource { axis_source } => {
+                vp.vp_axis_source = match axis_source {
+                    ws::WEnum::Value(wl_pointer::AxisSource::Wheel)
+                    | ws::WEnum::Value(wl_pointer::AxisSource::WheelTilt) => dak::AxisSource::Wheel,
+                    ws::WEnum::Value(_) => dak::AxisSource::Finger,
+                    ws::WEnum::Unknown(_) => {
+                        resource.post_error(zvp::Error::InvalidAxisSource, "Invalid axis source");
+                        return;
+                    }
+                };
+                return;
+            }
+            zvp::Request::Frame => {
+                // Only scrolling is held until the frame
+                if vp.vp_axis == (None, None) {
+                    return;
+                }
+
+                let (cx, cy) = state.c_atmos.lock().unwrap().get_cursor_pos();
+                let ev = dak::PlatformEvent::InputScroll {
+                    position: (cx as i32, cy as i32),
+                    xrel: vp.vp_axis.0.map(|v| v as i32),
+                    yrel: vp.vp_axis.1.map(|v| v as i32),
+                    v120_val: vp.vp_axis_discrete,
+                    source: vp.vp_axis_source,
+                    time: Instant::now(),
+                };
+                vp.vp_axis = (None, None);
+                vp.vp_axis_discrete = (0.0, 0.0);
+                ev
+            }
+            zvp::Request::Destroy => return,
+            _ => unimplemented!(),
+        };
+
+        state
+            .c_input
+            .handle_input_event(state.c_atmos.lock().unwrap().deref_mut(), &ev);
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zvp::ZwlrVirtualPointerV1,
+        data: &Mutex<VirtualPointer>,
+    ) {
+    }
+}
@@ -6,23 +6,44 @@
 // Austin Shafer 2020
 extern crate wayland_server as ws;
 
+use super::xdg_output;
+use crate::category5::atmosphere::OutputLayout;
 use crate::category5::ws::Resource;
 use crate::category5::Climate;
+use dak::DisplayDescription;
 use ws::protocol::wl_output;
 use ws::protocol::wl_output::{Mode, Subpixel, Transform};
 
+/// The wl_output global advertising one of vkcomp's outputs
+pub struct OutputGlobal {
+    og_global: ws::backend::GlobalId,
+    /// The layout last sent to clients for this output
+    og_layout: OutputLayout,
+}
+
+// The global data is the id of the output this advertises
 #[allow(unused_variables)]
-impl ws::GlobalDispatch<wl_output::WlOutput, ()> for Climate {
+impl ws::GlobalDispatch<wl_output::WlOutput, u32> for Climate {
     fn bind(
         state: &mut Self,
         handle: &ws::DisplayHandle,
         client: &ws::Client,
         resource: ws::New<wl_output::WlOutput>,
-        global_data: &(),
+        global_data: &u32,
         data_init: &mut ws::DataInit<'_, Self>,
     ) {
-        let out = data_init.init(resource, ());
-        state.send_geometry(out.clone());
+        let out = data_init.init(resource, *global_data);
+
+        // The output may have been destroyed since the client saw
+        // the global. In that case there is nothing to send.
+        if let Some(og) = state.get_output_global(*global_data) {
+            if out.version() >= 4 {
+                // The name must only be sent once
+                out.name(og.og_layout.ol_desc.dd_name.clone());
+            }
+            send_geometry(&out, &og.og_layout);
+            send_done(&out);
+        }
 
         // Add this new output object to our list to notify
         // when the output size changes
@@ -31,13 +52,13 @@ impl ws::GlobalDispatch<wl_output::WlOutput, ()> for Climate {
 }
 
 #[allow(unused_variables)]
-impl ws::Dispatch<wl_output::WlOutput, ()> for Climate {
+impl ws::Dispatch<wl_output::WlOutput, u32> for Climate {
     fn request(
         state: &mut Self,
         client: &ws::Client,
         resource: &wl_output::WlOutput,
         request: wl_output::Request,
-        data: &(),
+        data: &u32,
         dhandle: &ws::DisplayHandle,
         data_init: &mut ws::DataInit<'_, Self>,
     ) {
@@ -47,43 +68,136 @@ impl ws::Dispatch<wl_output::WlOutput, ()> for Climate {
         state: &mut Self,
         _client: ws::backend::ClientId,
         resource: &wl_output::WlOutput,
-        data: &(),
+        data: &u32,
     ) {
         // keep all of the outputs except this one
         state.c_outputs.retain(|o| o.id() != resource.id());
     }
 }
 
-impl Climate {
-    pub fn send_geometry(&mut self, out: wl_output::WlOutput) {
-        let res = self.c_atmos.lock().unwrap().get_resolution();
-        // send geometry
-        out.geometry(
-            0,
-            0,
-            res.0 as i32,
-            res.1 as i32,
-            Subpixel::Unknown,
-            "monitor".to_string(),
-            "".to_string(),
-            Transform::Normal,
-        );
-
-        out.mode(
-            Mode::Current,
-            res.0 as i32,
-            res.1 as i32,
-            60, // 60 Hz default
-        );
-
-        // let the client know we are done with the monitor config
+/// The human readable description advertised for an output
+pub fn get_description(desc: &DisplayDescription) -> String {
+    format!("{} {} ({})", desc.dd_make, desc.dd_model, desc.dd_name)
+}
+
+/// Send the position, mode, scale, and description of an output
+///
+/// The caller should follow this with `send_done`
+fn send_geometry(out: &wl_output::WlOutput, layout: &OutputLayout) {
+    let desc = &layout.ol_desc;
+    out.geometry(
+        layout.ol_rect.r_pos.0,
+        layout.ol_rect.r_pos.1,
+        desc.dd_physical_size.0 as i32,
+        desc.dd_physical_size.1 as i32,
+        Subpixel::Unknown,
+        desc.dd_make.clone(),
+        desc.dd_model.clone(),
+        Transform::Normal,
+    );
+
+    out.mode(
+        Mode::Current | Mode::Preferred,
+        layout.ol_resolution.0 as i32,
+        layout.ol_resolution.1 as i32,
+        desc.dd_refresh_rate as i32,
+    );
+
+    // Fractional scales are rounded up, and clients will be sent the exact
+    // value if they use wp_fractional_scale
+    if out.version() >= 2 {
+        out.scale(layout.get_buffer_scale());
+    }
+    if out.version() >= 4 {
+        out.description(get_description(desc));
+    }
+}
+
+/// Let the client know we are done with the monitor config
+fn send_done(out: &wl_output::WlOutput) {
+    if out.version() >= 2 {
         out.done();
     }
+}
+
+impl Climate {
+    fn get_output_global(&self, id: u32) -> Option<&OutputGlobal> {
+        self.c_output_globals
+            .iter()
+            .find(|og| og.og_layout.ol_id == id)
+    }
+
+    /// Match our wl_output globals to the outputs vkcomp is driving
+    ///
+    /// A global is created for each new output, and removed when its
+    /// output is destroyed. Clients bound to an output that moved or
+    /// changed modes are sent the updated geometry.
+    pub fn update_output_globals(&mut self, handle: &ws::DisplayHandle) {
+        let outputs = self.c_atmos.lock().unwrap().get_outputs();
+        let mut changed = false;
+
+        let mut i = 0;
+        while i < self.c_output_globals.len() {
+            let id = self.c_output_globals[i].og_layout.ol_id;
+            if outputs.iter().any(|o| o.ol_id == id) {
+                i += 1;
+            } else {
+                let og = self.c_output_globals.remove(i);
+                handle.remove_global::<Climate>(og.og_global);
+                changed = true;
+            }
+        }
+
+        for layout in outputs.into_iter() {
+            match self
+                .c_output_globals
+                .iter_mut()
+                .find(|og| og.og_layout.ol_id == layout.ol_id)
+            {
+                Some(og) => {
+                    if og.og_layout.ol_rect == layout.ol_rect
+                        && og.og_layout.ol_desc == layout.ol_desc
+                        && og.og_layout.ol_resolution == layout.ol_resolution
+                        && og.og_layout.ol_scale == layout.ol_scale
+                    {
+                        continue;
+                    }
+                    changed = true;
+
+                    for out in self.c_outputs.iter() {
+                        if *out.data::<u32>().unwrap() != layout.ol_id {
+                            continue;
+                        }
+                        send_geometry(out, &layout);
+
+                        // Update any xdg_outputs extending this one before
+                        // the done event is sent
+                        for xo in self.c_xdg_outputs.iter() {
+                            if xo.data::<wl_output::WlOutput>() == Some(out) {
+                                xdg_output::send_logical_geometry(xo, &layout);
+                                if xo.version() < 3 {
+                                    xo.done();
+                                }
+                            }
+                        }
+                        send_done(out);
+                    }
+                    og.og_layout = layout;
+                }
+                None => {
+                    self.c_output_globals.push(OutputGlobal {
+                        og_global: handle
+                            .create_global::<Climate, wl_output::WlOutput, u32>(4, layout.ol_id),
+                        og_layout: layout,
+                    });
+                    changed = true;
+                }
+            }
+        }
 
-    pub fn send_all_geometry(&mut self) {
-        for i in 0..self.c_outputs.len() {
-            let out = self.c_outputs[i].clone();
-            self.send_geometry(out);
+        // Surfaces may now be on an output with a different scale
+        if changed {
+            self.update_fractional_scales();
         }
     }
 }
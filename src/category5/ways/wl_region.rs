@@ -44,7 +44,7 @@ impl ws::Dispatch<wl_region::WlRegion, Arc<Mutex<Region>>> for Climate {
 }
 
 /// The private userdata for the wl_region
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Region {
     /// A list of rectangles which make up the
     /// active portion of the region
@@ -0,0 +1,241 @@
+// Implementation of the xdg_activation protocol
+//
+// Activation lets one client hand focus to another, such as a launcher
+// starting an app or a chat client opening a link in the browser. The
+// requesting client gets a token which the target passes back to us.
+//
+// To avoid windows stealing focus, a token only grants focus if it was
+// made by the focused window in response to recent user input. Windows
+// activated with any other token are marked urgent instead.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::xdg::activation::v1::server::{
+    xdg_activation_token_v1 as xat, xdg_activation_v1 as xa,
+};
+use ws::protocol::{wl_seat, wl_surface};
+use ws::Resource;
+
+use super::seat::Seat;
+use super::surface::Surface;
+use crate::category5::atmosphere::Atmosphere;
+use crate::category5::Climate;
+use utils::log;
+
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a token may be used after it was created
+const TOKEN_LIFETIME: Duration = Duration::from_secs(30);
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<xa::XdgActivationV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<xa::XdgActivationV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<xa::XdgActivationV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &xa::XdgActivationV1,
+        request: xa::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            xa::Request::GetActivationToken { id } => {
+                data_init.init(id, Mutex::new(TokenRequest::new()));
+            }
+            xa::Request::Activate { token, surface } => {
+                state.activate(&token, &surface);
+            }
+            xa::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &xa::XdgActivationV1,
+        data: &(),
+    ) {
+    }
+}
+
+/// The properties a client gives us while requesting a token
+pub struct TokenRequest {
+    /// The serial of the input event which caused this request, and
+    /// the seat it was delivered on
+    tr_serial: Option<(u32, wl_seat::WlSeat)>,
+    /// The surface requesting the token
+    tr_surface: Option<wl_surface::WlSurface>,
+    tr_app_id: Option<String>,
+    /// Tokens may only be requested once per object
+    tr_committed: bool,
+}
+
+impl TokenRequest {
+    fn new() -> Self {
+        Self {
+            tr_serial: None,
+            tr_surface: None,
+            tr_app_id: None,
+            tr_committed: false,
+        }
+    }
+
+    /// Is the requester allowed to pass focus to another window
+    ///
+    /// The requesting surface must belong to the focused window, and
+    /// the serial must be from the last input that window received.
+    fn may_grant_focus(&self, atmos: &Atmosphere) -> bool {
+        let (serial, seat) = match self.tr_serial.as_ref() {
+            Some(serial) => serial,
+            None => return false,
+        };
+        let surf = match self.tr_surface.as_ref() {
+            Some(surf) => surf.data::<Arc<Mutex<Surface>>>().unwrap().clone(),
+            None => return false,
+        };
+        let id = surf.lock().unwrap().s_id.clone();
+
+        let root = atmos.a_root_window.get_clone(&id).unwrap_or(id.clone());
+        if atmos.get_win_focus() != Some(root) {
+            return false;
+        }
+
+        let seat = seat.data::<Arc<Mutex<Seat>>>().unwrap().lock().unwrap();
+        atmos.a_owner.get(&id).map(|owner| *owner == seat.s_id) == Some(true)
+            && seat.is_recent_input_serial(*serial)
+    }
+}
+
+/// A token we have handed out
+pub struct ActivationToken {
+    at_token: String,
+    /// Does this token allow the window to take focus
+    at_valid: bool,
+    at_created: Instant,
+}
+
+/// Make a token which clients can't guess
+fn generate_token() -> String {
+    let mut bytes = [0u8; 16];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .expect("Could not read random bytes for activation token");
+
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Climate {
+    /// Handle a request to focus `surface`
+    ///
+    /// Tokens can only be used once. If the token doesn't allow this
+    /// window to take focus then it is marked as urgent instead.
+    fn activate(&mut self, token: &str, surface: &wl_surface::WlSurface) {
+        self.c_activation_tokens
+            .retain(|t| t.at_created.elapsed() < TOKEN_LIFETIME);
+        let valid = match self
+            .c_activation_tokens
+            .iter()
+            .position(|t| t.at_token == token)
+        {
+            Some(index) => self.c_activation_tokens.remove(index).at_valid,
+            None => false,
+        };
+
+        let id = surface
+            .data::<Arc<Mutex<Surface>>>()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .s_id
+            .clone();
+        let mut atmos = self.c_atmos.lock().unwrap();
+        let root = atmos.a_root_window.get_clone(&id).unwrap_or(id.clone());
+        // We can only raise windows which are on the desktop
+        let minimized = atmos.a_minimized.get(&root).map(|m| *m) == Some(true);
+
+        match valid && !minimized {
+            true => {
+                log::debug!("Activating window {:?}", root);
+                atmos.focus_on(Some(root));
+            }
+            false => {
+                log::debug!("Window {:?} may not take focus, marking urgent", root);
+                atmos.mark_urgent(&root);
+            }
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<xat::XdgActivationTokenV1, Mutex<TokenRequest>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &xat::XdgActivationTokenV1,
+        request: xat::Request,
+        data: &Mutex<TokenRequest>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut req = data.lock().unwrap();
+
+        match request {
+            xat::Request::SetSerial { serial, seat } => req.tr_serial = Some((serial, seat)),
+            xat::Request::SetAppId { app_id } => req.tr_app_id = Some(app_id),
+            xat::Request::SetSurface { surface } => req.tr_surface = Some(surface),
+            xat::Request::Commit => {
+                if req.tr_committed {
+                    resource.post_error(xat::Error::AlreadyUsed, "Token was already committed");
+                    return;
+                }
+                req.tr_committed = true;
+
+                let valid = req.may_grant_focus(&state.c_atmos.lock().unwrap());
+                let token = generate_token();
+                log::debug!(
+                    "Created activation token for app {:?}, valid: {}",
+                    req.tr_app_id,
+                    valid
+                );
+
+                state
+                    .c_activation_tokens
+                    .retain(|t| t.at_created.elapsed() < TOKEN_LIFETIME);
+                state.c_activation_tokens.push(ActivationToken {
+                    at_token: token.clone(),
+                    at_valid: valid,
+                    at_created: Instant::now(),
+                });
+                resource.done(token);
+            }
+            xat::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &xat::XdgActivationTokenV1,
+        data: &Mutex<TokenRequest>,
+    ) {
+    }
+}
@@ -0,0 +1,149 @@
+// Implementation of the xdg-decoration protocol
+//
+// This lets clients negotiate if their window decorations are drawn
+// by themselves or by us. Server side titlebars are drawn by vkcomp.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::xdg::decoration::zv1::server::{
+    zxdg_decoration_manager_v1 as zxdm, zxdg_toplevel_decoration_v1 as zxtd,
+};
+use wayland_protocols::xdg::shell::server::xdg_toplevel;
+use ws::Resource;
+
+use super::xdg_shell::ShellSurface;
+use crate::category5::atmosphere::Atmosphere;
+use crate::category5::Climate;
+
+use std::ops::DerefMut;
+use std::sync::{Arc, Mutex};
+
+/// The mode we pick when the client leaves it up to us
+///
+/// Most clients draw their own decorations, so default to that
+const PREFERRED_MODE: zxtd::Mode = zxtd::Mode::ClientSide;
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zxdm::ZxdgDecorationManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zxdm::ZxdgDecorationManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zxdm::ZxdgDecorationManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zxdm::ZxdgDecorationManagerV1,
+        request: zxdm::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zxdm::Request::GetToplevelDecoration { id, toplevel } => {
+                let shsurf = toplevel.data::<Arc<Mutex<ShellSurface>>>().cloned();
+                if let Some(ss) = shsurf.as_ref() {
+                    if ss.lock().unwrap().ss_decoration.is_some() {
+                        data_init.post_error(
+                            id,
+                            zxtd::Error::AlreadyConstructed,
+                            "xdg_toplevel already has a decoration object",
+                        );
+                        return;
+                    }
+                }
+
+                // The decoration just needs to find the toplevel it
+                // belongs to
+                let decoration = data_init.init(id, toplevel.clone());
+                if let Some(ss) = shsurf {
+                    ss.lock().unwrap().ss_decoration = Some(decoration);
+                }
+            }
+            zxdm::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zxdm::ZxdgDecorationManagerV1,
+        data: &(),
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<zxtd::ZxdgToplevelDecorationV1, xdg_toplevel::XdgToplevel> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zxtd::ZxdgToplevelDecorationV1,
+        request: zxtd::Request,
+        data: &xdg_toplevel::XdgToplevel,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+
+        match request {
+            zxtd::Request::SetMode { mode } => {
+                let mode = mode.into_result().unwrap_or(PREFERRED_MODE);
+                set_decoration_mode(atmos.deref_mut(), resource, data, mode);
+            }
+            zxtd::Request::UnsetMode => {
+                set_decoration_mode(atmos.deref_mut(), resource, data, PREFERRED_MODE)
+            }
+            zxtd::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zxtd::ZxdgToplevelDecorationV1,
+        data: &xdg_toplevel::XdgToplevel,
+    ) {
+        // Without a decoration object the client is responsible for
+        // drawing its own decorations again
+        if let Some(ss) = data.data::<Arc<Mutex<ShellSurface>>>() {
+            let mut atmos = state.c_atmos.lock().unwrap();
+            let mut ss = ss.lock().unwrap();
+            ss.ss_decoration = None;
+            ss.set_server_side_decorations(atmos.deref_mut(), false);
+        }
+    }
+}
+
+/// Apply a decoration mode and tell the client about it
+///
+/// The decoration configure event has to be followed by an
+/// xdg_surface.configure, which is what makes the client apply it.
+fn set_decoration_mode(
+    atmos: &mut Atmosphere,
+    decoration: &zxtd::ZxdgToplevelDecorationV1,
+    toplevel: &xdg_toplevel::XdgToplevel,
+    mode: zxtd::Mode,
+) {
+    let ss_cell = match toplevel.data::<Arc<Mutex<ShellSurface>>>() {
+        Some(ss) => ss.clone(),
+        None => return,
+    };
+    let mut ss = ss_cell.lock().unwrap();
+
+    ss.set_server_side_decorations(atmos, mode == zxtd::Mode::ServerSide);
+    decoration.configure(mode);
+    ss.reconfigure(atmos);
+}
@@ -0,0 +1,136 @@
+// Implementation of the xdg-output protocol
+//
+// This supplements wl_output with the logical position and size of
+// each output within the desktop. Tools like bars and screenshot
+// utilities use this to find where each display is.
+extern crate wayland_protocols;
+extern crate wayland_server as ws;
+
+use wayland_protocols::xdg::xdg_output::zv1::server::{
+    zxdg_output_manager_v1 as zxom, zxdg_output_v1 as zxo,
+};
+use ws::protocol::wl_output;
+use ws::Resource;
+
+use super::wl_output::get_description;
+use crate::category5::atmosphere::OutputLayout;
+use crate::category5::Climate;
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<zxom::ZxdgOutputManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<zxom::ZxdgOutputManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<zxom::ZxdgOutputManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zxom::ZxdgOutputManagerV1,
+        request: zxom::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zxom::Request::GetXdgOutput { id, output } => {
+                let xdg_output = data_init.init(id, output.clone());
+
+                let output_id = *output.data::<u32>().unwrap();
+                let layout = state
+                    .c_atmos
+                    .lock()
+                    .unwrap()
+                    .get_outputs()
+                    .into_iter()
+                    .find(|o| o.ol_id == output_id);
+                // Skip outputs that have already been destroyed
+                if let Some(layout) = layout {
+                    // The name never changes, so it is only sent here
+                    if xdg_output.version() >= 2 {
+                        xdg_output.name(layout.ol_desc.dd_name.clone());
+                    }
+                    send_logical_geometry(&xdg_output, &layout);
+                    send_done(&xdg_output, &output);
+                }
+
+                state.c_xdg_outputs.push(xdg_output);
+            }
+            zxom::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &zxom::ZxdgOutputManagerV1,
+        data: &(),
+    ) {
+    }
+}
+
+// The userdata is the wl_output this extends
+#[allow(unused_variables)]
+impl ws::Dispatch<zxo::ZxdgOutputV1, wl_output::WlOutput> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &zxo::ZxdgOutputV1,
+        request: zxo::Request,
+        data: &wl_output::WlOutput,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            zxo::Request::Destroy => {}
+            _ => unimplemented!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &zxo::ZxdgOutputV1,
+        data: &wl_output::WlOutput,
+    ) {
+        state.c_xdg_outputs.retain(|o| o.id() != resource.id());
+    }
+}
+
+/// Send the position and size of an output within the desktop
+///
+/// The logical size is the resolution divided by the exact scale, not
+/// the integer one in wl_output.scale. See `OutputLayout::get_buffer_scale`.
+/// This does not send a done event, see `send_done`.
+pub fn send_logical_geometry(xdg_output: &zxo::ZxdgOutputV1, layout: &OutputLayout) {
+    xdg_output.logical_position(layout.ol_rect.r_pos.0, layout.ol_rect.r_pos.1);
+    xdg_output.logical_size(layout.ol_rect.r_size.0, layout.ol_rect.r_size.1);
+
+    // This was moved to wl_output in version 4, but is still
+    // useful for older clients
+    if xdg_output.version() >= 2 {
+        xdg_output.description(get_description(&layout.ol_desc));
+    }
+}
+
+/// Mark the end of a round of xdg_output updates
+///
+/// Starting in version 3 this is done with wl_output.done instead
+pub fn send_done(xdg_output: &zxo::ZxdgOutputV1, output: &wl_output::WlOutput) {
+    if xdg_output.version() < 3 {
+        xdg_output.done();
+    } else if output.version() >= 2 {
+        output.done();
+    }
+}
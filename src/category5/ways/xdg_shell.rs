@@ -5,11 +5,13 @@
 extern crate wayland_protocols;
 extern crate wayland_server as ws;
 
+use wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1 as zxtd;
 use wayland_protocols::xdg::shell::server::*;
 use ws::protocol::wl_surface;
 use ws::Resource;
 
 use super::role::Role;
+use super::seat::Seat;
 use super::surface::*;
 use crate::category5::vkcomp::wm;
 use crate::category5::Climate;
@@ -22,6 +24,9 @@ use std::clone::Clone;
 use std::ops::DerefMut;
 use std::sync::{Arc, Mutex};
 
+#[cfg(test)]
+mod tests;
+
 // --------------------------------------------------------------
 // xdg_wm_base
 // --------------------------------------------------------------
@@ -87,6 +92,8 @@ pub fn xdg_wm_base_handle_request(
                 ss_last_acked: 0,
                 ss_xdg_toplevel: None,
                 ss_xdg_popup: None,
+                ss_saved_geometry: None,
+                ss_decoration: None,
             }));
 
             // Pass ourselves as user data
@@ -235,6 +242,11 @@ pub struct ShellSurface {
     // serial number used
     pub ss_serial: u32,
     pub ss_last_acked: u32,
+    // The window position and size from before it was maximized
+    ss_saved_geometry: Option<((f32, f32), (f32, f32))>,
+    /// The xdg-decoration object for this toplevel. There may only
+    /// be one, and it has to be destroyed before the toplevel.
+    pub ss_decoration: Option<zxtd::ZxdgToplevelDecorationV1>,
 }
 
 impl ShellSurface {
@@ -252,42 +264,78 @@ impl ShellSurface {
         positioner: xdg_positioner::XdgPositioner,
         userdata: Arc<Mutex<ShellSurface>>,
     ) {
+        // The positioner may be changed or destroyed after this, so
+        // keep a copy of its current rules
+        let pos = *positioner
+            .data::<Arc<Mutex<Positioner>>>()
+            .expect("Bug: positioner did not have userdata attached")
+            .lock()
+            .unwrap();
         self.ss_xdg_popup = Some(Popup {
             pu_pop: popup.clone(),
             pu_parent: parent.clone(),
+            pu_xdg_surface: xdg_surf.clone(),
+            pu_positioner: pos,
+            pu_geometry: None,
         });
 
         {
             // assign the popup role
             let surf_cell = self.ss_surface.clone();
             let mut surf = surf_cell.lock().unwrap();
-            let parent_surf = parent.as_ref().expect("Bug: popup missing parent");
-            // Now get our ShellSurface object from the XdgSurface protocol object
-            let parent_xdgsurf = parent_surf
-                .data::<Arc<Mutex<ShellSurface>>>()
-                .unwrap()
-                .lock()
-                .unwrap();
-            let parent_surf_id = &parent_xdgsurf.ss_surface.lock().unwrap().s_id;
-
-            // Now we can tell vkcomp to add this surface to the subsurface stack
-            // in Thundr
-            atmos.add_new_top_subsurf(parent_surf_id, &surf.s_id);
-            log::debug!(
-                "Adding popup subsurf {:?} to parent {:?}",
-                surf.s_id.get_raw_id(),
-                parent_surf_id,
-            );
+            // Popups of layer surfaces are created without a parent, it
+            // gets assigned later through zwlr_layer_surface_v1.get_popup
+            if let Some(parent_surf) = parent.as_ref() {
+                // Now get our ShellSurface object from the XdgSurface protocol object
+                let parent_xdgsurf = parent_surf
+                    .data::<Arc<Mutex<ShellSurface>>>()
+                    .unwrap()
+                    .lock()
+                    .unwrap();
+                let parent_surf_id = &parent_xdgsurf.ss_surface.lock().unwrap().s_id;
+
+                Self::add_popup_to_parent(atmos, parent_surf_id, &surf.s_id);
+            }
 
             surf.s_role = Some(Role::xdg_shell_popup(userdata.clone()));
+            Self::set_popup_reactive(atmos, &surf.s_id, pos.p_reactive);
+            self.reposition_popup(atmos, &mut surf, false);
+        }
+    }
 
-            surf.s_state.cs_xdg_state.xs_popup_state = Some(PopupState {
-                ps_positioner: positioner,
-            });
-            self.reposition_popup(&mut surf, xdg_surf);
+    /// Track if this popup should follow its parent around
+    fn set_popup_reactive(atmos: &mut Atmosphere, id: &SurfaceId, reactive: bool) {
+        atmos.a_reactive_popups.retain(|popup| popup != id);
+        if reactive {
+            atmos.a_reactive_popups.push(id.clone());
         }
     }
 
+    /// Attach a popup surface to the surface it was opened from
+    fn add_popup_to_parent(atmos: &mut Atmosphere, parent: &SurfaceId, id: &SurfaceId) {
+        // Now we can tell vkcomp to add this surface to the subsurface stack
+        // in Thundr
+        atmos.add_new_top_subsurf(parent, id);
+        log::debug!(
+            "Adding popup subsurf {:?} to parent {:?}",
+            id.get_raw_id(),
+            parent,
+        );
+    }
+
+    /// Assign a parent to a popup created without one
+    ///
+    /// This is used by layer shell, whose surfaces are not xdg_surfaces.
+    pub fn set_popup_parent(&mut self, atmos: &mut Atmosphere, parent: &SurfaceId) {
+        let surf_cell = self.ss_surface.clone();
+        let mut surf = surf_cell.lock().unwrap();
+        Self::add_popup_to_parent(atmos, parent, &surf.s_id);
+
+        // Now that we know where the parent is the popup can be kept
+        // inside the output
+        self.reposition_popup(atmos, &mut surf, false);
+    }
+
     /// Generate a fresh set of configure events
     ///
     /// This is called from other subsystems (input), which means we need to
@@ -318,13 +366,28 @@ impl ShellSurface {
                 // of the last one and update that.
                 match tlstate.tl_cached_size {
                     (0, 0) => {
-                        let raw_size = *atmos.a_window_size.get(&surf.s_id).unwrap();
+                        // If we haven't been committed yet, let the client pick
+                        let raw_size = atmos
+                            .a_window_size
+                            .get(&surf.s_id)
+                            .map(|ws| *ws)
+                            .unwrap_or((0.0, 0.0));
                         (raw_size.0 as i32, raw_size.1 as i32)
                     }
                     tlsize => tlsize,
                 }
             };
 
+            // Maximized windows take up the entire desktop, and fullscreen
+            // windows take up the entire output
+            if tlstate.tl_fullscreen {
+                let area = atmos.get_fullscreen_area(&surf.s_id, None);
+                size = (area.r_size.0 as i32, area.r_size.1 as i32);
+            } else if tlstate.tl_maximized {
+                let max_size = atmos.get_maximized_window_size(&surf.s_id);
+                size = (max_size.0 as i32, max_size.1 as i32);
+            }
+
             if resizing {
                 // Check that our cursor is not within the min size or exceeds the max
                 // size before we apply this mouse diff. If we start adding mouse movements
@@ -392,16 +455,19 @@ impl ShellSurface {
                 log::debug!("Resized to {:?}", size);
             }
 
-            // build an array of state flags to pass to toplevel.configure
+            // build an array of state flags to pass to toplevel.configure.
+            // Each state is a 32 bit value.
             let mut states: Vec<u8> = Vec::new();
+            let mut add_state =
+                |state: xdg_toplevel::State| states.extend((state as u32).to_ne_bytes());
             if tlstate.tl_maximized {
-                states.push(xdg_toplevel::State::Maximized as u8);
+                add_state(xdg_toplevel::State::Maximized);
             }
             if tlstate.tl_resizing {
-                states.push(xdg_toplevel::State::Resizing as u8);
+                add_state(xdg_toplevel::State::Resizing);
             }
             if tlstate.tl_fullscreen {
-                states.push(xdg_toplevel::State::Fullscreen as u8);
+                add_state(xdg_toplevel::State::Fullscreen);
             }
             log::debug!("xdg_surface: sending states {:?}", states);
 
@@ -415,6 +481,143 @@ impl ShellSurface {
         self.ss_serial += 1;
     }
 
+    /// Generate configure events from our current state
+    ///
+    /// This is for when the compositor changes the window state on its
+    /// own, instead of in response to a client request.
+    pub fn reconfigure(&mut self, atmos: &mut Atmosphere) {
+        let surf_cell = self.ss_surface.clone();
+        let mut surf = surf_cell.lock().unwrap();
+        let xdg_surf = match surf.s_role.as_ref() {
+            Some(Role::xdg_shell_toplevel(xdg_surf, _)) => xdg_surf.clone(),
+            _ => return,
+        };
+
+        self.configure(atmos, xdg_surf, &mut surf, false);
+    }
+
+    /// Toggle drawing server side decorations for this toplevel
+    ///
+    /// Windows are nudged down if needed so that their new titlebar
+    /// isn't pushed off the top of the desktop.
+    pub fn set_server_side_decorations(&mut self, atmos: &mut Atmosphere, enabled: bool) {
+        let id = self.ss_surface.lock().unwrap().s_id.clone();
+        atmos.a_ssd.set(&id, enabled);
+
+        // Fullscreen windows are meant to cover the menubar
+        if atmos.a_fullscreen.get(&id).map(|f| *f) == Some(true) {
+            return;
+        }
+        let (wx, wy) = *atmos.a_window_pos.get(&id).unwrap();
+        let barsize = atmos.get_titlebar_height(&id);
+        if wy < barsize {
+            atmos.set_window_origin(&id, (wx, barsize));
+        }
+    }
+
+    /// Ask the client to close this toplevel
+    pub fn close(&self) {
+        if let Some(toplevel) = self.ss_xdg_toplevel.as_ref() {
+            toplevel.close();
+        }
+    }
+
+    /// Remember where this window was before maximizing or going fullscreen
+    ///
+    /// Nothing is saved if the window is already maximized or fullscreen,
+    /// so that it is restored to where it was before either.
+    fn save_geometry(&mut self, atmos: &Atmosphere, id: &SurfaceId) {
+        if self.ss_saved_geometry.is_some() {
+            return;
+        }
+
+        let pos = *atmos.a_window_pos.get(id).unwrap();
+        let size = atmos
+            .a_window_size
+            .get(id)
+            .map(|ws| *ws)
+            .unwrap_or((0.0, 0.0));
+        self.ss_saved_geometry = Some((pos, size));
+    }
+
+    /// Maximize or restore this toplevel
+    ///
+    /// The window geometry is saved when maximizing, and put back once
+    /// the window is restored. Fullscreen windows stay where they are
+    /// until they leave fullscreen.
+    pub fn set_maximized(&mut self, atmos: &mut Atmosphere, maximized: bool) {
+        let surf_cell = self.ss_surface.clone();
+        let mut surf = surf_cell.lock().unwrap();
+        let id = surf.s_id.clone();
+        let xdg_surf = match surf.s_role.as_ref() {
+            Some(Role::xdg_shell_toplevel(xdg_surf, _)) => xdg_surf.clone(),
+            _ => return,
+        };
+        let tl = surf.s_state.cs_xdg_state.xs_tlstate.as_mut().unwrap();
+        if tl.tl_maximized == maximized {
+            return;
+        }
+        tl.tl_maximized = maximized;
+
+        if !tl.tl_fullscreen {
+            if maximized {
+                self.save_geometry(atmos, &id);
+                let origin = atmos.get_maximized_window_pos(&id);
+                atmos.set_window_origin(&id, origin);
+            } else if let Some((pos, size)) = self.ss_saved_geometry.take() {
+                atmos.set_window_origin(&id, pos);
+                tl.tl_cached_size = (size.0 as i32, size.1 as i32);
+            }
+        }
+
+        self.configure(atmos, xdg_surf, &mut surf, false);
+    }
+
+    /// Make this toplevel cover an entire output, or put it back
+    ///
+    /// `output` is the id of the output the client asked for. If it is
+    /// None then the output the window is on is used. A window leaving
+    /// fullscreen goes back to being maximized if it was before.
+    pub fn set_fullscreen(
+        &mut self,
+        atmos: &mut Atmosphere,
+        fullscreen: bool,
+        output: Option<u32>,
+    ) {
+        let surf_cell = self.ss_surface.clone();
+        let mut surf = surf_cell.lock().unwrap();
+        let id = surf.s_id.clone();
+        let xdg_surf = match surf.s_role.as_ref() {
+            Some(Role::xdg_shell_toplevel(xdg_surf, _)) => xdg_surf.clone(),
+            _ => return,
+        };
+        let tl = surf.s_state.cs_xdg_state.xs_tlstate.as_mut().unwrap();
+        if tl.tl_fullscreen == fullscreen {
+            return;
+        }
+        tl.tl_fullscreen = fullscreen;
+        // This changes the titlebar height, so set it before placing the window
+        atmos.a_fullscreen.set(&id, fullscreen);
+
+        if fullscreen {
+            self.save_geometry(atmos, &id);
+            let area = atmos.get_fullscreen_area(&id, output);
+            atmos.set_window_origin(&id, area.r_pos);
+        } else if tl.tl_maximized {
+            let origin = atmos.get_maximized_window_pos(&id);
+            atmos.set_window_origin(&id, origin);
+        } else if let Some((pos, size)) = self.ss_saved_geometry.take() {
+            atmos.set_window_origin(&id, pos);
+            tl.tl_cached_size = (size.0 as i32, size.1 as i32);
+        }
+        atmos.add_wm_task(wm::task::Task::set_fullscreen {
+            id: id.clone(),
+            fullscreen: fullscreen,
+        });
+
+        self.configure(atmos, xdg_surf, &mut surf, false);
+    }
+
     /// Check if this serial is the currently loaned out one,
     /// and if so set the existing state to be applied
     pub fn ack_configure(&mut self, serial: u32) {
@@ -474,6 +677,9 @@ impl ShellSurface {
         // Tell vkcomp to create a new window
         log::debug!("Setting surface {:?} to toplevel", surf.s_id.get_raw_id());
         atmos.a_toplevel.set(&surf.s_id, true);
+        // Start out in the corner of the desktop that panels have left free
+        let area = atmos.get_usable_desktop_area(&surf.s_id);
+        atmos.set_window_origin(&surf.s_id, area.r_pos);
         atmos.add_wm_task(wm::task::Task::new_toplevel(surf.s_id.clone()));
         // This places the surface at the front of the skiplist, aka
         // makes it in focus
@@ -569,38 +775,39 @@ impl ToplevelState {
             log::debug!("New surface pos is {:?}", *sp);
         }
 
-        // TODO: handle min/max/fullscreen/activated
+        // Keep the titlebar text up to date
+        if let Some(title) = self.tl_title.as_ref() {
+            if atmos.a_window_title.get(surf_id).map(|t| *t != *title) != Some(false) {
+                atmos.a_window_title.set(surf_id, title.clone());
+            }
+        }
+
+        // Maximized, fullscreen and minimized are handled when they are
+        // requested instead, see `ShellSurface::set_maximized` and
+        // `ShellSurface::set_fullscreen`.
     }
 }
 
 /// Surface state for xdg_popup surfaces
 ///
-/// This really just holds a positioner which will be used to
-/// recalculate the surface size/pos during a commit.
+/// This really just holds the geometry from the latest configure,
+/// which will be used to update the surface size/pos during a commit.
 #[derive(Clone)]
 pub struct PopupState {
-    /// Positioner for popup surface
-    ps_positioner: xdg_positioner::XdgPositioner,
+    /// Where the popup was placed, relative to its parent
+    ps_geometry: Rect<i32>,
 }
 
 impl PopupState {
     fn commit(&mut self, surf_id: &SurfaceId, atmos: &mut Atmosphere) {
         // Update the size and position from the latest reposition
-        let pos_cell = self
-            .ps_positioner
-            .data::<Arc<Mutex<Positioner>>>()
-            .expect("Bug: positioner did not have userdata attached")
-            .clone();
-        let positioner = pos_cell.lock().unwrap();
-
-        let pos_loc = positioner.get_loc();
+        let geom = &self.ps_geometry;
         atmos
             .a_surface_pos
-            .set(surf_id, (pos_loc.0 as f32, pos_loc.1 as f32));
-        atmos.a_window_size.set(
-            surf_id,
-            (positioner.p_width as f32, positioner.p_height as f32),
-        );
+            .set(surf_id, (geom.r_pos.0 as f32, geom.r_pos.1 as f32));
+        atmos
+            .a_window_size
+            .set(surf_id, (geom.r_size.0 as f32, geom.r_size.1 as f32));
     }
 }
 
@@ -740,7 +947,14 @@ impl ShellSurface {
 
         #[allow(unused_variables)]
         match req {
-            xdg_toplevel::Request::Destroy => (),
+            xdg_toplevel::Request::Destroy => {
+                if let Some(decoration) = self.ss_decoration.as_ref() {
+                    decoration.post_error(
+                        zxtd::Error::Orphaned,
+                        "xdg_toplevel destroyed before its decoration object",
+                    );
+                }
+            }
             xdg_toplevel::Request::SetParent { parent } => (),
             xdg_toplevel::Request::SetTitle { title } => tl.tl_title = Some(title),
             xdg_toplevel::Request::SetAppId { app_id } => tl.tl_app_id = Some(app_id),
@@ -781,11 +995,33 @@ impl ShellSurface {
             xdg_toplevel::Request::SetMinSize { width, height } => {
                 tl.tl_min_size = Some((width, height))
             }
-            xdg_toplevel::Request::SetMaximized => tl.tl_maximized = true,
-            xdg_toplevel::Request::UnsetMaximized => tl.tl_maximized = false,
-            xdg_toplevel::Request::SetFullscreen { output } => tl.tl_fullscreen = true,
-            xdg_toplevel::Request::UnsetFullscreen => tl.tl_fullscreen = false,
-            xdg_toplevel::Request::SetMinimized => tl.tl_minimized = true,
+            // These lock the surface themselves
+            xdg_toplevel::Request::SetMaximized => {
+                drop(surf);
+                self.set_maximized(atmos, true);
+            }
+            xdg_toplevel::Request::UnsetMaximized => {
+                drop(surf);
+                self.set_maximized(atmos, false);
+            }
+            xdg_toplevel::Request::SetFullscreen { output } => {
+                drop(surf);
+                // wl_output userdata is the id of the output it advertises
+                let output = output.and_then(|o| o.data::<u32>().copied());
+                self.set_fullscreen(atmos, true, output);
+            }
+            xdg_toplevel::Request::UnsetFullscreen => {
+                drop(surf);
+                self.set_fullscreen(atmos, false, None);
+            }
+            // There is no configure state for minimized windows, the
+            // client is just hidden until something restores it.
+            xdg_toplevel::Request::SetMinimized => {
+                drop(surf);
+                if atmos.a_minimized.get(&id).map(|m| *m) != Some(true) {
+                    atmos.minimize_window(&id);
+                }
+            }
             _ => unimplemented!(),
         }
     }
@@ -835,6 +1071,13 @@ fn xdg_positioner_handle_request(
     // add the reqeust data to our struct
     match req {
         xdg_positioner::Request::SetSize { width, height } => {
+            if width < 1 || height < 1 {
+                res.post_error(
+                    xdg_positioner::Error::InvalidInput,
+                    "Positioner size must be positive",
+                );
+                return;
+            }
             pos.p_width = width;
             pos.p_height = height;
         }
@@ -844,6 +1087,13 @@ fn xdg_positioner_handle_request(
             width,
             height,
         } => {
+            if width < 0 || height < 0 {
+                res.post_error(
+                    xdg_positioner::Error::InvalidInput,
+                    "Anchor rectangle size must not be negative",
+                );
+                return;
+            }
             pos.p_anchor_rect = Rect::new(x, y, width, height);
         }
         xdg_positioner::Request::SetAnchor { anchor } => {
@@ -898,53 +1148,205 @@ struct Positioner {
     p_parent_configure: u32,
 }
 
+/// Break an anchor into its horizontal and vertical directions
+///
+/// -1 is towards the left or top, and 1 is towards the right or bottom.
+fn get_anchor_dirs(anchor: xdg_positioner::Anchor) -> (i32, i32) {
+    match anchor {
+        xdg_positioner::Anchor::None => (0, 0),
+        xdg_positioner::Anchor::Top => (0, -1),
+        xdg_positioner::Anchor::Bottom => (0, 1),
+        xdg_positioner::Anchor::Left => (-1, 0),
+        xdg_positioner::Anchor::Right => (1, 0),
+        xdg_positioner::Anchor::TopLeft => (-1, -1),
+        xdg_positioner::Anchor::BottomLeft => (-1, 1),
+        xdg_positioner::Anchor::TopRight => (1, -1),
+        xdg_positioner::Anchor::BottomRight => (1, 1),
+        _ => unimplemented!(),
+    }
+}
+
+/// Break a gravity into its horizontal and vertical directions
+///
+/// This uses the same directions as `get_anchor_dirs`.
+fn get_gravity_dirs(gravity: xdg_positioner::Gravity) -> (i32, i32) {
+    match gravity {
+        xdg_positioner::Gravity::None => (0, 0),
+        xdg_positioner::Gravity::Top => (0, -1),
+        xdg_positioner::Gravity::Bottom => (0, 1),
+        xdg_positioner::Gravity::Left => (-1, 0),
+        xdg_positioner::Gravity::Right => (1, 0),
+        xdg_positioner::Gravity::TopLeft => (-1, -1),
+        xdg_positioner::Gravity::BottomLeft => (-1, 1),
+        xdg_positioner::Gravity::TopRight => (1, -1),
+        xdg_positioner::Gravity::BottomRight => (1, 1),
+        _ => unimplemented!(),
+    }
+}
+
+/// How far a span of the popup sticks out past each end of the bounds
+///
+/// This is done for one axis at a time. The first value is for the start
+/// of the bounds, and the second for the end. Negative values are how
+/// much room is left on that side.
+fn get_overflow(pos: i32, size: i32, start: i32, len: i32) -> (i32, i32) {
+    (start - pos, (pos + size) - (start + len))
+}
+
+fn is_constrained(pos: i32, size: i32, start: i32, len: i32) -> bool {
+    let (before, after) = get_overflow(pos, size, start, len);
+    before > 0 || after > 0
+}
+
+/// Slide a span along one axis until it fits in the bounds
+///
+/// As the spec says, we first slide in the direction of the gravity and
+/// then back the other way. Neither slide will push the opposite edge
+/// out of bounds.
+fn slide(pos: i32, size: i32, start: i32, len: i32, gravity: i32) -> i32 {
+    let slide_forward = |pos: i32| {
+        let (before, after) = get_overflow(pos, size, start, len);
+        match before > 0 {
+            true => pos + before.min(-after).max(0),
+            false => pos,
+        }
+    };
+    let slide_back = |pos: i32| {
+        let (before, after) = get_overflow(pos, size, start, len);
+        match after > 0 {
+            true => pos - after.min(-before).max(0),
+            false => pos,
+        }
+    };
+
+    match gravity < 0 {
+        true => slide_forward(slide_back(pos)),
+        false => slide_back(slide_forward(pos)),
+    }
+}
+
+/// Shrink a span along one axis so that it fits in the bounds
+///
+/// Returns the new position and size. The span is left alone if it
+/// doesn't overlap the bounds at all.
+fn resize(pos: i32, size: i32, start: i32, len: i32) -> (i32, i32) {
+    let new_start = pos.max(start);
+    let new_end = (pos + size).min(start + len);
+
+    match new_end > new_start {
+        true => (new_start, new_end - new_start),
+        false => (pos, size),
+    }
+}
+
 impl Positioner {
-    /// Create a surface local position from the positioner.
-    /// This should be called to reevaluate the end result of the popup location.
-    fn get_loc(&self) -> (i32, i32) {
-        // The spec states that we MUST have a non-zero anchor rect, and a size
-        let mut ret = (0, 0);
-
-        // First start off with an offset, if we have one
-        if let Some((x, y)) = self.p_offset {
-            ret.0 += x;
-            ret.1 += y;
+    /// Get the popup geometry, ignoring any constraints
+    ///
+    /// This is relative to the parent surface. `flip_x` and `flip_y` invert
+    /// the anchor and gravity on that axis, which is how the flip constraint
+    /// adjustments are tried.
+    fn get_unconstrained_geometry(&self, flip_x: bool, flip_y: bool) -> Rect<i32> {
+        let (mut anchor_x, mut anchor_y) = get_anchor_dirs(self.p_anchor);
+        let (mut gravity_x, mut gravity_y) = get_gravity_dirs(self.p_gravity);
+        if flip_x {
+            anchor_x = -anchor_x;
+            gravity_x = -gravity_x;
+        }
+        if flip_y {
+            anchor_y = -anchor_y;
+            gravity_y = -gravity_y;
         }
 
-        // Now add in the anchor rectangle and the anchor point
-        //
-        // The anchor rectangle will be positioned somewhere inside the parent. We then
-        // need to calculate a position on one of the edges of the anchor rectangle. Unless
-        // none is specified, we add the offset to the anchor rect's top left, then add some
-        // more based on the size to match the position on the edge that was requested.
+        // The anchor point is on the edge of the anchor rectangle in the
+        // direction of the anchor, or in the middle if there is none.
         let rect = &self.p_anchor_rect;
-        let anchor_offset = match self.p_anchor {
-            xdg_positioner::Anchor::None => (0, 0),
-            xdg_positioner::Anchor::Top => (rect.r_pos.0 + rect.r_size.0 / 2, rect.r_pos.1),
-            xdg_positioner::Anchor::Bottom => (
-                rect.r_pos.0 + rect.r_size.0 / 2,
-                rect.r_pos.1 + rect.r_size.1,
-            ),
-            xdg_positioner::Anchor::Left => (rect.r_pos.0, rect.r_pos.1 + rect.r_size.1 / 2),
-            xdg_positioner::Anchor::Right => (
-                rect.r_pos.0 + rect.r_size.0,
-                rect.r_pos.1 + rect.r_size.1 / 2,
-            ),
-            xdg_positioner::Anchor::TopLeft => (rect.r_pos.0, rect.r_pos.1),
-            xdg_positioner::Anchor::BottomLeft => (rect.r_pos.0, rect.r_pos.1 + rect.r_size.1),
-            xdg_positioner::Anchor::TopRight => (rect.r_pos.0 + rect.r_size.0, rect.r_pos.1),
-            xdg_positioner::Anchor::BottomRight => {
-                (rect.r_pos.0 + rect.r_size.0, rect.r_pos.1 + rect.r_size.1)
+        let point = (
+            rect.r_pos.0 + rect.r_size.0 * (anchor_x + 1) / 2,
+            rect.r_pos.1 + rect.r_size.1 * (anchor_y + 1) / 2,
+        );
+
+        // The popup then extends out from the anchor point in the
+        // direction of the gravity, or is centered on it.
+        let (offset_x, offset_y) = self.p_offset.unwrap_or((0, 0));
+        Rect::new(
+            point.0 + self.p_width * (gravity_x - 1) / 2 + offset_x,
+            point.1 + self.p_height * (gravity_y - 1) / 2 + offset_y,
+            self.p_width,
+            self.p_height,
+        )
+    }
+
+    /// Get the popup geometry after applying the constraint adjustments
+    ///
+    /// `bounds` is the area the popup should stay inside of, relative to
+    /// the parent surface. Each axis is adjusted on its own, trying flip,
+    /// then slide, then resize as the spec orders them.
+    fn get_geometry(&self, bounds: &Rect<i32>) -> Rect<i32> {
+        let adjust = self.p_constraint;
+        let mut geom = self.get_unconstrained_geometry(false, false);
+        let (mut gravity_x, mut gravity_y) = get_gravity_dirs(self.p_gravity);
+
+        // Flipping is only kept if it got us unconstrained
+        if adjust.contains(xdg_positioner::ConstraintAdjustment::FlipX)
+            && is_constrained(geom.r_pos.0, geom.r_size.0, bounds.r_pos.0, bounds.r_size.0)
+        {
+            let flipped = self.get_unconstrained_geometry(true, false);
+            if !is_constrained(
+                flipped.r_pos.0,
+                flipped.r_size.0,
+                bounds.r_pos.0,
+                bounds.r_size.0,
+            ) {
+                geom.r_pos.0 = flipped.r_pos.0;
+                gravity_x = -gravity_x;
             }
-            _ => unimplemented!(),
-        };
-        ret.0 += anchor_offset.0;
-        ret.1 += anchor_offset.1;
+        }
+        if adjust.contains(xdg_positioner::ConstraintAdjustment::FlipY)
+            && is_constrained(geom.r_pos.1, geom.r_size.1, bounds.r_pos.1, bounds.r_size.1)
+        {
+            let flipped = self.get_unconstrained_geometry(false, true);
+            if !is_constrained(
+                flipped.r_pos.1,
+                flipped.r_size.1,
+                bounds.r_pos.1,
+                bounds.r_size.1,
+            ) {
+                geom.r_pos.1 = flipped.r_pos.1;
+                gravity_y = -gravity_y;
+            }
+        }
+
+        if adjust.contains(xdg_positioner::ConstraintAdjustment::SlideX) {
+            geom.r_pos.0 = slide(
+                geom.r_pos.0,
+                geom.r_size.0,
+                bounds.r_pos.0,
+                bounds.r_size.0,
+                gravity_x,
+            );
+        }
+        if adjust.contains(xdg_positioner::ConstraintAdjustment::SlideY) {
+            geom.r_pos.1 = slide(
+                geom.r_pos.1,
+                geom.r_size.1,
+                bounds.r_pos.1,
+                bounds.r_size.1,
+                gravity_y,
+            );
+        }
 
-        // TODO: subtract on each side to handle gravity
+        if adjust.contains(xdg_positioner::ConstraintAdjustment::ResizeX) {
+            let (x, width) = resize(geom.r_pos.0, geom.r_size.0, bounds.r_pos.0, bounds.r_size.0);
+            geom.r_pos.0 = x;
+            geom.r_size.0 = width;
+        }
+        if adjust.contains(xdg_positioner::ConstraintAdjustment::ResizeY) {
+            let (y, height) = resize(geom.r_pos.1, geom.r_size.1, bounds.r_pos.1, bounds.r_size.1);
+            geom.r_pos.1 = y;
+            geom.r_size.1 = height;
+        }
 
-        // TODO: add the rest of the positioner elements
-        return ret;
+        geom
     }
 }
 
@@ -989,36 +1391,69 @@ impl ws::Dispatch<xdg_popup::XdgPopup, Arc<Mutex<ShellSurface>>> for Climate {
 pub struct Popup {
     pu_pop: xdg_popup::XdgPopup,
     pu_parent: Option<xdg_surface::XdgSurface>,
+    /// Our xdg_surface, which also gets a configure event whenever
+    /// the popup is placed
+    pu_xdg_surface: xdg_surface::XdgSurface,
+    /// The rules from the positioner this popup was created or last
+    /// repositioned with
+    pu_positioner: Positioner,
+    /// The geometry last sent to the client
+    pu_geometry: Option<Rect<i32>>,
 }
 
 impl ShellSurface {
     /// Calculate the position for this popup, and generate configure
     /// events broadcasting it.
-    /// This will use the repositioned value if it was set.
-    fn reposition_popup(&mut self, surf: &mut Surface, xdg_surf: &xdg_surface::XdgSurface) {
-        let pop = self.ss_xdg_popup.as_mut().unwrap();
-        let state = surf.s_state.cs_xdg_state.xs_popup_state.as_mut().unwrap();
+    ///
+    /// The popup is kept inside of the output its parent is on, using the
+    /// constraint adjustments the client allowed. If `only_if_moved` is set
+    /// then nothing is sent unless the popup's geometry changed.
+    fn reposition_popup(&mut self, atmos: &Atmosphere, surf: &mut Surface, only_if_moved: bool) {
+        let pop = match self.ss_xdg_popup.as_mut() {
+            Some(pop) => pop,
+            None => return,
+        };
 
-        let pos_cell = state
-            .ps_positioner
-            .data::<Arc<Mutex<Positioner>>>()
-            .expect("Bug: positioner did not have userdata attached")
-            .clone();
-        let pos = pos_cell.lock().unwrap();
+        let geom = match atmos.a_parent_window.get_clone(&surf.s_id) {
+            Some(parent) => {
+                // Move the bounds into the parent's coordinate space
+                let (px, py) = atmos.get_surface_desktop_pos(&parent);
+                let bounds = atmos.get_popup_bounds(&parent);
+                let bounds = Rect::new(
+                    (bounds.r_pos.0 - px) as i32,
+                    (bounds.r_pos.1 - py) as i32,
+                    bounds.r_size.0 as i32,
+                    bounds.r_size.1 as i32,
+                );
+                pop.pu_positioner.get_geometry(&bounds)
+            }
+            // Layer shell popups don't have a parent until get_popup is
+            // called on the layer surface
+            None => pop.pu_positioner.get_unconstrained_geometry(false, false),
+        };
+        if only_if_moved && pop.pu_geometry == Some(geom) {
+            return;
+        }
+        pop.pu_geometry = Some(geom);
+        surf.s_state.cs_xdg_state.xs_popup_state = Some(PopupState { ps_geometry: geom });
 
         // send configuration requests to the client
-        // width and height 0 means client picks a size
-        let popup_loc = pos.get_loc();
-        log::debug!("Popup location: {:?}", popup_loc);
+        log::debug!("Popup geometry: {:?}", geom);
         pop.pu_pop
-            .configure(popup_loc.0, popup_loc.1, pos.p_width, pos.p_height);
-        xdg_surf.configure(self.ss_serial);
+            .configure(geom.r_pos.0, geom.r_pos.1, geom.r_size.0, geom.r_size.1);
+        pop.pu_xdg_surface.configure(self.ss_serial);
         self.ss_serial += 1;
     }
 
-    fn popup_done(&mut self, atmos: &mut Atmosphere) {
+    /// Dismiss this popup
+    ///
+    /// This is used both when the client destroys the popup and when we
+    /// take it down, such as when a grab is broken.
+    pub fn popup_done(&mut self, atmos: &mut Atmosphere) {
         let mut surf = self.ss_surface.lock().unwrap();
         surf.s_role = None;
+        Self::set_popup_reactive(atmos, &surf.s_id, false);
+        atmos.end_popup_grab(&surf.s_id);
         atmos.skiplist_remove_window(&surf.s_id);
         atmos.add_wm_task(wm::task::Task::close_window(surf.s_id.clone()));
 
@@ -1037,7 +1472,7 @@ impl ShellSurface {
         atmos: &mut Atmosphere,
         _client: &ws::Client,
         _data_init: &mut ws::DataInit<'_, Climate>,
-        _popup: &xdg_popup::XdgPopup,
+        popup: &xdg_popup::XdgPopup,
         req: xdg_popup::Request,
     ) {
         // TODO: implement the remaining handlers
@@ -1047,17 +1482,83 @@ impl ShellSurface {
                 log::debug!("Popup destroyed. Dismissing it");
                 self.popup_done(atmos);
             }
-            // TODO: implement grab
             xdg_popup::Request::Grab { seat, serial } => {
-                log::debug!("Grabbing a popup is not supported");
-                self.popup_done(atmos);
+                let id = self.ss_surface.lock().unwrap().s_id.clone();
+                if atmos.a_buffer_size.get(&id).is_some() {
+                    popup.post_error(
+                        xdg_popup::Error::InvalidGrab,
+                        "Popups must grab before they are mapped",
+                    );
+                    return;
+                }
+
+                // The grab has to come from a click or key press on
+                // this client, and must be opened from the top of any
+                // existing grab chain.
+                let seat = seat.data::<Arc<Mutex<Seat>>>().unwrap().lock().unwrap();
+                let from_input = atmos.a_owner.get(&id).map(|owner| *owner == seat.s_id)
+                    == Some(true)
+                    && seat.is_recent_input_serial(serial);
+                drop(seat);
+                let parent = atmos.a_parent_window.get_clone(&id);
+                let from_top = match atmos.get_popup_grab() {
+                    Some(top) => parent == Some(top),
+                    None => true,
+                };
+
+                match from_input && from_top {
+                    true => atmos.start_popup_grab(&id),
+                    false => {
+                        log::debug!("Denying grab for popup {:?}", id);
+                        self.popup_done(atmos);
+                    }
+                }
             }
             xdg_popup::Request::Reposition { positioner, token } => {
-                let mut surf = self.ss_surface.lock().unwrap();
-                let state = surf.s_state.cs_xdg_state.xs_popup_state.as_mut().unwrap();
-                state.ps_positioner = positioner;
+                let pos = *positioner
+                    .data::<Arc<Mutex<Positioner>>>()
+                    .expect("Bug: positioner did not have userdata attached")
+                    .lock()
+                    .unwrap();
+                match self.ss_xdg_popup.as_mut() {
+                    Some(pop) => pop.pu_positioner = pos,
+                    None => return,
+                }
+
+                let surf_cell = self.ss_surface.clone();
+                let mut surf = surf_cell.lock().unwrap();
+                Self::set_popup_reactive(atmos, &surf.s_id, pos.p_reactive);
+                // The new configure has to follow this
+                popup.repositioned(token);
+                self.reposition_popup(atmos, &mut surf, false);
             }
             _ => unimplemented!(),
         }
     }
 }
+
+impl Climate {
+    /// Move reactive popups along with their parents
+    ///
+    /// Popups which asked to be reactive get a new configure whenever
+    /// their parent moves enough to change where they are placed. This
+    /// should be called after each round of dispatching.
+    pub fn update_reactive_popups(&mut self) {
+        let atmos = self.c_atmos.lock().unwrap();
+
+        for id in atmos.a_reactive_popups.clone().iter() {
+            let surf_cell = match atmos.get_surface_from_id(id) {
+                Some(surf) => surf,
+                None => continue,
+            };
+            // The ShellSurface has to be locked before the Surface
+            let shsurf_cell = match surf_cell.lock().unwrap().s_role.as_ref() {
+                Some(Role::xdg_shell_popup(ss)) => ss.clone(),
+                _ => continue,
+            };
+            let mut shsurf = shsurf_cell.lock().unwrap();
+            let mut surf = surf_cell.lock().unwrap();
+            shsurf.reposition_popup(&atmos, &mut surf, true);
+        }
+    }
+}
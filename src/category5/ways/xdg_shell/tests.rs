@@ -0,0 +1,133 @@
+// xdg_positioner placement tests
+use super::xdg_positioner::{Anchor, ConstraintAdjustment, Gravity};
+use super::*;
+
+/// The area popups must stay in, relative to the parent
+fn bounds() -> Rect<i32> {
+    Rect::new(0, 0, 800, 600)
+}
+
+fn positioner(
+    anchor_rect: Rect<i32>,
+    size: (i32, i32),
+    anchor: Anchor,
+    gravity: Gravity,
+    constraint: ConstraintAdjustment,
+) -> Positioner {
+    Positioner {
+        p_offset: None,
+        p_width: size.0,
+        p_height: size.1,
+        p_anchor_rect: anchor_rect,
+        p_anchor: anchor,
+        p_gravity: gravity,
+        p_constraint: constraint,
+        p_reactive: false,
+        p_parent_size: None,
+        p_parent_configure: 0,
+    }
+}
+
+/// Popups that fit are left where the client put them
+#[test]
+fn positioner_unconstrained() {
+    let pos = positioner(
+        Rect::new(100, 100, 50, 20),
+        (200, 150),
+        Anchor::BottomLeft,
+        Gravity::BottomRight,
+        ConstraintAdjustment::all(),
+    );
+    assert_eq!(pos.get_geometry(&bounds()), Rect::new(100, 120, 200, 150));
+}
+
+/// Flip a menu that would run off the bottom or right of the output
+#[test]
+fn positioner_flip_near_edge() {
+    let pos = positioner(
+        Rect::new(100, 500, 50, 20),
+        (200, 150),
+        Anchor::Bottom,
+        Gravity::Bottom,
+        ConstraintAdjustment::FlipY,
+    );
+    assert_eq!(pos.get_geometry(&bounds()), Rect::new(25, 350, 200, 150));
+
+    let pos = positioner(
+        Rect::new(700, 100, 20, 20),
+        (150, 40),
+        Anchor::Right,
+        Gravity::Right,
+        ConstraintAdjustment::FlipX,
+    );
+    assert_eq!(pos.get_geometry(&bounds()), Rect::new(550, 90, 150, 40));
+}
+
+/// A flip which is still constrained is not used, but sliding still is
+#[test]
+fn positioner_flip_then_slide() {
+    let pos = positioner(
+        Rect::new(300, 100, 10, 10),
+        (600, 40),
+        Anchor::Right,
+        Gravity::Right,
+        ConstraintAdjustment::FlipX,
+    );
+    assert_eq!(pos.get_geometry(&bounds()), Rect::new(310, 85, 600, 40));
+
+    let pos = positioner(
+        Rect::new(300, 100, 10, 10),
+        (600, 40),
+        Anchor::Right,
+        Gravity::Right,
+        ConstraintAdjustment::FlipX | ConstraintAdjustment::SlideX,
+    );
+    assert_eq!(pos.get_geometry(&bounds()), Rect::new(200, 85, 600, 40));
+}
+
+/// Slide a popup back inside of the left edge
+#[test]
+fn positioner_slide_near_edge() {
+    let pos = positioner(
+        Rect::new(20, 100, 10, 10),
+        (100, 50),
+        Anchor::Left,
+        Gravity::Left,
+        ConstraintAdjustment::SlideX,
+    );
+    assert_eq!(pos.get_geometry(&bounds()), Rect::new(0, 80, 100, 50));
+}
+
+#[test]
+fn slide_stays_in_bounds() {
+    // Slide back from the end, in either gravity
+    assert_eq!(slide(750, 100, 0, 800, 1), 700);
+    assert_eq!(slide(750, 100, 0, 800, -1), 700);
+    assert_eq!(slide(-30, 100, 0, 800, -1), 0);
+    // Spans larger than the bounds aren't pushed out the other side
+    assert_eq!(slide(-100, 1000, 0, 800, 1), -100);
+    assert_eq!(slide(-100, 1000, 0, 800, -1), -100);
+    assert_eq!(slide(-50, 1000, 0, 800, 1), -50);
+}
+
+/// Shrink a popup hanging off the right edge
+#[test]
+fn positioner_resize_near_edge() {
+    let pos = positioner(
+        Rect::new(700, 100, 10, 10),
+        (200, 50),
+        Anchor::TopRight,
+        Gravity::BottomRight,
+        ConstraintAdjustment::ResizeX,
+    );
+    assert_eq!(pos.get_geometry(&bounds()), Rect::new(710, 100, 90, 50));
+}
+
+#[test]
+fn resize_clips_to_bounds() {
+    assert_eq!(resize(-50, 200, 0, 800), (0, 150));
+    assert_eq!(resize(700, 200, 0, 800), (700, 100));
+    assert_eq!(resize(-50, 1000, 0, 800), (0, 800));
+    // Spans entirely outside of the bounds are left alone
+    assert_eq!(resize(900, 50, 0, 800), (900, 50));
+}
@@ -71,6 +71,6 @@ impl DeletionQueue {
         self.dq_last_signaled = sync_point;
 
         self.dq_point_queues
-            .retain(|pq| pq.pq_sync_point <= sync_point);
+            .retain(|pq| pq.pq_sync_point > sync_point);
     }
 }
@@ -116,18 +116,28 @@ impl DescPool {
     /// Descriptor layouts specify the number and characteristics
     /// of descriptor sets which will be made available to the
     /// pipeline through the pipeline layout.
-    fn create_layout(dev: &ash::Device) -> vk::DescriptorSetLayout {
+    ///
+    /// If `immutable_sampler` is specified it will be baked into the
+    /// layout, which is required for samplers doing YCbCr conversion.
+    fn create_layout(
+        dev: &ash::Device,
+        immutable_sampler: Option<vk::Sampler>,
+    ) -> vk::DescriptorSetLayout {
+        let samplers: Vec<vk::Sampler> = immutable_sampler.into_iter().collect();
         // supplies `descriptor_mesh_layouts`
         // There will be a sampler for each window
         //
         // This descriptor needs to be second in the pipeline list
         // so the shader can reference it as set 1
-        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+        let mut binding = vk::DescriptorSetLayoutBinding::builder()
             .binding(1)
             .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-            .descriptor_count(1)
-            .build()];
+            .descriptor_count(1);
+        if samplers.len() > 0 {
+            binding = binding.immutable_samplers(samplers.as_slice());
+        }
+        let bindings = [binding.build()];
         let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
 
         unsafe { dev.create_descriptor_set_layout(&info, None).unwrap() }
@@ -169,9 +179,9 @@ impl DescPool {
         return ret;
     }
 
-    pub fn new(dev: &ash::Device) -> Self {
+    pub fn new(dev: &ash::Device, immutable_sampler: Option<vk::Sampler>) -> Self {
         Self {
-            ds_layout: Self::create_layout(dev),
+            ds_layout: Self::create_layout(dev, immutable_sampler),
             ds_pools: Vec::new(),
         }
     }
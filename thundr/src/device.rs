@@ -18,6 +18,7 @@ use crate::display::drm::drm_device::DrmDevice;
 use crate::image::ImageVk;
 use crate::instance::Instance;
 use crate::platform::VKDeviceFeatures;
+use crate::ycbcr::{YcbcrConversion, YCBCR_FORMATS};
 use crate::{CreateInfo, Damage, DeletionQueue, Droppable, Result, ThundrError};
 use cat5_utils::log;
 
@@ -105,6 +106,9 @@ pub struct DeviceInternal {
     /// This controls allocation of image descriptors for all imagevks allocated
     /// on this Device.
     pub(crate) descpool: DescPool,
+    /// The YUV formats that can be imported, along with the samplers
+    /// and descriptor layouts needed to draw them
+    pub(crate) ycbcr_conversions: Vec<YcbcrConversion>,
 }
 
 impl Device {
@@ -138,6 +142,9 @@ impl Device {
             .descriptor_binding_partially_bound(true)
             .descriptor_binding_update_unused_while_pending(true)
             .build();
+        let mut vulkan11_features = vk::PhysicalDeviceVulkan11Features::builder()
+            .sampler_ycbcr_conversion(dev_features.vkc_supports_ycbcr_conversion)
+            .build();
 
         // for now we only have one graphics queue, so one priority
         let priorities = [1.0];
@@ -156,6 +163,7 @@ impl Device {
             .queue_create_infos(queue_infos.as_ref())
             .enabled_extension_names(dev_extension_names.as_slice())
             .enabled_features(&features)
+            .push_next(&mut vulkan11_features)
             .push_next(&mut vulkan12_features);
 
         #[cfg(feature = "aftermath")]
@@ -393,7 +401,7 @@ impl Device {
             dev.create_semaphore(&sema_create_info, None)
                 .or(Err(ThundrError::INVALID))?
         };
-        let descpool = DescPool::new(&dev);
+        let descpool = DescPool::new(&dev, None);
 
         // If supported, get the DRM device fd for the master node
         // for this VkDevice
@@ -425,6 +433,7 @@ impl Device {
                 deletion_queue: DeletionQueue::new(),
                 descpool: descpool,
                 image_sampler: vk::Sampler::null(),
+                ycbcr_conversions: Vec::new(),
             })),
             d_image_vk: img_ecs.add_component(),
             #[cfg(feature = "drm")]
@@ -437,12 +446,14 @@ impl Device {
             let copy_cmd_pool = ret.create_command_pool(transfer_queue_family);
             let copy_cbuf = ret.create_command_buffers(copy_cmd_pool, 1)[0];
             let sampler = ret.create_sampler();
+            let ycbcr_conversions = ret.create_ycbcr_conversions();
 
             let mut internal = ret.d_internal.write().unwrap();
             internal.d_self = Arc::downgrade(&ret);
             internal.copy_cmd_pool = copy_cmd_pool;
             internal.copy_cbuf = copy_cbuf;
             internal.image_sampler = sampler;
+            internal.ycbcr_conversions = ycbcr_conversions;
         }
 
         Ok(ret)
@@ -522,6 +533,51 @@ impl Device {
         unsafe { self.dev.create_sampler(&info, None).unwrap() }
     }
 
+    /// Create the conversions for the YUV formats this device can sample
+    fn create_ycbcr_conversions(&self) -> Vec<YcbcrConversion> {
+        if !self.dev_features.vkc_supports_ycbcr_conversion {
+            return Vec::new();
+        }
+
+        YCBCR_FORMATS
+            .iter()
+            .filter_map(|format| {
+                let mods = self.get_supported_drm_modifiers_for_format(format.yf_vk_format);
+                YcbcrConversion::new(&self.dev, format, mods.as_slice())
+            })
+            .collect()
+    }
+
+    /// Get the Vulkan format and conversion for a YUV DRM format
+    ///
+    /// Returns None if `drm_format` is not a YUV format we can import.
+    pub(crate) fn get_ycbcr_conversion(
+        &self,
+        drm_format: u32,
+    ) -> Option<(vk::Format, vk::SamplerYcbcrConversion)> {
+        self.d_internal
+            .read()
+            .unwrap()
+            .ycbcr_conversions
+            .iter()
+            .find(|c| c.yc_drm_format == drm_format)
+            .map(|c| (c.yc_vk_format, c.yc_conversion))
+    }
+
+    /// Get the YUV formats that can be imported as Thundr Images
+    ///
+    /// Returns a list of DRM fourcc and modifier pairs.
+    pub fn get_supported_ycbcr_drm_formats(&self) -> Vec<(u32, u64)> {
+        let mut ret = Vec::new();
+        for conversion in self.d_internal.read().unwrap().ycbcr_conversions.iter() {
+            for modifier in conversion.yc_modifiers.iter() {
+                ret.push((conversion.yc_drm_format, *modifier));
+            }
+        }
+
+        ret
+    }
+
     /// Wait for the latest timeline sync point to complete
     ///
     /// If no copy operation is in flight this returns immediately.
@@ -552,6 +608,30 @@ impl Device {
         internal.latest_acked_copy_timeline_point = internal.copy_timeline_point;
     }
 
+    /// Get the timeline point the next frame submitted will signal
+    ///
+    /// Work recorded into the frame being drawn is finished once the
+    /// device timeline reaches this point.
+    pub(crate) fn get_next_timeline_point(&self) -> u64 {
+        self.d_internal.read().unwrap().timeline_point + 1
+    }
+
+    /// Has the device timeline reached `point`
+    ///
+    /// Unlike `wait_for_latest_timeline` this doesn't block, so it can
+    /// be used to poll for a frame to finish.
+    pub fn is_timeline_point_signaled(&self, point: u64) -> bool {
+        let internal = self.d_internal.read().unwrap();
+
+        match unsafe { self.dev.get_semaphore_counter_value(internal.timeline_sema) } {
+            Ok(value) => value >= point,
+            Err(e) => {
+                log::error!("Could not get the timeline semaphore value: {:?}", e);
+                false
+            }
+        }
+    }
+
     /// Waits for the latest copy operation to complete
     ///
     /// This waits for the copy timeline
@@ -1351,11 +1431,7 @@ impl Device {
     ///
     /// This does not drop the item immediately, unless the timeline point
     /// is already known to be signaled.
-    pub fn schedule_drop_at_point(
-        &mut self,
-        item: Box<dyn Droppable + Send + Sync>,
-        sync_point: u64,
-    ) {
+    pub fn schedule_drop_at_point(&self, item: Box<dyn Droppable + Send + Sync>, sync_point: u64) {
         self.d_internal
             .write()
             .unwrap()
@@ -1397,11 +1473,34 @@ impl Device {
     /// This will use our DescPool to create a new vkDescriptor corresponding
     /// to the image passed in. The image is then written to the descriptor.
     pub fn create_new_image_descriptor(&self, view: vk::ImageView) -> Descriptor {
-        let mut internal = self.d_internal.write().unwrap();
+        self.create_new_image_descriptor_with_format(view, None)
+    }
 
-        let ret = internal.descpool.alloc_descriptor(&self.dev);
+    /// Create an image descriptor, which may be for a YUV image
+    ///
+    /// YUV images need to be allocated from the pool of the conversion used
+    /// by `ycbcr_format`, so that the layout has the right immutable sampler.
+    pub(crate) fn create_new_image_descriptor_with_format(
+        &self,
+        view: vk::ImageView,
+        ycbcr_format: Option<vk::Format>,
+    ) -> Descriptor {
+        let mut internal = self.d_internal.write().unwrap();
+        let internal = &mut *internal;
+
+        let ret = match ycbcr_format {
+            Some(format) => internal
+                .ycbcr_conversions
+                .iter_mut()
+                .find(|c| c.yc_vk_format == format)
+                .expect("No YCbCr conversion for format")
+                .yc_descpool
+                .alloc_descriptor(&self.dev),
+            None => internal.descpool.alloc_descriptor(&self.dev),
+        };
 
-        // Now write the new bindless descriptor
+        // Now write the new bindless descriptor. The sampler is ignored
+        // for YUV images since their layouts use an immutable one.
         let info = [vk::DescriptorImageInfo::builder()
             .sampler(internal.image_sampler)
             .image_view(view)
@@ -1437,6 +1536,9 @@ impl Drop for Device {
 
             internal.descpool.destroy(&self.dev);
             self.dev.destroy_sampler(internal.image_sampler, None);
+            for conversion in internal.ycbcr_conversions.iter_mut() {
+                conversion.destroy(&self.dev);
+            }
 
             self.dev
                 .destroy_semaphore(internal.copy_timeline_sema, None);
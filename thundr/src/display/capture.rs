@@ -0,0 +1,354 @@
+// Images that frames are copied into
+//
+// Screenshots and screen recording need the contents of a frame
+// outside of the swapchain. The frame is copied into one of these
+// while it is being drawn, and can then be read back by the CPU or
+// handed to another process as a dmabuf.
+extern crate ash;
+
+use crate::device::Device;
+use crate::display::Display;
+use crate::{Dmabuf, MappedImage, Result, ThundrError};
+
+use ash::vk;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// DRM_FORMAT_XRGB8888, which is how B8G8R8A8 images are laid out in memory
+const DRM_FORMAT_XRGB8888: u32 = 0x34325258;
+
+/// The destination of a frame copy
+///
+/// See `FrameRenderer::copy_frame`. The copy is only finished once the
+/// frame it was recorded in has been drawn, which can be checked with
+/// `is_copy_done`.
+pub struct CaptureImage {
+    ci_dev: Arc<Device>,
+    pub(crate) ci_image: vk::Image,
+    ci_view: vk::ImageView,
+    ci_mem: vk::DeviceMemory,
+    pub(crate) ci_size: (u32, u32),
+    /// Is this a client's dmabuf. These are handed back to the foreign
+    /// queue after copying and can't be mapped.
+    pub(crate) ci_is_dmabuf: bool,
+    /// The device timeline point signaled once the frame this was copied
+    /// in has been drawn. Zero if nothing was copied into it.
+    pub(crate) ci_copy_point: AtomicU64,
+}
+
+/// The Vulkan resources of a CaptureImage
+///
+/// If the image is dropped while a copy into it is in flight, these
+/// are handed to the deletion queue and freed once the copy is done.
+struct CaptureResources {
+    cr_dev: Arc<Device>,
+    cr_image: vk::Image,
+    cr_view: vk::ImageView,
+    cr_mem: vk::DeviceMemory,
+}
+
+impl Drop for CaptureResources {
+    fn drop(&mut self) {
+        unsafe {
+            self.cr_dev.dev.destroy_image_view(self.cr_view, None);
+            self.cr_dev.dev.destroy_image(self.cr_image, None);
+            self.cr_dev.free_memory(self.cr_mem);
+        }
+    }
+}
+
+impl Device {
+    /// Create an image for copying frames into system memory
+    ///
+    /// `format` must be the format of the swapchain being copied from.
+    pub(crate) fn create_capture_image(
+        &self,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+    ) -> Result<CaptureImage> {
+        let (image, view, mem) = self.create_image(
+            &vk::Extent2D {
+                width: width,
+                height: height,
+            },
+            format,
+            vk::ImageUsageFlags::TRANSFER_DST,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            vk::ImageTiling::LINEAR,
+        );
+
+        Ok(CaptureImage {
+            ci_dev: self.d_internal.read().unwrap().d_self.upgrade().unwrap(),
+            ci_image: image,
+            ci_view: view,
+            ci_mem: mem,
+            ci_size: (width, height),
+            ci_is_dmabuf: false,
+            ci_copy_point: AtomicU64::new(0),
+        })
+    }
+
+    /// Import a dmabuf to copy frames into
+    pub(crate) fn create_capture_image_from_dmabuf(&self, dmabuf: &Dmabuf) -> Result<CaptureImage> {
+        let (image, view, mem) = Device::create_image_from_dmabuf_internal(
+            self,
+            dmabuf,
+            vk::ImageUsageFlags::TRANSFER_DST,
+        )?;
+
+        Ok(CaptureImage {
+            ci_dev: self.d_internal.read().unwrap().d_self.upgrade().unwrap(),
+            ci_image: image,
+            ci_view: view,
+            ci_mem: mem,
+            ci_size: (dmabuf.db_width as u32, dmabuf.db_height as u32),
+            ci_is_dmabuf: true,
+            ci_copy_point: AtomicU64::new(0),
+        })
+    }
+}
+
+impl Display {
+    /// Get the DRM format frames of this display are copied out in
+    ///
+    /// Returns None if frames can't be copied, either because the
+    /// swapchain images can't be used as a transfer source or because
+    /// their format isn't one clients can be handed.
+    pub fn get_capture_format(&self) -> Option<u32> {
+        if !self
+            .d_state
+            .d_image_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return None;
+        }
+
+        match self.d_state.d_surface_format.format {
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => Some(DRM_FORMAT_XRGB8888),
+            _ => None,
+        }
+    }
+
+    /// Create an image for copying frames of this display into system memory
+    ///
+    /// The image can be read with `CaptureImage::read_bits`. This fails
+    /// if `get_capture_format` says frames can't be copied.
+    pub fn create_capture_image(&self, width: u32, height: u32) -> Result<CaptureImage> {
+        if self.get_capture_format().is_none() {
+            return Err(ThundrError::INVALID_FORMAT);
+        }
+        self.d_dev
+            .create_capture_image(width, height, self.d_state.d_surface_format.format)
+    }
+
+    /// Import a dmabuf to copy frames of this display into
+    pub fn create_capture_image_from_dmabuf(&self, dmabuf: &Dmabuf) -> Result<CaptureImage> {
+        if self.get_capture_format().is_none() {
+            return Err(ThundrError::INVALID_FORMAT);
+        }
+        self.d_dev.create_capture_image_from_dmabuf(dmabuf)
+    }
+}
+
+impl CaptureImage {
+    pub fn get_size(&self) -> (u32, u32) {
+        self.ci_size
+    }
+
+    /// Has the frame this was copied in finished drawing
+    ///
+    /// This doesn't block, so callers should poll it on later iterations
+    /// of their main loop until it returns true.
+    pub fn is_copy_done(&self) -> bool {
+        match self.ci_copy_point.load(Ordering::Acquire) {
+            0 => true,
+            point => self.ci_dev.is_timeline_point_signaled(point),
+        }
+    }
+
+    /// Get the copied pixels
+    ///
+    /// Returns None for dmabufs, the process that owns those reads them
+    /// itself, or if the copy has not finished yet.
+    pub fn read_bits(&self) -> Option<MappedImage> {
+        if self.ci_is_dmabuf || !self.is_copy_done() {
+            return None;
+        }
+
+        unsafe {
+            let sublayout = self.ci_dev.dev.get_image_subresource_layout(
+                self.ci_image,
+                vk::ImageSubresource::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .build(),
+            );
+
+            let ptr = self
+                .ci_dev
+                .dev
+                .map_memory(
+                    self.ci_mem,
+                    sublayout.offset,
+                    sublayout.size,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .ok()?;
+            let data =
+                std::slice::from_raw_parts(ptr as *const u8, sublayout.size as usize).to_vec();
+            self.ci_dev.dev.unmap_memory(self.ci_mem);
+
+            Some(MappedImage {
+                mi_data: data,
+                mi_width: self.ci_size.0,
+                mi_height: self.ci_size.1,
+                mi_stride: sublayout.row_pitch as u32,
+            })
+        }
+    }
+}
+
+impl Drop for CaptureImage {
+    fn drop(&mut self) {
+        let resources = CaptureResources {
+            cr_dev: self.ci_dev.clone(),
+            cr_image: self.ci_image,
+            cr_view: self.ci_view,
+            cr_mem: self.ci_mem,
+        };
+
+        // Don't pull the image out from under a copy in flight
+        match self.is_copy_done() {
+            true => drop(resources),
+            false => {
+                let point = self.ci_copy_point.load(Ordering::Acquire);
+                self.ci_dev
+                    .schedule_drop_at_point(Box::new(resources), point);
+            }
+        }
+    }
+}
+
+/// Record copying `region` of `src` into the top left corner of `dst`
+///
+/// `src` is returned to `src_layout` afterwards and `dst` is left in the
+/// GENERAL layout. If `release_family` is set then ownership of `dst` is
+/// released from that queue family to the foreign queue, which is needed
+/// before another process can use a dmabuf.
+pub(crate) unsafe fn record_image_copy(
+    dev: &Device,
+    cbuf: vk::CommandBuffer,
+    src: vk::Image,
+    src_layout: vk::ImageLayout,
+    dst: vk::Image,
+    region: vk::Rect2D,
+    release_family: Option<u32>,
+) {
+    let range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .layer_count(1)
+        .level_count(1)
+        .build();
+
+    // transition our destination to TRANSFER_DST. Its old contents
+    // are overwritten so they don't need to be kept.
+    let dst_begin = vk::ImageMemoryBarrier::builder()
+        .image(dst)
+        .src_access_mask(vk::AccessFlags::default())
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(range)
+        .build();
+
+    // transition the source to TRANSFER_SRC once drawing to it is done
+    let src_begin = vk::ImageMemoryBarrier::builder()
+        .image(src)
+        .src_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .old_layout(src_layout)
+        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(range)
+        .build();
+    dev.dev.cmd_pipeline_barrier(
+        cbuf,
+        vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[dst_begin, src_begin],
+    );
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .layer_count(1)
+        .build();
+    let image_copy = vk::ImageCopy::builder()
+        .src_subresource(subresource)
+        .src_offset(vk::Offset3D {
+            x: region.offset.x,
+            y: region.offset.y,
+            z: 0,
+        })
+        .dst_subresource(subresource)
+        .extent(vk::Extent3D {
+            width: region.extent.width,
+            height: region.extent.height,
+            depth: 1,
+        })
+        .build();
+    dev.dev.cmd_copy_image(
+        cbuf,
+        src,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        dst,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[image_copy],
+    );
+
+    let (src_family, dst_family) = match release_family {
+        Some(family) => (family, vk::QUEUE_FAMILY_FOREIGN_EXT),
+        None => (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+    };
+    // transition our destination to general so it can be mapped or
+    // used elsewhere
+    let dst_end = vk::ImageMemoryBarrier::builder()
+        .image(dst)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::HOST_READ)
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::GENERAL)
+        .src_queue_family_index(src_family)
+        .dst_queue_family_index(dst_family)
+        .subresource_range(range)
+        .build();
+
+    // transition the source back so we can keep drawing or present it
+    let src_end = vk::ImageMemoryBarrier::builder()
+        .image(src)
+        .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .new_layout(src_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(range)
+        .build();
+    dev.dev.cmd_pipeline_barrier(
+        cbuf,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::TRANSFER
+            | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[dst_end, src_end],
+    );
+}
@@ -0,0 +1,101 @@
+// Reading the make and model of a monitor from its EDID
+//
+// Only the small part of the EDID base block that we need is parsed
+// here: the manufacturer ID, the product code, and the monitor name
+// display descriptor.
+use super::drm::control::{connector, property, Device as ControlDevice};
+use super::drm_device::DrmDevice;
+use utils::log;
+
+const EDID_HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const EDID_BLOCK_SIZE: usize = 128;
+/// The four 18 byte descriptors in the base block
+const EDID_DESCRIPTORS: [usize; 4] = [54, 72, 90, 108];
+const EDID_DESCRIPTOR_MONITOR_NAME: u8 = 0xfc;
+
+/// Get the raw EDID blob of a connector
+///
+/// Returns None if the connector doesn't have one, which is the case for
+/// some virtual and embedded displays.
+pub fn get_edid(drm: &DrmDevice, con: &connector::Info) -> Option<Vec<u8>> {
+    let props = drm.get_properties(con.handle()).ok()?;
+    let (handles, values) = props.as_props_and_values();
+
+    for (handle, raw) in handles.iter().zip(values.iter()) {
+        let info = match drm.get_property(*handle) {
+            Ok(info) => info,
+            Err(_) => continue,
+        };
+        if info.name().to_str().map(|n| n != "EDID").unwrap_or(true) {
+            continue;
+        }
+
+        return match info.value_type().convert_value(*raw) {
+            property::Value::Blob(0) => None,
+            property::Value::Blob(blob) => drm
+                .get_property_blob(blob)
+                .map_err(|e| log::error!("Could not read the EDID blob: {:?}", e))
+                .ok(),
+            _ => None,
+        };
+    }
+
+    None
+}
+
+/// Decode the three letter PNP manufacturer ID
+///
+/// This is packed into two big endian bytes, five bits per letter
+/// with 1 being 'A'.
+fn get_manufacturer(edid: &[u8]) -> String {
+    let id = u16::from_be_bytes([edid[8], edid[9]]);
+    [(id >> 10) & 0x1f, (id >> 5) & 0x1f, id & 0x1f]
+        .iter()
+        .map(|&c| match c {
+            1..=26 => (b'A' + c as u8 - 1) as char,
+            _ => '?',
+        })
+        .collect()
+}
+
+/// Find the monitor name display descriptor
+///
+/// The name is up to 13 characters, ended with a newline and padded
+/// with spaces.
+fn get_monitor_name(edid: &[u8]) -> Option<String> {
+    for &offset in EDID_DESCRIPTORS.iter() {
+        let desc = &edid[offset..offset + 18];
+        // Display descriptors have a zero pixel clock
+        if desc[0] != 0 || desc[1] != 0 || desc[3] != EDID_DESCRIPTOR_MONITOR_NAME {
+            continue;
+        }
+
+        let text = &desc[5..];
+        let end = text.iter().position(|&c| c == b'\n').unwrap_or(text.len());
+        let name = String::from_utf8_lossy(&text[..end]).trim().to_string();
+        if !name.is_empty() {
+            return Some(name);
+        }
+    }
+
+    None
+}
+
+/// Get the make and model from an EDID
+///
+/// Monitors without a name descriptor use their product code as the
+/// model. Returns None if this isn't a valid EDID.
+pub fn get_make_model(edid: &[u8]) -> Option<(String, String)> {
+    if edid.len() < EDID_BLOCK_SIZE || edid[..8] != EDID_HEADER {
+        log::error!("Connector has an invalid EDID");
+        return None;
+    }
+
+    let make = get_manufacturer(edid);
+    let model = match get_monitor_name(edid) {
+        Some(name) => name,
+        None => format!("0x{:04X}", u16::from_le_bytes([edid[10], edid[11]])),
+    };
+
+    Some((make, model))
+}
@@ -4,6 +4,7 @@
 pub mod drm_device;
 use drm_device::DrmDevice;
 mod blob;
+mod edid;
 
 extern crate drm;
 use ash::vk;
@@ -12,7 +13,7 @@ use drm::control::{
 };
 use drm::{control, Device as DrmDeviceTrait};
 
-use super::{DisplayInfoPayload, DisplayState, Swapchain};
+use super::{DisplayDescription, DisplayInfoPayload, DisplayState, PresentationTime, Swapchain};
 use crate::device::Device;
 use crate::image::{Dmabuf, DmabufPlane};
 use crate::{CreateInfo, Result, ThundrError};
@@ -56,6 +57,9 @@ pub(crate) struct DrmSwapchainPayload {
     ds_conn: connector::Info,
     /// The index of the current mode in ds_conn
     ds_current_mode: usize,
+    /// The monitor's manufacturer and model, read from its EDID
+    ds_make: String,
+    ds_model: String,
 }
 
 impl DisplayInfoPayload for DrmSwapchainPayload {
@@ -89,6 +93,10 @@ pub struct DrmSwapchain {
     ds_image_mems: Vec<vk::DeviceMemory>,
     /// Have we committed yet, i.e. should we wait for flip?
     ds_committed: bool,
+    /// The frame number of our pending commit
+    ds_committed_frame: u64,
+    /// The time from the flip event of the last frame shown
+    ds_last_presentation: Option<PresentationTime>,
 }
 
 impl DrmSwapchain {
@@ -134,6 +142,9 @@ impl DrmSwapchain {
         //
         // For this we are going to create a set of DRM Framebuffers, and then import that
         // memory into Vulkan for the rest of Thundr to use.
+        dstate.d_image_usage = vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::TRANSFER_SRC;
         for _ in 0..2 {
             let bo = drm
                 .ds_gbm
@@ -161,6 +172,7 @@ impl DrmSwapchain {
                 &Dmabuf {
                     db_width: dstate.d_resolution.width as i32,
                     db_height: dstate.d_resolution.height as i32,
+                    db_format: gbm::Format::Argb8888 as u32,
                     db_planes: vec![DmabufPlane::new(
                         bo.fd().or(Err(ThundrError::INVALID_FD))?,      // dmabuf
                         0,                                              // plane
@@ -169,7 +181,7 @@ impl DrmSwapchain {
                         bo.modifier().or(Err(ThundrError::INVALID_FD))?.into(), // modifier
                     )],
                 },
-                vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                dstate.d_image_usage,
             )
             .map_err(|e| {
                 log::error!("Failed to import dmabuf from GBM: {}", e);
@@ -326,6 +338,10 @@ impl DrmSwapchain {
                 rmod.drm_format_modifier_plane_count == 1
             });
 
+            let (make, model) = edid::get_edid(&drm, con)
+                .and_then(|data| edid::get_make_model(&data))
+                .unwrap_or(("Unknown".to_string(), "Unknown".to_string()));
+
             payloads.push(Arc::new(DrmSwapchainPayload {
                 ds_plane: plane,
                 ds_plane_mods: mods,
@@ -335,6 +351,8 @@ impl DrmSwapchain {
                 // TODO: let user choose mode
                 ds_current_mode: 0,
                 ds_crtc: crtc.clone(),
+                ds_make: make,
+                ds_model: model,
             }));
         }
 
@@ -359,6 +377,8 @@ impl DrmSwapchain {
             ds_images: Vec::new(),
             ds_image_mems: Vec::new(),
             ds_committed: false,
+            ds_committed_frame: 0,
+            ds_last_presentation: None,
         })
     }
 }
@@ -460,6 +480,38 @@ impl Swapchain for DrmSwapchain {
         Ok((dpi_h as i32, dpi_v as i32))
     }
 
+    /// Describe the DRM connector we are driving
+    fn get_description(&self) -> DisplayDescription {
+        let payload = self
+            .ds_payload
+            .as_any()
+            .downcast_ref::<DrmSwapchainPayload>()
+            .unwrap();
+        let mode = payload.ds_conn.modes()[payload.ds_current_mode];
+
+        DisplayDescription {
+            dd_name: format!(
+                "{}-{}",
+                payload.ds_conn.interface().as_str(),
+                payload.ds_conn.interface_id()
+            ),
+            dd_make: payload.ds_make.clone(),
+            dd_model: payload.ds_model.clone(),
+            dd_physical_size: payload.ds_conn.size().unwrap_or((0, 0)),
+            dd_refresh_rate: mode.vrefresh() * 1000,
+        }
+    }
+
+    fn get_scanout_modifiers(&self) -> Vec<u64> {
+        let payload = self
+            .ds_payload
+            .as_any()
+            .downcast_ref::<DrmSwapchainPayload>()
+            .unwrap();
+
+        payload.ds_plane_mods.iter().map(|m| (*m).into()).collect()
+    }
+
     /// Update self.current_image with the swapchain image to render to
     ///
     /// This will wait for the previous atomic commit's flip event to fire
@@ -483,37 +535,42 @@ impl Swapchain for DrmSwapchain {
                 // First check the available event list. If there is an event for our CRTC
                 // then we remove it and are good to go.
                 let mut drm_events = self.ds_dev.d_drm_events.lock().unwrap();
-                if let Some(index) = drm_events
+                let mut our_flip = drm_events
                     .iter()
                     .position(|flip| flip.crtc == payload.ds_crtc.handle())
-                {
-                    drm_events.remove(index);
-                    break;
-                }
+                    .map(|index| drm_events.remove(index));
 
                 // If there was no pending flip, then acquire the DrmDevice and wait for
                 // new events. If our CRTC was found we are good to go, record any others
                 // in the pending events list
-                let drm = self.ds_dev.d_drm_node.as_ref().unwrap().lock().unwrap();
-
-                let events = drm.receive_events().map_err(|e| {
-                    log::debug!("Failed to get DRM events: {:?}", e);
-                    ThundrError::COULD_NOT_ACQUIRE_NEXT_IMAGE
-                })?;
-
-                let mut flip_event_found = false;
-                for ev in events {
-                    if let control::Event::PageFlip(flip) = ev {
-                        // Record all events except for our CRTC
-                        match flip.crtc == payload.ds_crtc.handle() {
-                            true => flip_event_found = true,
-                            false => drm_events.push(flip),
+                if our_flip.is_none() {
+                    let drm = self.ds_dev.d_drm_node.as_ref().unwrap().lock().unwrap();
+
+                    let events = drm.receive_events().map_err(|e| {
+                        log::debug!("Failed to get DRM events: {:?}", e);
+                        ThundrError::COULD_NOT_ACQUIRE_NEXT_IMAGE
+                    })?;
+
+                    for ev in events {
+                        if let control::Event::PageFlip(flip) = ev {
+                            // Record all events except for our CRTC
+                            match flip.crtc == payload.ds_crtc.handle() {
+                                true => our_flip = Some(flip),
+                                false => drm_events.push(flip),
+                            }
                         }
                     }
                 }
 
-                // We found our flip event, now we can exit
-                if flip_event_found {
+                // We found our flip event, now we can exit. The kernel
+                // timestamps these with CLOCK_MONOTONIC at the vblank.
+                if let Some(flip) = our_flip {
+                    self.ds_last_presentation = Some(PresentationTime {
+                        pt_frame: self.ds_committed_frame,
+                        pt_time: flip.duration,
+                        pt_sequence: flip.frame as u64,
+                        pt_hw_clock: true,
+                    });
                     self.ds_committed = false;
                     break;
                 }
@@ -626,8 +683,13 @@ impl Swapchain for DrmSwapchain {
             )
             .or(Err(ThundrError::PRESENT_FAILED));
         self.ds_committed = true;
+        self.ds_committed_frame = dstate.d_present_count;
         log::debug!("present: done with flip");
 
         ret
     }
+
+    fn get_last_presentation(&self) -> Option<PresentationTime> {
+        self.ds_last_presentation
+    }
 }
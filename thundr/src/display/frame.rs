@@ -1,8 +1,12 @@
 // Rendering object for one frame
 //
 // ashafer - 2024
+extern crate ash;
+
+use ash::vk;
 
 use crate::device::Device;
+use crate::display::capture::CaptureImage;
 use crate::display::{DisplayState, Swapchain};
 use crate::image::ImageVk;
 use crate::pipelines::*;
@@ -29,6 +33,10 @@ pub(crate) struct PushConstants {
     pub color: (f32, f32, f32, f32),
     /// The complete dimensions of the window.
     pub dims: Rect<i32>,
+    /// The region of the image to sample
+    pub src_rect: Rect<f32>,
+    /// The SurfaceTransform to undo when sampling the image
+    pub transform: i32,
 }
 
 /// Recording parameters
@@ -55,6 +63,8 @@ impl<'a> RecordParams<'a> {
                 use_color: -1,
                 color: (0.0, 0.0, 0.0, 0.0),
                 dims: Rect::new(0, 0, 0, 0),
+                src_rect: Rect::new(0.0, 0.0, 1.0, 1.0),
+                transform: 0,
             },
         }
     }
@@ -72,7 +82,7 @@ impl<'a> RecordParams<'a> {
 /// the next frame.
 pub struct FrameRenderer<'a> {
     pub(crate) fr_swapchain: &'a mut Box<dyn Swapchain>,
-    pub(crate) fr_dstate: &'a DisplayState,
+    pub(crate) fr_dstate: &'a mut DisplayState,
     pub(crate) fr_pipe: &'a mut GeomPipeline,
     /// The current draw calls parameters
     pub(crate) fr_params: RecordParams<'a>,
@@ -97,6 +107,50 @@ impl<'a> FrameRenderer<'a> {
         Ok(())
     }
 
+    /// Copy part of the frame drawn so far into `dst`
+    ///
+    /// Anything drawn after this is left out of the copy. `region` is in
+    /// pixels and must fit in both the frame and `dst`. The copy will be
+    /// done once the frame has been drawn, see `CaptureImage::read_bits`.
+    pub fn copy_frame(&mut self, dst: &CaptureImage, region: &Rect<i32>) -> Result<()> {
+        if !self
+            .fr_dstate
+            .d_image_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(ThundrError::INVALID_FORMAT);
+        }
+        let res = self.fr_dstate.d_resolution;
+        let dst_size = dst.get_size();
+        if region.r_pos.0 < 0
+            || region.r_pos.1 < 0
+            || region.r_size.0 <= 0
+            || region.r_size.1 <= 0
+            || (region.r_pos.0 + region.r_size.0) as u32 > res.width
+            || (region.r_pos.1 + region.r_size.1) as u32 > res.height
+            || region.r_size.0 as u32 > dst_size.0
+            || region.r_size.1 as u32 > dst_size.1
+        {
+            return Err(ThundrError::INVALID);
+        }
+
+        self.fr_pipe.copy_frame(
+            &self.fr_dstate,
+            dst,
+            vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: region.r_pos.0,
+                    y: region.r_pos.1,
+                },
+                extent: vk::Extent2D {
+                    width: region.r_size.0 as u32,
+                    height: region.r_size.1 as u32,
+                },
+            },
+        );
+        Ok(())
+    }
+
     /// Present the current swapchain image to the screen.
     ///
     /// Finally we can actually flip the buffers and present
@@ -105,6 +159,7 @@ impl<'a> FrameRenderer<'a> {
     /// Once this has been called this object can no longer be used
     pub fn present(&mut self) -> Result<()> {
         self.fr_pipe.end_record(&self.fr_dstate);
+        self.fr_dstate.d_present_count += 1;
         self.fr_swapchain.present(&self.fr_dstate)
     }
 }
@@ -3,7 +3,7 @@
 /// Austin Shafer - 2024
 use ash::vk;
 
-use super::{DisplayInfoPayload, DisplayState, Swapchain};
+use super::{DisplayDescription, DisplayInfoPayload, DisplayState, PresentationTime, Swapchain};
 use crate::device::Device;
 use crate::{Result, ThundrError};
 
@@ -35,6 +35,8 @@ pub struct HeadlessSwapchain {
     /// can free them
     h_images: Vec<vk::Image>,
     h_image_mems: Vec<vk::DeviceMemory>,
+    /// When our last frame was "presented"
+    h_last_presentation: Option<PresentationTime>,
 }
 
 impl HeadlessSwapchain {
@@ -64,11 +66,13 @@ impl HeadlessSwapchain {
             height: HEIGHT,
         };
 
+        dstate.d_image_usage =
+            vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::COLOR_ATTACHMENT;
         for _ in 0..2 {
             let (image, view, mem) = self.h_dev.create_image(
                 &resolution,
                 vk::Format::B8G8R8A8_UNORM,
-                vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                dstate.d_image_usage,
                 vk::ImageAspectFlags::COLOR,
                 vk::MemoryPropertyFlags::DEVICE_LOCAL
                     | vk::MemoryPropertyFlags::HOST_COHERENT
@@ -93,6 +97,7 @@ impl HeadlessSwapchain {
             h_dev: dev,
             h_images: Vec::new(),
             h_image_mems: Vec::new(),
+            h_last_presentation: None,
         })
     }
 }
@@ -171,6 +176,16 @@ impl Swapchain for HeadlessSwapchain {
         Ok((100, 100))
     }
 
+    fn get_description(&self) -> DisplayDescription {
+        DisplayDescription {
+            dd_name: "HEADLESS-1".to_string(),
+            dd_make: "Thundr".to_string(),
+            dd_model: "Headless".to_string(),
+            dd_physical_size: (0, 0),
+            dd_refresh_rate: 60000,
+        }
+    }
+
     /// Update self.current_image with the swapchain image to render to
     ///
     /// If the next image is not ready (i.e. if Vulkan returned NOT_READY or
@@ -191,10 +206,15 @@ impl Swapchain for HeadlessSwapchain {
     ///
     /// Finally we can actually flip the buffers and present
     /// this image.
-    fn present(&mut self, _dstate: &DisplayState) -> Result<()> {
+    fn present(&mut self, dstate: &DisplayState) -> Result<()> {
         // no-op here, nothing to present
+        self.h_last_presentation = Some(PresentationTime::now(dstate.d_present_count));
         Ok(())
     }
+
+    fn get_last_presentation(&self) -> Option<PresentationTime> {
+        self.h_last_presentation
+    }
 }
 
 impl Drop for HeadlessSwapchain {
@@ -19,6 +19,8 @@ pub mod headless;
 use headless::HeadlessSwapchain;
 pub mod frame;
 use frame::{FrameRenderer, RecordParams};
+pub mod capture;
+use capture::record_image_copy;
 
 #[cfg(feature = "drm")]
 pub mod drm;
@@ -36,6 +38,57 @@ pub trait DisplayInfoPayload {
     fn as_any(&self) -> &dyn std::any::Any;
 }
 
+/// A description of the physical display being presented to
+///
+/// This is not used by Thundr itself, but lets compositors tell
+/// their clients which displays are connected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayDescription {
+    /// Name of the connector in use, i.e. "DP-1"
+    pub dd_name: String,
+    pub dd_make: String,
+    pub dd_model: String,
+    /// Physical size in millimeters. Zero if unknown.
+    pub dd_physical_size: (u32, u32),
+    /// Refresh rate of the current mode in mHz
+    pub dd_refresh_rate: u32,
+}
+
+/// When a frame was shown on the display
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PresentationTime {
+    /// The frame this is for. This is the count returned by
+    /// `Display::get_present_count` after it was presented.
+    pub pt_frame: u64,
+    /// The CLOCK_MONOTONIC time when the frame started being shown
+    pub pt_time: std::time::Duration,
+    /// The vblank counter of the display, or the frame number if the
+    /// display does not have one
+    pub pt_sequence: u64,
+    /// Is this the time the display hardware reported at the vblank?
+    /// Otherwise this is the time when we finished presenting.
+    pub pt_hw_clock: bool,
+}
+
+impl PresentationTime {
+    /// Describe a frame which was presented just now
+    ///
+    /// This is used by backends that can't tell us when the frame
+    /// actually reached the display.
+    pub(crate) fn now(frame: u64) -> Self {
+        let time = nix::time::clock_gettime(nix::time::ClockId::CLOCK_MONOTONIC)
+            .map(|ts| std::time::Duration::new(ts.tv_sec() as u64, ts.tv_nsec() as u32))
+            .unwrap_or_default();
+
+        Self {
+            pt_frame: frame,
+            pt_time: time,
+            pt_sequence: frame,
+            pt_hw_clock: false,
+        }
+    }
+}
+
 /// Shared state that subsystems consume. We need this
 /// since Display holds rendering objects, but also has
 /// to pass down swapchain/image info so those rendering
@@ -54,6 +107,9 @@ pub struct DisplayState {
     // Vulkan surface capabilities
     pub d_surface_caps: vk::SurfaceCapabilitiesKHR,
     pub d_surface_format: vk::SurfaceFormatKHR,
+    /// How the swapchain images may be used. Frames can only be
+    /// copied out of them if this has TRANSFER_SRC.
+    pub d_image_usage: vk::ImageUsageFlags,
     /// index into swapchain images that we are currently using
     pub(crate) d_current_image: u32,
     /// Headless backend does not need a present sema
@@ -71,6 +127,9 @@ pub struct DisplayState {
     pub(crate) d_graphics_queue_family: u32,
     /// Frame end semaphore
     pub(crate) d_frame_sema: vk::Semaphore,
+    /// The number of frames that have been presented. This is used to
+    /// match presentation times to the frame they are for.
+    pub(crate) d_present_count: u64,
 }
 
 /// A display represents a physical screen
@@ -131,6 +190,16 @@ pub(crate) trait Swapchain {
     /// SDL we will ask SDL to tell us it.
     fn get_dpi(&self) -> Result<(i32, i32)>;
 
+    /// Describe the display this swapchain is presenting to
+    fn get_description(&self) -> DisplayDescription;
+
+    /// Get the ARGB8888 DRM format modifiers this display can scan out
+    ///
+    /// Only backends which drive DRM planes directly know this.
+    fn get_scanout_modifiers(&self) -> Vec<u64> {
+        Vec::new()
+    }
+
     /// Update self.current_image with the swapchain image to render to
     ///
     /// If the next image is not ready (i.e. if Vulkan returned NOT_READY or
@@ -144,6 +213,9 @@ pub(crate) trait Swapchain {
     /// Finally we can actually flip the buffers and present
     /// this image.
     fn present(&mut self, dstate: &DisplayState) -> Result<()>;
+
+    /// Get when the latest frame known to be on screen was presented
+    fn get_last_presentation(&self) -> Option<PresentationTime>;
 }
 
 impl Display {
@@ -176,6 +248,7 @@ impl Display {
             let dstate = DisplayState {
                 d_surface_caps: surface_caps,
                 d_surface_format: surface_format,
+                d_image_usage: vk::ImageUsageFlags::empty(),
                 d_resolution: vk::Extent2D {
                     width: 0,
                     height: 0,
@@ -194,6 +267,7 @@ impl Display {
                 d_frame_sema: frame_sema,
                 d_graphics_queue_family: queue_family,
                 d_images: Vec::with_capacity(0),
+                d_present_count: 0,
             };
 
             let pipe = GeomPipeline::new(dev.clone(), &dstate)?;
@@ -297,6 +371,23 @@ impl Display {
         Ok(())
     }
 
+    /// Get the number of frames that have been presented
+    ///
+    /// This is incremented every time a FrameRenderer is presented, and
+    /// identifies that frame in `get_last_presentation`.
+    pub fn get_present_count(&self) -> u64 {
+        self.d_state.d_present_count
+    }
+
+    /// Get when the latest frame known to be on screen was presented
+    ///
+    /// Depending on the backend this may be for a frame before the one
+    /// most recently presented, since we may not have heard back from the
+    /// display yet.
+    pub fn get_last_presentation(&self) -> Option<PresentationTime> {
+        self.d_swapchain.get_last_presentation()
+    }
+
     /// Get the DRM device major/minor in use by this Display's Device
     pub fn get_drm_dev(&self) -> Option<(i64, i64)> {
         self.d_dev.get_drm_dev()
@@ -310,6 +401,19 @@ impl Display {
         self.d_swapchain.get_dpi()
     }
 
+    /// Get the name, refresh rate, etc of this display
+    pub fn get_description(&self) -> DisplayDescription {
+        self.d_swapchain.get_description()
+    }
+
+    /// Get the DRM format modifiers this display can scan out
+    ///
+    /// These are also supported by the renderer. This is empty if the
+    /// backend doesn't know.
+    pub fn get_scanout_modifiers(&self) -> Vec<u64> {
+        self.d_swapchain.get_scanout_modifiers()
+    }
+
     /// Get the resolution of this display
     ///
     /// This returns the extent as used by Vulkan
@@ -392,7 +496,7 @@ impl Display {
 
         let frame = FrameRenderer {
             fr_swapchain: &mut self.d_swapchain,
-            fr_dstate: &self.d_state,
+            fr_dstate: &mut self.d_state,
             fr_pipe: &mut self.d_pipe,
             fr_params: params,
         };
@@ -436,102 +540,17 @@ impl Display {
                 vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
             );
 
-            let range = vk::ImageSubresourceRange::builder()
-                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                .layer_count(1)
-                .level_count(1)
-                .build();
-
-            // transition our tmp image to TRANSFER_DST
-            let tmp_src = vk::ImageMemoryBarrier::builder()
-                .image(image)
-                .src_access_mask(vk::AccessFlags::default())
-                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .subresource_range(range)
-                .build();
-
-            // transition our swapchain image to TRANSFER_SRC
-            let swapchain_src = vk::ImageMemoryBarrier::builder()
-                .image(self.d_state.d_images[self.d_state.d_current_image as usize])
-                .src_access_mask(vk::AccessFlags::MEMORY_READ)
-                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
-                .old_layout(present_layout)
-                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .subresource_range(range)
-                .build();
-            self.d_dev.dev.cmd_pipeline_barrier(
-                internal.copy_cbuf,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[tmp_src, swapchain_src],
-            );
-
-            // copy from the swapchain image
-            let image_copy = vk::ImageCopy::builder()
-                .src_subresource(
-                    vk::ImageSubresourceLayers::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .build(),
-                )
-                .dst_subresource(
-                    vk::ImageSubresourceLayers::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .build(),
-                )
-                .extent(self.d_state.d_resolution.into())
-                .build();
-
-            self.d_dev.dev.cmd_copy_image(
+            record_image_copy(
+                &self.d_dev,
                 internal.copy_cbuf,
                 self.d_state.d_images[self.d_state.d_current_image as usize],
-                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                present_layout,
                 image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[image_copy],
-            );
-
-            // transition our tmp image to general
-            let tmp_dst = vk::ImageMemoryBarrier::builder()
-                .image(image)
-                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .new_layout(vk::ImageLayout::GENERAL)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .subresource_range(range)
-                .build();
-
-            // transition the swapchain image back to optimal
-            let swapchain_dst = vk::ImageMemoryBarrier::builder()
-                .image(self.d_state.d_images[self.d_state.d_current_image as usize])
-                .src_access_mask(vk::AccessFlags::TRANSFER_READ)
-                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-                .new_layout(present_layout)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .subresource_range(range)
-                .build();
-            self.d_dev.dev.cmd_pipeline_barrier(
-                internal.copy_cbuf,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[tmp_dst, swapchain_dst],
+                vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: self.d_state.d_resolution,
+                },
+                None,
             );
 
             self.d_dev.cbuf_end_recording(internal.copy_cbuf);
@@ -595,7 +614,12 @@ impl Display {
                 }
             }
 
-            MappedImage { mi_data: data }
+            MappedImage {
+                mi_data: data,
+                mi_width: self.d_state.d_resolution.width,
+                mi_height: self.d_state.d_resolution.height,
+                mi_stride: sublayout.row_pitch as u32,
+            }
         }
     }
 }
@@ -12,7 +12,7 @@ use ash::extensions::khr;
 use ash::vk;
 use ash::Entry;
 
-use super::{DisplayInfoPayload, DisplayState, Swapchain};
+use super::{DisplayDescription, DisplayInfoPayload, DisplayState, PresentationTime, Swapchain};
 use crate::device::Device;
 use crate::{CreateInfo, Result as ThundrResult, SurfaceType, ThundrError, WindowInfo};
 use utils::log;
@@ -57,6 +57,9 @@ pub(crate) struct VkSwapchain {
     pub(crate) d_swapchain_loader: khr::Swapchain,
     /// the actual swapchain
     pub(crate) d_swapchain: vk::SwapchainKHR,
+    /// When our last frame was queued for presentation. Vulkan doesn't
+    /// tell us when it actually reaches the screen.
+    d_last_presentation: Option<PresentationTime>,
 }
 
 pub(crate) trait VkSwapchainBackend {
@@ -235,6 +238,18 @@ impl VkSwapchain {
             dstate.d_surface_caps.current_transform
         };
 
+        // Frames are copied out of the swapchain for screenshots, if the
+        // surface allows it
+        let mut usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        if dstate
+            .d_surface_caps
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+        dstate.d_image_usage = usage;
+
         let create_info = vk::SwapchainCreateInfoKHR::builder()
             .flags(vk::SwapchainCreateFlagsKHR::empty())
             .surface(self.d_surface)
@@ -242,7 +257,7 @@ impl VkSwapchain {
             .image_color_space(dstate.d_surface_format.color_space)
             .image_format(dstate.d_surface_format.format)
             .image_extent(dstate.d_resolution)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(usage)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
             .pre_transform(transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
@@ -357,6 +372,7 @@ impl VkSwapchain {
                 d_present_mode: mode,
                 d_swapchain_loader: swapchain_loader,
                 d_swapchain: vk::SwapchainKHR::null(),
+                d_last_presentation: None,
             })
         }
     }
@@ -453,6 +469,20 @@ impl Swapchain for VkSwapchain {
         self.d_back.get_dpi()
     }
 
+    /// Describe our display
+    ///
+    /// Neither SDL nor VK_KHR_display give us much to go on here, so
+    /// assume a standard 60Hz monitor.
+    fn get_description(&self) -> DisplayDescription {
+        DisplayDescription {
+            dd_name: "VK-1".to_string(),
+            dd_make: "Thundr".to_string(),
+            dd_model: "Vulkan Display".to_string(),
+            dd_physical_size: (0, 0),
+            dd_refresh_rate: 60000,
+        }
+    }
+
     /// Update self.current_image with the swapchain image to render to
     ///
     /// If the next image is not ready (i.e. if Vulkan returned NOT_READY or
@@ -538,13 +568,20 @@ impl Swapchain for VkSwapchain {
                 .d_swapchain_loader
                 .queue_present(dstate.d_present_queue, &info)
             {
-                Ok(_) => Ok(()),
+                Ok(_) => {
+                    self.d_last_presentation = Some(PresentationTime::now(dstate.d_present_count));
+                    Ok(())
+                }
                 Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Err(ThundrError::OUT_OF_DATE),
                 Err(vk::Result::SUBOPTIMAL_KHR) => Err(ThundrError::OUT_OF_DATE),
                 Err(_) => Err(ThundrError::PRESENT_FAILED),
             }
         }
     }
+
+    fn get_last_presentation(&self) -> Option<PresentationTime> {
+        self.d_last_presentation
+    }
 }
 
 impl Drop for VkSwapchain {
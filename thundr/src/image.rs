@@ -20,6 +20,7 @@ use std::sync::{Arc, RwLock};
 
 use ash::vk;
 use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::stat::fstat;
 
 // For now we only support one format.
 // According to the mesa source, this supports all modifiers.
@@ -72,16 +73,19 @@ impl DmabufPlane {
 pub struct Dmabuf {
     pub db_width: i32,
     pub db_height: i32,
+    /// The DRM fourcc code describing the layout of the pixels
+    pub db_format: u32,
 
     /// The individual plane specifications
     pub db_planes: Vec<DmabufPlane>,
 }
 
 impl Dmabuf {
-    pub fn new(width: i32, height: i32) -> Self {
+    pub fn new(width: i32, height: i32, format: u32) -> Self {
         Self {
             db_width: width,
             db_height: height,
+            db_format: format,
             db_planes: Vec::with_capacity(1),
         }
     }
@@ -105,6 +109,9 @@ pub struct ImageVk {
     /// Our image descriptor to pass to the Pipeline
     /// This tells the shaders how to find this image.
     pub iv_desc: Descriptor,
+    /// The format of this image if it is YUV. These have to be drawn with
+    /// the pipeline for their YCbCr conversion.
+    pub iv_ycbcr_format: Option<vk::Format>,
 }
 
 impl ImageVk {
@@ -137,6 +144,7 @@ impl ImageVk {
             height: 0,
         };
         self.iv_release_info = None;
+        self.iv_ycbcr_format = None;
     }
 }
 
@@ -318,6 +326,7 @@ impl Device {
                         iv_image_resolution: new_size,
                         iv_release_info: release,
                         iv_desc: self.create_new_image_descriptor(view),
+                        iv_ycbcr_format: None,
                     }),
                 );
                 image_internal.i_resolution = new_size;
@@ -362,6 +371,14 @@ impl Device {
     ///
     /// These are the modifiers that are importable as Thundr Images.
     pub fn get_supported_drm_modifiers(&self) -> Vec<vk::DrmFormatModifierPropertiesEXT> {
+        self.get_supported_drm_modifiers_for_format(TARGET_FORMAT)
+    }
+
+    /// Get the DRM modifiers this device supports for `format`
+    pub(crate) fn get_supported_drm_modifiers_for_format(
+        &self,
+        format: vk::Format,
+    ) -> Vec<vk::DrmFormatModifierPropertiesEXT> {
         use std::iter;
 
         // get_physical_device_format_properties2
@@ -374,7 +391,7 @@ impl Device {
         unsafe {
             self.inst.inst.get_physical_device_format_properties2(
                 self.pdev,
-                TARGET_FORMAT,
+                format,
                 &mut format_props,
             );
             let mut mods: Vec<_> = iter::repeat(vk::DrmFormatModifierPropertiesEXT::default())
@@ -384,7 +401,7 @@ impl Device {
             drm_fmt_props.p_drm_format_modifier_properties = mods.as_mut_ptr();
             self.inst.inst.get_physical_device_format_properties2(
                 self.pdev,
-                TARGET_FORMAT,
+                format,
                 &mut format_props,
             );
 
@@ -408,6 +425,26 @@ impl Device {
         return mods;
     }
 
+    /// Check that every plane of a dmabuf is in the same buffer
+    ///
+    /// Planes may be passed with different fds, but we don't support
+    /// importing disjoint images where the planes have separate memory.
+    fn planes_share_buffer(dmabuf: &Dmabuf) -> bool {
+        let stats: Vec<_> = dmabuf
+            .db_planes
+            .iter()
+            .map(|plane| fstat(plane.db_fd.as_raw_fd()).ok())
+            .collect();
+
+        match stats[0] {
+            Some(first) => stats.iter().all(|stat| match stat {
+                Some(stat) => stat.st_dev == first.st_dev && stat.st_ino == first.st_ino,
+                None => false,
+            }),
+            None => false,
+        }
+    }
+
     pub(crate) fn create_image_from_dmabuf_internal(
         dev: &Device,
         dmabuf: &Dmabuf,
@@ -417,12 +454,23 @@ impl Device {
         // A lot of this is duplicated from Renderer::create_image
         // Check validity of dmabuf format and print info
         // -------------------------------------------------------
-        // TODO: multiplanar support
+        if dmabuf.db_planes.len() == 0 {
+            return Err(ThundrError::INVALID_DMABUF);
+        }
         let plane = &dmabuf.db_planes[0];
 
+        // YUV formats are sampled with a conversion, anything else is
+        // treated as our normal RGB format
+        let ycbcr = dev.get_ycbcr_conversion(dmabuf.db_format);
+        let format = ycbcr.map(|(format, _)| format).unwrap_or(TARGET_FORMAT);
+        if dmabuf.db_planes.len() > 1 && (ycbcr.is_none() || !Self::planes_share_buffer(dmabuf)) {
+            log::error!("Cannot import disjoint multi-planar dmabuf {:?}", dmabuf);
+            return Err(ThundrError::INVALID_DMABUF);
+        }
+
         #[cfg(debug_assertions)]
         {
-            let mods = dev.get_supported_drm_modifiers();
+            let mods = dev.get_supported_drm_modifiers_for_format(format);
 
             for m in mods.iter() {
                 log::debug!("dmabuf {} found mod {:#?}", plane.db_fd.as_raw_fd(), m);
@@ -431,7 +479,7 @@ impl Device {
 
         // the parameters to use for image creation
         let mut img_fmt_info = vk::PhysicalDeviceImageFormatInfo2::builder()
-            .format(TARGET_FORMAT)
+            .format(format)
             .ty(vk::ImageType::TYPE_2D)
             .usage(vk::ImageUsageFlags::SAMPLED)
             .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
@@ -479,7 +527,7 @@ impl Device {
         };
         // Import the dmabuf
         // -------------------------------------------------------
-        dev.create_dmabuf_image(&dmabuf, &mut dmabuf_priv, image_usage)
+        dev.create_dmabuf_image(&dmabuf, &mut dmabuf_priv, image_usage, format, ycbcr)
             .map_err(|e| {
                 log::error!("Could not update dmabuf image: {:?}", e);
                 ThundrError::INVALID_DMABUF
@@ -491,22 +539,31 @@ impl Device {
         dmabuf: &Dmabuf,
         dmabuf_priv: &mut DmabufPrivate,
         image_usage: vk::ImageUsageFlags,
+        format: vk::Format,
+        ycbcr: Option<(vk::Format, vk::SamplerYcbcrConversion)>,
     ) -> Result<(vk::Image, vk::ImageView, vk::DeviceMemory)> {
-        // TODO: multiplanar support
+        // All planes are in the same buffer, so the memory is
+        // imported from the first one
         let plane = &dmabuf.db_planes[0];
 
         // Allocate an external image
         // -------------------------------------------------------
         // we create the image now, but will have to bind
         // some memory to it later.
-        let layouts = &[vk::SubresourceLayout::builder()
-            .offset(plane.db_offset as u64)
-            .row_pitch(plane.db_stride as u64)
-            .size(0)
-            .build()];
+        let layouts: Vec<vk::SubresourceLayout> = dmabuf
+            .db_planes
+            .iter()
+            .map(|plane| {
+                vk::SubresourceLayout::builder()
+                    .offset(plane.db_offset as u64)
+                    .row_pitch(plane.db_stride as u64)
+                    .size(0)
+                    .build()
+            })
+            .collect();
         let mut drm_create_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::builder()
             .drm_format_modifier(plane.db_mods)
-            .plane_layouts(layouts)
+            .plane_layouts(layouts.as_slice())
             .build();
 
         let mut ext_mem_info = vk::ExternalMemoryImageCreateInfo::builder()
@@ -520,8 +577,7 @@ impl Device {
         };
         let image_info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
-            // TODO: add other formats
-            .format(TARGET_FORMAT)
+            .format(format)
             .extent(extent)
             .image_type(vk::ImageType::TYPE_2D)
             .mip_levels(1)
@@ -607,8 +663,16 @@ impl Device {
                 .bind_image_memory(image, image_memory, 0)
                 .expect("Unable to bind device memory to image");
 
-            // finally make a view to wrap the image
-            let view_info = vk::ImageViewCreateInfo::builder()
+            // finally make a view to wrap the image. YUV images must
+            // have their conversion attached to the view as well.
+            let mut conversion_info = vk::SamplerYcbcrConversionInfo::builder()
+                .conversion(
+                    ycbcr
+                        .map(|(_, c)| c)
+                        .unwrap_or(vk::SamplerYcbcrConversion::null()),
+                )
+                .build();
+            let mut view_info = vk::ImageViewCreateInfo::builder()
                 .subresource_range(
                     vk::ImageSubresourceRange::builder()
                         .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -619,6 +683,9 @@ impl Device {
                 .image(image)
                 .format(image_info.format)
                 .view_type(vk::ImageViewType::TYPE_2D);
+            if ycbcr.is_some() {
+                view_info = view_info.push_next(&mut conversion_info);
+            }
 
             let view = self.dev.create_image_view(&view_info, None).unwrap();
 
@@ -670,6 +737,7 @@ impl Device {
             img_mem,
             view,
             false,
+            None,
             release_info,
         );
     }
@@ -686,6 +754,9 @@ impl Device {
     ) -> Result<Image> {
         let (image, view, image_memory) =
             Device::create_image_from_dmabuf_internal(&self, dmabuf, vk::ImageUsageFlags::SAMPLED)?;
+        let ycbcr_format = self
+            .get_ycbcr_conversion(dmabuf.db_format)
+            .map(|(format, _)| format);
 
         return self.create_image_common(
             ImagePrivate::Dmabuf,
@@ -697,6 +768,7 @@ impl Device {
             image_memory,
             view,
             true,
+            ycbcr_format,
             release_info,
         );
     }
@@ -717,9 +789,10 @@ impl Device {
         image_mem: vk::DeviceMemory,
         view: vk::ImageView,
         is_dmabuf: bool,
+        ycbcr_format: Option<vk::Format>,
         release: Option<Box<dyn Droppable + Send + Sync>>,
     ) -> Result<Image> {
-        let descriptor = self.create_new_image_descriptor(view);
+        let descriptor = self.create_new_image_descriptor_with_format(view, ycbcr_format);
 
         let image_vk = Arc::new(ImageVk {
             // use our device's weak pointer to get an Arc
@@ -731,6 +804,7 @@ impl Device {
             iv_image_resolution: *res,
             iv_release_info: release,
             iv_desc: descriptor,
+            iv_ycbcr_format: ycbcr_format,
         });
 
         let id = self.d_image_ecs.add_entity();
@@ -95,6 +95,7 @@ mod instance;
 mod pipelines;
 mod platform;
 mod surface;
+mod ycbcr;
 
 #[cfg(test)]
 mod tests;
@@ -109,10 +110,13 @@ pub(crate) use deletion_queue::DeletionQueue;
 pub use device::Device;
 #[cfg(feature = "drm")]
 use display::drm::DrmSwapchain;
-pub use display::{frame::FrameRenderer, Display, DisplayInfoPayload};
+pub use display::{
+    capture::CaptureImage, frame::FrameRenderer, Display, DisplayDescription, DisplayInfoPayload,
+    PresentationTime,
+};
 use display::{headless::HeadlessSwapchain, vkswapchain::VkSwapchain};
 use instance::Instance;
-pub use surface::Surface;
+pub use surface::{Surface, SurfaceTransform};
 
 // Re-export some things from utils so clients
 // can use them
@@ -369,10 +373,15 @@ impl<T> Droppable for T {}
 ///
 /// This is used to expose a CPU mapping of a VkImage. The main use
 /// case being for automated testing, we use this to dump the contents
-/// of a swapchain image to compare against a correct result.
+/// of a swapchain image to compare against a correct result. It is
+/// also how captured frames are read back.
 #[allow(dead_code)]
 pub struct MappedImage {
     pub mi_data: Vec<u8>,
+    pub mi_width: u32,
+    pub mi_height: u32,
+    /// The number of bytes in each row of `mi_data`. Rows may be padded.
+    pub mi_stride: u32,
 }
 
 // This is the public facing thundr api. Don't change it
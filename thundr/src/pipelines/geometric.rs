@@ -10,11 +10,13 @@ use std::ffi::CString;
 use std::io::Cursor;
 use std::marker::Copy;
 use std::mem;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use ash::{util, vk};
 
 use super::Pipeline;
+use crate::display::capture::{record_image_copy, CaptureImage};
 use crate::display::frame::{PushConstants, RecordParams};
 use crate::display::DisplayState;
 use crate::{Device, Image, Result, Surface, Viewport};
@@ -59,6 +61,9 @@ static QUAD_INDICES: [Vector3<u32>; 2] = [Vector3::new(1, 2, 3), Vector3::new(1,
 pub struct GeomPipeline {
     g_dev: Arc<Device>,
     pass: vk::RenderPass,
+    /// The same as `pass` but keeps what was already drawn. This is used
+    /// to continue drawing a frame after copying it.
+    g_load_pass: vk::RenderPass,
     pipeline: vk::Pipeline,
     pipeline_layout: vk::PipelineLayout,
     /// Pool for command buffers
@@ -88,6 +93,21 @@ pub struct GeomPipeline {
     index_buffer_memory: vk::DeviceMemory,
     /// Placeholder image for when the surface doesn't have one
     tmp_image: Option<Image>,
+    /// Pipelines for drawing YUV images, one for each format
+    g_ycbcr_pipelines: Vec<YcbcrPipeline>,
+    /// The pipeline currently bound in the cbuf being recorded
+    g_bound_pipeline: vk::Pipeline,
+}
+
+/// A pipeline for drawing images of one YUV format
+///
+/// The sampler doing the YCbCr conversion is baked into the image
+/// descriptor layout, so each format needs its own pipeline layout.
+/// These use the same shaders as the RGB pipeline.
+struct YcbcrPipeline {
+    yp_format: vk::Format,
+    yp_layout: vk::PipelineLayout,
+    yp_pipeline: vk::Pipeline,
 }
 
 /// Contiains a vertex and all its related data
@@ -157,6 +177,7 @@ impl Pipeline for GeomPipeline {
             self.g_dev
                 .dev
                 .cmd_bind_pipeline(cbuf, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.g_bound_pipeline = self.pipeline;
 
             // bind the vertex and index buffers from
             // the first image
@@ -246,7 +267,7 @@ impl Pipeline for GeomPipeline {
 
         // if we have an image bound to this surface grab its descriptor from the
         // imagevk. If not, then use the default tmp image
-        let image_desc = {
+        let (image_desc, ycbcr_format) = {
             let imagevk = params
                 .image_vk
                 .get(match image {
@@ -256,12 +277,35 @@ impl Pipeline for GeomPipeline {
                 .expect("Image does not have ImageVK");
 
             assert!(imagevk.iv_desc.d_set != vk::DescriptorSet::null());
-            imagevk.iv_desc.d_set
+            (imagevk.iv_desc.d_set, imagevk.iv_ycbcr_format)
+        };
+
+        // YUV images are drawn with the pipeline for their format
+        let (pipeline, pipeline_layout) = match ycbcr_format {
+            Some(format) => match self
+                .g_ycbcr_pipelines
+                .iter()
+                .find(|p| p.yp_format == format)
+            {
+                Some(ycbcr) => (ycbcr.yp_pipeline, ycbcr.yp_layout),
+                None => {
+                    log::error!("No pipeline for drawing YUV format {:?}", format);
+                    return true;
+                }
+            },
+            None => (self.pipeline, self.pipeline_layout),
         };
 
         // TODO: If this surface is not contained in the viewport then don't draw it
 
         unsafe {
+            if pipeline != self.g_bound_pipeline {
+                self.g_dev
+                    .dev
+                    .cmd_bind_pipeline(cbuf, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                self.g_bound_pipeline = pipeline;
+            }
+
             // Bind this surface's backing texture if it has one. Descriptor
             // sets can be updated elsewhere, but they must be bound before drawing
             //
@@ -270,7 +314,7 @@ impl Pipeline for GeomPipeline {
             self.g_dev.dev.cmd_bind_descriptor_sets(
                 cbuf,
                 vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline_layout,
+                pipeline_layout,
                 0, // first set
                 &[self.g_desc, image_desc],
                 &[], // dynamic offsets
@@ -278,7 +322,7 @@ impl Pipeline for GeomPipeline {
 
             self.g_dev.dev.cmd_push_constants(
                 cbuf,
-                self.pipeline_layout,
+                pipeline_layout,
                 vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
                 0, // offset
                 // Get the raw bytes for our push constants without doing any
@@ -358,6 +402,7 @@ impl Drop for GeomPipeline {
             self.g_dev.free_memory(self.uniform_buffers_memory);
 
             self.g_dev.dev.destroy_render_pass(self.pass, None);
+            self.g_dev.dev.destroy_render_pass(self.g_load_pass, None);
 
             self.g_dev
                 .dev
@@ -380,6 +425,13 @@ impl Drop for GeomPipeline {
             }
 
             self.g_dev.dev.destroy_pipeline(self.pipeline, None);
+
+            for ycbcr in self.g_ycbcr_pipelines.iter() {
+                self.g_dev.dev.destroy_pipeline(ycbcr.yp_pipeline, None);
+                self.g_dev
+                    .dev
+                    .destroy_pipeline_layout(ycbcr.yp_layout, None);
+            }
         }
     }
 }
@@ -411,6 +463,52 @@ impl GeomPipeline {
             surf.s_rect.r_size.0,
             surf.s_rect.r_size.1,
         );
+        params.push.src_rect = surf.s_src_rect;
+        params.push.transform = surf.s_transform as i32;
+    }
+
+    /// Copy what has been drawn so far in this frame
+    ///
+    /// Copies can't happen during a render pass, so this ends the current
+    /// one and starts another which keeps the image contents. The bound
+    /// pipeline and dynamic state carry over, so drawing can continue as
+    /// if nothing happened.
+    pub fn copy_frame(&mut self, dstate: &DisplayState, dst: &CaptureImage, region: vk::Rect2D) {
+        let cbuf = self.g_cbufs[dstate.d_current_image as usize];
+        // The copy is done once the frame being recorded is, which is
+        // the next one submitted
+        dst.ci_copy_point
+            .store(self.g_dev.get_next_timeline_point(), Ordering::Release);
+        let release_family = match dst.ci_is_dmabuf {
+            true => Some(dstate.d_graphics_queue_family),
+            false => None,
+        };
+
+        let pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.g_load_pass)
+            .framebuffer(self.framebuffers[dstate.d_current_image as usize])
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: dstate.d_resolution,
+            });
+
+        unsafe {
+            self.g_dev.dev.cmd_end_render_pass(cbuf);
+            record_image_copy(
+                &self.g_dev,
+                cbuf,
+                dstate.d_images[dstate.d_current_image as usize],
+                GeomPipeline::get_final_layout(&self.g_dev),
+                dst.ci_image,
+                region,
+                release_family,
+            );
+            self.g_dev.dev.cmd_begin_render_pass(
+                cbuf,
+                &pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+        }
     }
 
     /// Set our temporary image
@@ -450,7 +548,16 @@ impl GeomPipeline {
     /// This fills in the GeomPipeline struct in the Renderer
     pub fn new(dev: Arc<Device>, dstate: &DisplayState) -> Result<GeomPipeline> {
         unsafe {
-            let pass = GeomPipeline::create_pass(dstate.d_surface_format.format, &dev);
+            let pass = GeomPipeline::create_pass(
+                dstate.d_surface_format.format,
+                &dev,
+                vk::AttachmentLoadOp::CLEAR,
+            );
+            let load_pass = GeomPipeline::create_pass(
+                dstate.d_surface_format.format,
+                &dev,
+                vk::AttachmentLoadOp::LOAD,
+            );
 
             // This is a really annoying issue with CString ptrs
             let program_entrypoint_name = CString::new("main").unwrap();
@@ -469,31 +576,41 @@ impl GeomPipeline {
             // NOTE: These need to be referenced in order by the `set` modifier
             // in the shaders
             let ubo_layout = GeomPipeline::create_ubo_layout(&dev);
-            // These are the layout recognized by the pipeline
-            let descriptor_layouts = &[
-                ubo_layout, // set 0
-                dev.d_internal.read().unwrap().descpool.ds_layout,
-            ];
-
-            // make a push constant entry for the z ordering of a window
-            let constants = &[vk::PushConstantRange::builder()
-                .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
-                .offset(0)
-                // depth is measured as a normalized float
-                .size(std::mem::size_of::<PushConstants>() as u32)
-                .build()];
-
-            // even though we don't have anything special in our layout, we
-            // still need to have a created layout for the pipeline
-            let layout_info = vk::PipelineLayoutCreateInfo::builder()
-                .push_constant_ranges(constants)
-                .set_layouts(descriptor_layouts)
-                .build();
-            let layout = dev.dev.create_pipeline_layout(&layout_info, None).unwrap();
+            let image_layout = dev.d_internal.read().unwrap().descpool.ds_layout;
+            let layout = GeomPipeline::create_pipeline_layout(&dev, ubo_layout, image_layout);
 
             let pipeline =
                 GeomPipeline::create_pipeline(dstate, &dev, layout, pass, &*shader_stages);
 
+            // Every YUV format needs its own pipeline, since the image layout
+            // holds the sampler for that format
+            let ycbcr_layouts: Vec<(vk::Format, vk::DescriptorSetLayout)> = dev
+                .d_internal
+                .read()
+                .unwrap()
+                .ycbcr_conversions
+                .iter()
+                .map(|c| (c.yc_vk_format, c.yc_descpool.ds_layout))
+                .collect();
+            let ycbcr_pipelines = ycbcr_layouts
+                .iter()
+                .map(|(format, image_layout)| {
+                    let layout =
+                        GeomPipeline::create_pipeline_layout(&dev, ubo_layout, *image_layout);
+                    YcbcrPipeline {
+                        yp_format: *format,
+                        yp_layout: layout,
+                        yp_pipeline: GeomPipeline::create_pipeline(
+                            dstate,
+                            &dev,
+                            layout,
+                            pass,
+                            &*shader_stages,
+                        ),
+                    }
+                })
+                .collect();
+
             // Allocate a pool only for the ubo descriptors
             let g_desc_pool = Self::create_descriptor_pool(&dev);
             let layouts = [ubo_layout];
@@ -527,6 +644,7 @@ impl GeomPipeline {
             let mut ctx = GeomPipeline {
                 g_dev: dev,
                 pass: pass,
+                g_load_pass: load_pass,
                 pipeline: pipeline,
                 pipeline_layout: layout,
                 g_desc_layout: ubo_layout,
@@ -545,6 +663,8 @@ impl GeomPipeline {
                 index_buffer: ibuf,
                 index_buffer_memory: imem,
                 tmp_image: None,
+                g_ycbcr_pipelines: ycbcr_pipelines,
+                g_bound_pipeline: vk::Pipeline::null(),
             };
 
             // now we need to update the descriptor set with the
@@ -555,6 +675,38 @@ impl GeomPipeline {
         }
     }
 
+    /// Create a pipeline layout using `image_layout` for image descriptors
+    ///
+    /// Pipeline layouts specify the descriptor sets and push constants
+    /// that the shaders can access.
+    unsafe fn create_pipeline_layout(
+        dev: &Device,
+        ubo_layout: vk::DescriptorSetLayout,
+        image_layout: vk::DescriptorSetLayout,
+    ) -> vk::PipelineLayout {
+        // These are the layout recognized by the pipeline
+        let descriptor_layouts = &[
+            ubo_layout,   // set 0
+            image_layout, // set 1
+        ];
+
+        // make a push constant entry for the z ordering of a window
+        let constants = &[vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            // depth is measured as a normalized float
+            .size(std::mem::size_of::<PushConstants>() as u32)
+            .build()];
+
+        // even though we don't have anything special in our layout, we
+        // still need to have a created layout for the pipeline
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .push_constant_ranges(constants)
+            .set_layouts(descriptor_layouts)
+            .build();
+        dev.dev.create_pipeline_layout(&layout_info, None).unwrap()
+    }
+
     /// Render a frame, but do not present it
     ///
     /// Think of this as the "main" rendering operation. It will draw
@@ -581,16 +733,33 @@ impl GeomPipeline {
         );
     }
 
-    /// create a renderpass for the color/depth attachments
-    ///
-    /// Render passses signify what attachments are used in which
-    /// stages. They are composed of one or more subpasses.
-    unsafe fn create_pass(format: vk::Format, dev: &Device) -> vk::RenderPass {
+    /// The layout our render passes leave the swapchain image in
+    fn get_final_layout(dev: &Device) -> vk::ImageLayout {
         // According to the spec we can only use PRESENT_SRC when vkSwapchain's
         // ext is enabled
-        let layout = match dev.dev_features.vkc_supports_swapchain {
+        match dev.dev_features.vkc_supports_swapchain {
             true => vk::ImageLayout::PRESENT_SRC_KHR,
             false => vk::ImageLayout::GENERAL,
+        }
+    }
+
+    /// create a renderpass for the color/depth attachments
+    ///
+    /// Render passses signify what attachments are used in which
+    /// stages. They are composed of one or more subpasses.
+    ///
+    /// `load_op` decides if the swapchain image is cleared or if we keep
+    /// drawing on top of its contents.
+    unsafe fn create_pass(
+        format: vk::Format,
+        dev: &Device,
+        load_op: vk::AttachmentLoadOp,
+    ) -> vk::RenderPass {
+        let layout = GeomPipeline::get_final_layout(dev);
+        // Loading the image requires that it already has valid contents
+        let initial_layout = match load_op {
+            vk::AttachmentLoadOp::LOAD => layout,
+            _ => vk::ImageLayout::UNDEFINED,
         };
 
         let attachments = [
@@ -600,9 +769,9 @@ impl GeomPipeline {
             vk::AttachmentDescription {
                 format: format,
                 samples: vk::SampleCountFlags::TYPE_1,
-                load_op: vk::AttachmentLoadOp::CLEAR,
+                load_op: load_op,
                 store_op: vk::AttachmentStoreOp::STORE,
-                initial_layout: vk::ImageLayout::UNDEFINED,
+                initial_layout: initial_layout,
                 final_layout: layout,
                 ..Default::default()
             },
@@ -17,6 +17,10 @@ layout(push_constant) uniform PushConstants {
  // The complete dimensions of the window.
  ivec2 surface_pos;
  ivec2 surface_size;
+ // The region of the image to sample, as (x, y, width, height)
+ vec4 src_rect;
+ // The SurfaceTransform applied to the image
+ int transform;
 } push;
 
 /* The array of textures that are the window contents */
@@ -24,11 +24,43 @@ layout(push_constant) uniform PushConstants {
  // The complete dimensions of the window.
  ivec2 surface_pos;
  ivec2 surface_size;
+ // The region of the image to sample, as (x, y, width, height)
+ vec4 src_rect;
+ // The SurfaceTransform applied to the image
+ int transform;
 } push;
 
 /* The array of textures that are the window contents */
 layout(set = 1, binding = 1) uniform sampler2D image;
 
+/*
+ * Find where in the image to sample for a point on the surface
+ *
+ * The image has already been rotated/flipped by push.transform, so
+ * we apply it again here to find our location in the image. Rotations
+ * are counter-clockwise and flips happen before rotation.
+ */
+vec2 transform_coord(vec2 c) {
+ switch (push.transform) {
+ case 1: // 90
+  return vec2(c.y, 1.0 - c.x);
+ case 2: // 180
+  return vec2(1.0 - c.x, 1.0 - c.y);
+ case 3: // 270
+  return vec2(1.0 - c.y, c.x);
+ case 4: // flipped
+  return vec2(1.0 - c.x, c.y);
+ case 5: // flipped 90
+  return vec2(c.y, c.x);
+ case 6: // flipped 180
+  return vec2(c.x, 1.0 - c.y);
+ case 7: // flipped 270
+  return vec2(1.0 - c.y, 1.0 - c.x);
+ default:
+  return c;
+ }
+}
+
 void main() {
  // 1. loc should ALWAYS be 0,1 for the default quad.
  // 2. multiply by two since the axis are over the range (-1,1).
@@ -47,5 +79,7 @@ void main() {
 
  gl_Position = ubo.model * vec4(adjusted, 0.0, 1.0);
 
- fragcoord = coord;
+ // Crop to the source region before undoing the transform, since the
+ // source region is relative to the upright image.
+ fragcoord = transform_coord(push.src_rect.xy + coord * push.src_rect.zw);
 }
@@ -34,6 +34,8 @@ pub struct VKDeviceFeatures {
     pub vkc_supports_nvidia_aftermath: bool,
     /// Does this device support VkSwapchain
     pub vkc_supports_swapchain: bool,
+    /// Does this device support sampling YUV images with VkSamplerYcbcrConversion
+    pub vkc_supports_ycbcr_conversion: bool,
 
     // The following are the lists of extensions that map to the above features
     vkc_ext_mem_exts: [*const i8; 1],
@@ -91,6 +93,7 @@ impl VKDeviceFeatures {
             vkc_supports_phys_dev_drm: false,
             vkc_supports_nvidia_aftermath: false,
             vkc_supports_swapchain: false,
+            vkc_supports_ycbcr_conversion: false,
             vkc_ext_mem_exts: [khr::ExternalMemoryFd::name().as_ptr()],
             vkc_dmabuf_exts: [
                 vk::ExtExternalMemoryDmaBufFn::name().as_ptr(),
@@ -189,6 +192,17 @@ impl VKDeviceFeatures {
         }
         unsafe { inst.get_physical_device_features2(pdev, &mut features) }
 
+        // YCbCr conversion is core in Vulkan 1.1, but is still an optional feature
+        let mut ycbcr_features =
+            vk::PhysicalDeviceSamplerYcbcrConversionFeatures::builder().build();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut ycbcr_features)
+            .build();
+        unsafe { inst.get_physical_device_features2(pdev, &mut features) }
+        if ycbcr_features.sampler_ycbcr_conversion == 0 {
+            log::error!("This vulkan device does not support YCbCr sampler conversion");
+        }
+
         let uses_vk_surface = match info.surface_type {
             SurfaceType::Headless => false,
             _ => true,
@@ -208,6 +222,10 @@ impl VKDeviceFeatures {
         // Only enable VkSwapchain for a swapchain backend which uses it
         ret.vkc_supports_swapchain = supports_swapchain && uses_vk_surface;
         ret.vkc_supports_mut_swapchain = ret.vkc_supports_swapchain && supports_mut_swapchain;
+        // Importing YUV buffers is only useful if we can import dmabufs
+        ret.vkc_supports_ycbcr_conversion = ret.vkc_supports_drm_modifiers
+            && supports_dmabuf
+            && ycbcr_features.sampler_ycbcr_conversion > 0;
 
         match contains_extensions(exts.as_slice(), &ret.vkc_phys_dev_drm_exts) {
             true => ret.vkc_supports_phys_dev_drm = true,
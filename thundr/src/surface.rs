@@ -9,6 +9,44 @@ extern crate nix;
 
 use utils::region::Rect;
 
+/// How the contents of a surface's image are rotated or flipped
+///
+/// This describes the transform that has already been applied to the
+/// image. Thundr undoes it while sampling so that the image is shown
+/// upright. Rotations are counter-clockwise, and flips are about the
+/// vertical axis and happen before the rotation.
+#[repr(i32)]
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum SurfaceTransform {
+    Normal = 0,
+    Rotate90 = 1,
+    Rotate180 = 2,
+    Rotate270 = 3,
+    Flipped = 4,
+    Flipped90 = 5,
+    Flipped180 = 6,
+    Flipped270 = 7,
+}
+
+impl Default for SurfaceTransform {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl SurfaceTransform {
+    /// Is the image's width drawn along the surface's height
+    ///
+    /// This is true for the 90 and 270 degree rotations, in which case
+    /// the width and height of the image are swapped from the surface's.
+    pub fn swaps_dimensions(&self) -> bool {
+        match self {
+            Self::Rotate90 | Self::Rotate270 | Self::Flipped90 | Self::Flipped270 => true,
+            _ => false,
+        }
+    }
+}
+
 /// A surface represents a geometric region that will be
 /// drawn. It needs to have an image attached. The same
 /// image can be bound to multiple surfaces.
@@ -18,6 +56,12 @@ pub struct Surface {
     pub s_rect: Rect<i32>,
     /// For rendering a surface as a constant color
     pub s_color: Option<(f32, f32, f32, f32)>,
+    /// The rotation or flip applied to the bound image
+    pub s_transform: SurfaceTransform,
+    /// The region of the image to sample from, where the image spans
+    /// (0, 0) to (1, 1). The surface rect is filled with this region,
+    /// stretching it if the sizes differ.
+    pub s_src_rect: Rect<f32>,
 }
 
 impl Surface {
@@ -26,6 +70,8 @@ impl Surface {
         Self {
             s_rect: geometry,
             s_color: color,
+            s_transform: SurfaceTransform::Normal,
+            s_src_rect: Rect::new(0.0, 0.0, 1.0, 1.0),
         }
     }
 
@@ -64,4 +110,24 @@ impl Surface {
     pub fn set_color(&mut self, color: (f32, f32, f32, f32)) {
         self.s_color = Some(color);
     }
+
+    #[inline]
+    pub fn get_transform(&self) -> SurfaceTransform {
+        self.s_transform
+    }
+
+    #[inline]
+    pub fn set_transform(&mut self, transform: SurfaceTransform) {
+        self.s_transform = transform;
+    }
+
+    #[inline]
+    pub fn get_src_rect(&self) -> Rect<f32> {
+        self.s_src_rect
+    }
+
+    #[inline]
+    pub fn set_src_rect(&mut self, rect: Rect<f32>) {
+        self.s_src_rect = rect;
+    }
 }
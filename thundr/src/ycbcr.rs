@@ -0,0 +1,160 @@
+// Support for sampling multi-planar YUV images
+//
+// Video decoders hand out buffers in YUV formats, where the luma and
+// chroma are stored in separate planes. Vulkan can convert these to
+// RGB while sampling with a VkSamplerYcbcrConversion. The conversion's
+// sampler has to be baked into the descriptor layout used by the image,
+// so each format gets its own descriptor pool and pipeline.
+extern crate ash;
+
+use crate::descpool::DescPool;
+use utils::log;
+
+use ash::vk;
+
+/// A YUV format that can be imported from a dmabuf
+pub(crate) struct YcbcrFormat {
+    /// The DRM fourcc code for this format
+    pub yf_drm_format: u32,
+    pub yf_vk_format: vk::Format,
+    /// The number of planes the pixel data is split into
+    pub yf_plane_count: u32,
+}
+
+// DRM_FORMAT_NV12: Y plane followed by an interleaved 2x2 subsampled CbCr plane
+const DRM_FORMAT_NV12: u32 = 0x3231564e;
+// DRM_FORMAT_P010: NV12 with 10 bits per component in the high bits of 16
+const DRM_FORMAT_P010: u32 = 0x30313050;
+// DRM_FORMAT_YUV420: Y, Cb, and Cr each in their own plane
+const DRM_FORMAT_YUV420: u32 = 0x32315559;
+
+pub(crate) const YCBCR_FORMATS: [YcbcrFormat; 3] = [
+    YcbcrFormat {
+        yf_drm_format: DRM_FORMAT_NV12,
+        yf_vk_format: vk::Format::G8_B8R8_2PLANE_420_UNORM,
+        yf_plane_count: 2,
+    },
+    YcbcrFormat {
+        yf_drm_format: DRM_FORMAT_P010,
+        yf_vk_format: vk::Format::G10X6_B10X6R10X6_2PLANE_420_UNORM_3PACK16,
+        yf_plane_count: 2,
+    },
+    YcbcrFormat {
+        yf_drm_format: DRM_FORMAT_YUV420,
+        yf_vk_format: vk::Format::G8_B8_R8_3PLANE_420_UNORM,
+        yf_plane_count: 3,
+    },
+];
+
+/// The Vulkan objects needed to sample one YUV format
+pub(crate) struct YcbcrConversion {
+    pub yc_drm_format: u32,
+    pub yc_vk_format: vk::Format,
+    /// The DRM modifiers that images of this format can be imported with
+    pub yc_modifiers: Vec<u64>,
+    pub yc_conversion: vk::SamplerYcbcrConversion,
+    /// This sampler performs yc_conversion. It is an immutable sampler in
+    /// the layout of yc_descpool.
+    pub yc_sampler: vk::Sampler,
+    /// Allocates the descriptors for images of this format
+    pub yc_descpool: DescPool,
+}
+
+impl YcbcrConversion {
+    /// Create the conversion for `format`
+    ///
+    /// `mods` are the modifier properties for this format reported by the
+    /// device. Returns None if none of them can be sampled.
+    pub fn new(
+        dev: &ash::Device,
+        format: &YcbcrFormat,
+        mods: &[vk::DrmFormatModifierPropertiesEXT],
+    ) -> Option<Self> {
+        let usable: Vec<&vk::DrmFormatModifierPropertiesEXT> = mods
+            .iter()
+            .filter(|m| {
+                m.drm_format_modifier_plane_count == format.yf_plane_count
+                    && m.drm_format_modifier_tiling_features
+                        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+            })
+            .collect();
+        if usable.len() == 0 {
+            log::debug!("YUV format {:?} cannot be sampled", format.yf_vk_format);
+            return None;
+        }
+
+        // Only use the optional features if every modifier supports them
+        let supports_all = |feature| {
+            usable
+                .iter()
+                .all(|m| m.drm_format_modifier_tiling_features.contains(feature))
+        };
+        let filter = match supports_all(
+            vk::FormatFeatureFlags::SAMPLED_IMAGE_YCBCR_CONVERSION_LINEAR_FILTER,
+        ) {
+            true => vk::Filter::LINEAR,
+            false => vk::Filter::NEAREST,
+        };
+        let chroma_offset = match supports_all(vk::FormatFeatureFlags::MIDPOINT_CHROMA_SAMPLES) {
+            true => vk::ChromaLocation::MIDPOINT,
+            false => vk::ChromaLocation::COSITED_EVEN,
+        };
+
+        // Video is almost always BT.709 with limited range. linux_dmabuf
+        // doesn't let clients tell us otherwise.
+        let conversion_info = vk::SamplerYcbcrConversionCreateInfo::builder()
+            .format(format.yf_vk_format)
+            .ycbcr_model(vk::SamplerYcbcrModelConversion::YCBCR_709)
+            .ycbcr_range(vk::SamplerYcbcrRange::ITU_NARROW)
+            .components(vk::ComponentMapping::default())
+            .x_chroma_offset(chroma_offset)
+            .y_chroma_offset(chroma_offset)
+            .chroma_filter(filter)
+            .force_explicit_reconstruction(false);
+        let conversion = unsafe {
+            dev.create_sampler_ycbcr_conversion(&conversion_info, None)
+                .ok()?
+        };
+
+        // The filters must match the chroma filter, and the address mode
+        // must be clamp to edge when using a conversion
+        let mut sampler_conversion_info = vk::SamplerYcbcrConversionInfo::builder()
+            .conversion(conversion)
+            .build();
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(filter)
+            .min_filter(filter)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .anisotropy_enable(false)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .push_next(&mut sampler_conversion_info);
+        let sampler = unsafe { dev.create_sampler(&sampler_info, None).unwrap() };
+
+        Some(Self {
+            yc_drm_format: format.yf_drm_format,
+            yc_vk_format: format.yf_vk_format,
+            yc_modifiers: usable.iter().map(|m| m.drm_format_modifier).collect(),
+            yc_conversion: conversion,
+            yc_sampler: sampler,
+            yc_descpool: DescPool::new(dev, Some(sampler)),
+        })
+    }
+
+    /// Destroy the Vulkan resources for this conversion
+    ///
+    /// All images of this format must have been destroyed first.
+    pub fn destroy(&mut self, dev: &ash::Device) {
+        self.yc_descpool.destroy(dev);
+
+        unsafe {
+            dev.destroy_sampler(self.yc_sampler, None);
+            dev.destroy_sampler_ycbcr_conversion(self.yc_conversion, None);
+        }
+    }
+}
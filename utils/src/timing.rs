@@ -1,7 +1,9 @@
 // Helpers to handle budgeting subsystems based on time
 //
 // Austin Shafer - 2020
-use std::time::{Duration,SystemTime,UNIX_EPOCH};
+extern crate nix;
+use nix::time::{clock_gettime, ClockId};
+use std::time::{Duration,Instant,SystemTime,UNIX_EPOCH};
 
 pub fn get_current_time() -> Duration {
     SystemTime::now()
@@ -16,6 +18,20 @@ pub fn get_current_millis() -> u32 {
         .as_millis() as u32
 }
 
+// Convert a CLOCK_MONOTONIC timestamp into an Instant
+//
+// Input devices report when events happened on the monotonic clock,
+// so find how long ago that was and go back that far from now.
+pub fn get_instant_from_monotonic_usec(usec: u64) -> Instant {
+    let now = Instant::now();
+    let elapsed = match clock_gettime(ClockId::CLOCK_MONOTONIC) {
+        Ok(ts) => Duration::from(ts).saturating_sub(Duration::from_micros(usec)),
+        Err(_) => return now,
+    };
+
+    now.checked_sub(elapsed).unwrap_or(now)
+}
+
 // Manages subsystem timings
 //
 // The motivation for this is frame callbacks, which